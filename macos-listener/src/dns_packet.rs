@@ -0,0 +1,391 @@
+use std::io::{Read, Write};
+
+/// Size of the fixed DNS message header
+pub const DNS_HEADER_LEN: usize = 12;
+
+/// Longest encoded domain name allowed by RFC 1035
+const MAX_NAME_LEN: usize = 255;
+
+/// UDP payload size advertised through EDNS0 and used for receive buffers
+pub const MAX_UDP_PAYLOAD: usize = 4096;
+
+/// Resource record type of the EDNS0 OPT pseudo-record
+const OPT_RECORD_TYPE: u16 = 41;
+
+/// Extract the QNAME of the first question in a DNS message
+pub fn extract_query_domain(packet: &[u8]) -> Option<String> {
+    if packet.len() < DNS_HEADER_LEN {
+        return None; // DNS header is at least 12 bytes
+    }
+
+    let question_count = u16::from_be_bytes([packet[4], packet[5]]);
+    if question_count == 0 {
+        return None;
+    }
+
+    let (domain, _) = read_name(packet, DNS_HEADER_LEN)?;
+    if domain.is_empty() {
+        None
+    } else {
+        Some(domain)
+    }
+}
+
+/// Read a possibly compressed domain name starting at `offset`.
+///
+/// Returns the dotted name and the offset just past the name as it appears at
+/// `offset` (i.e. after the first compression pointer, if any). Returns `None` for
+/// truncated names, reserved label types, pointer loops and over-long names.
+pub fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut encoded_len = 0;
+    let mut end = None;
+    let mut visited_pointers = Vec::new();
+
+    loop {
+        let length = *packet.get(offset)? as usize;
+
+        match length & 0xC0 {
+            0x00 if length == 0 => {
+                return Some((labels.join("."), end.unwrap_or(offset + 1)));
+            }
+            0x00 => {
+                let label = packet.get(offset + 1..offset + 1 + length)?;
+                encoded_len += length + 1;
+                if encoded_len > MAX_NAME_LEN {
+                    return None;
+                }
+
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += length + 1;
+            }
+            0xC0 => {
+                let low = *packet.get(offset + 1)? as usize;
+                let target = ((length & 0x3F) << 8) | low;
+
+                // Following the same pointer twice means the name never terminates
+                if visited_pointers.contains(&target) {
+                    return None;
+                }
+                visited_pointers.push(target);
+
+                end.get_or_insert(offset + 2);
+                offset = target;
+            }
+            // 0x40 and 0x80 are reserved label types
+            _ => return None,
+        }
+    }
+}
+
+/// Build a SERVFAIL answer to `query`, keeping its ID and question section
+pub fn servfail_response(query: &[u8]) -> Option<Vec<u8>> {
+    if query.len() < DNS_HEADER_LEN {
+        return None;
+    }
+
+    let question_count = u16::from_be_bytes([query[4], query[5]]);
+    let mut question_end = DNS_HEADER_LEN;
+    for _ in 0..question_count {
+        let (_, name_end) = read_name(query, question_end)?;
+        question_end = name_end + 4; // QTYPE and QCLASS
+    }
+    if question_end > query.len() {
+        return None;
+    }
+
+    let mut response = query[..question_end].to_vec();
+    response[2] = 0x80 | (query[2] & 0x79); // QR set, keep opcode and RD
+    response[3] = 0x80 | 0x02; // RA set, RCODE 2 (server failure)
+    response[6..DNS_HEADER_LEN].fill(0); // no answer, authority or additional records
+    Some(response)
+}
+
+/// Give `response` the transaction ID of `query`, so clients that match answers by ID
+/// accept it even if the upstream renumbered the message
+pub fn with_transaction_id(mut response: Vec<u8>, query: &[u8]) -> Vec<u8> {
+    if response.len() >= DNS_HEADER_LEN && query.len() >= DNS_HEADER_LEN {
+        response[..2].copy_from_slice(&query[..2]);
+    }
+    response
+}
+
+/// Whether the TC bit is set, i.e. the answer did not fit and should be retried over TCP
+pub fn is_truncated(message: &[u8]) -> bool {
+    message.len() >= DNS_HEADER_LEN && message[2] & 0x02 != 0
+}
+
+/// Add an EDNS0 OPT pseudo-record advertising `udp_payload_size` to a query that has no
+/// additional records. Queries that already carry some (possibly their own OPT) are kept as is.
+pub fn with_edns0(query: &[u8], udp_payload_size: u16) -> Vec<u8> {
+    let mut query = query.to_vec();
+    if query.len() < DNS_HEADER_LEN || query[10..DNS_HEADER_LEN] != [0, 0] {
+        return query;
+    }
+
+    query[10..DNS_HEADER_LEN].copy_from_slice(&1u16.to_be_bytes()); // ARCOUNT
+    query.push(0); // root name
+    query.extend_from_slice(&OPT_RECORD_TYPE.to_be_bytes());
+    query.extend_from_slice(&udp_payload_size.to_be_bytes()); // CLASS carries the payload size
+    query.extend_from_slice(&[0, 0, 0, 0]); // extended RCODE, version and flags
+    query.extend_from_slice(&[0, 0]); // no options
+    query
+}
+
+/// Send `query` over a stream-based DNS connection and read the reply.
+///
+/// DNS over TCP (RFC 1035 section 4.2.2) prefixes every message with its length as a
+/// two-byte big-endian integer.
+pub fn exchange_over_tcp<S: Read + Write>(stream: &mut S, query: &[u8]) -> std::io::Result<Vec<u8>> {
+    write_tcp_message(stream, query)?;
+    read_tcp_message(stream)
+}
+
+/// Write one length-prefixed DNS message
+pub fn write_tcp_message<W: Write>(stream: &mut W, message: &[u8]) -> std::io::Result<()> {
+    let length = u16::try_from(message.len())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "DNS message too long"))?;
+
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&length.to_be_bytes());
+    framed.extend_from_slice(message);
+    stream.write_all(&framed)?;
+    stream.flush()
+}
+
+/// Read one length-prefixed DNS message; a stream closed before the prefix fails with
+/// `UnexpectedEof`
+pub fn read_tcp_message<R: Read>(stream: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length)?;
+    let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn header(question_count: u16) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34, 0x01, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        packet[4..6].copy_from_slice(&question_count.to_be_bytes());
+        packet
+    }
+
+    fn encode_labels(packet: &mut Vec<u8>, labels: &[&str]) {
+        for label in labels {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_extract_plain_query() {
+        let mut packet = header(1);
+        encode_labels(&mut packet, &["www", "example", "com"]);
+        packet.push(0);
+        packet.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]); // QTYPE A, QCLASS IN
+
+        assert_eq!(extract_query_domain(&packet), Some("www.example.com".to_string()));
+    }
+
+    #[test]
+    fn test_extract_query_with_compression_pointer() {
+        // QNAME is "api" followed by a pointer to "kion.cloud" stored after the question
+        let mut packet = header(1);
+        encode_labels(&mut packet, &["api"]);
+        let pointer_at = packet.len();
+        packet.extend_from_slice(&[0xC0, 0x00]);
+        packet.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        let target = packet.len();
+        encode_labels(&mut packet, &["kion", "cloud"]);
+        packet.push(0);
+        packet[pointer_at + 1] = target as u8;
+
+        assert_eq!(extract_query_domain(&packet), Some("api.kion.cloud".to_string()));
+
+        // The returned end offset points just past the pointer, at QTYPE
+        let (_, end) = read_name(&packet, DNS_HEADER_LEN).unwrap();
+        assert_eq!(end, pointer_at + 2);
+    }
+
+    #[test]
+    fn test_read_name_pointing_at_earlier_question() {
+        let mut packet = header(2);
+        encode_labels(&mut packet, &["www", "example", "com"]);
+        packet.push(0);
+        packet.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+
+        // Second question: "mail" + pointer to "example.com" inside the first QNAME
+        let second = packet.len();
+        encode_labels(&mut packet, &["mail"]);
+        packet.extend_from_slice(&[0xC0, (DNS_HEADER_LEN + 4) as u8]);
+
+        let (name, end) = read_name(&packet, second).unwrap();
+        assert_eq!(name, "mail.example.com");
+        assert_eq!(end, packet.len());
+    }
+
+    #[test]
+    fn test_pointer_loops_are_rejected() {
+        // A pointer to itself
+        let mut packet = header(1);
+        packet.extend_from_slice(&[0xC0, DNS_HEADER_LEN as u8]);
+        assert_eq!(extract_query_domain(&packet), None);
+
+        // Two names pointing at each other
+        let mut packet = header(1);
+        encode_labels(&mut packet, &["a"]);
+        packet.extend_from_slice(&[0xC0, 0x00]);
+        let second = packet.len();
+        encode_labels(&mut packet, &["b"]);
+        packet.extend_from_slice(&[0xC0, DNS_HEADER_LEN as u8]);
+        packet[DNS_HEADER_LEN + 3] = second as u8;
+        assert_eq!(extract_query_domain(&packet), None);
+    }
+
+    #[test]
+    fn test_malformed_names_are_rejected() {
+        // Label runs past the end of the packet
+        let mut packet = header(1);
+        packet.extend_from_slice(&[10, b'a', b'b']);
+        assert_eq!(extract_query_domain(&packet), None);
+
+        // Pointer past the end of the packet
+        let mut packet = header(1);
+        packet.extend_from_slice(&[0xC0, 0xFF]);
+        assert_eq!(extract_query_domain(&packet), None);
+
+        // Reserved label type
+        let mut packet = header(1);
+        packet.extend_from_slice(&[0x40, 0x00]);
+        assert_eq!(extract_query_domain(&packet), None);
+
+        // No questions / short header
+        assert_eq!(extract_query_domain(&header(0)), None);
+        assert_eq!(extract_query_domain(&[0u8; 6]), None);
+    }
+
+    #[test]
+    fn test_servfail_response_echoes_question() {
+        let mut query = header(1);
+        query[2] = 0x01; // RD
+        query[11] = 1; // an OPT record the response must drop
+        encode_labels(&mut query, &["example", "com"]);
+        query.push(0);
+        query.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        let question_end = query.len();
+        query.extend_from_slice(&[0, 0x00, 0x29, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+
+        let response = servfail_response(&query).unwrap();
+        assert_eq!(&response[..2], &[0x12, 0x34]);
+        assert_eq!(response[2], 0x81);
+        assert_eq!(response[3] & 0x0F, 2);
+        assert_eq!(&response[4..6], &[0, 1]);
+        assert_eq!(&response[6..DNS_HEADER_LEN], &[0; 6]);
+        assert_eq!(&response[DNS_HEADER_LEN..], &query[DNS_HEADER_LEN..question_end]);
+
+        // Truncated questions cannot be answered
+        assert_eq!(servfail_response(&query[..DNS_HEADER_LEN + 3]), None);
+        assert_eq!(servfail_response(&[0u8; 4]), None);
+    }
+
+    /// In-memory stream that replays `incoming` and records everything written
+    struct Duplex {
+        incoming: Cursor<Vec<u8>>,
+        outgoing: Vec<u8>,
+    }
+
+    impl Duplex {
+        fn new(incoming: Vec<u8>) -> Self {
+            Duplex { incoming: Cursor::new(incoming), outgoing: Vec::new() }
+        }
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.incoming.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.outgoing.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_with_edns0_adds_opt_record() {
+        let mut query = header(1);
+        encode_labels(&mut query, &["example", "com"]);
+        query.push(0);
+        query.extend_from_slice(&[0, 1, 0, 1]);
+
+        let extended = with_edns0(&query, 4096);
+        assert_eq!(&extended[10..12], &[0, 1]);
+        assert_eq!(&extended[..10], &query[..10]);
+        assert_eq!(&extended[DNS_HEADER_LEN..query.len()], &query[DNS_HEADER_LEN..]);
+        assert_eq!(&extended[query.len()..], &[0, 0, 41, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(extract_query_domain(&extended).as_deref(), Some("example.com"));
+
+        // Queries with additional records, such as their own OPT, are left alone
+        assert_eq!(with_edns0(&extended, 1232), extended);
+        assert_eq!(with_edns0(&[0x12, 0x34], 4096), vec![0x12, 0x34]);
+    }
+
+    #[test]
+    fn test_with_transaction_id_copies_query_id() {
+        let mut query = header(1);
+        query[..2].copy_from_slice(&[0xBE, 0xEF]);
+        let response = [&[0x12, 0x34, 0x81, 0x80][..], &header(1)[4..]].concat();
+
+        let rewritten = with_transaction_id(response.clone(), &query);
+        assert_eq!(&rewritten[..2], &[0xBE, 0xEF]);
+        assert_eq!(&rewritten[2..], &response[2..]);
+
+        // Messages too short to carry a header are left alone
+        assert_eq!(with_transaction_id(vec![0x12], &query), vec![0x12]);
+    }
+
+    #[test]
+    fn test_is_truncated() {
+        let mut response = header(1);
+        assert!(!is_truncated(&response));
+        response[2] |= 0x02;
+        assert!(is_truncated(&response));
+        assert!(!is_truncated(&response[..4]));
+    }
+
+    #[test]
+    fn test_exchange_over_tcp_uses_length_prefix() {
+        // Framed reply followed by trailing bytes that must not be consumed
+        let reply = [0x12, 0x34, 0x81, 0x80];
+        let mut incoming = vec![0x00, reply.len() as u8];
+        incoming.extend_from_slice(&reply);
+        incoming.extend_from_slice(&[0xFF, 0xFF]);
+
+        let mut query = header(1);
+        encode_labels(&mut query, &["example", "com"]);
+        query.push(0);
+
+        let mut stream = Duplex::new(incoming);
+        let response = exchange_over_tcp(&mut stream, &query).unwrap();
+
+        assert_eq!(response, reply);
+        assert_eq!(&stream.outgoing[..2], &(query.len() as u16).to_be_bytes());
+        assert_eq!(&stream.outgoing[2..], &query[..]);
+    }
+
+    #[test]
+    fn test_exchange_over_tcp_rejects_truncated_reply() {
+        // Length prefix announces 16 bytes but only 2 arrive
+        let mut stream = Duplex::new(vec![0x00, 0x10, 0x12, 0x34]);
+        assert!(exchange_over_tcp(&mut stream, &header(1)).is_err());
+    }
+}
@@ -4,47 +4,53 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use std::sync::mpsc;
 
+use crate::network_monitor::parse_netstat_addr;
 use crate::NetworkConnection;
 
 /// Real-time network monitoring using kqueue
 pub struct KqueueNetworkMonitor {
-    connections: Arc<Mutex<Vec<NetworkConnection>>>,
     change_receiver: Option<mpsc::Receiver<NetworkChange>>,
     is_monitoring: Arc<Mutex<bool>>,
 }
 
 #[derive(Debug, Clone)]
 pub enum NetworkChange {
-    ConnectionAdded(NetworkConnection),
-    ConnectionRemoved(NetworkConnection),
-    ConnectionUpdated(NetworkConnection),
+    Added(NetworkConnection),
+    Removed(NetworkConnection),
+    Updated(NetworkConnection),
 }
 
 impl KqueueNetworkMonitor {
     pub fn new() -> Self {
         Self {
-            connections: Arc::new(Mutex::new(Vec::new())),
             change_receiver: None,
             is_monitoring: Arc::new(Mutex::new(false)),
         }
     }
 
-    /// Start real-time monitoring using kqueue
+    /// Start real-time monitoring using kqueue.
+    ///
+    /// Takes an initial snapshot first, so a failure to read the connection table is reported
+    /// here and connections that already exist are not reported as added.
     pub fn start_monitoring(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let connections = Arc::clone(&self.connections);
+        let initial_connections = Self::get_connections_netstat()?;
         let is_monitoring = Arc::clone(&self.is_monitoring);
         
         let (tx, rx) = mpsc::channel();
         self.change_receiver = Some(rx);
 
+        // Mark as running before the thread checks the flag
+        *self.is_monitoring.lock().unwrap() = true;
+
         // Start monitoring thread
         thread::spawn(move || {
-            if let Err(e) = Self::monitor_loop(connections, is_monitoring, tx) {
+            let result = Self::monitor_loop(initial_connections, Arc::clone(&is_monitoring), tx);
+            *is_monitoring.lock().unwrap() = false;
+            if let Err(e) = result {
                 eprintln!("Kqueue monitoring error: {}", e);
             }
         });
 
-        *self.is_monitoring.lock().unwrap() = true;
         Ok(())
     }
 
@@ -54,9 +60,9 @@ impl KqueueNetworkMonitor {
         self.change_receiver = None;
     }
 
-    /// Get current connections
-    pub fn get_connections(&self) -> Vec<NetworkConnection> {
-        self.connections.lock().unwrap().clone()
+    /// Whether the monitoring thread is still running
+    pub fn is_monitoring(&self) -> bool {
+        *self.is_monitoring.lock().unwrap()
     }
 
     /// Get change events
@@ -74,11 +80,14 @@ impl KqueueNetworkMonitor {
 
     /// Main monitoring loop
     fn monitor_loop(
-        connections: Arc<Mutex<Vec<NetworkConnection>>>,
+        initial_connections: Vec<NetworkConnection>,
         is_monitoring: Arc<Mutex<bool>>,
         change_sender: mpsc::Sender<NetworkChange>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut previous_connections = HashMap::new();
+        let mut previous_connections: HashMap<String, NetworkConnection> = initial_connections
+            .into_iter()
+            .map(|conn| (Self::connection_key(&conn), conn))
+            .collect();
         let mut last_update = Instant::now();
 
         loop {
@@ -98,14 +107,14 @@ impl KqueueNetworkMonitor {
                 // Find new connections
                 for (key, conn) in &current_map {
                     if !previous_connections.contains_key(key) {
-                        change_sender.send(NetworkChange::ConnectionAdded(conn.clone()))?;
+                        change_sender.send(NetworkChange::Added(conn.clone()))?;
                     }
                 }
 
                 // Find removed connections
                 for (key, conn) in &previous_connections {
                     if !current_map.contains_key(key) {
-                        change_sender.send(NetworkChange::ConnectionRemoved(conn.clone()))?;
+                        change_sender.send(NetworkChange::Removed(conn.clone()))?;
                     }
                 }
 
@@ -113,17 +122,11 @@ impl KqueueNetworkMonitor {
                 for (key, conn) in &current_map {
                     if let Some(prev_conn) = previous_connections.get(key) {
                         if Self::connection_changed(prev_conn, conn) {
-                            change_sender.send(NetworkChange::ConnectionUpdated(conn.clone()))?;
+                            change_sender.send(NetworkChange::Updated(conn.clone()))?;
                         }
                     }
                 }
 
-                // Update stored connections
-                {
-                    let mut stored = connections.lock().unwrap();
-                    *stored = current_connections;
-                }
-
                 previous_connections = current_map;
                 last_update = Instant::now();
             }
@@ -167,8 +170,9 @@ impl KqueueNetworkMonitor {
     fn get_connections_netstat() -> Result<Vec<NetworkConnection>, Box<dyn std::error::Error>> {
         use std::process::Command;
         
+        // `-p` only takes a single protocol, so list everything and keep TCP/UDP lines
         let output = Command::new("netstat")
-            .args(&["-an", "-p", "tcp,udp"])
+            .args(["-an"])
             .output()?;
 
         if !output.status.success() {
@@ -179,7 +183,7 @@ impl KqueueNetworkMonitor {
         let mut connections = Vec::new();
 
         for line in output_str.lines() {
-            if line.contains("tcp") || line.contains("udp") {
+            if line.starts_with("tcp") || line.starts_with("udp") {
                 if let Some(conn) = Self::parse_netstat_line(line) {
                     connections.push(conn);
                 }
@@ -196,11 +200,11 @@ impl KqueueNetworkMonitor {
             return None;
         }
 
-        let protocol = if line.contains("tcp") { "TCP" } else { "UDP" };
+        let protocol = if line.starts_with("tcp") { "TCP" } else { "UDP" };
         
-        if let Ok(local_addr) = Self::parse_socket_addr(parts[3]) {
+        if let Ok(local_addr) = parse_netstat_addr(parts[3]) {
             let remote_addr = if parts.len() > 4 && !parts[4].is_empty() {
-                Self::parse_socket_addr(parts[4]).ok()
+                parse_netstat_addr(parts[4]).ok()
             } else {
                 None
             };
@@ -224,31 +228,20 @@ impl KqueueNetworkMonitor {
         }
     }
 
-    /// Parse socket address from string
-    fn parse_socket_addr(addr_str: &str) -> Result<std::net::SocketAddr, Box<dyn std::error::Error>> {
-        if addr_str.starts_with('*') {
-            let port_str = &addr_str[2..];
-            let port = port_str.parse::<u16>()?;
-            Ok(std::net::SocketAddr::new(
-                std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)), 
-                port
-            ))
-        } else if addr_str.contains(':') {
-            addr_str.parse::<std::net::SocketAddr>()
-                .map_err(|e| format!("Failed to parse '{}': {}", addr_str, e).into())
-        } else {
-            Err("Invalid address format".into())
-        }
-    }
-
-    /// Create a unique key for a connection
+    /// Create a unique key for a connection. The state is left out so state transitions are
+    /// reported as updates rather than a close followed by a new connection. The owning PID
+    /// keeps sockets shared by several processes apart; it is omitted while unresolved (0),
+    /// matching `NetworkConnection::is_same_connection`.
     fn connection_key(conn: &NetworkConnection) -> String {
-        format!("{}:{}:{}:{}",
+        let key = format!("{}:{}:{}",
             conn.local_addr,
             conn.remote_addr.map(|addr| addr.to_string()).unwrap_or_else(|| "None".to_string()),
-            conn.protocol,
-            conn.state
-        )
+            conn.protocol
+        );
+        match conn.process_id {
+            0 => key,
+            pid => format!("{}:{}", key, pid),
+        }
     }
 
     /// Check if a connection has changed
@@ -267,37 +260,55 @@ impl Default for KqueueNetworkMonitor {
     }
 }
 
-/// Example usage of kqueue monitoring
-pub fn example_usage() {
-    let mut monitor = KqueueNetworkMonitor::new();
-    
-    // Start real-time monitoring
-    if let Err(e) = monitor.start_monitoring() {
-        eprintln!("Failed to start monitoring: {}", e);
-        return;
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Monitor for changes
-    loop {
-        let changes = monitor.get_changes();
-        for change in changes {
-            match change {
-                NetworkChange::ConnectionAdded(conn) => {
-                    println!("New connection: {} -> {:?}", conn.local_addr, conn.remote_addr);
-                }
-                NetworkChange::ConnectionRemoved(conn) => {
-                    println!("Connection closed: {} -> {:?}", conn.local_addr, conn.remote_addr);
-                }
-                NetworkChange::ConnectionUpdated(conn) => {
-                    println!("Connection updated: {} -> {:?}", conn.local_addr, conn.remote_addr);
-                }
-            }
-        }
+    #[test]
+    fn test_parse_macos_netstat_lines() {
+        let tcp = KqueueNetworkMonitor::parse_netstat_line(
+            "tcp4       0      0  192.168.0.136.51696    17.57.146.20.5223      ESTABLISHED",
+        )
+        .unwrap();
+        assert_eq!(tcp.protocol, "TCP");
+        assert_eq!(tcp.local_addr, "192.168.0.136:51696".parse().unwrap());
+        assert_eq!(tcp.remote_addr, Some("17.57.146.20:5223".parse().unwrap()));
+        assert_eq!(tcp.state, "ESTABLISHED");
+
+        let udp = KqueueNetworkMonitor::parse_netstat_line("udp6       0      0  ::1.4200               *.*").unwrap();
+        assert_eq!(udp.protocol, "UDP");
+        assert_eq!(udp.local_addr, "[::1]:4200".parse().unwrap());
+        assert_eq!(udp.remote_addr, None);
+    }
 
-        // Get current connections
-        let connections = monitor.get_connections();
-        println!("Total connections: {}", connections.len());
+    #[test]
+    fn test_state_change_keeps_connection_key() {
+        let line = "tcp4       0      0  10.0.0.2.50000         10.0.0.1.443           ";
+        let established = KqueueNetworkMonitor::parse_netstat_line(&format!("{}ESTABLISHED", line)).unwrap();
+        let closing = KqueueNetworkMonitor::parse_netstat_line(&format!("{}FIN_WAIT_1", line)).unwrap();
+
+        assert_eq!(
+            KqueueNetworkMonitor::connection_key(&established),
+            KqueueNetworkMonitor::connection_key(&closing)
+        );
+        assert!(KqueueNetworkMonitor::connection_changed(&established, &closing));
+    }
 
-        std::thread::sleep(Duration::from_millis(100));
+    #[test]
+    fn test_connections_differing_by_pid_are_tracked_separately() {
+        let line = "tcp4       0      0  10.0.0.2.50000         10.0.0.1.443           ESTABLISHED";
+        let unresolved = KqueueNetworkMonitor::parse_netstat_line(line).unwrap();
+        let mut parent = unresolved.clone();
+        parent.process_id = 312;
+        let mut child = unresolved.clone();
+        child.process_id = 313;
+
+        let tracked: HashMap<String, NetworkConnection> = [parent, child, unresolved.clone(), unresolved]
+            .into_iter()
+            .map(|conn| (KqueueNetworkMonitor::connection_key(&conn), conn))
+            .collect();
+        let mut pids: Vec<u32> = tracked.values().map(|conn| conn.process_id).collect();
+        pids.sort();
+        assert_eq!(pids, vec![0, 312, 313]);
     }
 }
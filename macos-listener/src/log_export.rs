@@ -0,0 +1,246 @@
+use crate::{ConnectionLogEntry, NetworkConnection};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportFormat::Csv => write!(f, "CSV"),
+            ExportFormat::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+const CSV_HEADER: &str = "timestamp,event_type,local_addr,remote_addr,process_name,process_id,protocol,state";
+
+/// Render log entries in the given format
+pub fn format_entries(entries: &[ConnectionLogEntry], format: ExportFormat) -> Result<String, Box<dyn std::error::Error>> {
+    match format {
+        ExportFormat::Csv => Ok(to_csv(entries)),
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(&to_json(entries))?),
+    }
+}
+
+/// Write log entries to `path`, returning how many were exported
+pub fn export_to_file(
+    entries: &[ConnectionLogEntry],
+    format: ExportFormat,
+    path: &str,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let contents = format_entries(entries, format)?;
+    std::fs::write(path, contents)?;
+    Ok(entries.len())
+}
+
+/// Multi-line summary of a connection for pasting into a bug report
+pub fn connection_details(connection: &NetworkConnection) -> String {
+    let remote = connection.remote_addr.map(|addr| addr.to_string()).unwrap_or_else(|| "-".to_string());
+    format!(
+        "Local Address: {}\nRemote Address: {}\nProcess: {}\nProcess ID: {}\nProtocol: {}\nState: {}\nBytes Sent: {}\nBytes Received: {}\n",
+        connection.local_addr,
+        remote,
+        connection.process_name,
+        connection.process_id,
+        connection.protocol,
+        connection.state,
+        connection.bytes_sent,
+        connection.bytes_received,
+    )
+}
+
+/// `connection_details` for a log entry, preceded by when it was logged and what happened
+pub fn entry_details(entry: &ConnectionLogEntry) -> String {
+    format!(
+        "Timestamp: {}\nEvent: {:?}\n{}",
+        format_timestamp(entry),
+        entry.event_type,
+        connection_details(&entry.connection)
+    )
+}
+
+fn to_csv(entries: &[ConnectionLogEntry]) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+
+    for entry in entries {
+        let fields = [
+            format_timestamp(entry),
+            format!("{:?}", entry.event_type),
+            entry.connection.local_addr.to_string(),
+            entry.connection.remote_addr.map(|addr| addr.to_string()).unwrap_or_default(),
+            entry.connection.process_name.clone(),
+            entry.connection.process_id.to_string(),
+            entry.connection.protocol.clone(),
+            entry.connection.state.clone(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_escape(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+fn to_json(entries: &[ConnectionLogEntry]) -> serde_json::Value {
+    entries
+        .iter()
+        .map(|entry| {
+            serde_json::json!({
+                "timestamp": format_timestamp(entry),
+                "event_type": format!("{:?}", entry.event_type),
+                "local_addr": entry.connection.local_addr.to_string(),
+                "remote_addr": entry.connection.remote_addr.map(|addr| addr.to_string()),
+                "process_name": entry.connection.process_name,
+                "process_id": entry.connection.process_id,
+                "protocol": entry.connection.protocol,
+                "state": entry.connection.state,
+            })
+        })
+        .collect()
+}
+
+fn format_timestamp(entry: &ConnectionLogEntry) -> String {
+    chrono::DateTime::<chrono::Utc>::from(entry.timestamp).to_rfc3339()
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConnectionEvent;
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    fn entry(process_name: &str, remote: Option<&str>, event_type: ConnectionEvent) -> ConnectionLogEntry {
+        ConnectionLogEntry {
+            connection: NetworkConnection {
+                local_addr: "192.168.1.10:52000".parse().unwrap(),
+                remote_addr: remote.map(|addr| addr.parse().unwrap()),
+                protocol: "TCP".to_string(),
+                state: "ESTABLISHED".to_string(),
+                process_name: process_name.to_string(),
+                process_id: 42,
+                bytes_sent: 0,
+                bytes_received: 0,
+                last_updated: Instant::now(),
+                interface: "en0".to_string(),
+            },
+            timestamp: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            event_type,
+            id: 1,
+        }
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let entries = vec![
+            entry("curl", Some("93.184.216.34:443"), ConnectionEvent::New),
+            entry("Google Chrome, Helper", None, ConnectionEvent::Closed),
+        ];
+
+        let csv = format_entries(&entries, ExportFormat::Csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            "2023-11-14T22:13:20+00:00,New,192.168.1.10:52000,93.184.216.34:443,curl,42,TCP,ESTABLISHED"
+        );
+        // Fields containing commas are quoted, missing remote address is empty
+        assert_eq!(
+            lines[2],
+            "2023-11-14T22:13:20+00:00,Closed,192.168.1.10:52000,,\"Google Chrome, Helper\",42,TCP,ESTABLISHED"
+        );
+    }
+
+    #[test]
+    fn test_json_export() {
+        let entries = vec![entry("curl", Some("93.184.216.34:443"), ConnectionEvent::Established)];
+
+        let json = format_entries(&entries, ExportFormat::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(value[0]["event_type"], "Established");
+        assert_eq!(value[0]["remote_addr"], "93.184.216.34:443");
+        assert_eq!(value[0]["process_name"], "curl");
+        assert_eq!(value[0]["protocol"], "TCP");
+        assert_eq!(value[0]["state"], "ESTABLISHED");
+        assert_eq!(value[0]["timestamp"], "2023-11-14T22:13:20+00:00");
+    }
+
+    #[test]
+    fn test_log_filter_selects_exported_entries() {
+        let entries = [
+            entry("curl", Some("93.184.216.34:443"), ConnectionEvent::New),
+            entry("ssh", Some("10.0.0.5:22"), ConnectionEvent::Closed),
+        ];
+
+        let filtered: Vec<ConnectionLogEntry> = entries
+            .iter()
+            .filter(|entry| entry.matches_filter("CLOSED"))
+            .cloned()
+            .collect();
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].connection.process_name, "ssh");
+
+        assert!(entries.iter().all(|entry| entry.matches_filter("")));
+    }
+
+    #[test]
+    fn test_entry_details() {
+        let mut entry = entry("curl", Some("93.184.216.34:443"), ConnectionEvent::New);
+        entry.connection.bytes_sent = 512;
+        entry.connection.bytes_received = 2048;
+
+        assert_eq!(
+            entry_details(&entry),
+            "Timestamp: 2023-11-14T22:13:20+00:00\n\
+             Event: New\n\
+             Local Address: 192.168.1.10:52000\n\
+             Remote Address: 93.184.216.34:443\n\
+             Process: curl\n\
+             Process ID: 42\n\
+             Protocol: TCP\n\
+             State: ESTABLISHED\n\
+             Bytes Sent: 512\n\
+             Bytes Received: 2048\n"
+        );
+
+        entry.connection.remote_addr = None;
+        assert!(connection_details(&entry.connection).contains("Remote Address: -\n"));
+    }
+
+    #[test]
+    fn test_csv_escape() {
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn test_export_to_unwritable_path_fails() {
+        let entries = vec![entry("curl", None, ConnectionEvent::New)];
+        let result = export_to_file(&entries, ExportFormat::Csv, "/nonexistent-dir/log.csv");
+        assert!(result.is_err());
+    }
+}
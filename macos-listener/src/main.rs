@@ -1,18 +1,33 @@
 use eframe::egui;
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::process::Command;
 use std::net::{IpAddr, SocketAddr};
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 
+mod dns_packet;
+mod kqueue_monitor;
+mod log_export;
 mod network_monitor;
 mod socks5_client;
+mod socks5_connector;
 mod traffic_interceptor;
 mod traffic_interceptor_helpers;
 mod real_proxy;
+mod reverse_dns;
+mod rule_matcher;
+mod ruleset;
+mod throughput;
+mod tls_sni;
 use network_monitor::LowLevelNetworkMonitor;
-use traffic_interceptor::TrafficInterceptor;
+use kqueue_monitor::{KqueueNetworkMonitor, NetworkChange};
+use traffic_interceptor::{TrafficInterceptor, DEFAULT_MAX_INTERCEPTED_CONNECTIONS};
+use rule_matcher::CompiledRule;
 use real_proxy::RealTrafficProxy;
+use socks5_client::Socks5Client;
+use socks5_connector::DEFAULT_PROXY_TIMEOUT;
+use log_export::ExportFormat;
+use reverse_dns::{ReverseDnsPool, REVERSE_DNS_WORKERS};
 
 #[derive(Debug, Clone)]
 pub struct NetworkConnection {
@@ -28,6 +43,18 @@ pub struct NetworkConnection {
     pub interface: String,
 }
 
+impl NetworkConnection {
+    /// Whether `other` is the same socket seen in another snapshot: same addresses and
+    /// protocol, owned by the same process. Connections whose owner is unresolved (PID 0)
+    /// only match each other, so they still collapse to one entry per address pair.
+    pub fn is_same_connection(&self, other: &NetworkConnection) -> bool {
+        self.local_addr == other.local_addr
+            && self.remote_addr == other.remote_addr
+            && self.protocol == other.protocol
+            && self.process_id == other.process_id
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ConnectionLogEntry {
     pub connection: NetworkConnection,
@@ -36,7 +63,39 @@ pub struct ConnectionLogEntry {
     pub id: u64,
 }
 
-#[derive(Debug, Clone)]
+impl ConnectionLogEntry {
+    /// Log entry for a change pushed by the real-time monitor
+    fn from_network_change(change: NetworkChange, id: u64) -> Self {
+        let (connection, event_type) = match change {
+            NetworkChange::Added(conn) => (conn, ConnectionEvent::New),
+            NetworkChange::Removed(conn) => (conn, ConnectionEvent::Closed),
+            NetworkChange::Updated(conn) => (conn, ConnectionEvent::Updated),
+        };
+        Self {
+            connection,
+            timestamp: SystemTime::now(),
+            event_type,
+            id,
+        }
+    }
+
+    /// Case-insensitive match of the log filter against addresses, process, protocol, state and event
+    pub fn matches_filter(&self, filter: &str) -> bool {
+        if filter.is_empty() {
+            return true;
+        }
+        
+        let filter_lower = filter.to_lowercase();
+        self.connection.local_addr.to_string().to_lowercase().contains(&filter_lower)
+            || self.connection.remote_addr.map(|addr| addr.to_string().to_lowercase().contains(&filter_lower)).unwrap_or(false)
+            || self.connection.process_name.to_lowercase().contains(&filter_lower)
+            || self.connection.protocol.to_lowercase().contains(&filter_lower)
+            || self.connection.state.to_lowercase().contains(&filter_lower)
+            || format!("{:?}", self.event_type).to_lowercase().contains(&filter_lower)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ConnectionEvent {
     New,
     Updated,
@@ -50,7 +109,56 @@ pub struct ProxyRule {
     pub name: String,
     pub pattern: String,  // e.g., "*.kion.cloud", "100.64.1.*", "*.kiongroup.net"
     pub enabled: bool,
-    pub proxy_id: u32,
+    /// Where matching traffic goes; persisted as the proxy id or `"direct"`
+    pub proxy_id: RuleTarget,
+    /// Evaluation order; lower numbers are matched first, ties go to the older rule
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// Destination of a rule: a configured proxy, or a direct connection bypassing all proxies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "RuleTargetRepr", into = "RuleTargetRepr")]
+pub enum RuleTarget {
+    Proxy(u32),
+    Direct,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum RuleTargetRepr {
+    Proxy(u32),
+    Keyword(String),
+}
+
+impl TryFrom<RuleTargetRepr> for RuleTarget {
+    type Error = String;
+    
+    fn try_from(repr: RuleTargetRepr) -> Result<Self, Self::Error> {
+        match repr {
+            RuleTargetRepr::Proxy(id) => Ok(RuleTarget::Proxy(id)),
+            RuleTargetRepr::Keyword(keyword) if keyword == "direct" => Ok(RuleTarget::Direct),
+            RuleTargetRepr::Keyword(other) => Err(format!("unknown rule target '{}'", other)),
+        }
+    }
+}
+
+impl From<RuleTarget> for RuleTargetRepr {
+    fn from(target: RuleTarget) -> Self {
+        match target {
+            RuleTarget::Proxy(id) => RuleTargetRepr::Proxy(id),
+            RuleTarget::Direct => RuleTargetRepr::Keyword("direct".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for RuleTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleTarget::Proxy(id) => write!(f, "{}", id),
+            RuleTarget::Direct => write!(f, "direct"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -63,6 +171,9 @@ pub struct ProxyConfig {
     pub username: Option<String>,
     pub password: Option<String>,
     pub enabled: bool,
+    /// Latency or error from the last "Test" run; not persisted
+    #[serde(skip)]
+    pub last_test_result: Option<Result<Duration, String>>,
 }
 
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
@@ -92,9 +203,33 @@ impl std::fmt::Display for ProxyType {
 pub struct ProxyManager {
     pub proxies: Vec<ProxyConfig>,
     pub rules: Vec<ProxyRule>,
+    /// Enabled rules in matching form; rebuilt by `recompile` whenever `rules` changes
+    #[serde(skip)]
+    pub compiled_rules: Vec<CompiledRule>,
     pub next_proxy_id: u32,
     pub next_rule_id: u32,
     pub global_enabled: bool,
+    /// Usage per proxy id; shared by clones so the interceptors' copies report into the GUI's
+    #[serde(skip)]
+    pub stats: Arc<Mutex<HashMap<u32, ProxyStats>>>,
+    /// Matches per rule id, shared by clones like `stats`
+    #[serde(skip)]
+    pub rule_stats: Arc<Mutex<HashMap<u32, RuleStats>>>,
+}
+
+/// How often a proxy was selected for traffic and how much it carried; not persisted
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProxyStats {
+    pub matched_connections: u64,
+    pub bytes_relayed: u64,
+    pub last_used: Option<SystemTime>,
+}
+
+/// How often a rule matched live traffic, to spot rules that never fire; not persisted
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleStats {
+    pub hit_count: u64,
+    pub last_matched: Option<Instant>,
 }
 
 impl Default for ProxyManager {
@@ -102,9 +237,12 @@ impl Default for ProxyManager {
         Self {
             proxies: Vec::new(),
             rules: Vec::new(),
+            compiled_rules: Vec::new(),
             next_proxy_id: 1,
             next_rule_id: 1,
             global_enabled: false,
+            stats: Arc::new(Mutex::new(HashMap::new())),
+            rule_stats: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 }
@@ -123,28 +261,95 @@ impl ProxyManager {
             username: None,
             password: None,
             enabled: true,
+            last_test_result: None,
         };
         
         self.proxies.push(proxy);
         id
     }
     
-    pub fn add_rule(&mut self, name: String, pattern: String, proxy_id: u32) -> u32 {
+    /// Add a proxy with optional credentials; username and password must be given together
+    pub fn add_proxy_with_auth(
+        &mut self,
+        name: String,
+        host: String,
+        port: u16,
+        proxy_type: ProxyType,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Result<u32, Box<dyn std::error::Error>> {
+        Self::validate_credentials(&username, &password)?;
+        
+        let id = self.add_proxy(name, host, port, proxy_type);
+        if let Some(proxy) = self.proxies.iter_mut().find(|p| p.id == id) {
+            proxy.username = username;
+            proxy.password = password;
+        }
+        Ok(id)
+    }
+    
+    /// Replace the credentials of an existing proxy; `None` for both clears them
+    pub fn set_proxy_credentials(
+        &mut self,
+        proxy_id: u32,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Self::validate_credentials(&username, &password)?;
+        
+        let proxy = self
+            .proxies
+            .iter_mut()
+            .find(|p| p.id == proxy_id)
+            .ok_or_else(|| format!("Proxy {} not found", proxy_id))?;
+        proxy.username = username;
+        proxy.password = password;
+        Ok(())
+    }
+    
+    fn validate_credentials(
+        username: &Option<String>,
+        password: &Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match (username, password) {
+            (Some(_), Some(_)) | (None, None) => Ok(()),
+            _ => Err("Username and password must be provided together".into()),
+        }
+    }
+    
+    pub fn add_rule(&mut self, name: String, pattern: String, proxy_id: u32) -> Result<u32, Box<dyn std::error::Error>> {
+        self.add_rule_with_target(name, pattern, RuleTarget::Proxy(proxy_id))
+    }
+    
+    /// Add a rule routing to `target`; `RuleTarget::Direct` exempts matches from all proxies.
+    /// Fails if a `re:` sub-pattern is not a valid regex.
+    pub fn add_rule_with_target(
+        &mut self,
+        name: String,
+        pattern: String,
+        target: RuleTarget,
+    ) -> Result<u32, Box<dyn std::error::Error>> {
+        rule_matcher::validate_pattern(&pattern)?;
+        
         let id = self.next_rule_id;
         self.next_rule_id += 1;
         
+        // New rules go last
+        let priority = self.rules.iter().map(|r| r.priority).max().map_or(0, |p| p + 1);
         let rule = ProxyRule {
             id,
             name,
             pattern,
             enabled: true,
-            proxy_id,
+            proxy_id: target,
+            priority,
         };
         
         println!("Adding rule: {} -> {} (proxy_id: {})", rule.name, rule.pattern, rule.proxy_id);
         self.rules.push(rule);
+        self.recompile();
         println!("Total rules now: {}", self.rules.len());
-        id
+        Ok(id)
     }
     
     pub fn remove_proxy(&mut self, id: u32) -> bool {
@@ -153,7 +358,7 @@ impl ProxyManager {
             self.proxies.remove(pos);
             
             // Count rules that will be removed
-            let rules_to_remove: Vec<_> = self.rules.iter().filter(|r| r.proxy_id == id).collect();
+            let rules_to_remove: Vec<_> = self.rules.iter().filter(|r| r.proxy_id == RuleTarget::Proxy(id)).collect();
             if !rules_to_remove.is_empty() {
                 println!("Warning: Removing proxy '{}' will also remove {} rules:", proxy_name, rules_to_remove.len());
                 for rule in &rules_to_remove {
@@ -162,7 +367,8 @@ impl ProxyManager {
             }
             
             // Remove rules that use this proxy
-            self.rules.retain(|r| r.proxy_id != id);
+            self.rules.retain(|r| r.proxy_id != RuleTarget::Proxy(id));
+            self.recompile();
             true
         } else {
             false
@@ -172,12 +378,70 @@ impl ProxyManager {
     pub fn remove_rule(&mut self, id: u32) -> bool {
         if let Some(pos) = self.rules.iter().position(|r| r.id == id) {
             self.rules.remove(pos);
+            self.recompile();
             true
         } else {
             false
         }
     }
     
+    /// Sort `rules` into evaluation order and rebuild `compiled_rules` from them
+    pub fn recompile(&mut self) {
+        self.rules.sort_by_key(|r| (r.priority, r.id));
+        self.compiled_rules = rule_matcher::compile_rules(&self.rules);
+    }
+    
+    /// Move a rule one place earlier (`up`) or later in evaluation order.
+    /// Priorities are renumbered to match the resulting order.
+    pub fn move_rule(&mut self, id: u32, up: bool) -> bool {
+        self.rules.sort_by_key(|r| (r.priority, r.id));
+        let Some(pos) = self.rules.iter().position(|r| r.id == id) else {
+            return false;
+        };
+        
+        let target = if up { pos.checked_sub(1) } else { Some(pos + 1) };
+        match target {
+            Some(target) if target < self.rules.len() => self.rules.swap(pos, target),
+            _ => return false,
+        }
+        
+        for (index, rule) in self.rules.iter_mut().enumerate() {
+            rule.priority = index as i32;
+        }
+        self.recompile();
+        true
+    }
+    
+    /// Pattern covering `remote`: the address itself, or its /24 (/64 for IPv6)
+    pub fn override_pattern(remote: IpAddr, whole_subnet: bool) -> String {
+        match (remote.to_canonical(), whole_subnet) {
+            (IpAddr::V4(ip), false) => format!("{}/32", ip),
+            (IpAddr::V6(ip), false) => format!("{}/128", ip),
+            (IpAddr::V4(ip), true) => format!("{}/24", std::net::Ipv4Addr::from(u32::from(ip) & !0xff)),
+            (IpAddr::V6(ip), true) => format!("{}/64", std::net::Ipv6Addr::from(u128::from(ip) & !(u64::MAX as u128))),
+        }
+    }
+    
+    /// Pin traffic to `remote` (or its subnet) to `proxy_id` with a rule named after the
+    /// process that opened the connection. The rule is evaluated before all existing ones.
+    pub fn add_override_rule(
+        &mut self,
+        process_name: &str,
+        remote: IpAddr,
+        whole_subnet: bool,
+        proxy_id: u32,
+    ) -> Result<u32, Box<dyn std::error::Error>> {
+        let name = if process_name.is_empty() { "Manual override" } else { process_name };
+        let id = self.add_rule(name.to_string(), Self::override_pattern(remote, whole_subnet), proxy_id)?;
+        
+        let first_priority = self.rules.iter().map(|r| r.priority).min().unwrap_or(0);
+        if let Some(rule) = self.rules.iter_mut().find(|r| r.id == id) {
+            rule.priority = first_priority - 1;
+        }
+        self.recompile();
+        Ok(id)
+    }
+    
     pub fn get_proxy_for_connection(&self, remote_addr: &SocketAddr) -> Option<&ProxyConfig> {
         if !self.global_enabled {
             return None;
@@ -197,23 +461,80 @@ impl ProxyManager {
         
 //         println!("Checking proxy rules for hostname: {}", hostname);
         
-        for rule in &self.rules {
-            if !rule.enabled {
-                continue;
-            }
-            
-//             println!("Checking rule: {} (pattern: {})", rule.name, rule.pattern);
-            
-            if self.matches_pattern(&rule.pattern, &hostname) {
-                println!("Rule '{}' matched for hostname '{}'", rule.name, hostname);
-                return self.proxies.iter().find(|p| p.id == rule.proxy_id && p.enabled);
-            }
+        if let Some(rule) = self.matching_rule(&hostname, Some(remote_addr.ip())) {
+            println!("Rule '{}' matched for hostname '{}'", rule.name, hostname);
+            return self.proxy_for_target(rule.proxy_id);
         }
         
 //         println!("No matching rule found for hostname: {}", hostname);
         None
     }
     
+    /// Proxy for a connection to the host name `host`, matched by name only
+    pub fn get_proxy_for_host(&self, host: &str) -> Option<&ProxyConfig> {
+        if !self.global_enabled {
+            return None;
+        }
+        
+        self.matching_rule(host, None)
+            .and_then(|rule| self.proxy_for_target(rule.proxy_id))
+    }
+    
+    /// First enabled rule matching `hostname` (and `ip` when known), in evaluation order
+    pub fn matching_rule(&self, hostname: &str, ip: Option<IpAddr>) -> Option<&CompiledRule> {
+        self.compiled_rules.iter().find(|rule| rule.matches(hostname, ip))
+    }
+    
+    /// The enabled proxy a matching rule routes to; `None` for direct rules and missing proxies
+    pub fn proxy_for_target(&self, target: RuleTarget) -> Option<&ProxyConfig> {
+        match target {
+            RuleTarget::Proxy(id) => self.proxies.iter().find(|p| p.id == id && p.enabled),
+            RuleTarget::Direct => None,
+        }
+    }
+    
+    /// Count a connection routed through `proxy_id`
+    pub fn record_proxy_match(&self, proxy_id: u32) {
+        let mut stats = self.stats.lock().unwrap();
+        let entry = stats.entry(proxy_id).or_default();
+        entry.matched_connections += 1;
+        entry.last_used = Some(SystemTime::now());
+    }
+    
+    /// Add `bytes` relayed in either direction through `proxy_id`
+    pub fn record_proxy_bytes(&self, proxy_id: u32, bytes: u64) {
+        let mut stats = self.stats.lock().unwrap();
+        let entry = stats.entry(proxy_id).or_default();
+        entry.bytes_relayed += bytes;
+        entry.last_used = Some(SystemTime::now());
+    }
+    
+    /// Usage of `proxy_id`, zeroed if it has not carried traffic yet
+    pub fn proxy_stats(&self, proxy_id: u32) -> ProxyStats {
+        self.stats.lock().unwrap().get(&proxy_id).cloned().unwrap_or_default()
+    }
+    
+    pub fn reset_stats(&self) {
+        self.stats.lock().unwrap().clear();
+    }
+    
+    /// Count a match of `rule_id` against live traffic
+    pub fn record_rule_match(&self, rule_id: u32) {
+        let mut rule_stats = self.rule_stats.lock().unwrap();
+        let entry = rule_stats.entry(rule_id).or_default();
+        entry.hit_count += 1;
+        entry.last_matched = Some(Instant::now());
+    }
+    
+    /// Matches of `rule_id`, zeroed if it has not matched yet
+    pub fn rule_stats(&self, rule_id: u32) -> RuleStats {
+        self.rule_stats.lock().unwrap().get(&rule_id).cloned().unwrap_or_default()
+    }
+    
+    pub fn reset_rule_stats(&self) {
+        self.rule_stats.lock().unwrap().clear();
+    }
+    
     fn reverse_dns_lookup(&self, _ip: IpAddr) -> Option<String> {
         // For now, we'll implement a simple approach
         // In a real implementation, you would use proper reverse DNS lookup
@@ -221,44 +542,6 @@ impl ProxyManager {
         None
     }
     
-    fn matches_pattern(&self, pattern: &str, hostname: &str) -> bool {
-        // Exact match
-        if pattern == hostname {
-            return true;
-        }
-        
-        // Domain wildcard patterns (e.g., "*.kion.cloud")
-        if pattern.starts_with("*.") {
-            let suffix = &pattern[2..];
-            return hostname.ends_with(suffix);
-        }
-        
-        // Prefix wildcard patterns (e.g., "kion.*")
-        if pattern.ends_with(".*") {
-            let prefix = &pattern[..pattern.len() - 2];
-            return hostname.starts_with(prefix);
-        }
-        
-        // IP address wildcard patterns (e.g., "192.168.1.*")
-        if pattern.contains(".*") && !pattern.starts_with("*") && !pattern.ends_with("*") {
-            let parts: Vec<&str> = pattern.split(".*").collect();
-            if parts.len() == 2 {
-                let prefix = parts[0];
-                return hostname.starts_with(prefix);
-            }
-        }
-        
-        // General wildcard matching
-        if pattern.contains("*") {
-            let parts: Vec<&str> = pattern.split('*').collect();
-            if parts.len() == 2 {
-                return hostname.starts_with(parts[0]) && hostname.ends_with(parts[1]);
-            }
-        }
-        
-        false
-    }
-    
     /// Save configuration to JSON file
     pub fn save_to_file(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
         let json = serde_json::to_string_pretty(self)?;
@@ -267,40 +550,168 @@ impl ProxyManager {
         Ok(())
     }
     
+    /// Save configuration, creating the config directory if needed. The file is written
+    /// next to `path` first and renamed into place so a crash never leaves it half-written.
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = format!("{}.tmp", path);
+        self.save_to_file(&tmp_path)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+    
     /// Load configuration from JSON file
     pub fn load_from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let json = std::fs::read_to_string(path)?;
-        let manager: ProxyManager = serde_json::from_str(&json)?;
+        let mut manager: ProxyManager = serde_json::from_str(&json)?;
+        manager.recompile();
         println!("Loaded configuration with {} proxies and {} rules", manager.proxies.len(), manager.rules.len());
         Ok(manager)
     }
     
-    /// Get default config file path
+    /// Load configuration, falling back to defaults if the file is missing or corrupt.
+    /// A corrupt file is moved aside to `<path>.bak` so the next save doesn't destroy it.
+    pub fn load(path: &str) -> Self {
+        match Self::load_from_file(path) {
+            Ok(manager) => manager,
+            Err(e) if !std::path::Path::new(path).exists() => {
+                println!("No existing proxy configuration at {}, using defaults ({})", path, e);
+                Self::default()
+            }
+            Err(e) => {
+                let backup_path = format!("{}.bak", path);
+                eprintln!("Failed to load proxy configuration from {}: {}", path, e);
+                match std::fs::rename(path, &backup_path) {
+                    Ok(()) => eprintln!("Moved unreadable configuration to {}", backup_path),
+                    Err(e) => eprintln!("Failed to back up unreadable configuration: {}", e),
+                }
+                Self::default()
+            }
+        }
+    }
+    
+    /// Get default config file path (`~/Library/Application Support/macos-listener`)
     pub fn get_config_path() -> String {
+        let home_dir = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+        format!("{}/Library/Application Support/macos-listener/proxy-config.json", home_dir)
+    }
+    
+    /// Location used by older versions, read once if the new file doesn't exist yet
+    fn legacy_config_path() -> String {
         let home_dir = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
         format!("{}/.macos-listener-proxy-config.json", home_dir)
     }
+    
+    /// Load the user's configuration from `get_config_path`, migrating the legacy file if present
+    pub fn load_user_config() -> Self {
+        let config_path = Self::get_config_path();
+        let legacy_path = Self::legacy_config_path();
+        if !std::path::Path::new(&config_path).exists() && std::path::Path::new(&legacy_path).exists() {
+            println!("Migrating proxy configuration from {}", legacy_path);
+            return Self::load(&legacy_path);
+        }
+        Self::load(&config_path)
+    }
+}
+
+/// Number of connection log entries kept by default
+const DEFAULT_MAX_LOG_ENTRIES: usize = 1000;
+
+/// Drop the oldest log entries beyond `max_entries`, returning how many were dropped
+fn trim_connection_log(log: &mut VecDeque<ConnectionLogEntry>, max_entries: usize) -> u64 {
+    let mut dropped = 0;
+    while log.len() > max_entries {
+        log.pop_front();
+        dropped += 1;
+    }
+    dropped
+}
+
+/// Append New/Closed events for connections that appeared or disappeared between two snapshots
+fn append_connection_changes(
+    log: &mut VecDeque<ConnectionLogEntry>,
+    previous: &[NetworkConnection],
+    current: &[NetworkConnection],
+    next_id: &mut u64,
+) {
+    // Find new connections
+    for new_conn in current {
+        let is_new = !previous.iter().any(|prev_conn| prev_conn.is_same_connection(new_conn));
+
+        if is_new {
+            *next_id += 1;
+            let log_entry = ConnectionLogEntry {
+                connection: new_conn.clone(),
+                timestamp: SystemTime::now(),
+                event_type: ConnectionEvent::New,
+                id: *next_id,
+            };
+            log.push_back(log_entry);
+        }
+    }
+
+    // Find closed connections
+    for prev_conn in previous {
+        let is_closed = !current.iter().any(|new_conn| new_conn.is_same_connection(prev_conn));
+
+        if is_closed {
+            *next_id += 1;
+            let log_entry = ConnectionLogEntry {
+                connection: prev_conn.clone(),
+                timestamp: SystemTime::now(),
+                event_type: ConnectionEvent::Closed,
+                id: *next_id,
+            };
+            log.push_back(log_entry);
+        }
+    }
 }
 
 pub struct MacosListenerApp {
     connections: Arc<Mutex<Vec<NetworkConnection>>>,
     connection_log: Arc<Mutex<VecDeque<ConnectionLogEntry>>>,
+    /// Log entries kept before the oldest are dropped
+    max_log_entries: usize,
+    /// Log entries dropped since start
+    dropped_log_entries: u64,
+    /// Intercepted connections kept by the traffic interceptors
+    max_intercepted_connections: usize,
+    show_limits_dialog: bool,
     last_update: Instant,
     update_interval: Duration,
     selected_connection: Option<usize>,
     filter_text: String,
     show_local_only: bool,
     show_remote_only: bool,
+    /// TCP states shown in the connections table, from `network_monitor::TCP_STATES`; empty shows all
+    state_filter: HashSet<&'static str>,
+    /// Show the PTR name next to remote addresses
+    reverse_dns_enabled: bool,
+    /// Started the first time reverse DNS is enabled
+    reverse_dns: Option<ReverseDnsPool>,
+    remote_hostnames: HashMap<IpAddr, String>,
     sort_by: SortBy,
     sort_ascending: bool,
     stats: NetworkStats,
+    /// Bytes/sec derived from successive stats updates, drawn in the header
+    throughput: throughput::ThroughputHistory,
     log_filter_text: String,
     show_log_dialog: bool,
+    show_export_dialog: bool,
+    export_path: String,
+    export_format: ExportFormat,
+    /// Outcome of the last export, shown in the export dialog
+    export_status: Option<Result<String, String>>,
     selected_log_entry: Option<usize>,
     log_entry_id_counter: u64,
     previous_connections: Vec<NetworkConnection>,
     network_monitor: LowLevelNetworkMonitor,
     use_low_level: bool,
+    /// Log connection changes as the kqueue monitor reports them instead of diffing polls
+    use_realtime_monitor: bool,
+    realtime_monitor: Option<KqueueNetworkMonitor>,
     proxy_manager: ProxyManager,
     show_proxy_config: bool,
     show_proxy_rules: bool,
@@ -308,15 +719,35 @@ pub struct MacosListenerApp {
     new_proxy_host: String,
     new_proxy_port: String,
     new_proxy_type: ProxyType,
+    new_proxy_username: String,
+    new_proxy_password: String,
+    proxy_form_error: Option<String>,
+    /// Proxy whose credentials are being edited inline, with the pending username/password
+    editing_credentials: Option<(u32, String, String)>,
+    /// Results from background proxy tests, applied on the UI thread each frame
+    proxy_test_tx: mpsc::Sender<(u32, Result<Duration, String>)>,
+    proxy_test_rx: mpsc::Receiver<(u32, Result<Duration, String>)>,
+    proxy_tests_running: HashSet<u32>,
+    /// Seconds a proxy test may spend reaching and negotiating with the proxy
+    proxy_test_timeout_secs: u64,
     new_rule_name: String,
     new_rule_pattern: String,
-    selected_proxy_for_rule: Option<u32>,
+    selected_proxy_for_rule: Option<RuleTarget>,
+    rule_form_error: Option<String>,
+    /// Host name or IP typed into the rule tester, and the outcome of the last test
+    rule_test_input: String,
+    rule_test_result: Option<String>,
+    /// File the rules dialog imports rules from and exports them to
+    ruleset_path: String,
+    /// Outcome of the last rules import or export, shown in the rules dialog
+    ruleset_status: Option<Result<String, String>>,
     traffic_interceptor: Option<TrafficInterceptor>,
     system_interceptor: TrafficInterceptor,
     show_intercepted_traffic: bool,
     config_changed: bool,
     real_proxy: Option<RealTrafficProxy>,
     real_proxy_enabled: bool,
+    real_proxy_dns_port: u16,
     show_test_hostname: bool,
     test_hostname: String,
     test_result: Option<String>,
@@ -324,44 +755,52 @@ pub struct MacosListenerApp {
 
 impl Default for MacosListenerApp {
     fn default() -> Self {
-        // Try to load proxy configuration from file
-        let proxy_manager = match ProxyManager::load_from_file(&ProxyManager::get_config_path()) {
-            Ok(manager) => {
-                println!("Loaded proxy configuration from {}", ProxyManager::get_config_path());
-                println!("Loaded {} proxies and {} rules", manager.proxies.len(), manager.rules.len());
-                for (i, proxy) in manager.proxies.iter().enumerate() {
-                    println!("  Proxy {}: {} ({}:{})", i, proxy.name, proxy.host, proxy.port);
-                }
-                for (i, rule) in manager.rules.iter().enumerate() {
-                    println!("  Rule {}: {} -> {} (proxy_id: {})", i, rule.name, rule.pattern, rule.proxy_id);
-                }
-                manager
-            }
-            Err(e) => {
-                println!("No existing proxy configuration found, using defaults: {}", e);
-                ProxyManager::default()
-            }
-        };
+        // Load proxy configuration from file, falling back to defaults
+        let proxy_manager = ProxyManager::load_user_config();
+        println!("Loaded {} proxies and {} rules", proxy_manager.proxies.len(), proxy_manager.rules.len());
+        for (i, proxy) in proxy_manager.proxies.iter().enumerate() {
+            println!("  Proxy {}: {} ({}:{})", i, proxy.name, proxy.host, proxy.port);
+        }
+        for (i, rule) in proxy_manager.rules.iter().enumerate() {
+            println!("  Rule {}: {} -> {} (proxy_id: {})", i, rule.name, rule.pattern, rule.proxy_id);
+        }
+        
+        let (proxy_test_tx, proxy_test_rx) = mpsc::channel();
         
         Self {
             connections: Arc::new(Mutex::new(Vec::new())),
             connection_log: Arc::new(Mutex::new(VecDeque::new())),
+            max_log_entries: DEFAULT_MAX_LOG_ENTRIES,
+            dropped_log_entries: 0,
+            max_intercepted_connections: DEFAULT_MAX_INTERCEPTED_CONNECTIONS,
+            show_limits_dialog: false,
             last_update: Instant::now(),
             update_interval: Duration::from_secs(2),
             selected_connection: None,
             filter_text: String::new(),
             show_local_only: false,
             show_remote_only: false,
+            state_filter: HashSet::new(),
+            reverse_dns_enabled: false,
+            reverse_dns: None,
+            remote_hostnames: HashMap::new(),
             sort_by: SortBy::LocalAddr,
             sort_ascending: true,
             stats: NetworkStats::default(),
+            throughput: throughput::ThroughputHistory::new(throughput::THROUGHPUT_HISTORY_LEN),
             log_filter_text: String::new(),
             show_log_dialog: false,
+            show_export_dialog: false,
+            export_path: Self::default_export_path(ExportFormat::Csv),
+            export_format: ExportFormat::Csv,
+            export_status: None,
             selected_log_entry: None,
             log_entry_id_counter: 0,
             previous_connections: Vec::new(),
             network_monitor: LowLevelNetworkMonitor::new(),
             use_low_level: true,
+            use_realtime_monitor: false,
+            realtime_monitor: None,
             proxy_manager,
             show_proxy_config: false,
             show_proxy_rules: false,
@@ -369,15 +808,29 @@ impl Default for MacosListenerApp {
             new_proxy_host: String::new(),
             new_proxy_port: String::new(),
             new_proxy_type: ProxyType::Socks5,
+            new_proxy_username: String::new(),
+            new_proxy_password: String::new(),
+            proxy_form_error: None,
+            editing_credentials: None,
+            proxy_test_tx,
+            proxy_test_rx,
+            proxy_tests_running: HashSet::new(),
+            proxy_test_timeout_secs: DEFAULT_PROXY_TIMEOUT.as_secs(),
             new_rule_name: String::new(),
             new_rule_pattern: String::new(),
+            rule_form_error: None,
             selected_proxy_for_rule: None,
+            rule_test_input: String::new(),
+            rule_test_result: None,
+            ruleset_path: Self::default_ruleset_path(),
+            ruleset_status: None,
             traffic_interceptor: None,
             system_interceptor: TrafficInterceptor::new(Arc::new(Mutex::new(ProxyManager::default()))),
             show_intercepted_traffic: false,
             config_changed: false,
             real_proxy: None,
             real_proxy_enabled: false,
+            real_proxy_dns_port: real_proxy::DEFAULT_DNS_PROXY_PORT,
             show_test_hostname: false,
             test_hostname: String::new(),
             test_result: None,
@@ -402,6 +855,18 @@ impl Default for SortBy {
     }
 }
 
+impl SortBy {
+    /// Sort order after clicking the header of `column`: the current column flips direction,
+    /// any other column becomes the sort key, ascending
+    fn after_header_click(self, ascending: bool, column: SortBy) -> (SortBy, bool) {
+        if self == column {
+            (column, !ascending)
+        } else {
+            (column, true)
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct NetworkStats {
     pub total_connections: usize,
@@ -409,6 +874,8 @@ struct NetworkStats {
     pub udp_connections: usize,
     pub listening_ports: usize,
     pub established_connections: usize,
+    pub total_bytes_sent: u64,
+    pub total_bytes_received: u64,
     pub last_updated: Instant,
 }
 
@@ -420,6 +887,8 @@ impl Default for NetworkStats {
             udp_connections: 0,
             listening_ports: 0,
             established_connections: 0,
+            total_bytes_sent: 0,
+            total_bytes_received: 0,
             last_updated: Instant::now(),
         }
     }
@@ -427,6 +896,9 @@ impl Default for NetworkStats {
 
 impl eframe::App for MacosListenerApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.sync_realtime_monitor();
+        self.apply_network_changes();
+        
         // Update connections periodically
         if self.last_update.elapsed() > self.update_interval {
             self.update_connections();
@@ -436,7 +908,15 @@ impl eframe::App for MacosListenerApp {
         // Request repaint for smooth updates
         ctx.request_repaint_after(Duration::from_millis(100));
 
+        self.apply_proxy_test_results();
+        self.update_remote_hostnames();
         self.render_ui(ctx);
+        
+        // Persist proxy/rule edits made during this frame
+        if self.config_changed {
+            self.config_changed = false;
+            self.save_proxy_config();
+        }
     }
 }
 
@@ -450,11 +930,70 @@ impl MacosListenerApp {
         app
     }
     
+    /// Test a proxy on a background thread by connecting through it to `PROXY_TEST_TARGET`
+    fn start_proxy_test(&mut self, proxy_id: u32) {
+        let Some(proxy) = self.proxy_manager.proxies.iter().find(|p| p.id == proxy_id) else {
+            return;
+        };
+        if !self.proxy_tests_running.insert(proxy_id) {
+            return; // Already running
+        }
+        
+        let client = Socks5Client::new(proxy.clone())
+            .with_timeout(Duration::from_secs(self.proxy_test_timeout_secs));
+        let tx = self.proxy_test_tx.clone();
+        std::thread::spawn(move || {
+            let target: SocketAddr = PROXY_TEST_TARGET.parse().expect("valid test target");
+            let _ = tx.send((proxy_id, client.test_connection(target)));
+        });
+    }
+    
+    /// Store finished proxy test results on their `ProxyConfig`
+    fn apply_proxy_test_results(&mut self) {
+        while let Ok((proxy_id, result)) = self.proxy_test_rx.try_recv() {
+            self.proxy_tests_running.remove(&proxy_id);
+            if let Some(proxy) = self.proxy_manager.proxies.iter_mut().find(|p| p.id == proxy_id) {
+                proxy.last_test_result = Some(result);
+            }
+        }
+    }
+    
+    /// Queue PTR lookups for remote addresses not seen before and collect finished ones
+    fn update_remote_hostnames(&mut self) {
+        if !self.reverse_dns_enabled {
+            return;
+        }
+        let pool = self.reverse_dns.get_or_insert_with(|| {
+            ReverseDnsPool::new(REVERSE_DNS_WORKERS, reverse_dns::lookup)
+        });
+        if let Ok(connections) = self.connections.lock() {
+            for remote in connections.iter().filter_map(|conn| conn.remote_addr) {
+                pool.request(remote.ip());
+            }
+        }
+        for (ip, name) in pool.take_results() {
+            if let Some(name) = name {
+                self.remote_hostnames.insert(ip, name);
+            }
+        }
+    }
+
+    /// Remote address as shown in the connections table, with its PTR name when known
+    fn remote_addr_label(&self, remote_addr: Option<SocketAddr>) -> String {
+        let Some(addr) = remote_addr else {
+            return "N/A".to_string();
+        };
+        match self.remote_hostnames.get(&addr.ip()).filter(|_| self.reverse_dns_enabled) {
+            Some(name) => format!("{} ({})", addr, name),
+            None => addr.to_string(),
+        }
+    }
+
     /// Save proxy configuration to file
     fn save_proxy_config(&mut self) {
         println!("Attempting to save configuration with {} proxies and {} rules", 
                  self.proxy_manager.proxies.len(), self.proxy_manager.rules.len());
-        match self.proxy_manager.save_to_file(&ProxyManager::get_config_path()) {
+        match self.proxy_manager.save(&ProxyManager::get_config_path()) {
             Ok(_) => println!("Proxy configuration saved to {}", ProxyManager::get_config_path()),
             Err(e) => eprintln!("Failed to save proxy configuration: {}", e),
         }
@@ -463,8 +1002,12 @@ impl MacosListenerApp {
     fn update_connections(&mut self) {
         let connections = self.get_network_connections();
         
-        // Log connection changes
-        self.log_connection_changes(&connections);
+        // Log connection changes, unless the real-time monitor is already doing so
+        if self.realtime_monitor.is_some() {
+            self.previous_connections = connections.clone();
+        } else {
+            self.log_connection_changes(&connections);
+        }
         
         if let Ok(mut conns) = self.connections.lock() {
             *conns = connections;
@@ -472,65 +1015,63 @@ impl MacosListenerApp {
         self.update_stats();
     }
 
-    fn log_connection_changes(&mut self, new_connections: &[NetworkConnection]) {
-        let mut log = if let Ok(log) = self.connection_log.lock() {
-            log.clone()
-        } else {
-            return;
-        };
-
-        // Find new connections
-        for new_conn in new_connections {
-            let is_new = !self.previous_connections.iter().any(|prev_conn| {
-                prev_conn.local_addr == new_conn.local_addr && 
-                prev_conn.remote_addr == new_conn.remote_addr &&
-                prev_conn.protocol == new_conn.protocol
-            });
-
-            if is_new {
-                self.log_entry_id_counter += 1;
-                let log_entry = ConnectionLogEntry {
-                    connection: new_conn.clone(),
-                    timestamp: SystemTime::now(),
-                    event_type: ConnectionEvent::New,
-                    id: self.log_entry_id_counter,
-                };
-                log.push_back(log_entry);
+    /// Start or stop the real-time monitor to follow its checkbox, falling back to polling
+    /// if it cannot start or stops on its own
+    fn sync_realtime_monitor(&mut self) {
+        if self.use_realtime_monitor && self.realtime_monitor.is_none() {
+            let mut monitor = KqueueNetworkMonitor::new();
+            match monitor.start_monitoring() {
+                Ok(()) => {
+                    println!("⚡ Real-time connection monitoring started");
+                    self.realtime_monitor = Some(monitor);
+                }
+                Err(e) => {
+                    eprintln!("Real-time monitor failed to start: {}, falling back to polling", e);
+                    self.use_realtime_monitor = false;
+                }
+            }
+        } else if let Some(monitor) = self.realtime_monitor.as_mut() {
+            if !self.use_realtime_monitor {
+                monitor.stop_monitoring();
+                self.realtime_monitor = None;
+                println!("Real-time connection monitoring stopped");
+            } else if !monitor.is_monitoring() {
+                eprintln!("Real-time monitor stopped unexpectedly, falling back to polling");
+                self.use_realtime_monitor = false;
+                self.realtime_monitor = None;
             }
         }
+    }
 
-        // Find closed connections
-        for prev_conn in &self.previous_connections {
-            let is_closed = !new_connections.iter().any(|new_conn| {
-                new_conn.local_addr == prev_conn.local_addr && 
-                new_conn.remote_addr == prev_conn.remote_addr &&
-                new_conn.protocol == prev_conn.protocol
-            });
+    /// Move changes pushed by the real-time monitor into the connection log
+    fn apply_network_changes(&mut self) {
+        let changes = match self.realtime_monitor.as_mut() {
+            Some(monitor) => monitor.get_changes(),
+            None => return,
+        };
+        if changes.is_empty() {
+            return;
+        }
 
-            if is_closed {
+        if let Ok(mut log) = self.connection_log.lock() {
+            for change in changes {
                 self.log_entry_id_counter += 1;
-                let log_entry = ConnectionLogEntry {
-                    connection: prev_conn.clone(),
-                    timestamp: SystemTime::now(),
-                    event_type: ConnectionEvent::Closed,
-                    id: self.log_entry_id_counter,
-                };
-                log.push_back(log_entry);
+                log.push_back(ConnectionLogEntry::from_network_change(change, self.log_entry_id_counter));
             }
-        }
 
-        // Update previous connections
-        self.previous_connections = new_connections.to_vec();
-
-        // Keep only last 1000 entries
-        while log.len() > 1000 {
-            log.pop_front();
+            self.dropped_log_entries += trim_connection_log(&mut log, self.max_log_entries);
         }
+    }
 
-        // Update the shared log
-        if let Ok(mut shared_log) = self.connection_log.lock() {
-            *shared_log = log;
-        }
+    fn log_connection_changes(&mut self, new_connections: &[NetworkConnection]) {
+        let Ok(mut log) = self.connection_log.lock() else {
+            return;
+        };
+        append_connection_changes(&mut log, &self.previous_connections, new_connections, &mut self.log_entry_id_counter);
+        self.dropped_log_entries += trim_connection_log(&mut log, self.max_log_entries);
+        drop(log);
+
+        self.previous_connections = new_connections.to_vec();
     }
 
     fn get_network_connections(&mut self) -> Vec<NetworkConnection> {
@@ -784,7 +1325,14 @@ impl MacosListenerApp {
             self.stats.udp_connections = connections.iter().filter(|c| c.protocol == "UDP").count();
             self.stats.listening_ports = connections.iter().filter(|c| c.state == "LISTEN").count();
             self.stats.established_connections = connections.iter().filter(|c| c.state == "ESTABLISHED").count();
+            self.stats.total_bytes_sent = connections.iter().map(|c| c.bytes_sent).sum();
+            self.stats.total_bytes_received = connections.iter().map(|c| c.bytes_received).sum();
             self.stats.last_updated = Instant::now();
+            self.throughput.record(
+                self.stats.total_bytes_sent,
+                self.stats.total_bytes_received,
+                self.stats.last_updated,
+            );
         }
     }
 
@@ -799,6 +1347,13 @@ impl MacosListenerApp {
                 ui.label(format!("UDP: {}", self.stats.udp_connections));
                 ui.label(format!("Listening: {}", self.stats.listening_ports));
                 ui.label(format!("Established: {}", self.stats.established_connections));
+                
+                ui.separator();
+                
+                let rate = self.throughput.latest();
+                ui.colored_label(egui::Color32::LIGHT_BLUE, format!("↑ {}", throughput::format_rate(rate.sent_per_sec)));
+                ui.colored_label(egui::Color32::LIGHT_GREEN, format!("↓ {}", throughput::format_rate(rate.received_per_sec)));
+                throughput::plot(ui, &self.throughput, egui::vec2(180.0, 24.0));
             });
             
             ui.separator();
@@ -815,6 +1370,31 @@ impl MacosListenerApp {
                 
                 ui.checkbox(&mut self.show_local_only, "Local only");
                 ui.checkbox(&mut self.show_remote_only, "Remote only");
+                ui.checkbox(&mut self.reverse_dns_enabled, "Reverse DNS")
+                    .on_hover_text("Look up host names for remote addresses in the background");
+
+                let states_label = if self.state_filter.is_empty() {
+                    "States: All".to_string()
+                } else {
+                    format!("States: {} selected", self.state_filter.len())
+                };
+                ui.menu_button(states_label, |ui| {
+                    let mut all = self.state_filter.is_empty();
+                    if ui.checkbox(&mut all, "All").changed() && all {
+                        self.state_filter.clear();
+                    }
+                    ui.separator();
+                    for state in network_monitor::TCP_STATES {
+                        let mut selected = self.state_filter.contains(state);
+                        if ui.checkbox(&mut selected, state).changed() {
+                            if selected {
+                                self.state_filter.insert(state);
+                            } else {
+                                self.state_filter.remove(state);
+                            }
+                        }
+                    }
+                });
                 
                 ui.separator();
                 
@@ -823,6 +1403,8 @@ impl MacosListenerApp {
                 if ui.button("Force Traditional").clicked() {
                     self.use_low_level = false;
                 }
+                ui.checkbox(&mut self.use_realtime_monitor, "Real-time log (kqueue)")
+                    .on_hover_text("Log new and closed connections within ~100ms instead of on each refresh");
                 
                 ui.separator();
                 
@@ -842,17 +1424,30 @@ impl MacosListenerApp {
                 if ui.button("🧪 Test Hostname").clicked() {
                     self.show_test_hostname = true;
                 }
+                if ui.button("History Limits").clicked() {
+                    self.show_limits_dialog = true;
+                }
                 
                 ui.separator();
                 ui.label("Real Proxy (Actual Traffic Routing):");
                 ui.checkbox(&mut self.real_proxy_enabled, "Enable Real Traffic Proxy");
+                ui.label("DNS port:");
+                ui.add_enabled(
+                    self.real_proxy.is_none(),
+                    egui::DragValue::new(&mut self.real_proxy_dns_port).range(1..=65535),
+                );
                 
                 if self.real_proxy_enabled && self.real_proxy.is_none() {
                     let proxy_manager = Arc::new(Mutex::new(self.proxy_manager.clone()));
-                    self.real_proxy = Some(RealTrafficProxy::new(proxy_manager));
+                    let mut real_proxy = RealTrafficProxy::new(proxy_manager).with_dns_port(self.real_proxy_dns_port);
+                    if let Some(ref interceptor) = self.traffic_interceptor {
+                        real_proxy = real_proxy.with_interceptor(interceptor.clone());
+                    }
+                    self.real_proxy = Some(real_proxy);
                     if let Err(e) = self.real_proxy.as_ref().unwrap().start() {
-                        eprintln!("Failed to start real proxy: {}", e);
+                        eprintln!("Failed to start real proxy on DNS port {}: {}", self.real_proxy_dns_port, e);
                         self.real_proxy = None;
+                        self.real_proxy_enabled = false;
                     } else {
                         println!("Real traffic proxy started - DNS and TCP traffic will be intercepted and routed through SOCKS5");
                     }
@@ -865,7 +1460,10 @@ impl MacosListenerApp {
                 if ui.button("Start Traffic Interception").clicked() {
                     if self.traffic_interceptor.is_none() {
                         let proxy_manager = Arc::new(Mutex::new(self.proxy_manager.clone()));
-                        self.traffic_interceptor = Some(TrafficInterceptor::new(proxy_manager));
+                        self.traffic_interceptor = Some(
+                            TrafficInterceptor::new(proxy_manager)
+                                .with_max_intercepted_connections(self.max_intercepted_connections),
+                        );
                         if let Some(ref interceptor) = self.traffic_interceptor {
                             let _ = interceptor.start();
                         }
@@ -878,7 +1476,18 @@ impl MacosListenerApp {
                     }
                     self.traffic_interceptor = None;
                 }
-                
+
+                if let Some(ref interceptor) = self.traffic_interceptor {
+                    let label = if interceptor.is_paused() { "Resume Traffic Interception" } else { "Pause Traffic Interception" };
+                    if ui.button(label).clicked() {
+                        if interceptor.is_paused() {
+                            interceptor.resume();
+                        } else {
+                            interceptor.pause();
+                        }
+                    }
+                }
+
                 if ui.button("View Intercepted Traffic").clicked() {
                     self.show_intercepted_traffic = true;
                 }
@@ -886,7 +1495,10 @@ impl MacosListenerApp {
                 if ui.button("Start Traffic Interceptor").clicked() {
                     if self.traffic_interceptor.is_none() {
                         let proxy_manager = Arc::new(Mutex::new(self.proxy_manager.clone()));
-                        self.traffic_interceptor = Some(TrafficInterceptor::new(proxy_manager));
+                        self.traffic_interceptor = Some(
+                            TrafficInterceptor::new(proxy_manager)
+                                .with_max_intercepted_connections(self.max_intercepted_connections),
+                        );
                         if let Some(ref interceptor) = self.traffic_interceptor {
                             if let Err(e) = interceptor.start() {
                                 eprintln!("Failed to start traffic interceptor: {}", e);
@@ -975,6 +1587,10 @@ impl MacosListenerApp {
                                     log.clear();
                                 }
                             }
+                            if ui.button("Export Log").clicked() {
+                                self.show_export_dialog = true;
+                                self.export_status = None;
+                            }
                         });
                     });
                     
@@ -997,6 +1613,11 @@ impl MacosListenerApp {
             self.render_connection_dialog(ctx);
         }
         
+        // Log export dialog
+        if self.show_export_dialog {
+            self.render_export_dialog(ctx);
+        }
+        
         // Proxy configuration dialog
         if self.show_proxy_config {
             self.render_proxy_config_dialog(ctx);
@@ -1016,6 +1637,50 @@ impl MacosListenerApp {
         if self.show_test_hostname {
             self.render_test_hostname_dialog(ctx);
         }
+
+        // History limits dialog
+        if self.show_limits_dialog {
+            self.render_limits_dialog(ctx);
+        }
+    }
+
+    /// Column header that sorts the table by `column`, with ▲/▼ on the active one
+    fn sort_header(&mut self, ui: &mut egui::Ui, label: &str, column: SortBy) {
+        let text = if self.sort_by == column {
+            format!("{} {}", label, if self.sort_ascending { "▲" } else { "▼" })
+        } else {
+            label.to_string()
+        };
+        if ui.add(egui::Button::new(text).frame(false)).clicked() {
+            (self.sort_by, self.sort_ascending) = self.sort_by.after_header_click(self.sort_ascending, column);
+        }
+    }
+
+    /// Right-click menu on a connection row: turn its remote host or subnet into a rule
+    fn render_override_menu(&mut self, ui: &mut egui::Ui, conn: &NetworkConnection, remote: IpAddr) {
+        if self.proxy_manager.proxies.is_empty() {
+            ui.label("Configure a proxy first");
+            return;
+        }
+        
+        for (label, whole_subnet) in [("Route host via", false), ("Route subnet via", true)] {
+            let pattern = ProxyManager::override_pattern(remote, whole_subnet);
+            ui.menu_button(format!("{} ({})", label, pattern), |ui| {
+                let mut chosen = None;
+                for proxy in &self.proxy_manager.proxies {
+                    if ui.button(&proxy.name).clicked() {
+                        chosen = Some(proxy.id);
+                    }
+                }
+                if let Some(proxy_id) = chosen {
+                    match self.proxy_manager.add_override_rule(&conn.process_name, remote, whole_subnet, proxy_id) {
+                        Ok(_) => self.config_changed = true,
+                        Err(e) => eprintln!("Failed to add override rule: {}", e),
+                    }
+                    ui.close();
+                }
+            });
+        }
     }
 
     fn render_connections_table(&mut self, ui: &mut egui::Ui) {
@@ -1035,10 +1700,16 @@ impl MacosListenerApp {
                 if self.show_remote_only && conn.remote_addr.is_none() {
                     return false;
                 }
+                if !self.state_filter.is_empty()
+                    && !self.state_filter.contains(network_monitor::normalize_tcp_state(&conn.state))
+                {
+                    return false;
+                }
                 if !self.filter_text.is_empty() {
                     let filter_lower = self.filter_text.to_lowercase();
                     conn.local_addr.to_string().to_lowercase().contains(&filter_lower)
-                        || conn.remote_addr.map(|addr| addr.to_string().to_lowercase().contains(&filter_lower)).unwrap_or(false)
+                        || (conn.remote_addr.is_some()
+                            && self.remote_addr_label(conn.remote_addr).to_lowercase().contains(&filter_lower))
                         || conn.process_name.to_lowercase().contains(&filter_lower)
                         || conn.protocol.to_lowercase().contains(&filter_lower)
                         || conn.state.to_lowercase().contains(&filter_lower)
@@ -1081,26 +1752,30 @@ impl MacosListenerApp {
             .num_columns(9)
             .spacing([4.0, 2.0])
             .show(ui, |ui| {
-                ui.label("Local Address");
-                ui.label("Remote Address");
-                ui.label("Protocol");
-                ui.label("State");
-                ui.label("Process");
+                self.sort_header(ui, "Local Address", SortBy::LocalAddr);
+                self.sort_header(ui, "Remote Address", SortBy::RemoteAddr);
+                self.sort_header(ui, "Protocol", SortBy::Protocol);
+                self.sort_header(ui, "State", SortBy::State);
+                self.sort_header(ui, "Process", SortBy::Process);
                 ui.label("PID");
                 ui.label("Proxy");
-                ui.label("Bytes Sent");
-                ui.label("Bytes Received");
+                self.sort_header(ui, "Bytes Sent", SortBy::BytesSent);
+                self.sort_header(ui, "Bytes Received", SortBy::BytesReceived);
                 ui.end_row();
 
                 // Connection rows
                 for (idx, conn) in sorted_connections.iter().enumerate() {
                     let is_selected = self.selected_connection == Some(idx);
                     
-                    if ui.selectable_label(is_selected, &conn.local_addr.to_string()).clicked() {
+                    let response = ui.selectable_label(is_selected, &conn.local_addr.to_string());
+                    if response.clicked() {
                         self.selected_connection = Some(idx);
                     }
+                    if let Some(remote_addr) = conn.remote_addr {
+                        response.context_menu(|ui| self.render_override_menu(ui, conn, remote_addr.ip()));
+                    }
                     
-                    ui.label(conn.remote_addr.map(|addr| addr.to_string()).unwrap_or_else(|| "N/A".to_string()));
+                    ui.label(self.remote_addr_label(conn.remote_addr));
                     ui.label(&conn.protocol);
                     ui.label(&conn.state);
                     ui.label(&conn.process_name);
@@ -1140,6 +1815,9 @@ impl MacosListenerApp {
                     ui.label(format!("Bytes Sent: {}", conn.bytes_sent));
                     ui.label(format!("Bytes Received: {}", conn.bytes_received));
                     ui.label(format!("Last Updated: {:?}", conn.last_updated.elapsed()));
+                    if ui.button("Copy").clicked() {
+                        ui.ctx().copy_text(log_export::connection_details(conn));
+                    }
                 });
             }
         }
@@ -1154,19 +1832,7 @@ impl MacosListenerApp {
 
         let filtered_entries: Vec<_> = log_entries
             .iter()
-            .filter(|entry| {
-                if !self.log_filter_text.is_empty() {
-                    let filter_lower = self.log_filter_text.to_lowercase();
-                    entry.connection.local_addr.to_string().to_lowercase().contains(&filter_lower)
-                        || entry.connection.remote_addr.map(|addr| addr.to_string().to_lowercase().contains(&filter_lower)).unwrap_or(false)
-                        || entry.connection.process_name.to_lowercase().contains(&filter_lower)
-                        || entry.connection.protocol.to_lowercase().contains(&filter_lower)
-                        || entry.connection.state.to_lowercase().contains(&filter_lower)
-                        || format!("{:?}", entry.event_type).to_lowercase().contains(&filter_lower)
-                } else {
-                    true
-                }
-            })
+            .filter(|entry| entry.matches_filter(&self.log_filter_text))
             .cloned()
             .collect();
 
@@ -1217,6 +1883,102 @@ impl MacosListenerApp {
         });
     }
 
+    fn render_export_dialog(&mut self, ctx: &egui::Context) {
+        let mut export_clicked = false;
+        
+        egui::Window::new("Export Connection Log")
+            .open(&mut self.show_export_dialog)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Format:");
+                    let previous_format = self.export_format;
+                    ui.radio_value(&mut self.export_format, ExportFormat::Csv, "CSV");
+                    ui.radio_value(&mut self.export_format, ExportFormat::Json, "JSON");
+                    
+                    // Keep the file extension in step with the chosen format
+                    if self.export_format != previous_format {
+                        let old_suffix = format!(".{}", previous_format.extension());
+                        if let Some(stem) = self.export_path.strip_suffix(&old_suffix) {
+                            self.export_path = format!("{}.{}", stem, self.export_format.extension());
+                        }
+                    }
+                });
+                
+                ui.horizontal(|ui| {
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut self.export_path);
+                });
+                
+                if !self.log_filter_text.is_empty() {
+                    ui.label(format!("Only entries matching the log filter '{}' are exported", self.log_filter_text));
+                }
+                
+                if ui.button("Export").clicked() {
+                    export_clicked = true;
+                }
+                
+                match &self.export_status {
+                    Some(Ok(message)) => {
+                        ui.colored_label(egui::Color32::GREEN, message);
+                    }
+                    Some(Err(message)) => {
+                        ui.colored_label(egui::Color32::RED, message);
+                    }
+                    None => {}
+                }
+            });
+        
+        if export_clicked {
+            self.export_status = Some(self.export_log());
+        }
+    }
+    
+    /// Export the filtered connection log to `export_path`
+    fn export_log(&self) -> Result<String, String> {
+        let entries: Vec<ConnectionLogEntry> = match self.connection_log.lock() {
+            Ok(log) => log
+                .iter()
+                .filter(|entry| entry.matches_filter(&self.log_filter_text))
+                .cloned()
+                .collect(),
+            Err(_) => return Err("Connection log is unavailable".to_string()),
+        };
+        
+        match log_export::export_to_file(&entries, self.export_format, &self.export_path) {
+            Ok(count) => Ok(format!("Exported {} entries to {}", count, self.export_path)),
+            Err(e) => Err(format!("Failed to export log to {}: {}", self.export_path, e)),
+        }
+    }
+    
+    /// Write all proxies and rules to `ruleset_path`
+    fn export_ruleset(&self) -> Result<String, String> {
+        match ruleset::export_to_file(&self.proxy_manager, &self.ruleset_path) {
+            Ok((proxies, rules)) => Ok(format!("Exported {} proxies and {} rules to {}", proxies, rules, self.ruleset_path)),
+            Err(e) => Err(format!("Failed to export rules to {}: {}", self.ruleset_path, e)),
+        }
+    }
+    
+    /// Add the proxies and rules in `ruleset_path` to the current configuration
+    fn import_ruleset(&mut self) -> Result<String, String> {
+        match ruleset::import_from_file(&mut self.proxy_manager, &self.ruleset_path) {
+            Ok((proxies, rules)) => {
+                self.config_changed = true;
+                Ok(format!("Imported {} proxies and {} rules from {}", proxies, rules, self.ruleset_path))
+            }
+            Err(e) => Err(format!("Failed to import rules from {}: {}", self.ruleset_path, e)),
+        }
+    }
+    
+    fn default_ruleset_path() -> String {
+        let home_dir = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+        format!("{}/Downloads/proxy-rules.json", home_dir)
+    }
+    
+    fn default_export_path(format: ExportFormat) -> String {
+        let home_dir = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+        format!("{}/Downloads/connection-log.{}", home_dir, format.extension())
+    }
+    
     fn render_connection_dialog(&mut self, ctx: &egui::Context) {
         let log_entries = if let Ok(log) = self.connection_log.lock() {
             log.clone()
@@ -1226,19 +1988,7 @@ impl MacosListenerApp {
 
         let filtered_entries: Vec<_> = log_entries
             .iter()
-            .filter(|entry| {
-                if !self.log_filter_text.is_empty() {
-                    let filter_lower = self.log_filter_text.to_lowercase();
-                    entry.connection.local_addr.to_string().to_lowercase().contains(&filter_lower)
-                        || entry.connection.remote_addr.map(|addr| addr.to_string().to_lowercase().contains(&filter_lower)).unwrap_or(false)
-                        || entry.connection.process_name.to_lowercase().contains(&filter_lower)
-                        || entry.connection.protocol.to_lowercase().contains(&filter_lower)
-                        || entry.connection.state.to_lowercase().contains(&filter_lower)
-                        || format!("{:?}", entry.event_type).to_lowercase().contains(&filter_lower)
-                } else {
-                    true
-                }
-            })
+            .filter(|entry| entry.matches_filter(&self.log_filter_text))
             .cloned()
             .collect();
 
@@ -1322,6 +2072,9 @@ impl MacosListenerApp {
                         ui.separator();
                         
                         ui.horizontal(|ui| {
+                            if ui.button("Copy").clicked() {
+                                ui.ctx().copy_text(log_export::entry_details(entry));
+                            }
                             if ui.button("Close").clicked() {
                                 close_dialog = true;
                             }
@@ -1337,22 +2090,35 @@ impl MacosListenerApp {
     
     fn render_proxy_config_dialog(&mut self, ctx: &egui::Context) {
         let mut close_dialog = false;
+        let mut proxies_to_test = Vec::new();
         
         egui::Window::new("Proxy Configuration")
             .open(&mut self.show_proxy_config)
             .show(ctx, |ui| {
-                ui.heading("Proxy Servers");
+                ui.horizontal(|ui| {
+                    ui.heading("Proxy Servers");
+                    if ui.button("Reset Stats").clicked() {
+                        self.proxy_manager.reset_stats();
+                    }
+                    ui.label("Test timeout (s):");
+                    ui.add(egui::DragValue::new(&mut self.proxy_test_timeout_secs).range(1..=300));
+                });
                 ui.separator();
                 
                 // List existing proxies
                 egui::ScrollArea::vertical().show(ui, |ui| {
                     let mut proxies_to_remove = Vec::new();
                     let mut proxies_to_toggle = Vec::new();
+                    let mut credentials_to_save = None;
+                    let mut cancel_credentials_edit = false;
                     
                     for proxy in &self.proxy_manager.proxies {
                         ui.horizontal(|ui| {
                             ui.label(format!("{}: {}:{} ({})", 
                                 proxy.name, proxy.host, proxy.port, proxy.proxy_type));
+                            if let Some(username) = &proxy.username {
+                                ui.label(format!("🔐 {}", username));
+                            }
                             
                             let mut enabled = proxy.enabled;
                             ui.checkbox(&mut enabled, "Enabled");
@@ -1360,10 +2126,68 @@ impl MacosListenerApp {
                                 proxies_to_toggle.push(proxy.id);
                             }
                             
+                            if self.proxy_tests_running.contains(&proxy.id) {
+                                ui.label("⏳ Testing...");
+                            } else {
+                                match &proxy.last_test_result {
+                                    Some(Ok(latency)) => {
+                                        ui.colored_label(egui::Color32::GREEN, format!("● {} ms", latency.as_millis()));
+                                    }
+                                    Some(Err(error)) => {
+                                        ui.colored_label(egui::Color32::RED, "● Failed").on_hover_text(error);
+                                    }
+                                    None => {}
+                                }
+                            }
+                            
+                            let test_button = egui::Button::new("Test");
+                            if ui.add_enabled(!self.proxy_tests_running.contains(&proxy.id), test_button).clicked() {
+                                proxies_to_test.push(proxy.id);
+                            }
+                            
+                            if ui.button("Credentials").clicked() {
+                                self.editing_credentials = Some((
+                                    proxy.id,
+                                    proxy.username.clone().unwrap_or_default(),
+                                    proxy.password.clone().unwrap_or_default(),
+                                ));
+                            }
+                            
                             if ui.button("Remove").clicked() {
                                 proxies_to_remove.push(proxy.id);
                             }
                         });
+                        
+                        let stats = self.proxy_manager.proxy_stats(proxy.id);
+                        let last_used = stats
+                            .last_used
+                            .and_then(|time| time.elapsed().ok())
+                            .map_or("never".to_string(), |elapsed| format!("{}s ago", elapsed.as_secs()));
+                        ui.small(format!("Matched: {}, relayed: {} bytes, last used: {}",
+                            stats.matched_connections, stats.bytes_relayed, last_used));
+                        
+                        // Inline credential editor for the selected proxy
+                        if let Some((editing_id, username, password)) = &mut self.editing_credentials {
+                            if *editing_id == proxy.id {
+                                ui.horizontal(|ui| {
+                                    ui.label("Username:");
+                                    ui.text_edit_singleline(username);
+                                    ui.label("Password:");
+                                    ui.add(egui::TextEdit::singleline(password).password(true));
+                                    
+                                    if ui.button("Save").clicked() {
+                                        credentials_to_save = Some((
+                                            proxy.id,
+                                            non_empty(username),
+                                            non_empty(password),
+                                        ));
+                                    }
+                                    if ui.button("Cancel").clicked() {
+                                        cancel_credentials_edit = true;
+                                    }
+                                });
+                            }
+                        }
                     }
                     
                     // Apply changes after iteration
@@ -1378,6 +2202,20 @@ impl MacosListenerApp {
                             config_changed = true;
                         }
                     }
+                    if let Some((proxy_id, username, password)) = credentials_to_save {
+                        match self.proxy_manager.set_proxy_credentials(proxy_id, username, password) {
+                            Ok(()) => {
+                                self.editing_credentials = None;
+                                self.proxy_form_error = None;
+                                config_changed = true;
+                            }
+                            Err(e) => self.proxy_form_error = Some(e.to_string()),
+                        }
+                    }
+                    if cancel_credentials_edit {
+                        self.editing_credentials = None;
+                        self.proxy_form_error = None;
+                    }
                     
                     // Mark that configuration changed (will save after dialog closes)
                     if config_changed {
@@ -1415,24 +2253,49 @@ impl MacosListenerApp {
                         });
                 });
                 
+                ui.horizontal(|ui| {
+                    ui.label("Username:");
+                    ui.text_edit_singleline(&mut self.new_proxy_username);
+                });
+                
+                ui.horizontal(|ui| {
+                    ui.label("Password:");
+                    ui.add(egui::TextEdit::singleline(&mut self.new_proxy_password).password(true));
+                });
+                
                 if ui.button("Add Proxy").clicked() {
                     if !self.new_proxy_name.is_empty() && !self.new_proxy_host.is_empty() {
                         if let Ok(port) = self.new_proxy_port.parse::<u16>() {
-                            self.proxy_manager.add_proxy(
+                            let result = self.proxy_manager.add_proxy_with_auth(
                                 self.new_proxy_name.clone(),
                                 self.new_proxy_host.clone(),
                                 port,
-                                self.new_proxy_type.clone()
+                                self.new_proxy_type.clone(),
+                                non_empty(&self.new_proxy_username),
+                                non_empty(&self.new_proxy_password),
                             );
                             
-                            // Clear form
-                            self.new_proxy_name.clear();
-                            self.new_proxy_host.clear();
-                            self.new_proxy_port.clear();
+                            match result {
+                                Ok(_) => {
+                                    // Clear form
+                                    self.new_proxy_name.clear();
+                                    self.new_proxy_host.clear();
+                                    self.new_proxy_port.clear();
+                                    self.new_proxy_username.clear();
+                                    self.new_proxy_password.clear();
+                                    self.proxy_form_error = None;
+                                    self.config_changed = true;
+                                }
+                                Err(e) => self.proxy_form_error = Some(e.to_string()),
+                            }
                         }
                     }
                 }
                 
+                if let Some(error) = &self.proxy_form_error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                
                 ui.separator();
                 
                 ui.horizontal(|ui| {
@@ -1442,6 +2305,10 @@ impl MacosListenerApp {
                 });
             });
         
+        for proxy_id in proxies_to_test {
+            self.start_proxy_test(proxy_id);
+        }
+        
         if close_dialog {
             self.show_proxy_config = false;
             // Auto-save configuration when dialog closes
@@ -1451,22 +2318,43 @@ impl MacosListenerApp {
     
     fn render_proxy_rules_dialog(&mut self, ctx: &egui::Context) {
         let mut close_dialog = false;
+        let mut export_rules_clicked = false;
+        let mut import_rules_clicked = false;
         
         egui::Window::new("Proxy Rules")
             .open(&mut self.show_proxy_rules)
             .show(ctx, |ui| {
-                ui.heading("Routing Rules");
+                ui.horizontal(|ui| {
+                    ui.heading("Routing Rules");
+                    if ui.button("Reset Counters").clicked() {
+                        self.proxy_manager.reset_rule_stats();
+                    }
+                });
                 ui.separator();
                 
                 // List existing rules
                 egui::ScrollArea::vertical().show(ui, |ui| {
                     let mut rules_to_remove = Vec::new();
                     let mut rules_to_toggle = Vec::new();
+                    let mut rule_to_move = None;
+                    let rule_count = self.proxy_manager.rules.len();
                     
-                    for rule in &self.proxy_manager.rules {
+                    // Rules are kept in evaluation order, first match wins
+                    for (index, rule) in self.proxy_manager.rules.iter().enumerate() {
                         ui.horizontal(|ui| {
-                            ui.label(format!("{}: {} -> Proxy {}", 
-                                rule.name, rule.pattern, rule.proxy_id));
+                            if ui.add_enabled(index > 0, egui::Button::new("⬆")).clicked() {
+                                rule_to_move = Some((rule.id, true));
+                            }
+                            if ui.add_enabled(index + 1 < rule_count, egui::Button::new("⬇")).clicked() {
+                                rule_to_move = Some((rule.id, false));
+                            }
+                            
+                            match rule.proxy_id {
+                                RuleTarget::Proxy(proxy_id) => ui.label(format!("{}: {} -> Proxy {}", 
+                                    rule.name, rule.pattern, proxy_id)),
+                                RuleTarget::Direct => ui.label(format!("{}: {} -> Direct (bypass)", 
+                                    rule.name, rule.pattern)),
+                            };
                             
                             let mut enabled = rule.enabled;
                             ui.checkbox(&mut enabled, "Enabled");
@@ -1478,6 +2366,12 @@ impl MacosListenerApp {
                                 rules_to_remove.push(rule.id);
                             }
                         });
+                        
+                        let stats = self.proxy_manager.rule_stats(rule.id);
+                        let last_matched = stats
+                            .last_matched
+                            .map_or("never".to_string(), |time| format!("{}s ago", time.elapsed().as_secs()));
+                        ui.small(format!("Hits: {}, last matched: {}", stats.hit_count, last_matched));
                     }
                     
                     // Apply changes after iteration
@@ -1486,6 +2380,9 @@ impl MacosListenerApp {
                         self.proxy_manager.remove_rule(rule_id);
                         config_changed = true;
                     }
+                    if let Some((rule_id, up)) = rule_to_move {
+                        config_changed |= self.proxy_manager.move_rule(rule_id, up);
+                    }
                     for rule_id in rules_to_toggle {
                         if let Some(rule) = self.proxy_manager.rules.iter_mut().find(|r| r.id == rule_id) {
                             rule.enabled = !rule.enabled;
@@ -1495,6 +2392,7 @@ impl MacosListenerApp {
                     
                     // Mark that configuration changed (will save after dialog closes)
                     if config_changed {
+                        self.proxy_manager.recompile();
                         self.config_changed = true;
                     }
                 });
@@ -1511,44 +2409,93 @@ impl MacosListenerApp {
                 ui.horizontal(|ui| {
                     ui.label("Pattern:");
                     ui.text_edit_singleline(&mut self.new_rule_pattern);
-                    ui.label("(e.g., *.kion.cloud, 100.64.1.*)");
+                    ui.label(r"(e.g., *.kion.cloud, 100.64.1.*, re:api-\d+\.corp)");
                 });
                 
                 ui.horizontal(|ui| {
                     ui.label("Proxy:");
                     egui::ComboBox::from_id_salt("proxy_selection")
-                        .selected_text(if let Some(proxy_id) = self.selected_proxy_for_rule {
-                            self.proxy_manager.proxies.iter()
+                        .selected_text(match self.selected_proxy_for_rule {
+                            Some(RuleTarget::Proxy(proxy_id)) => self.proxy_manager.proxies.iter()
                                 .find(|p| p.id == proxy_id)
                                 .map(|p| p.name.clone())
-                                .unwrap_or_else(|| "Select proxy".to_string())
-                        } else {
-                            "Select proxy".to_string()
+                                .unwrap_or_else(|| "Select proxy".to_string()),
+                            Some(RuleTarget::Direct) => "Direct / bypass".to_string(),
+                            None => "Select proxy".to_string(),
                         })
                         .show_ui(ui, |ui| {
                             for proxy in &self.proxy_manager.proxies {
-                                ui.selectable_value(&mut self.selected_proxy_for_rule, Some(proxy.id), &proxy.name);
+                                ui.selectable_value(&mut self.selected_proxy_for_rule, Some(RuleTarget::Proxy(proxy.id)), &proxy.name);
                             }
+                            ui.separator();
+                            ui.selectable_value(&mut self.selected_proxy_for_rule, Some(RuleTarget::Direct), "Direct / bypass");
                         });
                 });
                 
                 if ui.button("Add Rule").clicked() {
                     if !self.new_rule_name.is_empty() && !self.new_rule_pattern.is_empty() {
-                        if let Some(proxy_id) = self.selected_proxy_for_rule {
-                            self.proxy_manager.add_rule(
+                        if let Some(target) = self.selected_proxy_for_rule {
+                            let result = self.proxy_manager.add_rule_with_target(
                                 self.new_rule_name.clone(),
                                 self.new_rule_pattern.clone(),
-                                proxy_id
+                                target
                             );
                             
-                            // Clear form
-                            self.new_rule_name.clear();
-                            self.new_rule_pattern.clear();
-                            self.selected_proxy_for_rule = None;
+                            match result {
+                                Ok(_) => {
+                                    // Clear form
+                                    self.new_rule_name.clear();
+                                    self.new_rule_pattern.clear();
+                                    self.selected_proxy_for_rule = None;
+                                    self.rule_form_error = None;
+                                }
+                                Err(e) => self.rule_form_error = Some(e.to_string()),
+                            }
                         }
                     }
                 }
                 
+                if let Some(error) = &self.rule_form_error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                
+                ui.separator();
+                
+                // Dry-run the matcher without generating traffic
+                ui.heading("Rule Tester");
+                ui.horizontal(|ui| {
+                    ui.label("Host or IP:");
+                    ui.text_edit_singleline(&mut self.rule_test_input);
+                    if ui.button("Test").clicked() {
+                        self.rule_test_result = Some(describe_rule_test(&self.proxy_manager, &self.rule_test_input));
+                    }
+                });
+                if let Some(ref result) = self.rule_test_result {
+                    ui.label(result);
+                }
+                
+                ui.separator();
+                
+                // Share proxies and rules with other machines
+                ui.heading("Import / Export");
+                ui.horizontal(|ui| {
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut self.ruleset_path);
+                });
+                ui.horizontal(|ui| {
+                    export_rules_clicked = ui.button("Export Rules").clicked();
+                    import_rules_clicked = ui.button("Import Rules").clicked();
+                });
+                match &self.ruleset_status {
+                    Some(Ok(message)) => {
+                        ui.colored_label(egui::Color32::GREEN, message);
+                    }
+                    Some(Err(message)) => {
+                        ui.colored_label(egui::Color32::RED, message);
+                    }
+                    None => {}
+                }
+                
                 ui.separator();
                 
                 ui.horizontal(|ui| {
@@ -1558,6 +2505,13 @@ impl MacosListenerApp {
                 });
             });
         
+        if export_rules_clicked {
+            self.ruleset_status = Some(self.export_ruleset());
+        }
+        if import_rules_clicked {
+            self.ruleset_status = Some(self.import_ruleset());
+        }
+        
         if close_dialog {
             self.show_proxy_rules = false;
             // Auto-save configuration when dialog closes
@@ -1578,6 +2532,9 @@ impl MacosListenerApp {
                     let intercepted_connections = interceptor.get_intercepted_connections();
                     
                     ui.label(format!("Total intercepted connections: {}", intercepted_connections.len()));
+                    let total_sent: u64 = intercepted_connections.iter().map(|conn| conn.bytes_sent).sum();
+                    let total_received: u64 = intercepted_connections.iter().map(|conn| conn.bytes_received).sum();
+                    ui.label(format!("Total bytes sent: {}, received: {}", total_sent, total_received));
                     ui.separator();
                     
                     egui::ScrollArea::vertical().show(ui, |ui| {
@@ -1608,6 +2565,7 @@ impl MacosListenerApp {
                                     ui.label("Direct connection");
                                 }
                                 
+                                ui.label(format!("Bytes sent: {}, received: {}", conn.bytes_sent, conn.bytes_received));
                                 ui.label(format!("Intercepted at: {:?}", conn.intercepted_at.elapsed()));
                             });
                         }
@@ -1631,6 +2589,41 @@ impl MacosListenerApp {
         }
     }
     
+    fn render_limits_dialog(&mut self, ctx: &egui::Context) {
+        let mut max_intercepted_connections = self.max_intercepted_connections;
+        let interceptor_dropped = self
+            .traffic_interceptor
+            .as_ref()
+            .map(|interceptor| interceptor.dropped_connections())
+            .unwrap_or(0);
+
+        egui::Window::new("History Limits")
+            .open(&mut self.show_limits_dialog)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Max log entries:");
+                    ui.add(egui::DragValue::new(&mut self.max_log_entries).range(1..=1_000_000));
+                });
+                ui.label(format!("Log entries dropped since start: {}", self.dropped_log_entries));
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("Max intercepted connections:");
+                    ui.add(egui::DragValue::new(&mut max_intercepted_connections).range(1..=1_000_000));
+                });
+                ui.label(format!("Intercepted connections dropped: {}", interceptor_dropped));
+            });
+
+        if max_intercepted_connections != self.max_intercepted_connections {
+            self.max_intercepted_connections = max_intercepted_connections;
+            self.system_interceptor.set_max_intercepted_connections(max_intercepted_connections);
+            if let Some(ref interceptor) = self.traffic_interceptor {
+                interceptor.set_max_intercepted_connections(max_intercepted_connections);
+            }
+        }
+    }
+
     fn render_test_hostname_dialog(&mut self, ctx: &egui::Context) {
         let mut close_dialog = false;
         let mut clear_result = false;
@@ -1834,6 +2827,62 @@ impl MacosListenerApp {
     }
 }
 
+/// Well-known endpoint reached through a proxy by the "Test" button
+const PROXY_TEST_TARGET: &str = "1.1.1.1:80";
+
+/// Dry-run `input` (a host name or IP address) through the rule matcher, describing
+/// which rule and proxy would be used. Lists the evaluation order when nothing matches.
+fn describe_rule_test(manager: &ProxyManager, input: &str) -> String {
+    let input = input.trim();
+    if input.is_empty() {
+        return "Enter a host name or IP address".to_string();
+    }
+    if !manager.global_enabled {
+        return "Proxy routing is disabled, so every connection goes direct".to_string();
+    }
+
+    let (rule, proxy) = match input.parse::<IpAddr>() {
+        Ok(ip) => (
+            manager.matching_rule(&ip.to_string(), Some(ip)),
+            manager.get_proxy_for_connection(&SocketAddr::new(ip, 0)),
+        ),
+        Err(_) => (manager.matching_rule(input, None), manager.get_proxy_for_host(input)),
+    };
+
+    match (rule, proxy) {
+        (Some(rule), Some(proxy)) => format!(
+            "Rule '{}' ({}) matched -> proxy '{}' ({}:{})",
+            rule.name, rule.pattern, proxy.name, proxy.host, proxy.port
+        ),
+        (Some(rule), None) if rule.proxy_id == RuleTarget::Direct => {
+            format!("Rule '{}' ({}) matched -> Direct (bypass)", rule.name, rule.pattern)
+        }
+        (Some(rule), None) => format!(
+            "Rule '{}' ({}) matched -> Direct (proxy {} is missing or disabled)",
+            rule.name, rule.pattern, rule.proxy_id
+        ),
+        (None, _) => {
+            let mut report = format!("No rule matched '{}' -> Direct\n\nEvaluation order:", input);
+            if manager.compiled_rules.is_empty() {
+                report.push_str("\n  (no enabled rules)");
+            }
+            for (index, rule) in manager.compiled_rules.iter().enumerate() {
+                report.push_str(&format!("\n  {}. {}: {} -> {}", index + 1, rule.name, rule.pattern, rule.proxy_id));
+            }
+            report
+        }
+    }
+}
+
+/// Treat an empty text field as an unset optional value
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
 
 fn main() -> Result<(), eframe::Error> {
     // Initialize logging
@@ -1851,4 +2900,477 @@ fn main() -> Result<(), eframe::Error> {
         options,
         Box::new(|_cc| Ok(Box::new(MacosListenerApp::new()))),
     )
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_proxy_with_auth() {
+        let mut manager = ProxyManager::default();
+        let id = manager
+            .add_proxy_with_auth(
+                "Corp".to_string(),
+                "10.0.0.1".to_string(),
+                1080,
+                ProxyType::Socks5,
+                Some("alice".to_string()),
+                Some("secret".to_string()),
+            )
+            .unwrap();
+
+        let proxy = manager.proxies.iter().find(|p| p.id == id).unwrap();
+        assert_eq!(proxy.username.as_deref(), Some("alice"));
+        assert_eq!(proxy.password.as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn test_credentials_must_be_provided_together() {
+        let mut manager = ProxyManager::default();
+        let result = manager.add_proxy_with_auth(
+            "Corp".to_string(),
+            "10.0.0.1".to_string(),
+            1080,
+            ProxyType::Socks5,
+            Some("alice".to_string()),
+            None,
+        );
+        assert!(result.is_err());
+        assert!(manager.proxies.is_empty());
+
+        let id = manager.add_proxy("Open".to_string(), "10.0.0.2".to_string(), 1080, ProxyType::Socks5);
+        assert!(manager.set_proxy_credentials(id, None, Some("secret".to_string())).is_err());
+        assert!(manager.set_proxy_credentials(id, Some("bob".to_string()), Some("pw".to_string())).is_ok());
+        assert_eq!(manager.proxies[0].username.as_deref(), Some("bob"));
+
+        // Clearing both removes authentication
+        manager.set_proxy_credentials(id, None, None).unwrap();
+        assert!(manager.proxies[0].username.is_none());
+        assert!(manager.proxies[0].password.is_none());
+
+        assert!(manager.set_proxy_credentials(999, None, None).is_err());
+    }
+
+    #[test]
+    fn test_rule_with_malformed_regex_is_rejected() {
+        let mut manager = ProxyManager::default();
+        let proxy = manager.add_proxy("Corp".to_string(), "10.0.0.1".to_string(), 1080, ProxyType::Socks5);
+
+        let result = manager.add_rule("Broken".to_string(), "re:api-[0-9".to_string(), proxy);
+        assert!(result.unwrap_err().to_string().contains("Invalid regex"));
+        assert!(manager.rules.is_empty());
+
+        manager.add_rule("Numbered".to_string(), r"re:api-\d+\.corp".to_string(), proxy).unwrap();
+        manager.global_enabled = true;
+        assert_eq!(manager.get_proxy_for_host("api-42.corp").unwrap().name, "Corp");
+        assert!(manager.get_proxy_for_host("api-x.corp").is_none());
+    }
+
+    #[test]
+    fn test_override_rule_matches_originating_connection() {
+        let mut manager = ProxyManager::default();
+        let corp = manager.add_proxy("Corp".to_string(), "10.0.0.1".to_string(), 1080, ProxyType::Socks5);
+        let lab = manager.add_proxy("Lab".to_string(), "10.0.0.2".to_string(), 1080, ProxyType::Socks5);
+        manager.add_rule("Everything".to_string(), "93.0.0.0/8".to_string(), lab).unwrap();
+        manager.global_enabled = true;
+
+        let remote: SocketAddr = "93.184.216.34:443".parse().unwrap();
+        assert_eq!(ProxyManager::override_pattern(remote.ip(), false), "93.184.216.34/32");
+        assert_eq!(ProxyManager::override_pattern(remote.ip(), true), "93.184.216.0/24");
+        assert_eq!(ProxyManager::override_pattern("2001:db8::1".parse().unwrap(), true), "2001:db8::/64");
+
+        let id = manager.add_override_rule("curl", remote.ip(), false, corp).unwrap();
+        let rule = manager.rules.iter().find(|r| r.id == id).unwrap();
+        assert_eq!(rule.name, "curl");
+
+        // The override wins over the broader rule added before it
+        assert_eq!(manager.get_proxy_for_connection(&remote).unwrap().name, "Corp");
+        assert_eq!(manager.get_proxy_for_connection(&"93.184.216.35:443".parse().unwrap()).unwrap().name, "Lab");
+
+        manager.add_override_rule("", remote.ip(), true, corp).unwrap();
+        assert_eq!(manager.rules[0].name, "Manual override");
+        assert_eq!(manager.get_proxy_for_connection(&"93.184.216.35:443".parse().unwrap()).unwrap().name, "Corp");
+    }
+
+    fn temp_config_path(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("macos-listener-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("proxy-config.json").to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let mut manager = ProxyManager {
+            global_enabled: true,
+            ..Default::default()
+        };
+        let corp = manager
+            .add_proxy_with_auth(
+                "Corp".to_string(),
+                "10.0.0.1".to_string(),
+                1080,
+                ProxyType::Socks5,
+                Some("alice".to_string()),
+                Some("secret".to_string()),
+            )
+            .unwrap();
+        let lab = manager.add_proxy("Lab".to_string(), "10.0.0.2".to_string(), 8080, ProxyType::Http);
+        manager.add_rule("Kion".to_string(), "*.kion.cloud".to_string(), corp).unwrap();
+        manager.add_rule("Lab net".to_string(), "100.64.0.0/10".to_string(), lab).unwrap();
+        let disabled = manager.add_rule("Old".to_string(), "old.example.com".to_string(), lab).unwrap();
+        manager.rules.iter_mut().find(|r| r.id == disabled).unwrap().enabled = false;
+        manager.recompile();
+
+        // save creates the missing config directory
+        let path = temp_config_path("round-trip");
+        manager.save(&path).unwrap();
+        let loaded = ProxyManager::load(&path);
+
+        assert_eq!(
+            serde_json::to_value(&loaded).unwrap(),
+            serde_json::to_value(&manager).unwrap()
+        );
+        assert_eq!(loaded.next_proxy_id, manager.next_proxy_id);
+        assert_eq!(loaded.next_rule_id, manager.next_rule_id);
+        // Compiled rules aren't serialized and must be rebuilt on load
+        assert_eq!(loaded.compiled_rules.len(), 2);
+
+        let _ = std::fs::remove_dir_all(std::path::Path::new(&path).parent().unwrap());
+    }
+
+    #[test]
+    fn test_load_falls_back_to_default() {
+        let path = temp_config_path("fallback");
+        let missing = ProxyManager::load(&path);
+        assert!(missing.proxies.is_empty());
+        assert_eq!(missing.next_proxy_id, 1);
+
+        // A corrupt file yields defaults and is kept aside rather than overwritten
+        std::fs::create_dir_all(std::path::Path::new(&path).parent().unwrap()).unwrap();
+        std::fs::write(&path, "{ not json").unwrap();
+        let corrupt = ProxyManager::load(&path);
+        assert!(corrupt.rules.is_empty());
+        assert!(!std::path::Path::new(&path).exists());
+        assert_eq!(std::fs::read_to_string(format!("{}.bak", path)).unwrap(), "{ not json");
+
+        let _ = std::fs::remove_dir_all(std::path::Path::new(&path).parent().unwrap());
+    }
+
+    #[test]
+    fn test_rule_priority_decides_first_match() {
+        let mut manager = ProxyManager {
+            global_enabled: true,
+            ..Default::default()
+        };
+        let broad_proxy = manager.add_proxy("Broad".to_string(), "10.0.0.1".to_string(), 1080, ProxyType::Socks5);
+        let specific_proxy = manager.add_proxy("Specific".to_string(), "10.0.0.2".to_string(), 1080, ProxyType::Socks5);
+
+        // The wildcard is added first, so by insertion order it would shadow the specific rule
+        let broad = manager.add_rule("Broad".to_string(), "100.64.*".to_string(), broad_proxy).unwrap();
+        let specific = manager.add_rule("Specific".to_string(), "100.64.1.5".to_string(), specific_proxy).unwrap();
+        let target: SocketAddr = "100.64.1.5:443".parse().unwrap();
+        assert_eq!(manager.get_proxy_for_connection(&target).unwrap().id, broad_proxy);
+
+        manager.rules.iter_mut().find(|r| r.id == specific).unwrap().priority = -1;
+        manager.recompile();
+        assert_eq!(manager.get_proxy_for_connection(&target).unwrap().id, specific_proxy);
+        assert_eq!(manager.rules[0].id, specific);
+
+        // Moving the wildcard back up restores it as the first match
+        assert!(manager.move_rule(broad, true));
+        assert_eq!(manager.get_proxy_for_connection(&target).unwrap().id, broad_proxy);
+        assert_eq!(manager.rules.iter().map(|r| r.priority).collect::<Vec<_>>(), vec![0, 1]);
+        assert!(!manager.move_rule(broad, true));
+    }
+
+    #[test]
+    fn test_direct_rule_beats_matching_proxy_rule() {
+        let mut manager = ProxyManager {
+            global_enabled: true,
+            ..Default::default()
+        };
+        let corp = manager.add_proxy("Corp".to_string(), "10.0.0.1".to_string(), 1080, ProxyType::Socks5);
+        manager.add_rule("Internal net".to_string(), "100.64.*".to_string(), corp).unwrap();
+        let bypass = manager.add_rule_with_target("Printer".to_string(), "100.64.1.5".to_string(), RuleTarget::Direct).unwrap();
+
+        let proxied: SocketAddr = "100.64.1.6:443".parse().unwrap();
+        let excluded: SocketAddr = "100.64.1.5:631".parse().unwrap();
+        assert_eq!(manager.get_proxy_for_connection(&proxied).unwrap().id, corp);
+        // Added last, the direct rule still falls behind the wildcard
+        assert_eq!(manager.get_proxy_for_connection(&excluded).unwrap().id, corp);
+
+        // Evaluated first, it short-circuits the proxy rule
+        assert!(manager.move_rule(bypass, true));
+        assert!(manager.get_proxy_for_connection(&excluded).is_none());
+        assert_eq!(manager.get_proxy_for_connection(&proxied).unwrap().id, corp);
+
+        // Removing the proxy keeps direct rules
+        manager.remove_proxy(corp);
+        assert_eq!(manager.rules.len(), 1);
+        assert_eq!(manager.rules[0].proxy_id, RuleTarget::Direct);
+    }
+
+    #[test]
+    fn test_proxy_stats_count_matches_per_proxy() {
+        let mut manager = ProxyManager {
+            global_enabled: true,
+            ..Default::default()
+        };
+        let corp = manager.add_proxy("Corp".to_string(), "10.0.0.1".to_string(), 1080, ProxyType::Socks5);
+        let lab = manager.add_proxy("Lab".to_string(), "10.0.0.2".to_string(), 1080, ProxyType::Socks5);
+        manager.add_rule("Corp".to_string(), "*.corp.example".to_string(), corp).unwrap();
+        manager.add_rule("Lab".to_string(), "*.lab.example".to_string(), lab).unwrap();
+
+        // The interceptor works on a clone, which reports into the same stats
+        let shared = Arc::new(Mutex::new(manager.clone()));
+        for domain in ["a.corp.example", "b.corp.example", "c.corp.example", "x.lab.example", "other.example"] {
+            TrafficInterceptor::should_proxy_domain(&shared, domain);
+        }
+        shared.lock().unwrap().record_proxy_bytes(lab, 300);
+        shared.lock().unwrap().record_proxy_bytes(lab, 200);
+
+        let corp_stats = manager.proxy_stats(corp);
+        assert_eq!(corp_stats.matched_connections, 3);
+        assert_eq!(corp_stats.bytes_relayed, 0);
+        assert!(corp_stats.last_used.is_some());
+        let lab_stats = manager.proxy_stats(lab);
+        assert_eq!(lab_stats.matched_connections, 1);
+        assert_eq!(lab_stats.bytes_relayed, 500);
+
+        manager.reset_stats();
+        assert_eq!(manager.proxy_stats(corp), ProxyStats::default());
+        assert_eq!(shared.lock().unwrap().proxy_stats(lab), ProxyStats::default());
+    }
+
+    #[test]
+    fn test_rule_stats_count_matches_per_rule() {
+        let mut manager = ProxyManager {
+            global_enabled: true,
+            ..Default::default()
+        };
+        let corp = manager.add_proxy("Corp".to_string(), "10.0.0.1".to_string(), 1080, ProxyType::Socks5);
+        manager.add_rule("Corp".to_string(), "*.corp.example".to_string(), corp).unwrap();
+        manager.add_rule("Unused".to_string(), "*.never.example".to_string(), corp).unwrap();
+        let (used, unused) = (manager.rules[0].id, manager.rules[1].id);
+
+        let shared = Arc::new(Mutex::new(manager.clone()));
+        TrafficInterceptor::should_proxy_domain(&shared, "a.corp.example");
+        TrafficInterceptor::should_proxy_domain(&shared, "b.corp.example");
+        TrafficInterceptor::should_proxy_domain(&shared, "other.example");
+
+        // Display lookups and the rule tester are not traffic and leave the counters alone
+        describe_rule_test(&manager, "c.corp.example");
+
+        let stats = manager.rule_stats(used);
+        assert_eq!(stats.hit_count, 2);
+        assert!(stats.last_matched.is_some());
+        assert_eq!(manager.rule_stats(unused), RuleStats::default());
+
+        manager.reset_rule_stats();
+        assert_eq!(shared.lock().unwrap().rule_stats(used), RuleStats::default());
+    }
+
+    #[test]
+    fn test_describe_rule_test() {
+        let mut manager = ProxyManager {
+            global_enabled: true,
+            ..Default::default()
+        };
+        let corp = manager.add_proxy("Corp".to_string(), "10.0.0.1".to_string(), 1080, ProxyType::Socks5);
+        manager.add_rule("Corp hosts".to_string(), "*.corp.example".to_string(), corp).unwrap();
+        manager.add_rule_with_target("Printers".to_string(), "100.64.1.0/24".to_string(), RuleTarget::Direct).unwrap();
+        manager.add_rule("Internal net".to_string(), "100.64.*".to_string(), corp).unwrap();
+
+        assert_eq!(manager.get_proxy_for_host("git.corp.example").unwrap().id, corp);
+        assert!(manager.get_proxy_for_host("example.com").is_none());
+
+        let report = describe_rule_test(&manager, " git.corp.example ");
+        assert!(report.starts_with("Rule 'Corp hosts'"), "{}", report);
+        assert!(report.contains("proxy 'Corp' (10.0.0.1:1080)"), "{}", report);
+
+        let report = describe_rule_test(&manager, "100.64.1.7");
+        assert!(report.contains("'Printers'") && report.contains("Direct (bypass)"), "{}", report);
+        let report = describe_rule_test(&manager, "100.64.2.7");
+        assert!(report.contains("'Internal net'") && report.contains("proxy 'Corp'"), "{}", report);
+
+        let report = describe_rule_test(&manager, "example.com");
+        assert!(report.starts_with("No rule matched 'example.com' -> Direct"), "{}", report);
+        assert!(report.contains("1. Corp hosts") && report.contains("3. Internal net"), "{}", report);
+
+        manager.global_enabled = false;
+        assert!(describe_rule_test(&manager, "git.corp.example").contains("disabled"));
+    }
+
+    #[test]
+    fn test_rule_target_serialization() {
+        let mut manager = ProxyManager::default();
+        manager.add_rule("Proxied".to_string(), "*.corp".to_string(), 7).unwrap();
+        manager.add_rule_with_target("Bypass".to_string(), "*.local".to_string(), RuleTarget::Direct).unwrap();
+
+        let json = serde_json::to_value(&manager).unwrap();
+        assert_eq!(json["rules"][0]["proxy_id"], 7);
+        assert_eq!(json["rules"][1]["proxy_id"], "direct");
+
+        let loaded: ProxyManager = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.rules[0].proxy_id, RuleTarget::Proxy(7));
+        assert_eq!(loaded.rules[1].proxy_id, RuleTarget::Direct);
+
+        let unknown = r#"{"id": 1, "name": "x", "pattern": "x", "enabled": true, "proxy_id": "nowhere"}"#;
+        assert!(serde_json::from_str::<ProxyRule>(unknown).is_err());
+    }
+
+    #[test]
+    fn test_rule_priority_defaults_for_old_configs() {
+        let json = r#"{"id": 3, "name": "Old", "pattern": "*.corp", "enabled": true, "proxy_id": 1}"#;
+        let rule: ProxyRule = serde_json::from_str(json).unwrap();
+        assert_eq!(rule.priority, 0);
+    }
+
+    #[test]
+    fn test_non_empty() {
+        assert_eq!(non_empty(""), None);
+        assert_eq!(non_empty("user"), Some("user".to_string()));
+    }
+
+    #[test]
+    fn test_log_entry_from_network_change() {
+        let connection = NetworkConnection {
+            local_addr: "10.0.0.2:50000".parse().unwrap(),
+            remote_addr: Some("10.0.0.1:443".parse().unwrap()),
+            protocol: "TCP".to_string(),
+            state: "ESTABLISHED".to_string(),
+            process_name: "Unknown".to_string(),
+            process_id: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            last_updated: Instant::now(),
+            interface: "Unknown".to_string(),
+        };
+
+        let cases = [
+            (NetworkChange::Added(connection.clone()), ConnectionEvent::New),
+            (NetworkChange::Removed(connection.clone()), ConnectionEvent::Closed),
+            (NetworkChange::Updated(connection.clone()), ConnectionEvent::Updated),
+        ];
+        for (id, (change, expected)) in cases.into_iter().enumerate() {
+            let entry = ConnectionLogEntry::from_network_change(change, id as u64);
+            assert_eq!(entry.event_type, expected);
+            assert_eq!(entry.id, id as u64);
+            assert_eq!(entry.connection.remote_addr, connection.remote_addr);
+        }
+    }
+
+    #[test]
+    fn test_trim_connection_log() {
+        let connection = NetworkConnection {
+            local_addr: "10.0.0.2:50000".parse().unwrap(),
+            remote_addr: None,
+            protocol: "UDP".to_string(),
+            state: "".to_string(),
+            process_name: "Unknown".to_string(),
+            process_id: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            last_updated: Instant::now(),
+            interface: "Unknown".to_string(),
+        };
+        let mut log: VecDeque<ConnectionLogEntry> = (1..=5)
+            .map(|id| ConnectionLogEntry::from_network_change(NetworkChange::Added(connection.clone()), id))
+            .collect();
+
+        assert_eq!(trim_connection_log(&mut log, 10), 0);
+        assert_eq!(log.len(), 5);
+
+        assert_eq!(trim_connection_log(&mut log, 3), 2);
+        let ids: Vec<u64> = log.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn test_append_connection_changes_across_snapshots() {
+        let connection = |local: &str, remote: &str| NetworkConnection {
+            local_addr: local.parse().unwrap(),
+            remote_addr: Some(remote.parse().unwrap()),
+            protocol: "TCP".to_string(),
+            state: "ESTABLISHED".to_string(),
+            process_name: "Unknown".to_string(),
+            process_id: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            last_updated: Instant::now(),
+            interface: "Unknown".to_string(),
+        };
+        let a = connection("10.0.0.2:50000", "10.0.0.1:443");
+        let b = connection("10.0.0.2:50001", "10.0.0.1:80");
+        let c = connection("10.0.0.2:50002", "10.0.0.3:22");
+        let snapshots = [vec![a.clone()], vec![a.clone(), b.clone()], vec![a.clone(), b.clone()], vec![c.clone()], vec![]];
+
+        let mut log = VecDeque::new();
+        let mut previous: Vec<NetworkConnection> = Vec::new();
+        let mut next_id = 0;
+        for snapshot in &snapshots {
+            append_connection_changes(&mut log, &previous, snapshot, &mut next_id);
+            previous = snapshot.clone();
+        }
+
+        let events: Vec<(u64, ConnectionEvent, SocketAddr)> = log
+            .iter()
+            .map(|entry| (entry.id, entry.event_type.clone(), entry.connection.local_addr))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (1, ConnectionEvent::New, a.local_addr),
+                (2, ConnectionEvent::New, b.local_addr),
+                (3, ConnectionEvent::New, c.local_addr),
+                (4, ConnectionEvent::Closed, a.local_addr),
+                (5, ConnectionEvent::Closed, b.local_addr),
+                (6, ConnectionEvent::Closed, c.local_addr),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_append_connection_changes_tracks_each_process() {
+        let connection = |process_id: u32| NetworkConnection {
+            local_addr: "10.0.0.2:50000".parse().unwrap(),
+            remote_addr: Some("10.0.0.1:443".parse().unwrap()),
+            protocol: "TCP".to_string(),
+            state: "ESTABLISHED".to_string(),
+            process_name: "worker".to_string(),
+            process_id,
+            bytes_sent: 0,
+            bytes_received: 0,
+            last_updated: Instant::now(),
+            interface: "Unknown".to_string(),
+        };
+        let (parent, child) = (connection(312), connection(313));
+
+        // A socket inherited across fork is reported once per owning process
+        let mut log = VecDeque::new();
+        let mut next_id = 0;
+        append_connection_changes(&mut log, std::slice::from_ref(&parent), &[parent.clone(), child.clone()], &mut next_id);
+        append_connection_changes(&mut log, &[parent.clone(), child.clone()], std::slice::from_ref(&child), &mut next_id);
+
+        let events: Vec<(ConnectionEvent, u32)> = log
+            .iter()
+            .map(|entry| (entry.event_type.clone(), entry.connection.process_id))
+            .collect();
+        assert_eq!(events, vec![(ConnectionEvent::New, 313), (ConnectionEvent::Closed, 312)]);
+
+        // Unresolved owners still dedupe by address pair
+        assert!(connection(0).is_same_connection(&connection(0)));
+        assert!(!connection(0).is_same_connection(&parent));
+    }
+
+    #[test]
+    fn test_sort_header_click() {
+        // Clicking the active column flips its direction
+        assert_eq!(SortBy::State.after_header_click(true, SortBy::State), (SortBy::State, false));
+        assert_eq!(SortBy::State.after_header_click(false, SortBy::State), (SortBy::State, true));
+
+        // Another column starts ascending, whatever the previous direction
+        assert_eq!(SortBy::State.after_header_click(false, SortBy::BytesSent), (SortBy::BytesSent, true));
+    }
+}
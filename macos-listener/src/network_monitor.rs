@@ -1,4 +1,4 @@
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::process::Command;
 use std::collections::HashMap;
 use std::time::Instant;
@@ -6,8 +6,15 @@ use std::time::Instant;
 // Import the main NetworkConnection type
 use crate::NetworkConnection;
 
+/// Identifies a socket for process attribution: (protocol, local address, remote address)
+type SocketKey = (String, SocketAddr, Option<SocketAddr>);
+
+/// How long process and socket ownership information is reused before being refreshed
+const PROCESS_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(5);
+
 pub struct LowLevelNetworkMonitor {
     process_cache: HashMap<u32, String>,
+    socket_owners: HashMap<SocketKey, u32>,
     last_cache_update: Instant,
 }
 
@@ -15,6 +22,7 @@ impl LowLevelNetworkMonitor {
     pub fn new() -> Self {
         Self {
             process_cache: HashMap::new(),
+            socket_owners: HashMap::new(),
             last_cache_update: Instant::now(),
         }
     }
@@ -23,15 +31,15 @@ impl LowLevelNetworkMonitor {
     pub fn get_connections(&mut self) -> Result<Vec<NetworkConnection>, Box<dyn std::error::Error>> {
         let mut connections = Vec::new();
 
-        // Method 1: Use netstat with optimized flags (most reliable on macOS)
-        if let Ok(netstat_conns) = self.get_connections_netstat_optimized() {
-            connections.extend(netstat_conns);
+        // Method 1: Read the kernel PCB lists via sysctl (no child process per poll)
+        if let Ok(sysctl_conns) = self.get_connections_sysctl() {
+            connections.extend(sysctl_conns);
         }
 
-        // Method 2: Use sysctl for direct kernel data access (if netstat fails)
+        // Method 2: Use netstat with optimized flags (if sysctl is unavailable)
         if connections.is_empty() {
-            if let Ok(sysctl_conns) = self.get_connections_sysctl() {
-                connections.extend(sysctl_conns);
+            if let Ok(netstat_conns) = self.get_connections_netstat_optimized() {
+                connections.extend(netstat_conns);
             }
         }
 
@@ -43,53 +51,69 @@ impl LowLevelNetworkMonitor {
         }
 
         // Update process cache periodically
-        if self.last_cache_update.elapsed() > std::time::Duration::from_secs(5) {
+        if self.process_cache.is_empty() || self.last_cache_update.elapsed() > PROCESS_CACHE_TTL {
             self.update_process_cache()?;
         }
 
+        self.attribute_processes(&mut connections);
+
         Ok(connections)
     }
 
-    /// Get connections using sysctl - most efficient method
-    fn get_connections_sysctl(&self) -> Result<Vec<NetworkConnection>, Box<dyn std::error::Error>> {
-        let mut connections = Vec::new();
+    /// Fill in `process_name`/`process_id` from the cached socket owners
+    pub fn attribute_processes(&self, connections: &mut [NetworkConnection]) {
+        for connection in connections.iter_mut() {
+            if let Some(pid) = self.find_socket_owner(connection) {
+                connection.process_id = pid;
+                connection.process_name = self.get_process_name(pid);
+            }
+        }
+    }
 
-        // Get TCP connections via sysctl
-        let tcp_output = Command::new("sysctl")
-            .args(&["-n", "net.inet.tcp.pcblist"])
-            .output()?;
+    /// Look up the PID owning a connection's socket
+    fn find_socket_owner(&self, connection: &NetworkConnection) -> Option<u32> {
+        let key = (connection.protocol.clone(), connection.local_addr, connection.remote_addr);
+        if let Some(pid) = self.socket_owners.get(&key) {
+            return Some(*pid);
+        }
 
-        if tcp_output.status.success() {
-            // Parse binary sysctl output
-            // Note: This requires parsing the raw binary data structure
-            // For now, we'll use a hybrid approach
-            connections.extend(self.parse_sysctl_tcp_output(&tcp_output.stdout)?);
+        // lsof reports wildcard binds as `*:port` for both IPv4 and IPv6, so match on the port
+        if connection.remote_addr.is_none() && connection.local_addr.ip().is_unspecified() {
+            return self.socket_owners.iter().find_map(|((protocol, local, remote), pid)| {
+                (protocol == &connection.protocol
+                    && remote.is_none()
+                    && local.ip().is_unspecified()
+                    && local.port() == connection.local_addr.port())
+                    .then_some(*pid)
+            });
         }
 
-        // Get UDP connections via sysctl
-        let udp_output = Command::new("sysctl")
-            .args(&["-n", "net.inet.udp.pcblist"])
-            .output()?;
+        None
+    }
 
-        if udp_output.status.success() {
-            connections.extend(self.parse_sysctl_udp_output(&udp_output.stdout)?);
-        }
+    /// Get connections using sysctl - most efficient method
+    fn get_connections_sysctl(&self) -> Result<Vec<NetworkConnection>, Box<dyn std::error::Error>> {
+        let mut connections = Vec::new();
+
+        // The `_n` variants of the PCB lists are made of self-describing, length-prefixed
+        // records, unlike the legacy layout which embeds kernel structs with pointers
+        let tcp_data = read_sysctl("net.inet.tcp.pcblist_n")?;
+        connections.extend(self.parse_sysctl_tcp_output(&tcp_data)?);
+
+        let udp_data = read_sysctl("net.inet.udp.pcblist_n")?;
+        connections.extend(self.parse_sysctl_udp_output(&udp_data)?);
 
         Ok(connections)
     }
 
-    /// Parse sysctl TCP output (simplified - real implementation would parse binary data)
-    fn parse_sysctl_tcp_output(&self, _data: &[u8]) -> Result<Vec<NetworkConnection>, Box<dyn std::error::Error>> {
-        // TODO: Implement proper binary parsing of sysctl output
-        // This is a placeholder that falls back to other methods
-        Ok(Vec::new())
+    /// Parse the binary `net.inet.tcp.pcblist_n` output
+    fn parse_sysctl_tcp_output(&self, data: &[u8]) -> Result<Vec<NetworkConnection>, Box<dyn std::error::Error>> {
+        Ok(parse_pcblist(data, "TCP"))
     }
 
-    /// Parse sysctl UDP output (simplified - real implementation would parse binary data)
-    fn parse_sysctl_udp_output(&self, _data: &[u8]) -> Result<Vec<NetworkConnection>, Box<dyn std::error::Error>> {
-        // TODO: Implement proper binary parsing of sysctl output
-        // This is a placeholder that falls back to other methods
-        Ok(Vec::new())
+    /// Parse the binary `net.inet.udp.pcblist_n` output
+    fn parse_sysctl_udp_output(&self, data: &[u8]) -> Result<Vec<NetworkConnection>, Box<dyn std::error::Error>> {
+        Ok(parse_pcblist(data, "UDP"))
     }
 
     /// Get connections using /proc/net/* files (Linux-style, may work on some macOS versions)
@@ -213,18 +237,9 @@ impl LowLevelNetworkMonitor {
 
     /// Parse TCP state from /proc/net/tcp
     fn parse_tcp_state(&self, state_hex: &str) -> String {
+        // /proc numbers the states from 1 in `TCP_STATES` order
         match u8::from_str_radix(state_hex, 16).unwrap_or(0) {
-            1 => "ESTABLISHED".to_string(),
-            2 => "SYN_SENT".to_string(),
-            3 => "SYN_RECV".to_string(),
-            4 => "FIN_WAIT1".to_string(),
-            5 => "FIN_WAIT2".to_string(),
-            6 => "TIME_WAIT".to_string(),
-            7 => "CLOSE".to_string(),
-            8 => "CLOSE_WAIT".to_string(),
-            9 => "LAST_ACK".to_string(),
-            10 => "LISTEN".to_string(),
-            11 => "CLOSING".to_string(),
+            state @ 1..=11 => TCP_STATES[usize::from(state) - 1].to_string(),
             _ => "UNKNOWN".to_string(),
         }
     }
@@ -309,35 +324,7 @@ impl LowLevelNetworkMonitor {
 
     /// Parse socket address from string
     fn parse_socket_addr(&self, addr_str: &str) -> Result<SocketAddr, Box<dyn std::error::Error>> {
-        if addr_str.starts_with('*') {
-            let port_str = &addr_str[2..];
-            let port = port_str.parse::<u16>()?;
-            Ok(SocketAddr::new(IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)), port))
-        } else if addr_str == "*.*" {
-            // Skip wildcard addresses
-            Err("Wildcard address".into())
-        } else {
-            // Handle addresses like "192.168.0.136.51696" or "::1.4200"
-            // We need to find the last dot and treat everything after it as port
-            if let Some(last_dot) = addr_str.rfind('.') {
-                let ip_part = &addr_str[..last_dot];
-                let port_part = &addr_str[last_dot + 1..];
-                
-                if let Ok(port) = port_part.parse::<u16>() {
-                    // Try to parse as IPv4 first
-                    if let Ok(ipv4) = ip_part.parse::<std::net::Ipv4Addr>() {
-                        return Ok(SocketAddr::new(IpAddr::V4(ipv4), port));
-                    }
-                    // Try to parse as IPv6
-                    if let Ok(ipv6) = ip_part.parse::<std::net::Ipv6Addr>() {
-                        return Ok(SocketAddr::new(IpAddr::V6(ipv6), port));
-                    }
-                }
-            }
-            
-            // Fallback: try to parse as standard SocketAddr format
-            addr_str.parse::<SocketAddr>().map_err(|e| format!("Failed to parse '{}': {}", addr_str, e).into())
-        }
+        parse_netstat_addr(addr_str)
     }
 
     /// Update process cache
@@ -362,6 +349,13 @@ impl LowLevelNetworkMonitor {
             }
         }
 
+        // Map sockets to PIDs; lsof may be missing or restricted, so keep the old map on failure
+        if let Ok(output) = Command::new("lsof").args(["-nP", "-i", "-F", "pPn"]).output() {
+            if output.status.success() {
+                self.socket_owners = parse_lsof_output(&String::from_utf8_lossy(&output.stdout));
+            }
+        }
+
         self.last_cache_update = Instant::now();
         Ok(())
     }
@@ -377,3 +371,501 @@ impl Default for LowLevelNetworkMonitor {
         Self::new()
     }
 }
+
+/// Size of `struct xinpgen`, which opens and closes every PCB list
+const XINPGEN_SIZE: usize = 24;
+
+/// Record kinds (`xi_kind`) from <sys/socketvar.h>
+const XSO_INPCB: u32 = 0x10;
+const XSO_TCPCB: u32 = 0x20;
+
+/// `inp_vflag` bits from <netinet/in_pcb.h>
+const INP_IPV4: u8 = 0x1;
+const INP_IPV6: u8 = 0x2;
+
+/// Field offsets within `struct xinpcb_n`
+const INPCB_FPORT: usize = 16;
+const INPCB_LPORT: usize = 18;
+const INPCB_VFLAG: usize = 48;
+const INPCB_FADDR: usize = 52;
+const INPCB_LADDR: usize = 68;
+const INPCB_MIN_LEN: usize = INPCB_LADDR + 16;
+
+/// Offset of `t_state` within `struct xtcpcb_n`
+const TCPCB_STATE: usize = 36;
+
+/// Reads a sysctl node into a buffer
+#[cfg(target_os = "macos")]
+fn read_sysctl(name: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let name = std::ffi::CString::new(name)?;
+    let mut len: libc::size_t = 0;
+
+    let ret = unsafe {
+        libc::sysctlbyname(name.as_ptr(), std::ptr::null_mut(), &mut len, std::ptr::null_mut(), 0)
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    // Leave headroom for sockets opened between the two calls
+    len += len / 8;
+    let mut buf = vec![0u8; len];
+    let ret = unsafe {
+        libc::sysctlbyname(name.as_ptr(), buf.as_mut_ptr().cast(), &mut len, std::ptr::null_mut(), 0)
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    buf.truncate(len);
+    Ok(buf)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn read_sysctl(name: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    Err(format!("sysctl {} is only available on macOS", name).into())
+}
+
+/// Parse a `pcblist_n` buffer: an `xinpgen` header, then one group of records per socket
+/// (`xinpcb_n` first, then socket, buffer, stats and, for TCP, `xtcpcb_n`), then an `xinpgen`
+/// trailer. Records are padded to 8 bytes. Truncated or unrecognised records are skipped.
+fn parse_pcblist(data: &[u8], protocol: &str) -> Vec<NetworkConnection> {
+    let mut connections = Vec::new();
+    let header_len = match read_u32(data, 0) {
+        Some(len) if len as usize >= XINPGEN_SIZE => len as usize,
+        _ => return connections,
+    };
+
+    let mut pending: Option<NetworkConnection> = None;
+    let mut offset = round_up8(header_len);
+    while let Some(len) = read_u32(data, offset) {
+        let len = len as usize;
+        // The trailer is another xinpgen; anything running past the buffer is a partial read
+        if len <= XINPGEN_SIZE || offset + len > data.len() {
+            break;
+        }
+
+        let record = &data[offset..offset + len];
+        match read_u32(record, 4) {
+            Some(XSO_INPCB) => {
+                connections.extend(pending.take());
+                pending = parse_inpcb(record, protocol);
+            }
+            Some(XSO_TCPCB) => {
+                if let (Some(connection), Some(state)) = (pending.as_mut(), read_u32(record, TCPCB_STATE)) {
+                    connection.state = tcp_state_name(state).to_string();
+                }
+            }
+            _ => {}
+        }
+
+        offset += round_up8(len);
+    }
+
+    connections.extend(pending);
+    connections
+}
+
+/// Build a connection from an `xinpcb_n` record
+fn parse_inpcb(record: &[u8], protocol: &str) -> Option<NetworkConnection> {
+    if record.len() < INPCB_MIN_LEN {
+        return None;
+    }
+
+    // Ports are stored in network byte order
+    let fport = u16::from_be_bytes([record[INPCB_FPORT], record[INPCB_FPORT + 1]]);
+    let lport = u16::from_be_bytes([record[INPCB_LPORT], record[INPCB_LPORT + 1]]);
+    let vflag = record[INPCB_VFLAG];
+
+    let (local_ip, remote_ip) = if vflag & INP_IPV4 != 0 {
+        // IPv4 addresses occupy the last 4 bytes of the 16-byte address union
+        (
+            IpAddr::V4(ipv4_at(record, INPCB_LADDR + 12)),
+            IpAddr::V4(ipv4_at(record, INPCB_FADDR + 12)),
+        )
+    } else if vflag & INP_IPV6 != 0 {
+        (
+            IpAddr::V6(ipv6_at(record, INPCB_LADDR)),
+            IpAddr::V6(ipv6_at(record, INPCB_FADDR)),
+        )
+    } else {
+        return None;
+    };
+
+    let remote_addr = if remote_ip.is_unspecified() && fport == 0 {
+        None
+    } else {
+        Some(SocketAddr::new(remote_ip, fport))
+    };
+
+    Some(NetworkConnection {
+        local_addr: SocketAddr::new(local_ip, lport),
+        remote_addr,
+        protocol: protocol.to_string(),
+        // TCP sockets get their state from the xtcpcb_n record that follows
+        state: if protocol == "TCP" { "UNKNOWN".to_string() } else { protocol.to_string() },
+        process_name: "Unknown".to_string(),
+        process_id: 0,
+        bytes_sent: 0,
+        bytes_received: 0,
+        last_updated: Instant::now(),
+        interface: "Unknown".to_string(),
+    })
+}
+
+/// Parse `lsof -F pPn` output, where each field is on its own line prefixed by its tag:
+/// `p` starts a process, `P` gives the protocol of the following `n` (address) line
+fn parse_lsof_output(output: &str) -> HashMap<SocketKey, u32> {
+    let mut owners = HashMap::new();
+    let mut pid = None;
+    let mut protocol = None;
+
+    for line in output.lines() {
+        let (tag, value) = match line.chars().next() {
+            Some(tag) => (tag, &line[tag.len_utf8()..]),
+            None => continue,
+        };
+
+        match tag {
+            'p' => {
+                pid = value.parse::<u32>().ok();
+                protocol = None;
+            }
+            'f' => protocol = None,
+            'P' => protocol = Some(value.to_string()),
+            'n' => {
+                let (Some(pid), Some(protocol)) = (pid, protocol.as_ref()) else {
+                    continue;
+                };
+                let (local, remote) = match value.split_once("->") {
+                    Some((local, remote)) => (local, Some(remote)),
+                    None => (value, None),
+                };
+                let Some(local) = parse_lsof_addr(local) else {
+                    continue;
+                };
+                let remote = match remote.map(parse_lsof_addr) {
+                    Some(None) => continue,
+                    Some(addr) => addr,
+                    None => None,
+                };
+                owners.insert((protocol.clone(), local, remote), pid);
+            }
+            _ => {}
+        }
+    }
+
+    owners
+}
+
+/// Parse an lsof address such as `127.0.0.1:80`, `[::1]:80` or `*:80`
+fn parse_lsof_addr(addr: &str) -> Option<SocketAddr> {
+    if let Some(port) = addr.strip_prefix("*:") {
+        return Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port.parse().ok()?));
+    }
+    addr.parse().ok()
+}
+
+/// Parse a netstat address such as `*.53`, `192.168.0.136.51696`, `::1.4200` or
+/// `fe80::1%lo0.5353`, where the port follows the last `.`. Colon-separated forms like
+/// `[::1]:443` are accepted as a fallback.
+pub fn parse_netstat_addr(addr_str: &str) -> Result<SocketAddr, Box<dyn std::error::Error>> {
+    if addr_str == "*.*" {
+        return Err("Wildcard address".into());
+    }
+    if let Some(port_str) = addr_str.strip_prefix("*.").or_else(|| addr_str.strip_prefix("*:")) {
+        let port = port_str.parse::<u16>()?;
+        return Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port));
+    }
+
+    if let Some((ip_part, port_part)) = addr_str.rsplit_once('.') {
+        if let Ok(port) = port_part.parse::<u16>() {
+            // Drop an IPv6 scope id ("%en0"), which `Ipv6Addr` doesn't accept
+            let ip_part = ip_part.split('%').next().unwrap_or(ip_part);
+            if let Ok(ipv4) = ip_part.parse::<Ipv4Addr>() {
+                return Ok(SocketAddr::new(IpAddr::V4(ipv4), port));
+            }
+            if let Ok(ipv6) = ip_part.parse::<Ipv6Addr>() {
+                return Ok(SocketAddr::new(IpAddr::V6(ipv6), port));
+            }
+        }
+    }
+
+    addr_str.parse::<SocketAddr>().map_err(|e| format!("Failed to parse '{}': {}", addr_str, e).into())
+}
+
+/// TCP state names as produced by `parse_tcp_state`, offered by the connections table's state filter
+pub const TCP_STATES: [&str; 12] = [
+    "ESTABLISHED",
+    "SYN_SENT",
+    "SYN_RECV",
+    "FIN_WAIT1",
+    "FIN_WAIT2",
+    "TIME_WAIT",
+    "CLOSE",
+    "CLOSE_WAIT",
+    "LAST_ACK",
+    "LISTEN",
+    "CLOSING",
+    "UNKNOWN",
+];
+
+/// The `TCP_STATES` name for `state`, which may use netstat's spelling (e.g. `SYN_RCVD`)
+pub fn normalize_tcp_state(state: &str) -> &str {
+    match state {
+        "CLOSED" => "CLOSE",
+        "SYN_RCVD" => "SYN_RECV",
+        "FIN_WAIT_1" => "FIN_WAIT1",
+        "FIN_WAIT_2" => "FIN_WAIT2",
+        other => other,
+    }
+}
+
+/// TCP state names as printed by netstat
+fn tcp_state_name(state: u32) -> &'static str {
+    match state {
+        0 => "CLOSED",
+        1 => "LISTEN",
+        2 => "SYN_SENT",
+        3 => "SYN_RCVD",
+        4 => "ESTABLISHED",
+        5 => "CLOSE_WAIT",
+        6 => "FIN_WAIT_1",
+        7 => "CLOSING",
+        8 => "LAST_ACK",
+        9 => "FIN_WAIT_2",
+        10 => "TIME_WAIT",
+        _ => "UNKNOWN",
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+}
+
+fn ipv4_at(data: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(data[offset], data[offset + 1], data[offset + 2], data[offset + 3])
+}
+
+fn ipv6_at(data: &[u8], offset: usize) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(&data[offset..offset + 16]);
+    Ipv6Addr::from(octets)
+}
+
+fn round_up8(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tcp_state_vocabulary() {
+        let monitor = LowLevelNetworkMonitor::new();
+        assert_eq!(monitor.parse_tcp_state("01"), "ESTABLISHED");
+        assert_eq!(monitor.parse_tcp_state("0A"), "LISTEN");
+        assert_eq!(monitor.parse_tcp_state("0B"), "CLOSING");
+        assert_eq!(monitor.parse_tcp_state("ff"), "UNKNOWN");
+
+        // netstat's names map onto the same filter entries
+        for state in 0..=11 {
+            assert!(TCP_STATES.contains(&normalize_tcp_state(tcp_state_name(state))), "{}", state);
+        }
+        assert_eq!(normalize_tcp_state("SYN_RCVD"), "SYN_RECV");
+        assert_eq!(normalize_tcp_state("UDP"), "UDP");
+    }
+
+    /// Size of the records emitted between xinpcb_n and xtcpcb_n in these fixtures
+    const XSOCKET_LEN: usize = 108;
+    const XINPCB_LEN: usize = 104;
+    const XTCPCB_LEN: usize = 196;
+
+    fn xinpgen(count: u32) -> Vec<u8> {
+        let mut record = vec![0u8; XINPGEN_SIZE];
+        record[0..4].copy_from_slice(&(XINPGEN_SIZE as u32).to_ne_bytes());
+        record[4..8].copy_from_slice(&count.to_ne_bytes());
+        record
+    }
+
+    fn record(kind: u32, len: usize) -> Vec<u8> {
+        let mut record = vec![0u8; round_up8(len)];
+        record[0..4].copy_from_slice(&(len as u32).to_ne_bytes());
+        record[4..8].copy_from_slice(&kind.to_ne_bytes());
+        record
+    }
+
+    fn xinpcb_v4(local: SocketAddr, remote: SocketAddr) -> Vec<u8> {
+        let mut inpcb = record(XSO_INPCB, XINPCB_LEN);
+        inpcb[INPCB_FPORT..INPCB_FPORT + 2].copy_from_slice(&remote.port().to_be_bytes());
+        inpcb[INPCB_LPORT..INPCB_LPORT + 2].copy_from_slice(&local.port().to_be_bytes());
+        inpcb[INPCB_VFLAG] = INP_IPV4;
+        if let (IpAddr::V4(l), IpAddr::V4(r)) = (local.ip(), remote.ip()) {
+            inpcb[INPCB_LADDR + 12..INPCB_LADDR + 16].copy_from_slice(&l.octets());
+            inpcb[INPCB_FADDR + 12..INPCB_FADDR + 16].copy_from_slice(&r.octets());
+        }
+        inpcb
+    }
+
+    fn xinpcb_v6(local: SocketAddr) -> Vec<u8> {
+        let mut inpcb = record(XSO_INPCB, XINPCB_LEN);
+        inpcb[INPCB_LPORT..INPCB_LPORT + 2].copy_from_slice(&local.port().to_be_bytes());
+        inpcb[INPCB_VFLAG] = INP_IPV6;
+        if let IpAddr::V6(l) = local.ip() {
+            inpcb[INPCB_LADDR..INPCB_LADDR + 16].copy_from_slice(&l.octets());
+        }
+        inpcb
+    }
+
+    fn xtcpcb(state: u32) -> Vec<u8> {
+        let mut tcpcb = record(XSO_TCPCB, XTCPCB_LEN);
+        tcpcb[TCPCB_STATE..TCPCB_STATE + 4].copy_from_slice(&state.to_ne_bytes());
+        tcpcb
+    }
+
+    #[test]
+    fn test_parse_netstat_addr() {
+        assert_eq!(parse_netstat_addr("192.168.0.136.51696").unwrap(), "192.168.0.136:51696".parse().unwrap());
+        assert_eq!(parse_netstat_addr("::1.4200").unwrap(), "[::1]:4200".parse().unwrap());
+        assert_eq!(parse_netstat_addr("2607:f8b0::1.443").unwrap(), "[2607:f8b0::1]:443".parse().unwrap());
+        assert_eq!(parse_netstat_addr("fe80::1%lo0.5353").unwrap(), "[fe80::1]:5353".parse().unwrap());
+        assert_eq!(parse_netstat_addr("*.53").unwrap(), "0.0.0.0:53".parse().unwrap());
+        assert_eq!(parse_netstat_addr("[::1]:8080").unwrap(), "[::1]:8080".parse().unwrap());
+        assert_eq!(parse_netstat_addr("10.0.0.1:22").unwrap(), "10.0.0.1:22".parse().unwrap());
+
+        assert!(parse_netstat_addr("*.*").is_err());
+        assert!(parse_netstat_addr("garbage").is_err());
+        assert!(parse_netstat_addr("10.0.0.1.99999").is_err());
+    }
+
+    #[test]
+    fn test_parse_tcp_pcblist() {
+        let local: SocketAddr = "192.168.1.20:51696".parse().unwrap();
+        let remote: SocketAddr = "140.82.112.3:443".parse().unwrap();
+        let listener: SocketAddr = "[::1]:4200".parse().unwrap();
+
+        let mut data = xinpgen(2);
+        data.extend(xinpcb_v4(local, remote));
+        data.extend(record(0x001, XSOCKET_LEN));
+        data.extend(xtcpcb(4));
+        data.extend(xinpcb_v6(listener));
+        data.extend(record(0x001, XSOCKET_LEN));
+        data.extend(xtcpcb(1));
+        data.extend(xinpgen(2));
+
+        let monitor = LowLevelNetworkMonitor::new();
+        let connections = monitor.parse_sysctl_tcp_output(&data).unwrap();
+
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].local_addr, local);
+        assert_eq!(connections[0].remote_addr, Some(remote));
+        assert_eq!(connections[0].protocol, "TCP");
+        assert_eq!(connections[0].state, "ESTABLISHED");
+        assert_eq!(connections[1].local_addr, listener);
+        assert_eq!(connections[1].remote_addr, None);
+        assert_eq!(connections[1].state, "LISTEN");
+    }
+
+    #[test]
+    fn test_parse_udp_pcblist() {
+        let local: SocketAddr = "0.0.0.0:5353".parse().unwrap();
+        let unspecified: SocketAddr = "0.0.0.0:0".parse().unwrap();
+
+        let mut data = xinpgen(1);
+        data.extend(xinpcb_v4(local, unspecified));
+        data.extend(record(0x001, XSOCKET_LEN));
+        data.extend(xinpgen(1));
+
+        let monitor = LowLevelNetworkMonitor::new();
+        let connections = monitor.parse_sysctl_udp_output(&data).unwrap();
+
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].local_addr, local);
+        assert_eq!(connections[0].remote_addr, None);
+        assert_eq!(connections[0].protocol, "UDP");
+        assert_eq!(connections[0].state, "UDP");
+    }
+
+    fn connection(protocol: &str, local: &str, remote: Option<&str>) -> NetworkConnection {
+        NetworkConnection {
+            local_addr: local.parse().unwrap(),
+            remote_addr: remote.map(|r| r.parse().unwrap()),
+            protocol: protocol.to_string(),
+            state: "ESTABLISHED".to_string(),
+            process_name: "Unknown".to_string(),
+            process_id: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            last_updated: Instant::now(),
+            interface: "Unknown".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_lsof_output() {
+        let output = "p312\nf4\nPTCP\nn192.168.1.20:51696->140.82.112.3:443\nf7\nPUDP\nn*:5353\n\
+                      p988\nf12\nPTCP\nn[::1]:4200\nf13\nPTCP\nn[fe80::1%lo0]:22\n";
+        let owners = parse_lsof_output(output);
+
+        assert_eq!(owners.len(), 3);
+        let established = (
+            "TCP".to_string(),
+            "192.168.1.20:51696".parse().unwrap(),
+            Some("140.82.112.3:443".parse().unwrap()),
+        );
+        assert_eq!(owners.get(&established), Some(&312));
+        assert_eq!(owners.get(&("UDP".to_string(), "0.0.0.0:5353".parse().unwrap(), None)), Some(&312));
+        assert_eq!(owners.get(&("TCP".to_string(), "[::1]:4200".parse().unwrap(), None)), Some(&988));
+    }
+
+    #[test]
+    fn test_attribute_processes() {
+        let mut monitor = LowLevelNetworkMonitor::new();
+        monitor.process_cache.insert(312, "Safari".to_string());
+        monitor.process_cache.insert(988, "node".to_string());
+        monitor.socket_owners = parse_lsof_output(
+            "p312\nf4\nPTCP\nn192.168.1.20:51696->140.82.112.3:443\np988\nf5\nPTCP\nn*:4200\n",
+        );
+
+        let mut connections = vec![
+            connection("TCP", "192.168.1.20:51696", Some("140.82.112.3:443")),
+            // IPv6 wildcard listener from sysctl, reported by lsof as `*:4200`
+            connection("TCP", "[::]:4200", None),
+            connection("TCP", "192.168.1.20:51697", Some("140.82.112.3:443")),
+        ];
+        monitor.attribute_processes(&mut connections);
+
+        assert_eq!(connections[0].process_name, "Safari");
+        assert_eq!(connections[0].process_id, 312);
+        assert_eq!(connections[1].process_name, "node");
+        assert_eq!(connections[1].process_id, 988);
+        assert_eq!(connections[2].process_name, "Unknown");
+        assert_eq!(connections[2].process_id, 0);
+    }
+
+    #[test]
+    fn test_parse_pcblist_skips_malformed_records() {
+        let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let remote: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+
+        let mut data = xinpgen(3);
+        // Too short to hold the address fields
+        data.extend(record(XSO_INPCB, 40));
+        data.extend(xinpcb_v4(local, remote));
+        data.extend(xtcpcb(4));
+        // Truncated final group: its length runs past the end of the buffer
+        let mut truncated = xinpcb_v4(local, remote);
+        truncated.truncate(32);
+        data.extend(truncated);
+
+        let connections = parse_pcblist(&data, "TCP");
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].local_addr, local);
+        assert_eq!(connections[0].state, "ESTABLISHED");
+
+        assert!(parse_pcblist(&[], "TCP").is_empty());
+        assert!(parse_pcblist(&[1, 2, 3], "UDP").is_empty());
+    }
+}
@@ -1,10 +1,45 @@
 use std::sync::{Arc, Mutex};
-use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
 use std::io::{Read, Write};
 use std::thread;
 use std::time::Duration;
-use crate::{ProxyConfig, ProxyManager, ProxyRule};
-use pcap::{Device, Capture};
+use crate::rule_matcher::CompiledRule;
+use crate::dns_packet;
+use crate::reverse_dns;
+use crate::socks5_client::Socks5Client;
+use crate::socks5_connector::{self, Socks5Connector, DEFAULT_PROXY_TIMEOUT};
+use crate::tls_sni;
+use crate::traffic_interceptor::TrafficInterceptor;
+use crate::{ProxyConfig, ProxyManager, RuleTarget};
+
+/// Upstream resolver for DNS queries, reached through the proxy for matched domains
+const UPSTREAM_DNS_SERVER: &str = "8.8.8.8:53";
+
+/// Default port of the local DNS proxy the system resolver is pointed at
+pub const DEFAULT_DNS_PROXY_PORT: u16 = 5353;
+
+/// How long to wait for the upstream resolver when forwarding directly
+const UPSTREAM_DNS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bytes copied by `forward_data_bidirectional`, for the intercepted-connection stats
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ForwardStats {
+    /// Client -> proxy
+    pub bytes_sent: u64,
+    /// Proxy -> client
+    pub bytes_received: u64,
+}
+
+/// Largest first payload read to find the destination: one full TLS record
+const MAX_FIRST_PAYLOAD: usize = tls_sni::RECORD_HEADER_LEN + 16 * 1024;
+
+/// Where an intercepted TCP connection is headed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpDestination {
+    /// Host name from TLS SNI or the HTTP `Host` header, if the client sent one
+    pub host: Option<String>,
+    pub addr: SocketAddr,
+}
 
 /// Real traffic proxy that actually intercepts and routes traffic
 pub struct RealTrafficProxy {
@@ -12,6 +47,7 @@ pub struct RealTrafficProxy {
     is_running: Arc<Mutex<bool>>,
     dns_proxy_port: u16,
     tcp_proxy_port: u16,
+    interceptor: Option<TrafficInterceptor>,
 }
 
 impl RealTrafficProxy {
@@ -19,11 +55,24 @@ impl RealTrafficProxy {
         Self {
             proxy_manager,
             is_running: Arc::new(Mutex::new(false)),
-            dns_proxy_port: 5353, // DNS proxy port
+            dns_proxy_port: DEFAULT_DNS_PROXY_PORT, // DNS proxy port
             tcp_proxy_port: 8080, // TCP proxy port
+            interceptor: None,
         }
     }
 
+    /// Listen for DNS queries on `port` instead of the default
+    pub fn with_dns_port(mut self, port: u16) -> Self {
+        self.dns_proxy_port = port;
+        self
+    }
+
+    /// Record relayed TCP connections and their byte totals in `interceptor`'s history
+    pub fn with_interceptor(mut self, interceptor: TrafficInterceptor) -> Self {
+        self.interceptor = Some(interceptor);
+        self
+    }
+
     /// Start the real proxy service
     pub fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
         let mut is_running = self.is_running.lock().unwrap();
@@ -31,6 +80,10 @@ impl RealTrafficProxy {
             println!("🚨 Real proxy is already running!");
             return Ok(());
         }
+        
+        // Bind up front so a busy or privileged port is reported to the caller
+        let dns_socket = UdpSocket::bind(("127.0.0.1", self.dns_proxy_port))?;
+        println!("📡 DNS proxy listening on 127.0.0.1:{}", self.dns_proxy_port);
         *is_running = true;
         drop(is_running);
 
@@ -54,11 +107,13 @@ impl RealTrafficProxy {
         // Start system-level traffic interception
         let traffic_manager = Arc::clone(&self.proxy_manager);
         let traffic_is_running = Arc::clone(&self.is_running);
+        let tcp_proxy_port = self.tcp_proxy_port;
+        let interceptor = self.interceptor.clone();
         
         println!("🚀 Starting system-level traffic interception...");
         println!("📋 This will intercept ALL system traffic and route matching connections through SOCKS5");
         thread::spawn(move || {
-            if let Err(e) = Self::start_traffic_interception(traffic_manager, traffic_is_running) {
+            if let Err(e) = Self::start_traffic_interception(traffic_manager, traffic_is_running, dns_socket, tcp_proxy_port, interceptor) {
                 eprintln!("❌ Traffic interception error: {}", e);
             }
         });
@@ -79,25 +134,28 @@ impl RealTrafficProxy {
     fn start_traffic_interception(
         proxy_manager: Arc<Mutex<ProxyManager>>,
         is_running: Arc<Mutex<bool>>,
+        dns_socket: UdpSocket,
+        tcp_proxy_port: u16,
+        interceptor: Option<TrafficInterceptor>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         println!("🌐 Starting system-level traffic interception...");
         println!("📋 This will intercept ALL system traffic and route matching connections through SOCKS5");
         
-        // Start DNS interception
+        // Start the local DNS proxy
         let dns_manager = Arc::clone(&proxy_manager);
         let dns_running = Arc::clone(&is_running);
         thread::spawn(move || {
-            if let Err(e) = Self::intercept_dns_traffic(dns_manager, dns_running) {
-                eprintln!("❌ DNS interception error: {}", e);
+            if let Err(e) = Self::run_dns_proxy(dns_socket, dns_manager, dns_running) {
+                eprintln!("❌ DNS proxy error: {}", e);
             }
         });
 
-        // Start TCP interception
+        // Start the TCP proxy, which routes connections by their SNI or Host header
         let tcp_manager = Arc::clone(&proxy_manager);
         let tcp_running = Arc::clone(&is_running);
         thread::spawn(move || {
-            if let Err(e) = Self::intercept_tcp_traffic(tcp_manager, tcp_running) {
-                eprintln!("❌ TCP interception error: {}", e);
+            if let Err(e) = Self::start_tcp_proxy(tcp_manager, tcp_running, tcp_proxy_port, interceptor) {
+                eprintln!("❌ TCP proxy error: {}", e);
             }
         });
 
@@ -109,83 +167,77 @@ impl RealTrafficProxy {
         Ok(())
     }
 
-    /// Intercept DNS traffic at system level
-    fn intercept_dns_traffic(
+    /// Answer DNS queries arriving on `dns_socket` until the proxy is stopped.
+    ///
+    /// Queries for domains matching a proxy rule are tunnelled through that proxy, everything
+    /// else is forwarded to the upstream resolver. Each query is handled on its own thread so a
+    /// slow proxy does not hold up other lookups.
+    fn run_dns_proxy(
+        dns_socket: UdpSocket,
         proxy_manager: Arc<Mutex<ProxyManager>>,
         is_running: Arc<Mutex<bool>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        println!("🌐 Intercepting DNS traffic at system level...");
-        
-        // Find the default network interface
-        let device = Device::lookup()?.ok_or("No network interface found")?;
-        println!("📡 Using network interface: {}", device.name);
-        println!("🔍 Looking for DNS queries on port 53...");
-        
-        // Create packet capture
-        let mut cap = Capture::from_device(device)?
-            .promisc(true)
-            .snaplen(65536)
-            .timeout(100)
-            .open()?;
-        
-        // Filter for DNS traffic (port 53)
-        cap.filter("udp port 53", true)?;
+        // Wake up regularly to notice when the proxy is stopped
+        dns_socket.set_read_timeout(Some(Duration::from_millis(100)))?;
         
+        let mut buffer = [0u8; 512];
         while *is_running.lock().unwrap() {
-            match cap.next_packet() {
-                Ok(packet) => {
-                    
-                    // Parse DNS packet
-                    if let Some(domain) = Self::extract_domain_from_dns_packet(&packet.data) {
-                        // Check if this domain should be proxied
-                        if let Some(proxy_config) = Self::should_proxy_domain(&proxy_manager, &domain) {
-                            println!("🌐 DNS RULE MATCH! '{}' -> {} (proxy: {}:{})", 
-                                     domain, proxy_config.name, proxy_config.host, proxy_config.port);
-                            
-                            // Route DNS query through SOCKS5 proxy
-                            Self::route_dns_through_socks5(&domain, &proxy_config)?;
+            match dns_socket.recv_from(&mut buffer) {
+                Ok((size, client_addr)) => {
+                    let query = buffer[..size].to_vec();
+                    let reply_socket = dns_socket.try_clone()?;
+                    let manager = Arc::clone(&proxy_manager);
+                    thread::spawn(move || {
+                        let response = Self::resolve_dns_query(&manager, &query);
+                        if let Some(response) = response {
+                            if let Err(e) = reply_socket.send_to(&response, client_addr) {
+                                eprintln!("❌ Failed to send DNS response to {}: {}", client_addr, e);
+                            }
                         }
-                    }
-                    
+                    });
                 }
-                Err(pcap::Error::TimeoutExpired) => {
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
                     // Timeout is normal, continue
                     continue;
                 }
                 Err(e) => {
-                    eprintln!("❌ DNS packet capture error: {}", e);
-                    break;
+                    eprintln!("❌ DNS proxy receive error: {}", e);
                 }
             }
         }
         
-        println!("🛑 DNS interception stopped");
+        println!("🛑 DNS proxy stopped");
         Ok(())
     }
 
-    /// Intercept TCP traffic at system level
-    fn intercept_tcp_traffic(
-        proxy_manager: Arc<Mutex<ProxyManager>>,
-        is_running: Arc<Mutex<bool>>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        println!("🔗 Intercepting TCP traffic at system level...");
-        println!("📋 This is a simplified implementation - real traffic interception requires system-level privileges");
-        
-        // This is a simplified implementation
-        // In a real implementation, you would:
-        // 1. Use libpcap to capture TCP packets
-        // 2. Parse HTTP headers to extract hostnames
-        // 3. Parse TLS SNI to extract hostnames
-        // 4. Check rules against hostnames
-        // 5. Route through SOCKS5 proxy or direct connection
+    /// Produce the response for one client query; `None` if not even a SERVFAIL can be built
+    fn resolve_dns_query(proxy_manager: &Arc<Mutex<ProxyManager>>, query: &[u8]) -> Option<Vec<u8>> {
+        let domain = Self::extract_domain_from_dns_packet(query);
+        let proxy_config = domain
+            .as_deref()
+            .and_then(|domain| Self::should_proxy_domain(proxy_manager, domain));
+        
+        let result = match proxy_config {
+            Some(proxy_config) => {
+                println!("🌐 DNS RULE MATCH! '{}' -> {} (proxy: {}:{})", 
+                         domain.as_deref().unwrap_or_default(), proxy_config.name, proxy_config.host, proxy_config.port);
+                let result = Self::route_dns_through_socks5(query, &proxy_config);
+                if let Ok(response) = &result {
+                    let bytes = (query.len() + response.len()) as u64;
+                    proxy_manager.lock().unwrap().record_proxy_bytes(proxy_config.id, bytes);
+                }
+                result
+            }
+            None => Self::forward_to_system_dns(query),
+        };
         
-        while *is_running.lock().unwrap() {
-            // Simulate TCP connection processing
-            thread::sleep(Duration::from_millis(300));
+        match result {
+            Ok(response) => Some(dns_packet::with_transaction_id(response, query)),
+            Err(e) => {
+                eprintln!("❌ Failed to resolve '{}': {}", domain.as_deref().unwrap_or("<unparsed query>"), e);
+                dns_packet::servfail_response(query)
+            }
         }
-        
-        println!("🛑 TCP interception stopped");
-        Ok(())
     }
 
     /// Start TCP proxy server
@@ -193,6 +245,7 @@ impl RealTrafficProxy {
         proxy_manager: Arc<Mutex<ProxyManager>>,
         is_running: Arc<Mutex<bool>>,
         port: u16,
+        interceptor: Option<TrafficInterceptor>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let listener = TcpListener::bind(format!("127.0.0.1:{}", port))?;
         println!("🔗 TCP proxy listening on 127.0.0.1:{}", port);
@@ -227,9 +280,10 @@ impl RealTrafficProxy {
                     
                     let proxy_manager = Arc::clone(&proxy_manager);
                     let is_running = Arc::clone(&is_running);
+                    let interceptor = interceptor.clone();
                     
                     thread::spawn(move || {
-                        if let Err(e) = Self::handle_tcp_connection(stream, proxy_manager, is_running) {
+                        if let Err(e) = Self::handle_tcp_connection(stream, proxy_manager, is_running, interceptor.as_ref()) {
                             eprintln!("❌ TCP proxy connection error: {}", e);
                         }
                     });
@@ -248,115 +302,124 @@ impl RealTrafficProxy {
         mut client_stream: TcpStream,
         proxy_manager: Arc<Mutex<ProxyManager>>,
         is_running: Arc<Mutex<bool>>,
+        interceptor: Option<&TrafficInterceptor>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let client_addr = client_stream.peer_addr()?;
         println!("🔍 Processing TCP connection from {}", client_addr);
 
-        // Read the first packet to determine destination
-        let mut buffer = [0u8; 1024];
-        let size = client_stream.read(&mut buffer)?;
+        // Read the first payload to determine destination
+        let first_payload = Self::read_first_payload(&mut client_stream)?;
         
-        if size == 0 {
+        if first_payload.is_empty() {
             println!("⚠️  Empty packet from {}, closing connection", client_addr);
             return Ok(());
         }
 
-        println!("📦 Received {} bytes from {}", size, client_addr);
+        println!("📦 Received {} bytes from {}", first_payload.len(), client_addr);
         
-        // Parse destination from the packet (simplified)
-        match Self::extract_destination_from_packet(&buffer[..size]) {
+        // The listener only sees the redirected address, so the destination comes from the payload
+        match Self::extract_destination_from_packet(&first_payload, None) {
             Ok(destination) => {
-                println!("🎯 Extracted destination: {}", destination);
+                println!("🎯 Extracted destination: {} ({})", 
+                         destination.host.as_deref().unwrap_or("no host name"), destination.addr);
                 
                 // Check if this connection should be proxied
                 if let Some(proxy_config) = Self::should_proxy_connection(&proxy_manager, &destination) {
                     println!("✅ RULE MATCH! Proxying TCP connection to {} through {}:{}", 
-                             destination, proxy_config.host, proxy_config.port);
-                    println!("🔗 SOCKS5 connection: {} -> {} -> {}", client_addr, proxy_config.host, destination);
-                    Self::proxy_tcp_connection(client_stream, destination, &proxy_config)?;
+                             destination.addr, proxy_config.host, proxy_config.port);
+                    println!("🔗 SOCKS5 connection: {} -> {} -> {}", client_addr, proxy_config.host, destination.addr);
+                    let stats = Self::relay_tcp_connection(client_stream, &destination, &first_payload, &proxy_config, interceptor)?;
+                    proxy_manager
+                        .lock()
+                        .unwrap()
+                        .record_proxy_bytes(proxy_config.id, stats.bytes_sent + stats.bytes_received);
                 } else {
-                    println!("❌ No rule match for {} - direct connection", destination);
+                    println!("❌ No rule match for {} - direct connection", destination.addr);
                     // For now, just close the connection
                     // In a real implementation, you'd establish a direct connection
                 }
             }
             Err(e) => {
                 println!("⚠️  Could not extract destination from packet: {}", e);
-                println!("📄 Packet content (first 100 bytes): {:?}", &buffer[..size.min(100)]);
+                println!("📄 Packet content (first 100 bytes): {:?}", &first_payload[..first_payload.len().min(100)]);
             }
         }
 
         Ok(())
     }
 
-    /// Check if DNS query should be proxied (simplified)
-    fn should_proxy_dns_query(
-        _proxy_manager: &Arc<Mutex<ProxyManager>>,
-        _dns_packet: &[u8],
-    ) -> Option<ProxyConfig> {
-        // Simplified implementation - always return None for now
-        None
+    /// Read the client's first payload, waiting for the rest of a TLS record that arrives
+    /// in several segments so the whole ClientHello can be inspected
+    fn read_first_payload(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+        let mut buffer = vec![0u8; MAX_FIRST_PAYLOAD];
+        let mut size = stream.read(&mut buffer)?;
+        
+        while size > 0 {
+            match tls_sni::record_len(&buffer[..size]) {
+                Some(record_len) if size < record_len.min(MAX_FIRST_PAYLOAD) => {
+                    let read = stream.read(&mut buffer[size..])?;
+                    if read == 0 {
+                        break;
+                    }
+                    size += read;
+                }
+                _ => break,
+            }
+        }
+        
+        buffer.truncate(size);
+        Ok(buffer)
     }
 
     /// Check if TCP connection should be proxied
     fn should_proxy_connection(
         proxy_manager: &Arc<Mutex<ProxyManager>>,
-        destination: &SocketAddr,
+        destination: &TcpDestination,
     ) -> Option<ProxyConfig> {
-        let manager = proxy_manager.lock().unwrap();
-        
-        // Quick exit if global proxy is disabled
-        if !manager.global_enabled {
-            return None;
-        }
+        {
+            let manager = proxy_manager.lock().unwrap();
 
-        // Quick exit if no rules are configured
-        if manager.rules.is_empty() {
-            return None;
+            // Quick exit if global proxy is disabled
+            if !manager.global_enabled {
+                return None;
+            }
+
+            // Quick exit if no rules are configured
+            if manager.compiled_rules.is_empty() {
+                return None;
+            }
         }
 
-        // Try to resolve IP to hostname, fallback to IP string
-        let hostname = Self::resolve_ip_to_hostname(destination.ip())
-            .unwrap_or_else(|| {
-                match destination.ip() {
-                    IpAddr::V4(ip) => ip.to_string(),
-                    IpAddr::V6(ip) => ip.to_string(),
-                }
-            });
+        // Prefer the name the client asked for, then try to resolve IP to hostname, fallback to IP string.
+        // The manager is unlocked meanwhile, since a PTR query can take up to REVERSE_DNS_TIMEOUT.
+        let ip = destination.addr.ip();
+        let hostname = destination.host.clone()
+            .or_else(|| Self::resolve_ip_to_hostname(ip))
+            .unwrap_or_else(|| ip.to_string());
+        let manager = proxy_manager.lock().unwrap();
 
         // Quick pre-filter: check if this hostname could potentially match any rule
-        if !Self::could_match_any_rule(&hostname, &manager.rules) {
+        if !Self::could_match_any_rule(&hostname, ip, &manager.compiled_rules) {
             // Silently skip - no need to log every non-matching connection
             return None;
         }
         
-        for rule in &manager.rules {
-            if !rule.enabled {
-                continue;
-            }
-
-            // Split pattern by semicolon and check each sub-pattern
-            let patterns: Vec<&str> = rule.pattern.split(';').collect();
-            
-            let mut any_match = false;
-            for sub_pattern in patterns {
-                let trimmed_pattern = sub_pattern.trim();
-                if trimmed_pattern.is_empty() {
-                    continue;
-                }
-                
-                if Self::matches_pattern(trimmed_pattern, &hostname) {
-                    any_match = true;
-                    break;
-                }
-            }
+        for rule in &manager.compiled_rules {
+            let any_match = rule.matches(&hostname, Some(ip));
             
             if any_match {
                 println!("🎯 RULE MATCH! '{}' -> {} (hostname: '{}')", rule.name, rule.pattern, hostname);
+                manager.record_rule_match(rule.rule_id);
+                
+                if rule.proxy_id == RuleTarget::Direct {
+                    println!("➡️ Direct rule, bypassing proxies");
+                    return None;
+                }
                 
                 // Find the proxy for this rule
-                if let Some(proxy) = manager.proxies.iter().find(|p| p.id == rule.proxy_id && p.enabled) {
+                if let Some(proxy) = manager.proxy_for_target(rule.proxy_id) {
                     println!("🚀 Routing through proxy: {} ({}:{})", proxy.name, proxy.host, proxy.port);
+                    manager.record_proxy_match(proxy.id);
                     return Some(proxy.clone());
                 } else {
                     println!("❌ Proxy {} not found or disabled", rule.proxy_id);
@@ -367,73 +430,131 @@ impl RealTrafficProxy {
         None
     }
 
-    /// Extract destination from TCP packet (simplified)
-    fn extract_destination_from_packet(packet: &[u8]) -> Result<SocketAddr, Box<dyn std::error::Error>> {
-        // This is a simplified implementation
-        // In reality, you'd need to parse HTTP headers, SNI for TLS, etc.
-        
-        // For now, try to extract from HTTP Host header
+    /// Extract destination from the first TCP payload.
+    ///
+    /// TLS connections are identified by the SNI in their ClientHello, plaintext HTTP by its
+    /// `Host` header. The port comes from `original_dst` when known, otherwise from the `Host`
+    /// header or the protocol default, and the address is resolved from the host name.
+    fn extract_destination_from_packet(
+        packet: &[u8],
+        original_dst: Option<SocketAddr>,
+    ) -> Result<TcpDestination, Box<dyn std::error::Error>> {
+        let (host, port) = if let Some(sni) = tls_sni::extract_sni(packet) {
+            (sni, original_dst.map_or(443, |addr| addr.port()))
+        } else if let Some((host, host_port)) = Self::extract_http_host(packet) {
+            (host, original_dst.map(|addr| addr.port()).or(host_port).unwrap_or(80))
+        } else {
+            return Err("Could not extract destination from packet".into());
+        };
+
+        let addr = match original_dst {
+            Some(addr) => addr,
+            None => match host.parse::<IpAddr>() {
+                Ok(ip) => SocketAddr::new(ip, port),
+                Err(_) => (host.as_str(), port)
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| format!("Could not resolve {}", host))?,
+            },
+        };
+
+        Ok(TcpDestination { host: Some(host), addr })
+    }
+
+    /// Host and optional port from the `Host` header of a plaintext HTTP request
+    fn extract_http_host(packet: &[u8]) -> Option<(String, Option<u16>)> {
         let packet_str = String::from_utf8_lossy(packet);
-        for line in packet_str.lines() {
-            if line.to_lowercase().starts_with("host:") {
-                let host = line[5..].trim();
-                // Default to port 80 for HTTP
-                return Ok(SocketAddr::new(host.parse()?, 80));
-            }
+        let value = packet_str
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.trim().eq_ignore_ascii_case("host").then_some(value.trim())
+            })?;
+
+        // Bracketed IPv6 literal, optionally with a port
+        if let Some(rest) = value.strip_prefix('[') {
+            let (ip, after) = rest.split_once(']')?;
+            let port = after.strip_prefix(':').and_then(|port| port.parse().ok());
+            return Some((ip.to_string(), port));
+        }
+
+        let (host, port) = match value.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port.parse().ok()?)),
+            None => (value, None),
+        };
+        if host.is_empty() {
+            return None;
         }
-
-        // Fallback - this is not a real implementation
-        Err("Could not extract destination from packet".into())
+        Some((host.to_ascii_lowercase(), port))
     }
 
-    /// Proxy DNS query through SOCKS5 (simplified)
-    fn proxy_dns_query(
-        _listener: &TcpStream,
-        _dns_packet: &[u8],
-        _client_addr: SocketAddr,
-        _proxy_config: &ProxyConfig,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // Simplified implementation
-        println!("DNS query would be proxied through SOCKS5");
-        Ok(())
+    /// Forward DNS query to the upstream resolver over UDP
+    fn forward_to_system_dns(dns_packet: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let dns_socket = UdpSocket::bind("0.0.0.0:0")?;
+        dns_socket.set_read_timeout(Some(UPSTREAM_DNS_TIMEOUT))?;
+        dns_socket.send_to(dns_packet, UPSTREAM_DNS_SERVER)?;
+        
+        let mut response = vec![0u8; 4096];
+        let size = dns_socket.recv(&mut response)?;
+        response.truncate(size);
+        
+        Ok(response)
     }
 
-    /// Forward DNS query to system DNS (simplified)
-    fn forward_to_system_dns(
-        _listener: &TcpStream,
-        _dns_packet: &[u8],
-        _client_addr: SocketAddr,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // Simplified implementation
-        println!("DNS query would be forwarded to system DNS");
-        Ok(())
+    /// Proxy a matched connection, recording it and its byte totals in `interceptor` if set
+    fn relay_tcp_connection(
+        client_stream: TcpStream,
+        destination: &TcpDestination,
+        initial_data: &[u8],
+        proxy_config: &ProxyConfig,
+        interceptor: Option<&TrafficInterceptor>,
+    ) -> Result<ForwardStats, Box<dyn std::error::Error>> {
+        let recorded = interceptor.map(|interceptor| {
+            let name = destination.host.clone().unwrap_or_else(|| destination.addr.to_string());
+            (interceptor, interceptor.record_relayed_connection(name, proxy_config.clone()))
+        });
+
+        let stats = Self::proxy_tcp_connection(client_stream, destination.addr, initial_data, proxy_config)?;
+        if let Some((interceptor, connection_id)) = recorded {
+            interceptor.update_connection_bytes(connection_id, stats.bytes_sent, stats.bytes_received);
+        }
+        Ok(stats)
     }
 
     /// Proxy TCP connection through SOCKS5
     fn proxy_tcp_connection(
         client_stream: TcpStream,
         destination: SocketAddr,
+        initial_data: &[u8],
         proxy_config: &ProxyConfig,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<ForwardStats, Box<dyn std::error::Error>> {
         println!("🔗 Starting SOCKS5 proxy connection...");
         
         // Connect to SOCKS5 proxy
-        let proxy_addr = format!("{}:{}", proxy_config.host, proxy_config.port);
-        println!("🌐 Connecting to SOCKS5 proxy: {}", proxy_addr);
+        println!("🌐 Connecting to SOCKS5 proxy: {}:{}", proxy_config.host, proxy_config.port);
         
-        let mut proxy_stream = TcpStream::connect(&proxy_addr)?;
+        let mut proxy_stream = socks5_connector::connect_to_proxy(proxy_config, DEFAULT_PROXY_TIMEOUT)?;
         println!("✅ Connected to SOCKS5 proxy");
         
         // Perform SOCKS5 handshake
         println!("🤝 Performing SOCKS5 handshake...");
-        Self::socks5_handshake_sync(&mut proxy_stream, proxy_config)?;
+        let connector = Socks5Connector::from_proxy_config(proxy_config);
+        connector.handshake(&mut proxy_stream)?;
         println!("✅ SOCKS5 handshake completed");
         
         // Connect to destination through proxy
         println!("🎯 Connecting to destination {} through SOCKS5...", destination);
-        Self::socks5_connect(&mut proxy_stream, destination)?;
+        connector.connect(&mut proxy_stream, destination)?;
         println!("✅ Connected to destination through SOCKS5");
         
+        // The tunnel may sit idle for as long as the client likes once negotiated
+        proxy_stream.set_read_timeout(None)?;
+        proxy_stream.set_write_timeout(None)?;
+        
+        // The payload read to find the destination still has to reach it
+        proxy_stream.write_all(initial_data)?;
+        
         // Start bidirectional data forwarding
         let client_addr = client_stream.peer_addr()?;
         let proxy_addr = proxy_stream.peer_addr()?;
@@ -441,351 +562,77 @@ impl RealTrafficProxy {
         println!("🔄 Starting data forwarding: {} <-> {} <-> {}", 
                  client_addr, proxy_addr, destination);
         
-        // Forward data between client and proxy
-        Self::forward_data_bidirectional(client_stream, proxy_stream)?;
+        // Forward data between client and proxy; the first payload was sent above
+        let mut stats = Self::forward_data_bidirectional(client_stream, proxy_stream)?;
+        stats.bytes_sent += initial_data.len() as u64;
         
-        println!("🏁 SOCKS5 proxy connection completed");
-        Ok(())
-    }
-
-    /// SOCKS5 handshake (simplified version)
-    fn socks5_handshake(
-        _stream: &mut TcpStream,
-        _proxy_config: &ProxyConfig,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // Simplified implementation
-        println!("SOCKS5 handshake would be performed");
-        Ok(())
+        println!("🏁 SOCKS5 proxy connection completed ({} bytes sent, {} bytes received)",
+                 stats.bytes_sent, stats.bytes_received);
+        Ok(stats)
     }
 
-    /// SOCKS5 handshake (sync version)
-    fn socks5_handshake_sync(
-        stream: &mut TcpStream,
-        proxy_config: &ProxyConfig,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        println!("🤝 SOCKS5 handshake: sending authentication methods");
-        
-        // Send authentication methods
-        let auth_methods = if proxy_config.username.is_some() {
-            println!("   Using username/password authentication");
-            vec![0x05, 0x01, 0x02, 0x00] // Username/password and no auth
-        } else {
-            println!("   Using no authentication");
-            vec![0x05, 0x01, 0x00] // No authentication
-        };
-        
-        stream.write_all(&auth_methods)?;
-        println!("✅ Sent authentication methods");
-        
-        // Read server response
-        let mut response = [0u8; 2];
-        stream.read_exact(&mut response)?;
-        println!("📥 Received server response: {:?}", response);
-        
-        if response[0] != 0x05 {
-            return Err("Invalid SOCKS5 version".into());
-        }
-        
-        // Handle authentication if required
-        if response[1] == 0x02 && proxy_config.username.is_some() {
-            println!("🔐 Server requires username/password authentication");
-            Self::socks5_authenticate_sync(stream, proxy_config)?;
-        } else if response[1] != 0x00 {
-            return Err("SOCKS5 authentication failed".into());
-        } else {
-            println!("✅ No authentication required");
-        }
-        
-        Ok(())
-    }
+    /// Forward data bidirectionally between two streams
+    fn forward_data_bidirectional(
+        client_stream: TcpStream,
+        proxy_stream: TcpStream,
+    ) -> Result<ForwardStats, Box<dyn std::error::Error>> {
+        let client_reader = client_stream.try_clone()?;
+        let proxy_writer = proxy_stream.try_clone()?;
 
-    /// SOCKS5 authentication
-    fn socks5_authenticate(
-        stream: &mut TcpStream,
-        proxy_config: &ProxyConfig,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let username = proxy_config.username.as_ref().unwrap();
-        let password = proxy_config.password.as_ref().unwrap();
-        
-        let mut auth_request = vec![0x01, username.len() as u8];
-        auth_request.extend_from_slice(username.as_bytes());
-        auth_request.push(password.len() as u8);
-        auth_request.extend_from_slice(password.as_bytes());
-        
-        stream.write_all(&auth_request)?;
-        
-        let mut response = [0u8; 2];
-        stream.read_exact(&mut response)?;
-        
-        if response[0] != 0x01 || response[1] != 0x00 {
-            return Err("SOCKS5 authentication failed".into());
-        }
-        
-        Ok(())
-    }
+        // Each direction gets its own thread so neither side waits on the other
+        let upstream = thread::spawn(move || Self::copy_until_closed(client_reader, proxy_writer));
+        let bytes_received = Self::copy_until_closed(proxy_stream, client_stream);
+        let bytes_sent = upstream.join().map_err(|_| "client -> proxy forwarding thread panicked")?;
 
-    /// SOCKS5 authentication (sync version)
-    fn socks5_authenticate_sync(
-        stream: &mut TcpStream,
-        proxy_config: &ProxyConfig,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let username = proxy_config.username.as_ref().unwrap();
-        let password = proxy_config.password.as_ref().unwrap();
-        
-        let mut auth_request = vec![0x01, username.len() as u8];
-        auth_request.extend_from_slice(username.as_bytes());
-        auth_request.push(password.len() as u8);
-        auth_request.extend_from_slice(password.as_bytes());
-        
-        stream.write_all(&auth_request)?;
-        
-        let mut response = [0u8; 2];
-        stream.read_exact(&mut response)?;
-        
-        if response[0] != 0x01 || response[1] != 0x00 {
-            return Err("SOCKS5 authentication failed".into());
-        }
-        
-        Ok(())
+        Ok(ForwardStats { bytes_sent, bytes_received })
     }
 
-    /// SOCKS5 connect command
-    fn socks5_connect(
-        stream: &mut TcpStream,
-        destination: SocketAddr,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut connect_request = vec![0x05, 0x01, 0x00]; // VER, CMD, RSV
-        
-        match destination.ip() {
-            IpAddr::V4(ip) => {
-                connect_request.push(0x01); // ATYP: IPv4
-                connect_request.extend_from_slice(&ip.octets());
-            }
-            IpAddr::V6(ip) => {
-                connect_request.push(0x04); // ATYP: IPv6
-                connect_request.extend_from_slice(&ip.octets());
-            }
-        }
-        
-        connect_request.extend_from_slice(&destination.port().to_be_bytes());
-        stream.write_all(&connect_request)?;
-        
-        // Read response
-        let mut response = vec![0u8; 4];
-        stream.read_exact(&mut response)?;
-        
-        if response[0] != 0x05 || response[1] != 0x00 {
-            return Err("SOCKS5 connection failed".into());
-        }
-        
-        // Skip the rest of the response
-        let atyp = response[3];
-        let addr_len = match atyp {
-            0x01 => 4,  // IPv4
-            0x04 => 16, // IPv6
-            0x03 => {   // Domain name
-                let mut len_buf = [0u8; 1];
-                stream.read_exact(&mut len_buf)?;
-                len_buf[0] as usize
-            }
-            _ => return Err("Invalid address type".into()),
-        };
-        
-        let mut addr_buf = vec![0u8; addr_len];
-        stream.read_exact(&mut addr_buf)?;
-        
-        let mut port_buf = [0u8; 2];
-        stream.read_exact(&mut port_buf)?;
-        
-        Ok(())
-    }
+    /// Copy `from` into `to` until either side closes or errors, then shut down both
+    /// streams so the opposite direction unblocks too. Returns the bytes copied.
+    fn copy_until_closed(mut from: TcpStream, mut to: TcpStream) -> u64 {
+        let mut buffer = [0u8; 8192];
+        let mut total = 0u64;
 
-    /// Forward data bidirectionally between two streams
-    fn forward_data_bidirectional(
-        mut client_stream: TcpStream,
-        mut proxy_stream: TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // use std::io::copy;
-        
-        // This is a simplified implementation
-        // In reality, you'd need to handle both directions concurrently
-        let mut buffer = [0u8; 4096];
-        
         loop {
-            // Read from client and write to proxy
-            match client_stream.read(&mut buffer) {
-                Ok(0) => break, // Connection closed
-                Ok(size) => {
-                    proxy_stream.write_all(&buffer[..size])?;
-                }
-                Err(_) => break,
-            }
-            
-            // Read from proxy and write to client
-            match proxy_stream.read(&mut buffer) {
-                Ok(0) => break, // Connection closed
+            match from.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
                 Ok(size) => {
-                    client_stream.write_all(&buffer[..size])?;
+                    if to.write_all(&buffer[..size]).is_err() {
+                        break;
+                    }
+                    total += size as u64;
                 }
-                Err(_) => break,
             }
         }
-        
-        Ok(())
+
+        let _ = from.shutdown(Shutdown::Both);
+        let _ = to.shutdown(Shutdown::Both);
+        total
     }
 
     /// Quick check if hostname could potentially match any rule
-    fn could_match_any_rule(hostname: &str, rules: &[ProxyRule]) -> bool {
-        for rule in rules {
-            if !rule.enabled {
-                continue;
-            }
-            
-            // Split pattern by semicolon and check each sub-pattern
-            let patterns: Vec<&str> = rule.pattern.split(';').collect();
-            
-            for sub_pattern in patterns {
-                let trimmed_pattern = sub_pattern.trim();
-                if trimmed_pattern.is_empty() {
-                    continue;
-                }
-                
-                // Quick pattern matching - check if this could potentially match
-                if Self::quick_pattern_match(trimmed_pattern, hostname) {
-                    return true;
-                }
-            }
-        }
-        false
-    }
-    
-    /// Quick pattern matching for pre-filtering
-    fn quick_pattern_match(pattern: &str, hostname: &str) -> bool {
-        // For domain patterns like *.kion.cloud
-        if pattern.starts_with("*.") {
-            let suffix = &pattern[2..];
-            return hostname.ends_with(suffix);
-        }
-        
-        // For IP patterns like 100.64.1.*
-        if pattern.contains(".*") && !pattern.starts_with("*") {
-            let prefix = pattern.split(".*").next().unwrap_or("");
-            return hostname.starts_with(prefix);
-        }
-        
-        // For exact matches
-        if pattern == hostname {
-            return true;
-        }
-        
-        // For prefix patterns like kion.*
-        if pattern.ends_with(".*") {
-            let prefix = &pattern[..pattern.len() - 2];
-            return hostname.starts_with(prefix);
-        }
-        
-        false
+    fn could_match_any_rule(hostname: &str, ip: IpAddr, rules: &[CompiledRule]) -> bool {
+        rules.iter().any(|rule| rule.matches(hostname, Some(ip)))
     }
 
     /// Try to resolve IP address to hostname
     fn resolve_ip_to_hostname(ip: IpAddr) -> Option<String> {
-        // For localhost addresses, return special names
         match ip {
-            IpAddr::V4(ipv4) => {
-                if ipv4.is_loopback() {
-                    return Some("localhost".to_string());
-                }
-                if ipv4.is_private() {
-                    // Try reverse DNS lookup for private IPs
-                    return Self::reverse_dns_lookup(ip);
-                }
-            }
-            IpAddr::V6(ipv6) => {
-                if ipv6.is_loopback() {
-                    return Some("localhost".to_string());
-                }
-                if ipv6.is_unicast_link_local() {
-                    return Some("link-local".to_string());
-                }
-            }
+            IpAddr::V4(ipv4) if ipv4.is_loopback() => Some("localhost".to_string()),
+            IpAddr::V6(ipv6) if ipv6.is_loopback() => Some("localhost".to_string()),
+            IpAddr::V6(ipv6) if ipv6.is_unicast_link_local() => Some("link-local".to_string()),
+            _ => Self::reverse_dns_lookup(ip),
         }
-        
-        // Try reverse DNS lookup
-        Self::reverse_dns_lookup(ip)
     }
     
-    /// Perform reverse DNS lookup
+    /// PTR name of `ip`, falling back to a placeholder for private addresses without one
     fn reverse_dns_lookup(ip: IpAddr) -> Option<String> {
-        // This is a simplified implementation
-        // In a real implementation, you'd use a DNS resolver
-        match ip {
-            IpAddr::V4(ipv4) => {
-                // Check for common private IP ranges
-                if ipv4.octets()[0] == 192 && ipv4.octets()[1] == 168 {
-                    return Some(format!("private-{}.{}.{}.{}", 
-                        ipv4.octets()[0], ipv4.octets()[1], ipv4.octets()[2], ipv4.octets()[3]));
-                }
-                if ipv4.octets()[0] == 10 {
-                    return Some(format!("private-{}.{}.{}.{}", 
-                        ipv4.octets()[0], ipv4.octets()[1], ipv4.octets()[2], ipv4.octets()[3]));
-                }
-                if ipv4.octets()[0] == 172 && ipv4.octets()[1] >= 16 && ipv4.octets()[1] <= 31 {
-                    return Some(format!("private-{}.{}.{}.{}", 
-                        ipv4.octets()[0], ipv4.octets()[1], ipv4.octets()[2], ipv4.octets()[3]));
-                }
-                
-                // Check for 100.64.x.x range (Carrier-Grade NAT)
-                if ipv4.octets()[0] == 100 && ipv4.octets()[1] == 64 {
-                    return Some(format!("100.64.{}.{}", ipv4.octets()[2], ipv4.octets()[3]));
-                }
-            }
-            IpAddr::V6(_) => {
-                // For IPv6, just return a generic name
-                return Some("ipv6-address".to_string());
-            }
-        }
-        
-        None
+        reverse_dns::lookup(ip).or_else(|| reverse_dns::synthesized_name(ip))
     }
 
     /// Extract domain name from DNS packet
     fn extract_domain_from_dns_packet(packet: &[u8]) -> Option<String> {
-        // This is a simplified DNS packet parser
-        // In a real implementation, you'd parse the full DNS packet structure
-        
-        if packet.len() < 12 {
-            return None; // DNS header is at least 12 bytes
-        }
-        
-        // Skip DNS header (12 bytes) and parse the question section
-        let mut offset = 12;
-        let mut domain = String::new();
-        
-        while offset < packet.len() {
-            let length = packet[offset] as usize;
-            if length == 0 {
-                break; // End of domain name
-            }
-            
-            if offset + length >= packet.len() {
-                break; // Invalid packet
-            }
-            
-            if !domain.is_empty() {
-                domain.push('.');
-            }
-            
-            let label = String::from_utf8_lossy(&packet[offset + 1..offset + 1 + length]);
-            domain.push_str(&label);
-            
-            offset += length + 1;
-        }
-        
-        if domain.is_empty() {
-            None
-        } else {
-            Some(domain)
-        }
+        dns_packet::extract_query_domain(packet)
     }
     
     /// Check if domain should be proxied
@@ -795,29 +642,21 @@ impl RealTrafficProxy {
     ) -> Option<ProxyConfig> {
         let manager = proxy_manager.lock().unwrap();
         
-        if !manager.global_enabled || manager.rules.is_empty() {
+        if !manager.global_enabled || manager.compiled_rules.is_empty() {
             return None;
         }
         
-        for rule in &manager.rules {
-            if !rule.enabled {
-                continue;
-            }
-            
-            // Split pattern by semicolon and check each sub-pattern
-            let patterns: Vec<&str> = rule.pattern.split(';').collect();
-            
-            for sub_pattern in patterns {
-                let trimmed_pattern = sub_pattern.trim();
-                if trimmed_pattern.is_empty() {
-                    continue;
+        for rule in &manager.compiled_rules {
+            if rule.matches(domain, None) {
+                manager.record_rule_match(rule.rule_id);
+                if rule.proxy_id == RuleTarget::Direct {
+                    return None;
                 }
                 
-                if Self::matches_pattern(trimmed_pattern, domain) {
-                    // Find the proxy for this rule
-                    if let Some(proxy) = manager.proxies.iter().find(|p| p.id == rule.proxy_id && p.enabled) {
-                        return Some(proxy.clone());
-                    }
+                // Find the proxy for this rule
+                if let Some(proxy) = manager.proxy_for_target(rule.proxy_id) {
+                    manager.record_proxy_match(proxy.id);
+                    return Some(proxy.clone());
                 }
             }
         }
@@ -825,52 +664,309 @@ impl RealTrafficProxy {
         None
     }
     
-    /// Route DNS query through SOCKS5 proxy
+    /// Route a client's DNS query through SOCKS5 proxy using DNS over TCP, returning the raw
+    /// response. The query is sent unchanged so the answer carries the client's ID and question.
     fn route_dns_through_socks5(
-        domain: &str,
+        query_packet: &[u8],
         proxy_config: &ProxyConfig,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // This is a simplified implementation
-        // In a real implementation, you would:
-        // 1. Connect to SOCKS5 proxy
-        // 2. Perform SOCKS5 handshake
-        // 3. Send DNS query through proxy
-        // 4. Return the response
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let dns_addr: SocketAddr = UPSTREAM_DNS_SERVER.parse()?;
+        let mut proxy_stream = Socks5Client::new(proxy_config.clone()).connect(dns_addr)?;
+        println!("🎯 Connected to DNS server {} through proxy {}:{}", 
+                 dns_addr, proxy_config.host, proxy_config.port);
         
-        Ok(())
+        let response = dns_packet::exchange_over_tcp(&mut proxy_stream, query_packet)?;
+        println!("📥 DNS response received through proxy ({} bytes)", response.len());
+        
+        Ok(response)
     }
+}
 
-    /// Pattern matching for proxy rules
-    fn matches_pattern(pattern: &str, hostname: &str) -> bool {
-        if pattern == hostname {
-            return true;
-        }
-        
-        if pattern.starts_with("*.") {
-            let suffix = &pattern[2..];
-            return hostname.ends_with(suffix);
-        }
-        
-        if pattern.ends_with(".*") {
-            let prefix = &pattern[..pattern.len() - 2];
-            return hostname.starts_with(prefix);
-        }
-        
-        if pattern.contains(".*") && !pattern.starts_with("*") && !pattern.ends_with("*") {
-            let parts: Vec<&str> = pattern.split(".*").collect();
-            if parts.len() == 2 {
-                let prefix = parts[0];
-                return hostname.starts_with(prefix);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProxyType;
+    use std::sync::mpsc;
+
+    /// A connected loopback pair: (accepted side, connecting side)
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let connector = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        (accepted, connector)
+    }
+
+    #[test]
+    fn test_forward_data_bidirectional_asymmetric_traffic() {
+        let (client_stream, mut client) = socket_pair();
+        let (mut server, proxy_stream) = socket_pair();
+        for stream in [&client, &server] {
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
         }
-        
-        if pattern.contains("*") {
-            let parts: Vec<&str> = pattern.split('*').collect();
-            if parts.len() == 2 {
-                return hostname.starts_with(parts[0]) && hostname.ends_with(parts[1]);
-            }
+
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let stats = RealTrafficProxy::forward_data_bidirectional(client_stream, proxy_stream);
+            let _ = done_tx.send(stats.map_err(|e| e.to_string()));
+        });
+
+        // The server speaks first while the client has nothing to send
+        let greeting = b"220 ready\r\n";
+        server.write_all(greeting).unwrap();
+        let mut received = vec![0u8; greeting.len()];
+        client.read_exact(&mut received).unwrap();
+        assert_eq!(&received, greeting);
+
+        let request = b"GET bulk\r\n";
+        client.write_all(request).unwrap();
+        let mut received = vec![0u8; request.len()];
+        server.read_exact(&mut received).unwrap();
+        assert_eq!(&received, request);
+
+        let bulk = vec![0x5a; 256 * 1024];
+        server.write_all(&bulk).unwrap();
+        let mut received = vec![0u8; bulk.len()];
+        client.read_exact(&mut received).unwrap();
+        assert_eq!(received, bulk);
+
+        // Closing the client tears down both directions
+        drop(client);
+        let stats = done_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("forwarding did not finish")
+            .unwrap();
+        assert_eq!(stats.bytes_sent, request.len() as u64);
+        assert_eq!(stats.bytes_received, (greeting.len() + bulk.len()) as u64);
+
+        let mut rest = Vec::new();
+        assert_eq!(server.read_to_end(&mut rest).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_relayed_connection_bytes_are_recorded() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_port = listener.local_addr().unwrap().port();
+
+        // Mock SOCKS5 proxy that accepts the CONNECT, then answers the client's request
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+
+            let mut received = [0u8; 9];
+            stream.read_exact(&mut received).unwrap();
+            assert_eq!(&received, b"helloping");
+            stream.write_all(b"pong-response").unwrap();
+        });
+
+        let mut manager = ProxyManager::default();
+        let proxy_id = manager.add_proxy("Local".to_string(), "127.0.0.1".to_string(), proxy_port, ProxyType::Socks5);
+        let proxy_config = manager.proxies.iter().find(|proxy| proxy.id == proxy_id).unwrap().clone();
+        let interceptor = TrafficInterceptor::new(Arc::new(Mutex::new(manager)));
+
+        let (client_stream, mut client) = socket_pair();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let destination = TcpDestination {
+            host: Some("relay.example".to_string()),
+            addr: "10.0.0.1:443".parse().unwrap(),
+        };
+        let relay_interceptor = interceptor.clone();
+        let relay = thread::spawn(move || {
+            RealTrafficProxy::relay_tcp_connection(client_stream, &destination, b"hello", &proxy_config, Some(&relay_interceptor))
+                .map_err(|e| e.to_string())
+        });
+
+        client.write_all(b"ping").unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert_eq!(response, b"pong-response");
+        drop(client);
+        server.join().unwrap();
+
+        let stats = relay.join().unwrap().unwrap();
+        // The first payload counts towards the bytes sent
+        assert_eq!(stats, ForwardStats { bytes_sent: 9, bytes_received: 13 });
+        let recorded = interceptor.get_intercepted_connections();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].domain.as_deref(), Some("relay.example"));
+        assert_eq!((recorded[0].bytes_sent, recorded[0].bytes_received), (9, 13));
+    }
+
+    #[test]
+    fn test_matched_dns_query_is_tunnelled_through_socks5() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_port = listener.local_addr().unwrap().port();
+
+        let mut query = vec![0xAB, 0xCD, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(b"\x08internal\x04corp\x00\x00\x01\x00\x01");
+        let answer = [&query[..2], &[0x81, 0x80][..], &query[4..]].concat();
+
+        // Mock SOCKS5 proxy that checks the CONNECT target and answers the framed query
+        let expected_query = query.clone();
+        let server_answer = answer.clone();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request[4..], &[8, 8, 8, 8, 0, 53]);
+            stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).unwrap();
+            let mut received = vec![0u8; u16::from_be_bytes(length) as usize];
+            stream.read_exact(&mut received).unwrap();
+            assert_eq!(received, expected_query);
+
+            stream.write_all(&(server_answer.len() as u16).to_be_bytes()).unwrap();
+            stream.write_all(&server_answer).unwrap();
+        });
+
+        let mut manager = ProxyManager {
+            global_enabled: true,
+            ..Default::default()
+        };
+        let proxy = manager.add_proxy("Local".to_string(), "127.0.0.1".to_string(), proxy_port, ProxyType::Socks5);
+        manager.add_rule("Corp".to_string(), "*.corp".to_string(), proxy).unwrap();
+        let manager = Arc::new(Mutex::new(manager));
+
+        let response = RealTrafficProxy::resolve_dns_query(&manager, &query).unwrap();
+        assert_eq!(response, answer);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_tunnelled_dns_query_keeps_id_type_and_class() {
+        // AAAA and TXT questions, the latter in the CHAOS class, with the client's own IDs
+        for (id, qtype, qclass) in [([0x3C, 0x5A], 28u16, 1u16), ([0x00, 0x07], 16, 3)] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let proxy_port = listener.local_addr().unwrap().port();
+
+            let mut query = [&id[..], &[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0][..]].concat();
+            query.extend_from_slice(b"\x04mail\x04corp\x00");
+            query.extend_from_slice(&qtype.to_be_bytes());
+            query.extend_from_slice(&qclass.to_be_bytes());
+
+            // Mock SOCKS5 proxy that echoes the framed query back as the answer
+            let server = thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut greeting = [0u8; 3];
+                stream.read_exact(&mut greeting).unwrap();
+                stream.write_all(&[0x05, 0x00]).unwrap();
+                let mut request = [0u8; 10];
+                stream.read_exact(&mut request).unwrap();
+                stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+
+                let mut length = [0u8; 2];
+                stream.read_exact(&mut length).unwrap();
+                let mut received = vec![0u8; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut received).unwrap();
+                stream.write_all(&length).unwrap();
+                stream.write_all(&received).unwrap();
+                received
+            });
+
+            let mut manager = ProxyManager {
+                global_enabled: true,
+                ..Default::default()
+            };
+            let proxy = manager.add_proxy("Local".to_string(), "127.0.0.1".to_string(), proxy_port, ProxyType::Socks5);
+            manager.add_rule("Corp".to_string(), "*.corp".to_string(), proxy).unwrap();
+            let manager = Arc::new(Mutex::new(manager));
+
+            RealTrafficProxy::resolve_dns_query(&manager, &query).unwrap();
+            let forwarded = server.join().unwrap();
+            assert_eq!(forwarded, query, "qtype {} qclass {}", qtype, qclass);
         }
-        
-        false
+    }
+
+    #[test]
+    fn test_response_id_matches_client_query() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_port = listener.local_addr().unwrap().port();
+
+        let mut query = vec![0x6B, 0x21, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(b"\x08internal\x04corp\x00\x00\x01\x00\x01");
+
+        // Mock SOCKS5 proxy whose resolver answers under a different ID
+        let server_answer = [&[0x12, 0x34, 0x81, 0x80][..], &query[4..]].concat();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+
+            dns_packet::read_tcp_message(&mut stream).unwrap();
+            dns_packet::write_tcp_message(&mut stream, &server_answer).unwrap();
+        });
+
+        let mut manager = ProxyManager {
+            global_enabled: true,
+            ..Default::default()
+        };
+        let proxy = manager.add_proxy("Local".to_string(), "127.0.0.1".to_string(), proxy_port, ProxyType::Socks5);
+        manager.add_rule("Corp".to_string(), "*.corp".to_string(), proxy).unwrap();
+        let manager = Arc::new(Mutex::new(manager));
+
+        let response = RealTrafficProxy::resolve_dns_query(&manager, &query).unwrap();
+        server.join().unwrap();
+        assert_eq!(&response[..2], &query[..2]);
+        assert_eq!(&response[2..4], &[0x81, 0x80]);
+    }
+
+    #[test]
+    fn test_destination_from_tls_client_hello() {
+        // The original port is kept, the host name comes from SNI
+        let original: SocketAddr = "93.184.216.34:8443".parse().unwrap();
+        let destination = RealTrafficProxy::extract_destination_from_packet(&tls_sni::CLIENT_HELLO, Some(original)).unwrap();
+        assert_eq!(destination.host.as_deref(), Some("api.example.com"));
+        assert_eq!(destination.addr, original);
+    }
+
+    #[test]
+    fn test_destination_from_http_host_header() {
+        let request = b"GET / HTTP/1.1\r\nUser-Agent: curl\r\nHOST: 10.1.2.3:8080\r\n\r\n";
+        let destination = RealTrafficProxy::extract_destination_from_packet(request, None).unwrap();
+        assert_eq!(destination.host.as_deref(), Some("10.1.2.3"));
+        assert_eq!(destination.addr, "10.1.2.3:8080".parse().unwrap());
+
+        assert_eq!(
+            RealTrafficProxy::extract_http_host(b"GET / HTTP/1.1\r\nHost: [::1]:3000\r\n\r\n"),
+            Some(("::1".to_string(), Some(3000)))
+        );
+        assert_eq!(
+            RealTrafficProxy::extract_http_host(b"GET / HTTP/1.1\r\nHost: Intranet.Corp\r\n\r\n"),
+            Some(("intranet.corp".to_string(), None))
+        );
+        // Headers after the blank line belong to the body
+        assert_eq!(RealTrafficProxy::extract_http_host(b"GET / HTTP/1.1\r\n\r\nHost: body"), None);
+        assert!(RealTrafficProxy::extract_destination_from_packet(b"\x00\x01binary", None).is_err());
+    }
+
+    #[test]
+    fn test_rules_match_sni_host_name() {
+        let mut manager = ProxyManager {
+            global_enabled: true,
+            ..Default::default()
+        };
+        let proxy = manager.add_proxy("Corp".to_string(), "10.0.0.1".to_string(), 1080, ProxyType::Socks5);
+        manager.add_rule("Example API".to_string(), "api.example.com".to_string(), proxy).unwrap();
+        let manager = Arc::new(Mutex::new(manager));
+
+        let original: SocketAddr = "93.184.216.34:443".parse().unwrap();
+        let destination = RealTrafficProxy::extract_destination_from_packet(&tls_sni::CLIENT_HELLO, Some(original)).unwrap();
+        assert_eq!(RealTrafficProxy::should_proxy_connection(&manager, &destination).unwrap().id, proxy);
+
+        let unnamed = TcpDestination { host: None, addr: original };
+        assert!(RealTrafficProxy::should_proxy_connection(&manager, &unnamed).is_none());
     }
 }
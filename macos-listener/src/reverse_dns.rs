@@ -0,0 +1,272 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+use trust_dns_resolver::system_conf::read_system_conf;
+use trust_dns_resolver::Resolver;
+
+/// Worker threads resolving remote addresses for the connections table
+pub const REVERSE_DNS_WORKERS: usize = 4;
+
+/// Longest a single PTR query may take; the interceptor waits for it
+pub const REVERSE_DNS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long an answer, including "no PTR record", is reused before asking again
+pub const REVERSE_DNS_CACHE_TTL: Duration = Duration::from_secs(600);
+
+/// Addresses kept in the cache; it is emptied when it grows past this
+const REVERSE_DNS_CACHE_CAPACITY: usize = 4096;
+
+/// Blocking PTR lookups with a short timeout, cached so checking the same address on
+/// every interceptor poll costs one query
+pub struct PtrResolver {
+    /// `None` when the resolver could not be created; every lookup then misses
+    resolver: Option<Resolver>,
+    cache: Mutex<HashMap<IpAddr, (Option<String>, Instant)>>,
+}
+
+impl PtrResolver {
+    /// Ask the name servers in `config`, giving up after `REVERSE_DNS_TIMEOUT`
+    pub fn new(config: ResolverConfig, mut options: ResolverOpts) -> Self {
+        options.timeout = REVERSE_DNS_TIMEOUT;
+        options.attempts = 0;
+        let resolver = Resolver::new(config, options)
+            .map_err(|e| eprintln!("Reverse DNS unavailable: {}", e))
+            .ok();
+        Self {
+            resolver,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Use the system's name servers, or the default ones if they cannot be read
+    pub fn from_system_conf() -> Self {
+        let (config, options) = read_system_conf().unwrap_or_else(|e| {
+            eprintln!("Could not read system DNS configuration ({}), using defaults", e);
+            (ResolverConfig::default(), ResolverOpts::default())
+        });
+        Self::new(config, options)
+    }
+
+    /// Host name from the PTR record of `ip`, without the trailing dot
+    pub fn lookup(&self, ip: IpAddr) -> Option<String> {
+        if let Some((name, at)) = self.cache.lock().unwrap().get(&ip) {
+            if at.elapsed() < REVERSE_DNS_CACHE_TTL {
+                return name.clone();
+            }
+        }
+
+        // Queried without holding the cache lock so lookups of other addresses are not held up
+        let name = self.resolver.as_ref().and_then(|resolver| {
+            let lookup = resolver.reverse_lookup(ip).ok()?;
+            lookup
+                .iter()
+                .next()
+                .map(|name| name.to_utf8().trim_end_matches('.').to_string())
+        });
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= REVERSE_DNS_CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(ip, (name.clone(), Instant::now()));
+        name
+    }
+}
+
+/// PTR name of `ip` through a process-wide `PtrResolver` on the system configuration.
+/// Blocks for up to `REVERSE_DNS_TIMEOUT` when the answer is not cached.
+pub fn lookup(ip: IpAddr) -> Option<String> {
+    static SHARED: OnceLock<PtrResolver> = OnceLock::new();
+    SHARED.get_or_init(PtrResolver::from_system_conf).lookup(ip)
+}
+
+/// Placeholder name for a private or carrier-grade NAT address, used when it has no PTR record
+pub fn synthesized_name(ip: IpAddr) -> Option<String> {
+    let IpAddr::V4(ipv4) = ip else {
+        return None;
+    };
+    let octets = ipv4.octets();
+    if ipv4.is_private() {
+        return Some(format!("private-{}", ipv4));
+    }
+    if octets[0] == 100 && octets[1] == 64 {
+        return Some(format!("100.64.{}.{}", octets[2], octets[3]));
+    }
+    None
+}
+
+/// Resolves addresses to host names on a fixed number of worker threads, so slow PTR
+/// lookups never block the caller. Each address is looked up once per pool.
+pub struct ReverseDnsPool {
+    requests: Sender<IpAddr>,
+    results: Receiver<(IpAddr, Option<String>)>,
+    requested: HashSet<IpAddr>,
+}
+
+impl ReverseDnsPool {
+    /// Start `workers` threads answering requests with `lookup`. They exit when the pool is dropped.
+    pub fn new(workers: usize, lookup: fn(IpAddr) -> Option<String>) -> Self {
+        let (request_tx, request_rx) = mpsc::channel::<IpAddr>();
+        let (result_tx, result_rx) = mpsc::channel();
+        let request_rx = Arc::new(Mutex::new(request_rx));
+
+        for _ in 0..workers.max(1) {
+            let request_rx = request_rx.clone();
+            let result_tx = result_tx.clone();
+            std::thread::spawn(move || loop {
+                // The queue lock is released before the lookup so other workers keep going
+                let Ok(ip) = request_rx.lock().unwrap().recv() else {
+                    break;
+                };
+                if result_tx.send((ip, lookup(ip))).is_err() {
+                    break;
+                }
+            });
+        }
+
+        Self {
+            requests: request_tx,
+            results: result_rx,
+            requested: HashSet::new(),
+        }
+    }
+
+    /// Queue a lookup of `ip`, unless it was requested before
+    pub fn request(&mut self, ip: IpAddr) {
+        if self.requested.insert(ip) {
+            let _ = self.requests.send(ip);
+        }
+    }
+
+    /// Answers received since the last call, `None` for addresses without a PTR record
+    pub fn take_results(&self) -> Vec<(IpAddr, Option<String>)> {
+        self.results.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use trust_dns_resolver::config::{NameServerConfig, Protocol};
+
+    /// Answer every query with a PTR record for `name`, counting the queries received
+    fn spawn_fake_ptr_server(name: &'static str) -> (std::net::SocketAddr, Arc<AtomicUsize>) {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut end = 12;
+                while end < len && buf[end] != 0 {
+                    end += usize::from(buf[end]) + 1;
+                }
+                let question_end = end + 5;
+                if question_end > len {
+                    continue;
+                }
+
+                let mut rdata = Vec::new();
+                for label in name.split('.') {
+                    rdata.push(label.len() as u8);
+                    rdata.extend_from_slice(label.as_bytes());
+                }
+                rdata.push(0);
+
+                let mut response = buf[..2].to_vec();
+                response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+                response.extend_from_slice(&buf[12..question_end]);
+                response.extend_from_slice(&[0xc0, 0x0c, 0, 12, 0, 1, 0, 0, 0, 60]);
+                response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+                response.extend_from_slice(&rdata);
+                let _ = socket.send_to(&response, peer);
+            }
+        });
+        (addr, queries)
+    }
+
+    fn resolver_for(server: std::net::SocketAddr) -> PtrResolver {
+        let mut config = ResolverConfig::new();
+        config.add_name_server(NameServerConfig::new(server, Protocol::Udp));
+        let mut options = ResolverOpts::default();
+        options.use_hosts_file = false;
+        PtrResolver::new(config, options)
+    }
+
+    #[test]
+    fn test_ptr_lookup_is_cached() {
+        let (server, queries) = spawn_fake_ptr_server("files.corp.example");
+        let resolver = resolver_for(server);
+        let ip: IpAddr = "10.0.0.5".parse().unwrap();
+
+        assert_eq!(resolver.lookup(ip).as_deref(), Some("files.corp.example"));
+        assert_eq!(resolver.lookup(ip).as_deref(), Some("files.corp.example"));
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_unanswered_ptr_lookup_times_out() {
+        // Never answers
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = resolver_for(silent.local_addr().unwrap());
+        let ip: IpAddr = "10.0.0.5".parse().unwrap();
+
+        let started = Instant::now();
+        assert_eq!(resolver.lookup(ip), None);
+        assert!(started.elapsed() < REVERSE_DNS_TIMEOUT * 3, "{:?}", started.elapsed());
+
+        // The miss is cached too
+        let started = Instant::now();
+        assert_eq!(resolver.lookup(ip), None);
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_synthesized_names() {
+        assert_eq!(synthesized_name("10.0.0.5".parse().unwrap()).as_deref(), Some("private-10.0.0.5"));
+        assert_eq!(synthesized_name("172.20.1.2".parse().unwrap()).as_deref(), Some("private-172.20.1.2"));
+        assert_eq!(synthesized_name("100.64.3.4".parse().unwrap()).as_deref(), Some("100.64.3.4"));
+        assert_eq!(synthesized_name("93.184.216.34".parse().unwrap()), None);
+        assert_eq!(synthesized_name("fd00::1".parse().unwrap()), None);
+    }
+
+    static LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+
+    fn fake_lookup(ip: IpAddr) -> Option<String> {
+        LOOKUPS.fetch_add(1, Ordering::SeqCst);
+        match ip {
+            IpAddr::V4(v4) if v4.octets()[3] == 1 => Some("one.example".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_pool_resolves_each_address_once() {
+        let mut pool = ReverseDnsPool::new(2, fake_lookup);
+        let named: IpAddr = "192.0.2.1".parse().unwrap();
+        let unnamed: IpAddr = "192.0.2.2".parse().unwrap();
+        for _ in 0..3 {
+            pool.request(named);
+            pool.request(unnamed);
+        }
+
+        let mut results = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while results.len() < 2 && Instant::now() < deadline {
+            results.extend(pool.take_results());
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        results.sort();
+
+        assert_eq!(results, vec![(named, Some("one.example".to_string())), (unnamed, None)]);
+        assert_eq!(LOOKUPS.load(Ordering::SeqCst), 2);
+        assert!(pool.take_results().is_empty());
+    }
+}
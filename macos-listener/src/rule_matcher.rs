@@ -0,0 +1,484 @@
+use std::net::IpAddr;
+
+use regex::Regex;
+
+use crate::{ProxyRule, RuleTarget};
+
+/// A single sub-pattern of a rule, classified once so matching doesn't re-parse it.
+///
+/// Supported patterns:
+/// - exact: `api.kion.cloud`
+/// - suffix: `*.kion.cloud` (one or more labels before `kion.cloud`, not the bare domain)
+/// - prefix: `kion.*`, `100.64.1.*` (one or more labels after the prefix)
+/// - CIDR: `10.0.0.0/8`, `fd00::/8` (matched against the connection IP)
+/// - glob: `*.internal.*.corp`, `web*.example.com`, matched label by label,
+///   where `*` never crosses a `.`
+/// - regex: `re:api-\d+\.example\.com`, anchored to the whole hostname
+///   (it cannot contain `;`, which separates sub-patterns)
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompiledPattern {
+    Exact(String),
+    Suffix(String),
+    Prefix(String),
+    Cidr(IpAddr, u8),
+    Glob(String, Vec<String>),
+    Regex(AnchoredRegex),
+}
+
+/// A `re:` pattern compiled as `^(?:...)$`, compared by its source text
+#[derive(Debug, Clone)]
+pub struct AnchoredRegex(Regex);
+
+impl PartialEq for AnchoredRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl CompiledPattern {
+    /// Classify `pattern`; fails only for a malformed `re:` pattern
+    pub fn compile(pattern: &str) -> Result<Self, String> {
+        if let Some(expression) = pattern.strip_prefix("re:") {
+            return Regex::new(&format!("^(?:{})$", expression))
+                .map(|regex| CompiledPattern::Regex(AnchoredRegex(regex)))
+                .map_err(|e| format!("Invalid regex '{}': {}", expression, e));
+        }
+
+        if let Some((network, prefix_len)) = parse_cidr(pattern) {
+            return Ok(CompiledPattern::Cidr(network, prefix_len));
+        }
+
+        if let Some(suffix) = pattern.strip_prefix("*.") {
+            if !suffix.contains('*') {
+                return Ok(CompiledPattern::Suffix(suffix.to_string()));
+            }
+        }
+
+        if let Some(prefix) = pattern.strip_suffix(".*") {
+            if !prefix.contains('*') {
+                return Ok(CompiledPattern::Prefix(prefix.to_string()));
+            }
+        }
+
+        if pattern.contains('*') {
+            let labels = pattern.split('.').map(str::to_string).collect();
+            return Ok(CompiledPattern::Glob(pattern.to_string(), labels));
+        }
+
+        Ok(CompiledPattern::Exact(pattern.to_string()))
+    }
+
+    /// Match against a hostname (or IP string); CIDR patterns are tested against `ip`,
+    /// falling back to parsing `hostname` as an address
+    pub fn matches(&self, hostname: &str, ip: Option<IpAddr>) -> bool {
+        match self {
+            CompiledPattern::Exact(exact) => exact == hostname,
+            // The wildcard must cover whole labels: `*.kion.cloud` is not `evilkion.cloud`
+            CompiledPattern::Suffix(suffix) => hostname
+                .strip_suffix(suffix.as_str())
+                .is_some_and(|rest| rest.len() > 1 && rest.ends_with('.')),
+            CompiledPattern::Prefix(prefix) => hostname
+                .strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.len() > 1 && rest.starts_with('.')),
+            CompiledPattern::Cidr(network, prefix_len) => ip
+                .or_else(|| hostname.parse().ok())
+                .map(|ip| cidr_contains(*network, *prefix_len, ip))
+                .unwrap_or(false),
+            CompiledPattern::Glob(pattern, labels) => {
+                pattern == hostname || glob_match_labels(labels, hostname)
+            }
+            CompiledPattern::Regex(AnchoredRegex(regex)) => regex.is_match(hostname),
+        }
+    }
+}
+
+/// A rule with its `;`-separated pattern pre-split and compiled
+#[derive(Debug, Clone)]
+pub struct CompiledRule {
+    pub rule_id: u32,
+    pub name: String,
+    pub pattern: String,
+    pub proxy_id: RuleTarget,
+    pub patterns: Vec<CompiledPattern>,
+}
+
+impl CompiledRule {
+    /// Sub-patterns that fail to compile are logged and left out; `validate_pattern`
+    /// keeps them out of rules added through `ProxyManager`
+    pub fn compile(rule: &ProxyRule) -> Self {
+        let patterns = sub_patterns(&rule.pattern)
+            .filter_map(|pattern| match CompiledPattern::compile(pattern) {
+                Ok(compiled) => Some(compiled),
+                Err(e) => {
+                    eprintln!("Skipping pattern in rule '{}': {}", rule.name, e);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            rule_id: rule.id,
+            name: rule.name.clone(),
+            pattern: rule.pattern.clone(),
+            proxy_id: rule.proxy_id,
+            patterns,
+        }
+    }
+
+    /// True if any sub-pattern matches
+    pub fn matches(&self, hostname: &str, ip: Option<IpAddr>) -> bool {
+        self.patterns.iter().any(|pattern| pattern.matches(hostname, ip))
+    }
+}
+
+/// Check that every `;`-separated sub-pattern of `pattern` compiles
+pub fn validate_pattern(pattern: &str) -> Result<(), String> {
+    for sub_pattern in sub_patterns(pattern) {
+        CompiledPattern::compile(sub_pattern)?;
+    }
+    Ok(())
+}
+
+fn sub_patterns(pattern: &str) -> impl Iterator<Item = &str> {
+    pattern.split(';').map(str::trim).filter(|pattern| !pattern.is_empty())
+}
+
+/// Compile the enabled rules, preserving their order (see `ProxyManager::recompile`)
+pub fn compile_rules(rules: &[ProxyRule]) -> Vec<CompiledRule> {
+    rules
+        .iter()
+        .filter(|rule| rule.enabled)
+        .map(CompiledRule::compile)
+        .collect()
+}
+
+/// Parse `a.b.c.d/len` or `v6addr/len`
+pub fn parse_cidr(pattern: &str) -> Option<(IpAddr, u8)> {
+    let (addr, len) = pattern.split_once('/')?;
+    let network: IpAddr = addr.trim().parse().ok()?;
+    let prefix_len: u8 = len.trim().parse().ok()?;
+
+    let max_len = match network {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    (prefix_len <= max_len).then_some((network, prefix_len))
+}
+
+/// Check whether `ip` falls inside `network/prefix_len`
+pub fn cidr_contains(network: IpAddr, prefix_len: u8, ip: IpAddr) -> bool {
+    // Treat IPv4-mapped IPv6 addresses (::ffff:a.b.c.d) as IPv4
+    match (network, ip.to_canonical()) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Match dot-separated labels pairwise; both sides must have the same number of labels
+fn glob_match_labels(pattern_labels: &[String], hostname: &str) -> bool {
+    let host_labels: Vec<&str> = hostname.split('.').collect();
+
+    pattern_labels.len() == host_labels.len()
+        && pattern_labels
+            .iter()
+            .zip(&host_labels)
+            .all(|(pattern, label)| glob_match(pattern, label))
+}
+
+/// Match a single label where `*` matches any run of characters
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let middle: Vec<&str> = parts.collect();
+    let Some((last, middle)) = middle.split_last() else {
+        // No `*` at all
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn matches_pattern(pattern: &str, hostname: &str) -> bool {
+        CompiledPattern::compile(pattern).unwrap().matches(hostname, None)
+    }
+
+    fn matches_target(pattern: &str, hostname: &str, ip: IpAddr) -> bool {
+        CompiledPattern::compile(pattern).unwrap().matches(hostname, Some(ip))
+    }
+
+    fn rule(id: u32, pattern: &str, enabled: bool) -> ProxyRule {
+        ProxyRule {
+            id,
+            name: format!("rule {}", id),
+            pattern: pattern.to_string(),
+            enabled,
+            proxy_id: RuleTarget::Proxy(1),
+            priority: 0,
+        }
+    }
+
+    /// The per-call string matcher used before rules were compiled
+    fn legacy_matches_pattern(pattern: &str, hostname: &str) -> bool {
+        if pattern == hostname {
+            return true;
+        }
+        if let Some((network, prefix_len)) = parse_cidr(pattern) {
+            return hostname
+                .parse::<IpAddr>()
+                .map(|ip| cidr_contains(network, prefix_len, ip))
+                .unwrap_or(false);
+        }
+        if let Some(suffix) = pattern.strip_prefix("*.") {
+            if !suffix.contains('*') {
+                return hostname.ends_with(&format!(".{}", suffix)) && hostname.len() > suffix.len() + 1;
+            }
+        }
+        if let Some(prefix) = pattern.strip_suffix(".*") {
+            if !prefix.contains('*') {
+                return hostname.starts_with(&format!("{}.", prefix)) && hostname.len() > prefix.len() + 1;
+            }
+        }
+        if pattern.contains('*') {
+            let labels: Vec<String> = pattern.split('.').map(str::to_string).collect();
+            return glob_match_labels(&labels, hostname);
+        }
+        false
+    }
+
+    fn legacy_rule_matches(rule: &ProxyRule, hostname: &str) -> bool {
+        rule.enabled
+            && rule
+                .pattern
+                .split(';')
+                .map(str::trim)
+                .filter(|pattern| !pattern.is_empty())
+                .any(|pattern| legacy_matches_pattern(pattern, hostname))
+    }
+
+    #[test]
+    fn test_existing_patterns() {
+        assert!(matches_pattern("api.kion.cloud", "api.kion.cloud"));
+        assert!(matches_pattern("*.kion.cloud", "a.b.kion.cloud"));
+        assert!(matches_pattern("kion.*", "kion.net"));
+        assert!(matches_pattern("100.64.1.*", "100.64.1.20"));
+        assert!(matches_pattern("api*.example.com", "api-eu.example.com"));
+        assert!(!matches_pattern("*.kion.cloud", "kion.net"));
+        assert!(!matches_pattern("100.64.1.*", "100.64.2.20"));
+    }
+
+    /// The intended semantics of every pattern shape, in one place
+    #[test]
+    fn test_pattern_semantics_table() {
+        let cases = [
+            // exact
+            ("api.kion.cloud", "api.kion.cloud", true),
+            ("api.kion.cloud", "www.api.kion.cloud", false),
+            // `*.suffix`: one or more whole labels, never the bare domain
+            ("*.kion.cloud", "api.kion.cloud", true),
+            ("*.kion.cloud", "a.b.kion.cloud", true),
+            ("*.kion.cloud", "kion.cloud", false),
+            ("*.kion.cloud", "evilkion.cloud", false),
+            // `prefix.*`: one or more whole labels after the prefix
+            ("kion.*", "kion.net", true),
+            ("kion.*", "kion.co.uk", true),
+            ("kion.*", "kion", false),
+            ("kion.*", "kionx.net", false),
+            // `prefix*suffix` within a single label
+            ("web*.example.com", "web01.example.com", true),
+            ("web*.example.com", "web.example.com", true),
+            ("web*.example.com", "web.eu.example.com", false),
+            ("api*com", "api.example.com", false),
+            // `a.*.b`: the middle wildcard is exactly one label
+            ("a.*.b", "a.x.b", true),
+            ("a.*.b", "a.x.y.b", false),
+            ("a.*.b", "a.b", false),
+            // trailing-octet IP globs
+            ("100.64.1.*", "100.64.1.20", true),
+            ("100.64.1.*", "100.64.10.5", false),
+            ("100.64.1.*", "100.64.2.20", false),
+            ("100.64.1.*", "100.64.1", false),
+        ];
+
+        for (pattern, host, expected) in cases {
+            assert_eq!(matches_pattern(pattern, host), expected, "{} vs {}", pattern, host);
+        }
+    }
+
+    #[test]
+    fn test_cidr_ipv4() {
+        assert!(matches_pattern("10.0.0.0/8", "10.20.30.40"));
+        assert!(matches_pattern("192.168.1.0/24", "192.168.1.255"));
+        assert!(matches_pattern("0.0.0.0/0", "8.8.8.8"));
+        assert!(matches_pattern("100.64.1.7/32", "100.64.1.7"));
+        assert!(!matches_pattern("10.0.0.0/8", "11.0.0.1"));
+        assert!(!matches_pattern("192.168.1.0/24", "192.168.2.1"));
+        // CIDR patterns never match hostnames
+        assert!(!matches_pattern("10.0.0.0/8", "internal.corp"));
+        // IPv4-mapped IPv6 addresses are treated as IPv4
+        assert!(matches_pattern("10.0.0.0/8", "::ffff:10.1.2.3"));
+    }
+
+    #[test]
+    fn test_cidr_ipv6() {
+        assert!(matches_pattern("fd00::/8", "fd12:3456::1"));
+        assert!(matches_pattern("2001:db8::/32", "2001:db8:ffff::1"));
+        assert!(!matches_pattern("2001:db8::/32", "2001:db9::1"));
+        assert!(!matches_pattern("fd00::/8", "10.0.0.1"));
+    }
+
+    #[test]
+    fn test_invalid_cidr_is_not_a_match() {
+        assert!(parse_cidr("10.0.0.0/33").is_none());
+        assert!(parse_cidr("not-an-ip/8").is_none());
+        assert!(!matches_pattern("10.0.0.0/33", "10.0.0.1"));
+    }
+
+    #[test]
+    fn test_matches_target_uses_connection_ip() {
+        let ip: IpAddr = "10.1.2.3".parse().unwrap();
+        assert!(matches_target("10.0.0.0/8", "db.internal.corp", ip));
+        assert!(!matches_target("172.16.0.0/12", "db.internal.corp", ip));
+        assert!(matches_target("*.internal.corp", "db.internal.corp", ip));
+    }
+
+    #[test]
+    fn test_multi_wildcard_domains() {
+        assert!(matches_pattern("*.internal.*.corp", "db.internal.eu.corp"));
+        assert!(matches_pattern("*.internal.*.corp", "api.internal.us.corp"));
+        assert!(matches_pattern("web*.*.example.com", "web01.eu.example.com"));
+        assert!(!matches_pattern("*.internal.*.corp", "db.external.eu.corp"));
+        assert!(!matches_pattern("*.internal.*.corp", "internal.eu.corp"));
+        assert!(!matches_pattern("*.internal.*.corp", "db.internal.eu.corp.evil.com"));
+        assert!(!matches_pattern("web*.*.example.com", "api01.eu.example.com"));
+    }
+
+    #[test]
+    fn test_compile_classifies_patterns() {
+        assert_eq!(CompiledPattern::compile("api.kion.cloud").unwrap(), CompiledPattern::Exact("api.kion.cloud".into()));
+        assert_eq!(CompiledPattern::compile("*.kion.cloud").unwrap(), CompiledPattern::Suffix("kion.cloud".into()));
+        assert_eq!(CompiledPattern::compile("100.64.1.*").unwrap(), CompiledPattern::Prefix("100.64.1".into()));
+        assert_eq!(
+            CompiledPattern::compile("10.0.0.0/8").unwrap(),
+            CompiledPattern::Cidr("10.0.0.0".parse().unwrap(), 8)
+        );
+        assert!(matches!(CompiledPattern::compile("*.internal.*.corp").unwrap(), CompiledPattern::Glob(..)));
+        assert!(matches!(CompiledPattern::compile("re:api-\\d+\\.corp").unwrap(), CompiledPattern::Regex(..)));
+    }
+
+    #[test]
+    fn test_regex_pattern_is_anchored() {
+        assert!(matches_pattern(r"re:api-\d+\.corp\.example", "api-12.corp.example"));
+        assert!(!matches_pattern(r"re:api-\d+\.corp\.example", "api-x.corp.example"));
+        // Anchoring: the whole hostname has to match, not just a substring
+        assert!(!matches_pattern(r"re:api-\d+\.corp\.example", "evil-api-12.corp.example.net"));
+        assert!(matches_pattern(r"re:(db|cache)\.internal|\w+\.lab", "cache.internal"));
+        assert!(!matches_pattern(r"re:(db|cache)\.internal|\w+\.lab", "db.internal.evil"));
+
+        let compiled = compile_rules(&[rule(1, r"re:api-\d+\.corp; 10.0.0.0/8", true)]);
+        assert_eq!(compiled[0].patterns.len(), 2);
+        assert!(compiled[0].matches("api-7.corp", None));
+        assert!(compiled[0].matches("10.1.2.3", None));
+    }
+
+    #[test]
+    fn test_malformed_regex_is_rejected() {
+        let error = validate_pattern("*.corp; re:api-(\\d+").unwrap_err();
+        assert!(error.contains("Invalid regex 'api-(\\d+'"), "{}", error);
+        assert!(validate_pattern(r"*.corp; re:api-\d+").is_ok());
+
+        // A malformed regex that reaches the matcher anyway is dropped, not matched literally
+        let compiled = compile_rules(&[rule(1, "re:api-(; *.corp", true)]);
+        assert_eq!(compiled[0].patterns, vec![CompiledPattern::Suffix("corp".into())]);
+        assert!(!compiled[0].matches("re:api-(", None));
+    }
+
+    #[test]
+    fn test_compile_rules_splits_and_skips_disabled() {
+        let rules = vec![
+            rule(1, "*.kion.cloud; 10.0.0.0/8 ;", true),
+            rule(2, "*.disabled.net", false),
+        ];
+        let compiled = compile_rules(&rules);
+
+        assert_eq!(compiled.len(), 1);
+        assert_eq!(compiled[0].rule_id, 1);
+        assert_eq!(compiled[0].patterns.len(), 2);
+        assert!(compiled[0].matches("api.kion.cloud", None));
+        assert!(compiled[0].matches("db.internal", Some("10.9.8.7".parse().unwrap())));
+        assert!(!compiled[0].matches("kion.net", None));
+    }
+
+    #[test]
+    fn test_compiled_rules_match_legacy_matcher() {
+        let rules = vec![
+            rule(1, "*.kion.cloud;*.kiongroup.net", true),
+            rule(2, "100.64.1.*", true),
+            rule(3, "kion.*", true),
+            rule(4, "10.0.0.0/8; fd00::/8", true),
+            rule(5, "*.internal.*.corp", true),
+            rule(6, "web*.example.com;api.example.com", true),
+            rule(7, "*.disabled.net", false),
+        ];
+        let hosts = [
+            "api.kion.cloud", "kion.cloud", "a.b.kiongroup.net", "kion.net", "100.64.1.20",
+            "100.64.2.20", "10.20.30.40", "11.0.0.1", "fd12::1", "2001:db8::1",
+            "db.internal.eu.corp", "db.external.eu.corp", "web01.example.com",
+            "api.example.com", "www.example.org", "x.disabled.net", "localhost", "kionx.net",
+            "100.64.10.5", "evilkion.cloud",
+        ];
+
+        let compiled = compile_rules(&rules);
+        let iterations = 2_000;
+
+        let legacy_start = Instant::now();
+        let mut legacy_results = Vec::new();
+        for _ in 0..iterations {
+            legacy_results.clear();
+            for host in hosts {
+                legacy_results.push(rules.iter().position(|r| legacy_rule_matches(r, host)).map(|i| rules[i].id));
+            }
+        }
+        let legacy_elapsed = legacy_start.elapsed();
+
+        let compiled_start = Instant::now();
+        let mut compiled_results = Vec::new();
+        for _ in 0..iterations {
+            compiled_results.clear();
+            for host in hosts {
+                compiled_results.push(compiled.iter().find(|r| r.matches(host, None)).map(|r| r.rule_id));
+            }
+        }
+        let compiled_elapsed = compiled_start.elapsed();
+
+        println!(
+            "{} lookups: legacy {:?}, compiled {:?}",
+            iterations * hosts.len(),
+            legacy_elapsed,
+            compiled_elapsed
+        );
+        assert_eq!(legacy_results, compiled_results);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use crate::{rule_matcher, ProxyConfig, ProxyManager, ProxyRule, RuleTarget};
+
+/// Format version written by `export_to_file`; bump it and migrate in `parse` when the
+/// layout changes
+pub const RULESET_VERSION: u32 = 1;
+
+/// The shareable part of a `ProxyManager`: its proxies and the rules routing to them
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Ruleset {
+    pub version: u32,
+    pub proxies: Vec<ProxyConfig>,
+    pub rules: Vec<ProxyRule>,
+}
+
+/// Just enough of a file to tell which format it is in
+#[derive(serde::Deserialize)]
+struct RulesetHeader {
+    version: u32,
+}
+
+impl Ruleset {
+    /// Snapshot the proxies and rules of `manager`, rules in evaluation order
+    pub fn from_manager(manager: &ProxyManager) -> Self {
+        let mut rules = manager.rules.clone();
+        rules.sort_by_key(|r| (r.priority, r.id));
+        Self {
+            version: RULESET_VERSION,
+            proxies: manager.proxies.clone(),
+            rules,
+        }
+    }
+
+    /// Read a ruleset, rejecting versions this build does not understand
+    pub fn parse(json: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let header: RulesetHeader = serde_json::from_str(json)
+            .map_err(|e| format!("Not a proxy ruleset file: {}", e))?;
+        match header.version {
+            RULESET_VERSION => Ok(serde_json::from_str(json)?),
+            version => Err(format!(
+                "Unsupported ruleset version {} (this build reads version {})",
+                version, RULESET_VERSION
+            )
+            .into()),
+        }
+    }
+
+    /// Every rule must have a valid pattern and route direct or to a proxy defined in the same file
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        for rule in &self.rules {
+            rule_matcher::validate_pattern(&rule.pattern)
+                .map_err(|e| format!("Rule '{}': {}", rule.name, e))?;
+            if let RuleTarget::Proxy(proxy_id) = rule.proxy_id {
+                if !self.proxies.iter().any(|p| p.id == proxy_id) {
+                    return Err(format!(
+                        "Rule '{}' routes to proxy {}, which is not defined in the file",
+                        rule.name, proxy_id
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write the proxies and rules of `manager` to `path`, returning how many of each were exported
+pub fn export_to_file(manager: &ProxyManager, path: &str) -> Result<(usize, usize), Box<dyn std::error::Error>> {
+    let ruleset = Ruleset::from_manager(manager);
+    std::fs::write(path, serde_json::to_string_pretty(&ruleset)?)?;
+    Ok((ruleset.proxies.len(), ruleset.rules.len()))
+}
+
+/// Add the proxies and rules in `path` to `manager`, returning how many of each were imported
+pub fn import_from_file(manager: &mut ProxyManager, path: &str) -> Result<(usize, usize), Box<dyn std::error::Error>> {
+    let ruleset = Ruleset::parse(&std::fs::read_to_string(path)?)?;
+    import(manager, ruleset)
+}
+
+/// Append `ruleset` to `manager`. Imported proxies and rules get fresh ids, rule targets are
+/// remapped to match, and the rules are evaluated after the existing ones in their file order.
+/// Nothing is added if any rule has an invalid pattern or refers to a proxy missing from the ruleset.
+pub fn import(manager: &mut ProxyManager, ruleset: Ruleset) -> Result<(usize, usize), Box<dyn std::error::Error>> {
+    ruleset.validate()?;
+
+    let mut proxy_ids = HashMap::new();
+    for mut proxy in ruleset.proxies {
+        let id = manager.next_proxy_id;
+        manager.next_proxy_id += 1;
+        proxy_ids.insert(proxy.id, id);
+        proxy.id = id;
+        manager.proxies.push(proxy);
+    }
+
+    let mut rules = ruleset.rules;
+    rules.sort_by_key(|r| (r.priority, r.id));
+    let first_priority = manager.rules.iter().map(|r| r.priority).max().map_or(0, |p| p + 1);
+    let rule_count = rules.len();
+    for (offset, mut rule) in rules.into_iter().enumerate() {
+        rule.id = manager.next_rule_id;
+        manager.next_rule_id += 1;
+        rule.priority = first_priority + offset as i32;
+        if let RuleTarget::Proxy(old_id) = rule.proxy_id {
+            rule.proxy_id = RuleTarget::Proxy(proxy_ids[&old_id]);
+        }
+        manager.rules.push(rule);
+    }
+    manager.recompile();
+
+    Ok((proxy_ids.len(), rule_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProxyType;
+
+    fn sample_manager() -> ProxyManager {
+        let mut manager = ProxyManager::default();
+        let corp = manager
+            .add_proxy_with_auth(
+                "Corp".to_string(),
+                "10.0.0.1".to_string(),
+                1080,
+                ProxyType::Socks5,
+                Some("alice".to_string()),
+                Some("secret".to_string()),
+            )
+            .unwrap();
+        let lab = manager.add_proxy("Lab".to_string(), "10.0.0.2".to_string(), 3128, ProxyType::Http);
+        manager.add_rule("Corp".to_string(), "*.corp.example".to_string(), corp).unwrap();
+        manager.add_rule_with_target("Bypass".to_string(), "git.corp.example".to_string(), RuleTarget::Direct).unwrap();
+        manager.add_rule("Lab".to_string(), "10.20.0.0/16".to_string(), lab).unwrap();
+        manager
+    }
+
+    #[test]
+    fn test_round_trip_into_manager_with_existing_ids() {
+        let source = sample_manager();
+        let path = std::env::temp_dir().join(format!("ruleset-round-trip-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(export_to_file(&source, path).unwrap(), (2, 3));
+
+        // The target already uses the ids the file was written with
+        let mut target = ProxyManager::default();
+        let existing = target.add_proxy("Home".to_string(), "192.168.1.1".to_string(), 1080, ProxyType::Socks5);
+        target.add_rule("Home".to_string(), "*.home".to_string(), existing).unwrap();
+
+        assert_eq!(import_from_file(&mut target, path).unwrap(), (2, 3));
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(target.proxies.len(), 3);
+        let ids: std::collections::HashSet<u32> = target.proxies.iter().map(|p| p.id).collect();
+        assert_eq!(ids.len(), 3);
+
+        // Rules keep their order after the existing one and still point at the right proxies
+        let imported: Vec<(&str, String)> = target.rules[1..]
+            .iter()
+            .map(|rule| {
+                let target_name = match rule.proxy_id {
+                    RuleTarget::Proxy(id) => target.proxies.iter().find(|p| p.id == id).unwrap().name.clone(),
+                    RuleTarget::Direct => "direct".to_string(),
+                };
+                (rule.name.as_str(), target_name)
+            })
+            .collect();
+        assert_eq!(
+            imported,
+            vec![("Corp", "Corp".to_string()), ("Bypass", "direct".to_string()), ("Lab", "Lab".to_string())]
+        );
+        assert_eq!(target.rules[0].name, "Home");
+
+        let corp = target.proxies.iter().find(|p| p.name == "Corp").unwrap();
+        assert_eq!(corp.username.as_deref(), Some("alice"));
+        assert_eq!(corp.proxy_type, ProxyType::Socks5);
+
+        target.global_enabled = true;
+        assert_eq!(target.get_proxy_for_host("svn.corp.example").unwrap().name, "Corp");
+        assert_eq!(target.get_proxy_for_host("10.20.3.4").unwrap().name, "Lab");
+    }
+
+    #[test]
+    fn test_dangling_proxy_reference_is_rejected() {
+        let mut ruleset = Ruleset::from_manager(&sample_manager());
+        ruleset.proxies.retain(|p| p.name != "Lab");
+
+        let mut target = ProxyManager::default();
+        let error = import(&mut target, ruleset).unwrap_err().to_string();
+        assert!(error.contains("Rule 'Lab' routes to proxy"), "{}", error);
+        assert!(target.proxies.is_empty());
+        assert!(target.rules.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_version_is_rejected() {
+        let error = Ruleset::parse(r#"{"version": 2, "proxies": [], "rules": []}"#).unwrap_err();
+        assert!(error.to_string().contains("Unsupported ruleset version 2"));
+        assert!(Ruleset::parse(r#"{"proxies": []}"#).is_err());
+        assert!(Ruleset::parse(r#"{"version": 1, "proxies": [], "rules": []}"#).is_ok());
+    }
+}
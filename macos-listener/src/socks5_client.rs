@@ -1,16 +1,25 @@
-use std::net::{IpAddr, SocketAddr};
 use std::io::{Read, Write};
+use std::net::SocketAddr;
 use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use crate::socks5_connector::{self, Socks5Connector, DEFAULT_PROXY_TIMEOUT};
 use crate::{ProxyConfig, ProxyType};
 
 #[derive(Debug)]
 pub struct Socks5Client {
     proxy_config: ProxyConfig,
+    timeout: Duration,
 }
 
 impl Socks5Client {
     pub fn new(proxy_config: ProxyConfig) -> Self {
-        Self { proxy_config }
+        Self { proxy_config, timeout: DEFAULT_PROXY_TIMEOUT }
+    }
+    
+    /// Limit for reaching the proxy and for each read/write while negotiating with it
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
     }
     
     pub fn connect(&self, target_addr: SocketAddr) -> Result<TcpStream, Box<dyn std::error::Error>> {
@@ -21,123 +30,64 @@ impl Socks5Client {
         }
     }
     
+    /// Connect to `target_addr` through the proxy and report how long it took
+    pub fn test_connection(&self, target_addr: SocketAddr) -> Result<Duration, String> {
+        let started = Instant::now();
+        self.connect(target_addr).map_err(|e| e.to_string())?;
+        Ok(started.elapsed())
+    }
+    
     fn connect_socks5(&self, target_addr: SocketAddr) -> Result<TcpStream, Box<dyn std::error::Error>> {
-        let proxy_addr = SocketAddr::new(
-            self.proxy_config.host.parse::<IpAddr>()?,
-            self.proxy_config.port
-        );
-        
-        let mut stream = TcpStream::connect(proxy_addr)?;
-        
-        // SOCKS5 handshake
-        // Step 1: Send authentication methods
-        let auth_methods = if self.proxy_config.username.is_some() {
-            vec![0x02, 0x00] // Username/password and no auth
-        } else {
-            vec![0x00] // No authentication
-        };
-        
-        let mut request = vec![0x05, auth_methods.len() as u8];
-        request.extend_from_slice(&auth_methods);
-        stream.write_all(&request)?;
-        
-        // Step 2: Receive server's choice
-        let mut response = [0u8; 2];
-        stream.read_exact(&mut response)?;
-        
-        if response[0] != 0x05 {
-            return Err("Invalid SOCKS5 version".into());
-        }
-        
-        // Handle authentication if required
-        if response[1] == 0x02 && self.proxy_config.username.is_some() {
-            self.authenticate_socks5(&mut stream)?;
-        } else if response[1] != 0x00 {
-            return Err("Authentication failed".into());
-        }
-        
-        // Step 3: Send connection request
-        let mut connect_request = vec![0x05, 0x01, 0x00]; // VER, CMD, RSV
-        
-        match target_addr.ip() {
-            IpAddr::V4(ip) => {
-                connect_request.push(0x01); // ATYP: IPv4
-                connect_request.extend_from_slice(&ip.octets());
-            }
-            IpAddr::V6(ip) => {
-                connect_request.push(0x04); // ATYP: IPv6
-                connect_request.extend_from_slice(&ip.octets());
-            }
-        }
-        
-        connect_request.extend_from_slice(&target_addr.port().to_be_bytes());
-        stream.write_all(&connect_request)?;
-        
-        // Step 4: Receive connection response
-        let mut response = vec![0u8; 4];
-        stream.read_exact(&mut response)?;
-        
-        if response[0] != 0x05 || response[1] != 0x00 {
-            return Err("SOCKS5 connection failed".into());
-        }
-        
-        // Skip the rest of the response (bound address)
-        let atyp = response[3];
-        let addr_len = match atyp {
-            0x01 => 4,  // IPv4
-            0x04 => 16, // IPv6
-            0x03 => {   // Domain name
-                let mut len_buf = [0u8; 1];
-                stream.read_exact(&mut len_buf)?;
-                len_buf[0] as usize
-            }
-            _ => return Err("Invalid address type".into()),
-        };
-        
-        let mut addr_buf = vec![0u8; addr_len];
-        stream.read_exact(&mut addr_buf)?;
+        let mut stream = self.connect_to_proxy()?;
         
-        let mut port_buf = [0u8; 2];
-        stream.read_exact(&mut port_buf)?;
+        let connector = Socks5Connector::from_proxy_config(&self.proxy_config);
+        connector.handshake(&mut stream)?;
+        connector.connect(&mut stream, target_addr)?;
         
         Ok(stream)
     }
     
-    fn authenticate_socks5(&self, stream: &mut TcpStream) -> Result<(), Box<dyn std::error::Error>> {
-        let username = self.proxy_config.username.as_ref().unwrap();
-        let password = self.proxy_config.password.as_ref().unwrap();
-        
-        let mut auth_request = vec![0x01, username.len() as u8];
-        auth_request.extend_from_slice(username.as_bytes());
-        auth_request.push(password.len() as u8);
-        auth_request.extend_from_slice(password.as_bytes());
-        
-        stream.write_all(&auth_request)?;
-        
-        let mut response = [0u8; 2];
-        stream.read_exact(&mut response)?;
-        
-        if response[0] != 0x01 || response[1] != 0x00 {
-            return Err("SOCKS5 authentication failed".into());
+    /// HTTP proxy via the CONNECT method
+    fn connect_http(&self, target_addr: SocketAddr) -> Result<TcpStream, Box<dyn std::error::Error>> {
+        let mut stream = self.connect_to_proxy()?;
+        
+        let request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", target_addr);
+        stream.write_all(request.as_bytes())?;
+        
+        // Read the response head byte by byte so no tunnelled data is consumed
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() > 8192 {
+                return Err("HTTP proxy response header too large".into());
+            }
+            stream.read_exact(&mut byte)?;
+            head.push(byte[0]);
         }
         
-        Ok(())
-    }
-    
-    fn connect_http(&self, _target_addr: SocketAddr) -> Result<TcpStream, Box<dyn std::error::Error>> {
-        // HTTP proxy implementation would go here
-        Err("HTTP proxy not implemented yet".into())
+        let head = String::from_utf8_lossy(&head);
+        let status_line = head.lines().next().unwrap_or_default();
+        match status_line.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(stream),
+            _ => Err(format!("HTTP proxy refused CONNECT: {}", status_line).into()),
+        }
     }
     
     fn connect_socks4(&self, _target_addr: SocketAddr) -> Result<TcpStream, Box<dyn std::error::Error>> {
         // SOCKS4 proxy implementation would go here
         Err("SOCKS4 proxy not implemented yet".into())
     }
+    
+    fn connect_to_proxy(&self) -> Result<TcpStream, Box<dyn std::error::Error>> {
+        socks5_connector::connect_to_proxy(&self.proxy_config, self.timeout)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::net::TcpListener;
+    use std::thread;
     
     #[test]
     fn test_socks5_client_creation() {
@@ -150,10 +100,92 @@ mod tests {
             username: None,
             password: None,
             enabled: true,
+            last_test_result: None,
         };
         
         let client = Socks5Client::new(proxy_config);
         assert_eq!(client.proxy_config.host, "127.0.0.1");
         assert_eq!(client.proxy_config.port, 1080);
     }
+    
+    fn local_proxy(proxy_type: ProxyType) -> (ProxyConfig, TcpListener) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_config = ProxyConfig {
+            id: 1,
+            name: "Local".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            proxy_type,
+            username: None,
+            password: None,
+            enabled: true,
+            last_test_result: None,
+        };
+        (proxy_config, listener)
+    }
+    
+    #[test]
+    fn test_connection_through_socks5() {
+        let (proxy_config, listener) = local_proxy(ProxyType::Socks5);
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+        
+        let client = Socks5Client::new(proxy_config);
+        assert!(client.test_connection("1.1.1.1:80".parse().unwrap()).is_ok());
+        server.join().unwrap();
+    }
+    
+    #[test]
+    fn test_connection_through_http_proxy() {
+        let (proxy_config, listener) = local_proxy(ProxyType::Http);
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut byte = [0u8; 1];
+            while !request.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        
+        let client = Socks5Client::new(proxy_config);
+        assert!(client.test_connection("1.1.1.1:80".parse().unwrap()).is_ok());
+        assert!(server.join().unwrap().starts_with("CONNECT 1.1.1.1:80 HTTP/1.1\r\n"));
+    }
+    
+    #[test]
+    fn test_connection_reports_refused_http_connect() {
+        let (proxy_config, listener) = local_proxy(ProxyType::Http);
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 256];
+            let _ = stream.read(&mut buf);
+            let _ = stream.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n");
+        });
+        
+        let client = Socks5Client::new(proxy_config);
+        let error = client.test_connection("1.1.1.1:80".parse().unwrap()).unwrap_err();
+        assert!(error.contains("403"));
+    }
+    
+    #[test]
+    fn test_connection_times_out_on_silent_proxy() {
+        // The listener accepts the TCP connection but never answers the greeting
+        let (proxy_config, _listener) = local_proxy(ProxyType::Socks5);
+        
+        let client = Socks5Client::new(proxy_config).with_timeout(Duration::from_millis(200));
+        let started = Instant::now();
+        let error = client.test_connection("1.1.1.1:80".parse().unwrap()).unwrap_err();
+        assert_eq!(error, "SOCKS5 proxy timed out");
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
 }
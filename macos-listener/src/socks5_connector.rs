@@ -0,0 +1,491 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+use crate::ProxyConfig;
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_VERSION: u8 = 0x01;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NO_ACCEPTABLE: u8 = 0xFF;
+
+const CMD_CONNECT: u8 = 0x01;
+const CMD_UDP_ASSOCIATE: u8 = 0x03;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// How long to wait for the relay to return a datagram
+const UDP_RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default limit for reaching a proxy and for each read/write while negotiating with it
+pub const DEFAULT_PROXY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Open a TCP connection to the proxy itself, resolving its host if needed. Connecting and
+/// every later read or write on the stream give up after `timeout`.
+pub fn connect_to_proxy(proxy_config: &ProxyConfig, timeout: Duration) -> Result<TcpStream, Box<dyn std::error::Error>> {
+    let proxy_addr = (proxy_config.host.as_str(), proxy_config.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("Could not resolve proxy host {}", proxy_config.host))?;
+
+    let stream = TcpStream::connect_timeout(&proxy_addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(stream)
+}
+
+/// Report a read or write that ran into the socket timeout as a silent proxy
+fn proxy_io_error(error: std::io::Error) -> Box<dyn std::error::Error> {
+    match error.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => "SOCKS5 proxy timed out".into(),
+        _ => error.into(),
+    }
+}
+
+/// Client side of the SOCKS5 protocol (RFC 1928) with username/password auth (RFC 1929)
+#[derive(Debug, Clone, Default)]
+pub struct Socks5Connector {
+    credentials: Option<(String, String)>,
+}
+
+impl Socks5Connector {
+    pub fn new(username: Option<String>, password: Option<String>) -> Self {
+        let credentials = match (username, password) {
+            (Some(username), password) => Some((username, password.unwrap_or_default())),
+            (None, _) => None,
+        };
+        Self { credentials }
+    }
+
+    pub fn from_proxy_config(proxy_config: &ProxyConfig) -> Self {
+        Self::new(proxy_config.username.clone(), proxy_config.password.clone())
+    }
+
+    /// Negotiate an authentication method, authenticating if the server asks for it
+    pub fn handshake(&self, stream: &mut TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+        let methods: &[u8] = if self.credentials.is_some() {
+            &[METHOD_USERNAME_PASSWORD, METHOD_NO_AUTH]
+        } else {
+            &[METHOD_NO_AUTH]
+        };
+
+        let mut request = vec![SOCKS_VERSION, methods.len() as u8];
+        request.extend_from_slice(methods);
+        stream.write_all(&request).map_err(proxy_io_error)?;
+
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).map_err(proxy_io_error)?;
+
+        if response[0] != SOCKS_VERSION {
+            return Err("Invalid SOCKS5 version".into());
+        }
+
+        match response[1] {
+            METHOD_NO_AUTH => Ok(()),
+            METHOD_USERNAME_PASSWORD if self.credentials.is_some() => self.authenticate(stream),
+            METHOD_NO_ACCEPTABLE => Err("SOCKS5 proxy accepted none of the offered authentication methods".into()),
+            method => Err(format!("SOCKS5 proxy selected unsupported authentication method {:#04x}", method).into()),
+        }
+    }
+
+    /// Username/password sub-negotiation, run after the server selects method 0x02
+    pub fn authenticate(&self, stream: &mut TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+        let (username, password) = self
+            .credentials
+            .as_ref()
+            .ok_or("SOCKS5 proxy requires credentials but none are configured")?;
+
+        if username.len() > u8::MAX as usize || password.len() > u8::MAX as usize {
+            return Err("SOCKS5 username and password must be at most 255 bytes".into());
+        }
+
+        let mut auth_request = vec![AUTH_VERSION, username.len() as u8];
+        auth_request.extend_from_slice(username.as_bytes());
+        auth_request.push(password.len() as u8);
+        auth_request.extend_from_slice(password.as_bytes());
+        stream.write_all(&auth_request).map_err(proxy_io_error)?;
+
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).map_err(proxy_io_error)?;
+
+        if response[0] != AUTH_VERSION || response[1] != 0x00 {
+            return Err("SOCKS5 authentication failed".into());
+        }
+
+        Ok(())
+    }
+
+    /// Issue a CONNECT for `target` and consume the server's reply
+    pub fn connect(&self, stream: &mut TcpStream, target: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+        Self::send_request(stream, CMD_CONNECT, target)?;
+        Self::read_reply(stream)?;
+        Ok(())
+    }
+
+    /// Issue a UDP ASSOCIATE and return the relay address datagrams must be sent to.
+    /// The association lasts as long as `stream` stays open.
+    pub fn udp_associate(&self, stream: &mut TcpStream) -> Result<SocketAddr, Box<dyn std::error::Error>> {
+        // Our UDP source address isn't known yet, so ask for any
+        let unspecified = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+        Self::send_request(stream, CMD_UDP_ASSOCIATE, unspecified)?;
+
+        let relay = Self::read_reply(stream)?.ok_or("SOCKS5 UDP relay address is a domain name")?;
+
+        // Servers commonly answer 0.0.0.0 meaning "the address you connected to"
+        if relay.ip().is_unspecified() {
+            Ok(SocketAddr::new(stream.peer_addr()?.ip(), relay.port()))
+        } else {
+            Ok(relay)
+        }
+    }
+
+    /// VER, CMD, RSV, then the address
+    fn send_request(stream: &mut TcpStream, command: u8, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+        let mut request = vec![SOCKS_VERSION, command, 0x00];
+        encode_addr(&mut request, addr);
+        stream.write_all(&request).map_err(proxy_io_error)?;
+        Ok(())
+    }
+
+    /// Read a reply and return the bound address, or `None` if the server sent a domain name
+    fn read_reply(stream: &mut TcpStream) -> Result<Option<SocketAddr>, Box<dyn std::error::Error>> {
+        // VER, REP, RSV, ATYP
+        let mut response = [0u8; 4];
+        stream.read_exact(&mut response).map_err(proxy_io_error)?;
+
+        if response[0] != SOCKS_VERSION {
+            return Err("Invalid SOCKS5 version".into());
+        }
+        if response[1] != 0x00 {
+            return Err(format!("SOCKS5 connection failed (reply code {:#04x})", response[1]).into());
+        }
+
+        let addr_len = match response[3] {
+            ATYP_IPV4 => 4,
+            ATYP_IPV6 => 16,
+            ATYP_DOMAIN => {
+                let mut len_buf = [0u8; 1];
+                stream.read_exact(&mut len_buf).map_err(proxy_io_error)?;
+                len_buf[0] as usize
+            }
+            _ => return Err("Invalid address type".into()),
+        };
+
+        let mut bound = vec![0u8; addr_len + 2];
+        stream.read_exact(&mut bound).map_err(proxy_io_error)?;
+
+        let port = u16::from_be_bytes([bound[addr_len], bound[addr_len + 1]]);
+        let ip = match response[3] {
+            ATYP_IPV4 => IpAddr::V4(Ipv4Addr::new(bound[0], bound[1], bound[2], bound[3])),
+            ATYP_IPV6 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&bound[..16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(SocketAddr::new(ip, port)))
+    }
+}
+
+/// A UDP ASSOCIATE session; dropping it closes the control connection and ends the association
+pub struct Socks5UdpAssociation {
+    _control: TcpStream,
+    socket: UdpSocket,
+    relay: SocketAddr,
+}
+
+impl Socks5UdpAssociation {
+    /// Request an association over `control`, on which the handshake must already be done
+    pub fn establish(connector: &Socks5Connector, mut control: TcpStream) -> Result<Self, Box<dyn std::error::Error>> {
+        let relay = connector.udp_associate(&mut control)?;
+
+        let bind_addr = if relay.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind_addr)?;
+        // Only accept datagrams coming back from the relay
+        socket.connect(relay)?;
+        socket.set_read_timeout(Some(UDP_RESPONSE_TIMEOUT))?;
+
+        Ok(Self { _control: control, socket, relay })
+    }
+
+    pub fn relay_addr(&self) -> SocketAddr {
+        self.relay
+    }
+
+    /// Send `payload` to `target` through the relay
+    pub fn send_to(&self, target: SocketAddr, payload: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        self.socket.send(&encode_udp_datagram(target, payload))?;
+        Ok(())
+    }
+
+    /// Receive the next datagram from the relay, returning its source and payload
+    pub fn recv_from(&self) -> Result<(SocketAddr, Vec<u8>), Box<dyn std::error::Error>> {
+        let mut buf = vec![0u8; 65535];
+        let size = self.socket.recv(&mut buf)?;
+        let (source, payload) = decode_udp_datagram(&buf[..size])?;
+        Ok((source, payload.to_vec()))
+    }
+
+    /// Send one datagram and wait for the reply
+    pub fn exchange(&self, target: SocketAddr, payload: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        self.send_to(target, payload)?;
+        let (_, response) = self.recv_from()?;
+        Ok(response)
+    }
+}
+
+/// ATYP followed by the address and port in network order
+fn encode_addr(buf: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+/// Prefix `payload` with the SOCKS5 UDP request header (RSV, FRAG=0, ATYP, DST.ADDR, DST.PORT)
+pub fn encode_udp_datagram(target: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = vec![0x00, 0x00, 0x00];
+    encode_addr(&mut datagram, target);
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+/// Split a relayed datagram into its source address and payload
+pub fn decode_udp_datagram(datagram: &[u8]) -> Result<(SocketAddr, &[u8]), Box<dyn std::error::Error>> {
+    if datagram.len() < 4 {
+        return Err("SOCKS5 UDP datagram too short".into());
+    }
+    if datagram[2] != 0x00 {
+        return Err("Fragmented SOCKS5 UDP datagrams are not supported".into());
+    }
+
+    let (ip, header_len) = match datagram[3] {
+        ATYP_IPV4 if datagram.len() >= 10 => {
+            (IpAddr::V4(Ipv4Addr::new(datagram[4], datagram[5], datagram[6], datagram[7])), 8)
+        }
+        ATYP_IPV6 if datagram.len() >= 22 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&datagram[4..20]);
+            (IpAddr::V6(Ipv6Addr::from(octets)), 20)
+        }
+        ATYP_IPV4 | ATYP_IPV6 => return Err("SOCKS5 UDP datagram too short".into()),
+        _ => return Err("Unsupported address type in SOCKS5 UDP datagram".into()),
+    };
+
+    let port = u16::from_be_bytes([datagram[header_len], datagram[header_len + 1]]);
+    Ok((SocketAddr::new(ip, port), &datagram[header_len + 2..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Runs `server` against the accepted side of a loopback connection
+    fn with_server<F>(server: F) -> (TcpStream, thread::JoinHandle<Vec<u8>>)
+    where
+        F: FnOnce(&mut TcpStream) -> Vec<u8> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            server(&mut stream)
+        });
+        (client, handle)
+    }
+
+    fn read_n(stream: &mut TcpStream, n: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; n];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_handshake_without_credentials() {
+        let (mut client, server) = with_server(|stream| {
+            let greeting = read_n(stream, 3);
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            greeting
+        });
+
+        Socks5Connector::default().handshake(&mut client).unwrap();
+        assert_eq!(server.join().unwrap(), vec![0x05, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn test_handshake_advertises_both_methods_with_credentials() {
+        let (mut client, server) = with_server(|stream| {
+            let mut received = read_n(stream, 4);
+            stream.write_all(&[0x05, 0x02]).unwrap();
+            received.extend(read_n(stream, 1 + 1 + 5 + 1 + 6));
+            stream.write_all(&[0x01, 0x00]).unwrap();
+            received
+        });
+
+        let connector = Socks5Connector::new(Some("alice".to_string()), Some("secret".to_string()));
+        connector.handshake(&mut client).unwrap();
+
+        let received = server.join().unwrap();
+        // nmethods must match the number of listed methods
+        assert_eq!(&received[..4], &[0x05, 0x02, 0x02, 0x00]);
+        assert_eq!(&received[4..], b"\x01\x05alice\x06secret");
+    }
+
+    #[test]
+    fn test_handshake_rejected_credentials() {
+        let (mut client, _server) = with_server(|stream| {
+            read_n(stream, 4);
+            stream.write_all(&[0x05, 0x02]).unwrap();
+            read_n(stream, 1 + 1 + 1 + 1 + 1);
+            stream.write_all(&[0x01, 0x01]).unwrap();
+            Vec::new()
+        });
+
+        let connector = Socks5Connector::new(Some("a".to_string()), Some("b".to_string()));
+        let error = connector.handshake(&mut client).unwrap_err();
+        assert!(error.to_string().contains("authentication failed"));
+    }
+
+    #[test]
+    fn test_handshake_fails_when_auth_required_without_credentials() {
+        let (mut client, _server) = with_server(|stream| {
+            read_n(stream, 3);
+            stream.write_all(&[0x05, 0xFF]).unwrap();
+            Vec::new()
+        });
+
+        assert!(Socks5Connector::default().handshake(&mut client).is_err());
+    }
+
+    #[test]
+    fn test_connect_ipv4_skips_domain_bound_address() {
+        let (mut client, server) = with_server(|stream| {
+            let request = read_n(stream, 10);
+            stream.write_all(&[0x05, 0x00, 0x00, 0x03, 4]).unwrap();
+            stream.write_all(b"host").unwrap();
+            stream.write_all(&[0x1F, 0x90]).unwrap();
+            stream.write_all(b"payload").unwrap();
+            request
+        });
+
+        let target: SocketAddr = "93.184.216.34:443".parse().unwrap();
+        Socks5Connector::default().connect(&mut client, target).unwrap();
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![0x05, 0x01, 0x00, 0x01, 93, 184, 216, 34, 0x01, 0xBB]
+        );
+        // The reply has been fully consumed, so application data follows directly
+        assert_eq!(read_n(&mut client, 7), b"payload");
+    }
+
+    #[test]
+    fn test_connect_ipv6_request() {
+        let (mut client, server) = with_server(|stream| {
+            let request = read_n(stream, 22);
+            stream.write_all(&[0x05, 0x00, 0x00, 0x04]).unwrap();
+            stream.write_all(&[0u8; 18]).unwrap();
+            request
+        });
+
+        let target: SocketAddr = "[2001:db8::1]:8080".parse().unwrap();
+        Socks5Connector::default().connect(&mut client, target).unwrap();
+
+        let request = server.join().unwrap();
+        assert_eq!(&request[..4], &[0x05, 0x01, 0x00, 0x04]);
+        assert_eq!(&request[4..20], &"2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        assert_eq!(&request[20..], &8080u16.to_be_bytes());
+    }
+
+    #[test]
+    fn test_udp_datagram_round_trip() {
+        let target: SocketAddr = "8.8.8.8:53".parse().unwrap();
+        let datagram = encode_udp_datagram(target, b"query");
+        assert_eq!(&datagram[..10], &[0, 0, 0, 0x01, 8, 8, 8, 8, 0, 53]);
+
+        let (source, payload) = decode_udp_datagram(&datagram).unwrap();
+        assert_eq!(source, target);
+        assert_eq!(payload, b"query");
+
+        let v6: SocketAddr = "[2001:db8::53]:53".parse().unwrap();
+        let datagram = encode_udp_datagram(v6, b"x");
+        let (source, payload) = decode_udp_datagram(&datagram).unwrap();
+        assert_eq!((source, payload), (v6, &b"x"[..]));
+
+        // Fragments and truncated headers are rejected
+        let mut fragment = encode_udp_datagram(target, b"query");
+        fragment[2] = 1;
+        assert!(decode_udp_datagram(&fragment).is_err());
+        assert!(decode_udp_datagram(&[0, 0, 0, 0x01, 8, 8]).is_err());
+    }
+
+    #[test]
+    fn test_udp_associate_relays_through_mock_server() {
+        let relay = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let relay_port = relay.local_addr().unwrap().port();
+        let target: SocketAddr = "9.9.9.9:53".parse().unwrap();
+
+        // Control connection: answer the greeting and the ASSOCIATE with 0.0.0.0:<relay port>
+        let (client, control) = with_server(move |stream| {
+            read_n(stream, 3);
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            let request = read_n(stream, 10);
+            let mut reply = vec![0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0];
+            reply.extend_from_slice(&relay_port.to_be_bytes());
+            stream.write_all(&reply).unwrap();
+
+            // Hold the association open until the client closes it
+            let mut buf = [0u8; 1];
+            let _ = stream.read(&mut buf);
+            request
+        });
+
+        // Relay: unwrap the request and answer with the target as source
+        let relay_thread = thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+            let (size, from) = relay.recv_from(&mut buf).unwrap();
+            let (destination, payload) = decode_udp_datagram(&buf[..size]).unwrap();
+            assert_eq!(destination, target);
+            assert_eq!(payload, b"ping");
+            relay.send_to(&encode_udp_datagram(target, b"pong"), from).unwrap();
+        });
+
+        let mut client = client;
+        let connector = Socks5Connector::default();
+        connector.handshake(&mut client).unwrap();
+        let association = Socks5UdpAssociation::establish(&connector, client).unwrap();
+        assert_eq!(association.relay_addr(), SocketAddr::from(([127, 0, 0, 1], relay_port)));
+
+        assert_eq!(association.exchange(target, b"ping").unwrap(), b"pong");
+        relay_thread.join().unwrap();
+
+        drop(association);
+        assert_eq!(control.join().unwrap(), vec![0x05, 0x03, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_connect_failure_reply() {
+        let (mut client, _server) = with_server(|stream| {
+            read_n(stream, 10);
+            stream.write_all(&[0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+            Vec::new()
+        });
+
+        let target: SocketAddr = "10.0.0.1:80".parse().unwrap();
+        let error = Socks5Connector::default().connect(&mut client, target).unwrap_err();
+        assert!(error.to_string().contains("connection failed"));
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// Samples kept for the header graph
+pub const THROUGHPUT_HISTORY_LEN: usize = 60;
+
+/// Aggregate rates over the interval since the previous sample
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ThroughputSample {
+    pub sent_per_sec: f64,
+    pub received_per_sec: f64,
+}
+
+/// Rolling bytes/sec history, derived from the total byte counts of each stats update
+#[derive(Debug)]
+pub struct ThroughputHistory {
+    samples: VecDeque<ThroughputSample>,
+    capacity: usize,
+    last_totals: Option<(u64, u64, Instant)>,
+}
+
+impl ThroughputHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            last_totals: None,
+        }
+    }
+
+    /// Add a sample from the current totals. The first call only sets the baseline, and a
+    /// total that drops (its connections closed) counts as no traffic rather than negative.
+    pub fn record(&mut self, bytes_sent: u64, bytes_received: u64, now: Instant) {
+        if let Some((last_sent, last_received, last_at)) = self.last_totals {
+            let elapsed = now.duration_since(last_at).as_secs_f64();
+            if elapsed <= 0.0 {
+                return;
+            }
+            if self.samples.len() == self.capacity {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(ThroughputSample {
+                sent_per_sec: bytes_sent.saturating_sub(last_sent) as f64 / elapsed,
+                received_per_sec: bytes_received.saturating_sub(last_received) as f64 / elapsed,
+            });
+        }
+        self.last_totals = Some((bytes_sent, bytes_received, now));
+    }
+
+    pub fn samples(&self) -> impl Iterator<Item = &ThroughputSample> {
+        self.samples.iter()
+    }
+
+    pub fn latest(&self) -> ThroughputSample {
+        self.samples.back().copied().unwrap_or_default()
+    }
+
+    /// Highest rate in either direction, used to scale the graph
+    pub fn peak(&self) -> f64 {
+        self.samples
+            .iter()
+            .map(|s| s.sent_per_sec.max(s.received_per_sec))
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Draw the sent and received rates as two lines in a small framed box
+pub fn plot(ui: &mut egui::Ui, history: &ThroughputHistory, size: egui::Vec2) {
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_stroke(
+        rect,
+        2.0,
+        ui.visuals().widgets.noninteractive.bg_stroke,
+        egui::StrokeKind::Inside,
+    );
+
+    // With no traffic every point sits on the baseline
+    let peak = history.peak();
+    let scale = if peak > 0.0 { 1.0 / peak } else { 0.0 };
+    let step = rect.width() / (THROUGHPUT_HISTORY_LEN.max(2) - 1) as f32;
+    // Newest sample on the right edge
+    let count = history.samples().count();
+    let line = |rate: fn(&ThroughputSample) -> f64| -> Vec<egui::Pos2> {
+        history
+            .samples()
+            .enumerate()
+            .map(|(i, sample)| {
+                let height = (rate(sample) * scale) as f32 * (rect.height() - 4.0);
+                egui::pos2(rect.right() - (count - 1 - i) as f32 * step, rect.bottom() - 2.0 - height)
+            })
+            .collect()
+    };
+
+    painter.add(egui::Shape::line(line(|s| s.sent_per_sec), egui::Stroke::new(1.5, egui::Color32::LIGHT_BLUE)));
+    painter.add(egui::Shape::line(line(|s| s.received_per_sec), egui::Stroke::new(1.5, egui::Color32::LIGHT_GREEN)));
+}
+
+/// Human-readable rate, e.g. `1.5 KB/s`
+pub fn format_rate(bytes_per_sec: f64) -> String {
+    const UNITS: [&str; 4] = ["B/s", "KB/s", "MB/s", "GB/s"];
+    let mut value = bytes_per_sec;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rates_from_totals() {
+        let start = Instant::now();
+        let mut history = ThroughputHistory::new(THROUGHPUT_HISTORY_LEN);
+
+        history.record(1_000, 5_000, start);
+        assert_eq!(history.samples().count(), 0);
+
+        history.record(3_000, 9_000, start + Duration::from_secs(2));
+        assert_eq!(
+            history.latest(),
+            ThroughputSample { sent_per_sec: 1_000.0, received_per_sec: 2_000.0 }
+        );
+
+        // Closed connections take their bytes out of the totals
+        history.record(500, 9_000, start + Duration::from_secs(3));
+        assert_eq!(history.latest(), ThroughputSample::default());
+        assert_eq!(history.peak(), 2_000.0);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let start = Instant::now();
+        let mut history = ThroughputHistory::new(3);
+        for i in 0..10u64 {
+            history.record(i * 100, 0, start + Duration::from_secs(i));
+        }
+        let sent: Vec<f64> = history.samples().map(|s| s.sent_per_sec).collect();
+        assert_eq!(sent, vec![100.0, 100.0, 100.0]);
+
+        // All-zero traffic stays flat
+        let mut idle = ThroughputHistory::new(3);
+        idle.record(0, 0, start);
+        idle.record(0, 0, start + Duration::from_secs(1));
+        assert_eq!(idle.peak(), 0.0);
+    }
+
+    #[test]
+    fn test_format_rate() {
+        assert_eq!(format_rate(0.0), "0 B/s");
+        assert_eq!(format_rate(512.0), "512 B/s");
+        assert_eq!(format_rate(1536.0), "1.5 KB/s");
+        assert_eq!(format_rate(3.0 * 1024.0 * 1024.0), "3.0 MB/s");
+    }
+}
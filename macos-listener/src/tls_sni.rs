@@ -0,0 +1,189 @@
+/// TLS record content type of handshake messages
+const CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+
+/// Handshake message type of a ClientHello
+const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+
+/// Extension carrying the requested server name (RFC 6066)
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+
+/// Server name entry type for DNS host names
+const NAME_TYPE_HOST_NAME: u8 = 0x00;
+
+/// Size of the TLS record header: content type, version and length
+pub const RECORD_HEADER_LEN: usize = 5;
+
+/// Whether `payload` starts with a TLS handshake record
+pub fn is_tls_handshake(payload: &[u8]) -> bool {
+    payload.len() >= 3 && payload[0] == CONTENT_TYPE_HANDSHAKE && payload[1] == 0x03
+}
+
+/// Total length of the TLS record starting `payload`, header included
+pub fn record_len(payload: &[u8]) -> Option<usize> {
+    if !is_tls_handshake(payload) || payload.len() < RECORD_HEADER_LEN {
+        return None;
+    }
+    Some(RECORD_HEADER_LEN + u16::from_be_bytes([payload[3], payload[4]]) as usize)
+}
+
+/// Extract the SNI host name from the ClientHello at the start of a TLS connection.
+///
+/// Only the first record is inspected, so a ClientHello split across several records (rare,
+/// but allowed) yields `None`.
+pub fn extract_sni(payload: &[u8]) -> Option<String> {
+    let record_end = record_len(payload)?.min(payload.len());
+    let mut reader = Reader::new(&payload[RECORD_HEADER_LEN..record_end]);
+
+    if reader.u8()? != HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+    reader.skip(3)?; // handshake length
+    reader.skip(2 + 32)?; // client version and random
+    let session_id_len = reader.u8()? as usize;
+    reader.skip(session_id_len)?;
+    let cipher_suites_len = reader.u16()? as usize;
+    reader.skip(cipher_suites_len)?;
+    let compression_len = reader.u8()? as usize;
+    reader.skip(compression_len)?;
+
+    let extensions_len = reader.u16()? as usize;
+    let mut extensions = Reader::new(reader.take(extensions_len)?);
+    while !extensions.is_empty() {
+        let extension_type = extensions.u16()?;
+        let extension_len = extensions.u16()? as usize;
+        let data = extensions.take(extension_len)?;
+        if extension_type == EXTENSION_SERVER_NAME {
+            return server_name(data);
+        }
+    }
+
+    None
+}
+
+/// Pick the host name out of a server_name extension body
+fn server_name(data: &[u8]) -> Option<String> {
+    let mut reader = Reader::new(data);
+    let list_len = reader.u16()? as usize;
+    let mut list = Reader::new(reader.take(list_len)?);
+
+    while !list.is_empty() {
+        let name_type = list.u8()?;
+        let name_len = list.u16()? as usize;
+        let name = list.take(name_len)?;
+        if name_type == NAME_TYPE_HOST_NAME {
+            // Host names are ASCII; anything else is not worth matching rules against
+            if name.is_empty() || !name.is_ascii() {
+                return None;
+            }
+            return Some(String::from_utf8_lossy(name).to_ascii_lowercase());
+        }
+    }
+
+    None
+}
+
+/// Bounds-checked cursor over a byte slice
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.data.len() {
+            return None;
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(taken)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+/// ClientHello for `api.example.com` captured from OpenSSL 3.5, shared with other modules' tests
+#[cfg(test)]
+pub(crate) const CLIENT_HELLO: [u8; 517] = [
+    0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00, 0x01, 0xfc, 0x03, 0x03, 0x93, 0xa3, 0xd1, 0x2d, 0x54,
+    0x4d, 0x97, 0x5c, 0x86, 0xde, 0xb6, 0x78, 0x5f, 0x00, 0xdc, 0x62, 0x46, 0xdf, 0x8d, 0x5c, 0x4b,
+    0x42, 0x16, 0x3a, 0xf2, 0xde, 0x19, 0x3f, 0xf4, 0xe7, 0x3f, 0xd2, 0x20, 0x98, 0x31, 0x19, 0x11,
+    0x73, 0x29, 0xb5, 0xe6, 0x46, 0x22, 0x02, 0x97, 0x37, 0x7a, 0x4c, 0x0d, 0x86, 0x17, 0x55, 0xd9,
+    0xd8, 0x2f, 0x75, 0xbb, 0xde, 0x7a, 0x8b, 0x1a, 0xdd, 0xcd, 0x79, 0xfe, 0x00, 0x24, 0x13, 0x02,
+    0x13, 0x03, 0x13, 0x01, 0xc0, 0x2c, 0xc0, 0x30, 0xc0, 0x2b, 0xc0, 0x2f, 0xcc, 0xa9, 0xcc, 0xa8,
+    0xc0, 0x24, 0xc0, 0x28, 0xc0, 0x23, 0xc0, 0x27, 0x00, 0x9f, 0x00, 0x9e, 0x00, 0x6b, 0x00, 0x67,
+    0x00, 0xff, 0x01, 0x00, 0x01, 0x8f, 0x00, 0x00, 0x00, 0x14, 0x00, 0x12, 0x00, 0x00, 0x0f, 0x61,
+    0x70, 0x69, 0x2e, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x63, 0x6f, 0x6d, 0x00, 0x0b,
+    0x00, 0x04, 0x03, 0x00, 0x01, 0x02, 0x00, 0x0a, 0x00, 0x16, 0x00, 0x14, 0x00, 0x1d, 0x00, 0x17,
+    0x00, 0x1e, 0x00, 0x19, 0x00, 0x18, 0x01, 0x00, 0x01, 0x01, 0x01, 0x02, 0x01, 0x03, 0x01, 0x04,
+    0x00, 0x23, 0x00, 0x00, 0x00, 0x16, 0x00, 0x00, 0x00, 0x17, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x2a,
+    0x00, 0x28, 0x04, 0x03, 0x05, 0x03, 0x06, 0x03, 0x08, 0x07, 0x08, 0x08, 0x08, 0x09, 0x08, 0x0a,
+    0x08, 0x0b, 0x08, 0x04, 0x08, 0x05, 0x08, 0x06, 0x04, 0x01, 0x05, 0x01, 0x06, 0x01, 0x03, 0x03,
+    0x03, 0x01, 0x03, 0x02, 0x04, 0x02, 0x05, 0x02, 0x06, 0x02, 0x00, 0x2b, 0x00, 0x05, 0x04, 0x03,
+    0x04, 0x03, 0x03, 0x00, 0x2d, 0x00, 0x02, 0x01, 0x01, 0x00, 0x33, 0x00, 0x26, 0x00, 0x24, 0x00,
+    0x1d, 0x00, 0x20, 0x85, 0x16, 0x06, 0xc9, 0x15, 0x83, 0x44, 0x63, 0xc1, 0xe0, 0x9b, 0xcb, 0xe2,
+    0xe2, 0xc2, 0x17, 0x48, 0x7d, 0xa2, 0x81, 0xe3, 0x7d, 0xd6, 0x3a, 0x98, 0xd2, 0x69, 0xa4, 0x07,
+    0xa0, 0xc5, 0x2c, 0x00, 0x15, 0x00, 0xde, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_sni_from_captured_client_hello() {
+        assert!(is_tls_handshake(&CLIENT_HELLO));
+        assert_eq!(record_len(&CLIENT_HELLO), Some(CLIENT_HELLO.len()));
+        assert_eq!(extract_sni(&CLIENT_HELLO), Some("api.example.com".to_string()));
+    }
+
+    #[test]
+    fn test_truncated_client_hello_is_rejected() {
+        // Cut inside the extensions block
+        assert_eq!(extract_sni(&CLIENT_HELLO[..200]), None);
+        assert_eq!(extract_sni(&CLIENT_HELLO[..RECORD_HEADER_LEN]), None);
+        assert_eq!(record_len(&CLIENT_HELLO[..4]), None);
+    }
+
+    #[test]
+    fn test_non_tls_payload_is_ignored() {
+        let request = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        assert!(!is_tls_handshake(request));
+        assert_eq!(extract_sni(request), None);
+
+        // A handshake record that is not a ClientHello (ServerHello)
+        let mut server_hello = CLIENT_HELLO;
+        server_hello[RECORD_HEADER_LEN] = 0x02;
+        assert_eq!(extract_sni(&server_hello), None);
+    }
+}
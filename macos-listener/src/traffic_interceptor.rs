@@ -1,14 +1,25 @@
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
-use std::net::UdpSocket;
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use crate::dns_packet;
 use crate::{ProxyConfig, ProxyManager, NetworkConnection};
 
-/// Low-level traffic interceptor that captures and routes traffic through external SOCKS5 proxy
+/// Where the DNS interceptor listens for UDP and TCP queries
+const DNS_INTERCEPT_ADDR: &str = "127.0.0.1:5353";
+
+/// Number of intercepted connections kept by default
+pub const DEFAULT_MAX_INTERCEPTED_CONNECTIONS: usize = 1000;
+
+/// Low-level traffic interceptor that captures and routes traffic through external SOCKS5 proxy.
+/// Clones share the same state and history.
+#[derive(Clone)]
 pub struct TrafficInterceptor {
     proxy_manager: Arc<Mutex<ProxyManager>>,
     is_running: Arc<Mutex<bool>>,
-    intercepted_connections: Arc<Mutex<Vec<InterceptedConnection>>>,
+    is_paused: Arc<Mutex<bool>>,
+    intercepted_connections: Arc<Mutex<InterceptedHistory>>,
     connection_counter: Arc<Mutex<u64>>,
 }
 
@@ -24,6 +35,43 @@ pub struct InterceptedConnection {
     pub domain: Option<String>,
 }
 
+/// Intercepted connections in arrival order, evicting the oldest once `max_len` is reached
+#[derive(Debug)]
+pub struct InterceptedHistory {
+    pub connections: VecDeque<InterceptedConnection>,
+    max_len: usize,
+    dropped: u64,
+}
+
+impl InterceptedHistory {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            connections: VecDeque::new(),
+            max_len,
+            dropped: 0,
+        }
+    }
+
+    /// Append a connection, evicting the oldest entries beyond the limit
+    pub fn push(&mut self, connection: InterceptedConnection) {
+        self.connections.push_back(connection);
+        while self.connections.len() > self.max_len {
+            self.connections.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    /// Change the limit; existing entries are only evicted by the next `push`
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len.max(1);
+    }
+
+    /// Number of connections evicted since the history was created
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum InterceptionStatus {
     Pending,
@@ -38,7 +86,8 @@ impl TrafficInterceptor {
         Self {
             proxy_manager,
             is_running: Arc::new(Mutex::new(false)),
-            intercepted_connections: Arc::new(Mutex::new(Vec::new())),
+            is_paused: Arc::new(Mutex::new(false)),
+            intercepted_connections: Arc::new(Mutex::new(InterceptedHistory::new(DEFAULT_MAX_INTERCEPTED_CONNECTIONS))),
             connection_counter: Arc::new(Mutex::new(0)),
         }
     }
@@ -61,6 +110,7 @@ impl TrafficInterceptor {
         // Start system-level traffic interception
         let proxy_manager = Arc::clone(&self.proxy_manager);
         let is_running = Arc::clone(&self.is_running);
+        let is_paused = Arc::clone(&self.is_paused);
         let intercepted_connections = Arc::clone(&self.intercepted_connections);
         let connection_counter = Arc::clone(&self.connection_counter);
 
@@ -68,6 +118,7 @@ impl TrafficInterceptor {
             if let Err(e) = Self::interception_loop(
                 proxy_manager,
                 is_running,
+                is_paused,
                 intercepted_connections,
                 connection_counter,
             ) {
@@ -83,19 +134,78 @@ impl TrafficInterceptor {
     pub fn stop(&self) {
         let mut is_running = self.is_running.lock().unwrap();
         *is_running = false;
+        *self.is_paused.lock().unwrap() = false;
         println!("🛑 Traffic interceptor stopped");
     }
 
+    /// Stop routing traffic while keeping the interceptor and its history
+    pub fn pause(&self) {
+        *self.is_paused.lock().unwrap() = true;
+        println!("⏸️ Traffic interception paused");
+    }
+
+    /// Resume routing traffic after `pause`
+    pub fn resume(&self) {
+        *self.is_paused.lock().unwrap() = false;
+        println!("▶️ Traffic interception resumed");
+    }
+
+    /// Check if interception is paused
+    pub fn is_paused(&self) -> bool {
+        *self.is_paused.lock().unwrap()
+    }
+
     /// Get intercepted connections
     pub fn get_intercepted_connections(&self) -> Vec<InterceptedConnection> {
-        self.intercepted_connections.lock().unwrap().clone()
+        self.intercepted_connections.lock().unwrap().connections.iter().cloned().collect()
+    }
+
+    /// Keep at most `max_connections` intercepted connections
+    pub fn with_max_intercepted_connections(self, max_connections: usize) -> Self {
+        self.set_max_intercepted_connections(max_connections);
+        self
+    }
+
+    /// Change how many intercepted connections are kept while running
+    pub fn set_max_intercepted_connections(&self, max_connections: usize) {
+        self.intercepted_connections.lock().unwrap().set_max_len(max_connections);
+    }
+
+    /// Number of intercepted connections evicted from the history so far
+    pub fn dropped_connections(&self) -> u64 {
+        self.intercepted_connections.lock().unwrap().dropped()
+    }
+
+    /// Set the byte totals relayed for intercepted connection `id`; returns false if the
+    /// record has already been evicted
+    pub fn update_connection_bytes(&self, id: u64, sent: u64, received: u64) -> bool {
+        Self::store_connection_bytes(&self.intercepted_connections, id, sent, received)
+    }
+
+    /// Record a TCP connection to `destination` proxied by a relay outside the interceptor;
+    /// the returned id is passed to `update_connection_bytes` once the relay finishes
+    pub fn record_relayed_connection(&self, destination: String, proxy_used: ProxyConfig) -> u64 {
+        let mut counter = self.connection_counter.lock().unwrap();
+        *counter += 1;
+        let connection_id = *counter;
+        drop(counter);
+
+        Self::record_intercepted_connection(
+            &self.intercepted_connections,
+            connection_id,
+            destination,
+            Some(proxy_used),
+            InterceptionStatus::Proxied,
+        );
+        connection_id
     }
 
     /// Main interception loop
     fn interception_loop(
         proxy_manager: Arc<Mutex<ProxyManager>>,
         is_running: Arc<Mutex<bool>>,
-        intercepted_connections: Arc<Mutex<Vec<InterceptedConnection>>>,
+        is_paused: Arc<Mutex<bool>>,
+        intercepted_connections: Arc<Mutex<InterceptedHistory>>,
         connection_counter: Arc<Mutex<u64>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         println!("🔍 Starting system-level traffic interception loop...");
@@ -103,11 +213,12 @@ impl TrafficInterceptor {
         // Start DNS interception
         let dns_manager = Arc::clone(&proxy_manager);
         let dns_running = Arc::clone(&is_running);
+        let dns_paused = Arc::clone(&is_paused);
         let dns_connections = Arc::clone(&intercepted_connections);
         let dns_counter = Arc::clone(&connection_counter);
         
         thread::spawn(move || {
-            if let Err(e) = Self::intercept_dns_traffic(dns_manager, dns_running, dns_connections, dns_counter) {
+            if let Err(e) = Self::intercept_dns_traffic(dns_manager, dns_running, dns_paused, dns_connections, dns_counter) {
                 eprintln!("❌ DNS interception error: {}", e);
             }
         });
@@ -115,11 +226,12 @@ impl TrafficInterceptor {
         // Start TCP interception
         let tcp_manager = Arc::clone(&proxy_manager);
         let tcp_running = Arc::clone(&is_running);
+        let tcp_paused = Arc::clone(&is_paused);
         let tcp_connections = Arc::clone(&intercepted_connections);
         let tcp_counter = Arc::clone(&connection_counter);
         
         thread::spawn(move || {
-            if let Err(e) = Self::intercept_tcp_traffic(tcp_manager, tcp_running, tcp_connections, tcp_counter) {
+            if let Err(e) = Self::intercept_tcp_traffic(tcp_manager, tcp_running, tcp_paused, tcp_connections, tcp_counter) {
                 eprintln!("❌ TCP interception error: {}", e);
             }
         });
@@ -127,11 +239,12 @@ impl TrafficInterceptor {
         // Start UDP interception
         let udp_manager = Arc::clone(&proxy_manager);
         let udp_running = Arc::clone(&is_running);
+        let udp_paused = Arc::clone(&is_paused);
         let udp_connections = Arc::clone(&intercepted_connections);
         let udp_counter = Arc::clone(&connection_counter);
         
         thread::spawn(move || {
-            if let Err(e) = Self::intercept_udp_traffic(udp_manager, udp_running, udp_connections, udp_counter) {
+            if let Err(e) = Self::intercept_udp_traffic(udp_manager, udp_running, udp_paused, udp_connections, udp_counter) {
                 eprintln!("❌ UDP interception error: {}", e);
             }
         });
@@ -145,79 +258,39 @@ impl TrafficInterceptor {
         Ok(())
     }
 
-    /// Intercept DNS traffic at system level
+    /// Intercept DNS traffic at system level, over UDP and TCP on the same port
     fn intercept_dns_traffic(
         proxy_manager: Arc<Mutex<ProxyManager>>,
         is_running: Arc<Mutex<bool>>,
-        intercepted_connections: Arc<Mutex<Vec<InterceptedConnection>>>,
+        is_paused: Arc<Mutex<bool>>,
+        intercepted_connections: Arc<Mutex<InterceptedHistory>>,
         connection_counter: Arc<Mutex<u64>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         println!("🌐 Intercepting DNS traffic at system level...");
         
-        // Create DNS interceptor socket
-        let dns_socket = UdpSocket::bind("127.0.0.1:5353")?;
-        println!("📡 DNS interceptor listening on 127.0.0.1:5353");
+        // Create DNS interceptor sockets
+        let dns_socket = UdpSocket::bind(DNS_INTERCEPT_ADDR)?;
+        let tcp_listener = TcpListener::bind(DNS_INTERCEPT_ADDR)?;
+        println!("📡 DNS interceptor listening on {} (UDP and TCP)", DNS_INTERCEPT_ADDR);
+        
+        let context = DnsInterceptContext {
+            proxy_manager,
+            is_paused,
+            intercepted_connections,
+            connection_counter,
+        };
+        
+        let tcp_context = context.clone();
+        let tcp_running = Arc::clone(&is_running);
+        thread::spawn(move || Self::intercept_dns_tcp_traffic(tcp_listener, tcp_running, tcp_context));
         
-        let mut buffer = [0u8; 512];
+        let mut buffer = [0u8; dns_packet::MAX_UDP_PAYLOAD];
         
         while *is_running.lock().unwrap() {
             match dns_socket.recv_from(&mut buffer) {
                 Ok((size, client_addr)) => {
-                    let mut counter = connection_counter.lock().unwrap();
-                    *counter += 1;
-                    let connection_id = *counter;
-                    drop(counter);
-
-                    println!("📨 DNS query #{} from {} ({} bytes)", connection_id, client_addr, size);
-                    
-                    // Parse DNS query
-                    if let Some(domain) = Self::extract_domain_from_dns_packet(&buffer[..size]) {
-                        println!("🔍 DNS query for domain: {}", domain);
-                        
-                        // Check if this domain should be proxied
-                        if let Some(proxy_config) = Self::should_proxy_domain(&proxy_manager, &domain) {
-                            println!("✅ DNS RULE MATCH! '{}' -> {} (proxy: {}:{})", 
-                                     domain, proxy_config.name, proxy_config.host, proxy_config.port);
-                            
-                            // Route DNS query through SOCKS5 proxy
-                            if let Ok(response) = Self::route_dns_through_socks5(&domain, &proxy_config) {
-                                dns_socket.send_to(&response, client_addr)?;
-                                println!("✅ DNS response sent to {}", client_addr);
-                                
-                                // Record intercepted connection
-                                Self::record_intercepted_connection(
-                                    &intercepted_connections,
-                                    connection_id,
-                                    domain,
-                                    Some(proxy_config),
-                                    InterceptionStatus::Proxied,
-                                );
-                            } else {
-                                println!("❌ Failed to route DNS query through SOCKS5");
-                                Self::record_intercepted_connection(
-                                    &intercepted_connections,
-                                    connection_id,
-                                    domain,
-                                    Some(proxy_config),
-                                    InterceptionStatus::Failed,
-                                );
-                            }
-                        } else {
-                            println!("❌ No rule match for DNS domain: {}", domain);
-                            // Forward to system DNS
-                            if let Ok(response) = Self::forward_to_system_dns(&buffer[..size]) {
-                                dns_socket.send_to(&response, client_addr)?;
-                                println!("🔗 DNS forwarded to system DNS");
-                                
-                                Self::record_intercepted_connection(
-                                    &intercepted_connections,
-                                    connection_id,
-                                    domain,
-                                    None,
-                                    InterceptionStatus::Direct,
-                                );
-                            }
-                        }
+                    if let Some(response) = Self::answer_dns_query(&buffer[..size], client_addr, DnsTransport::Udp, &context) {
+                        dns_socket.send_to(&response, client_addr)?;
                     }
                 }
                 Err(e) => {
@@ -232,4 +305,211 @@ impl TrafficInterceptor {
         println!("🛑 DNS interception stopped");
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Accept DNS-over-TCP clients, serving each connection on its own thread
+    fn intercept_dns_tcp_traffic(listener: TcpListener, is_running: Arc<Mutex<bool>>, context: DnsInterceptContext) {
+        for stream in listener.incoming() {
+            if !*is_running.lock().unwrap() {
+                break;
+            }
+            match stream {
+                Ok(stream) => {
+                    let context = context.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = Self::handle_dns_tcp_connection(stream, &context) {
+                            println!("⚠️  DNS TCP connection closed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => eprintln!("❌ DNS TCP accept error: {}", e),
+            }
+        }
+        
+        println!("🛑 DNS TCP interception stopped");
+    }
+
+    /// Answer length-prefixed queries until the client closes the connection
+    fn handle_dns_tcp_connection(mut stream: TcpStream, context: &DnsInterceptContext) -> std::io::Result<()> {
+        let client_addr = stream.peer_addr()?;
+        loop {
+            let query = match dns_packet::read_tcp_message(&mut stream) {
+                Ok(query) => query,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            if let Some(response) = Self::answer_dns_query(&query, client_addr, DnsTransport::Tcp, context) {
+                dns_packet::write_tcp_message(&mut stream, &response)?;
+            }
+        }
+    }
+
+    /// Route one DNS query received over `transport` and record it, returning the response
+    /// for the client. Unmatched queries are forwarded upstream over the same transport.
+    fn answer_dns_query(
+        query: &[u8],
+        client_addr: SocketAddr,
+        transport: DnsTransport,
+        context: &DnsInterceptContext,
+    ) -> Option<Vec<u8>> {
+        // Paused: answer from system DNS without routing or recording the query
+        if *context.is_paused.lock().unwrap() {
+            return Self::forward_dns(query, transport)
+                .ok()
+                .map(|response| dns_packet::with_transaction_id(response, query));
+        }
+
+        let mut counter = context.connection_counter.lock().unwrap();
+        *counter += 1;
+        let connection_id = *counter;
+        drop(counter);
+
+        println!("📨 DNS query #{} from {} over {:?} ({} bytes)", connection_id, client_addr, transport, query.len());
+        
+        // Parse DNS query
+        let domain = Self::extract_domain_from_dns_packet(query)?;
+        println!("🔍 DNS query for domain: {}", domain);
+        
+        // Check if this domain should be proxied
+        let (proxy_config, result) = match Self::should_proxy_domain(&context.proxy_manager, &domain) {
+            Some(proxy_config) => {
+                println!("✅ DNS RULE MATCH! '{}' -> {} (proxy: {}:{})", 
+                         domain, proxy_config.name, proxy_config.host, proxy_config.port);
+                let result = match transport {
+                    DnsTransport::Udp => Self::route_dns_datagram_through_socks5(query, &proxy_config),
+                    DnsTransport::Tcp => Self::route_dns_through_socks5(query, &proxy_config),
+                };
+                (Some(proxy_config), result)
+            }
+            None => {
+                println!("❌ No rule match for DNS domain: {}", domain);
+                (None, Self::forward_dns(query, transport))
+            }
+        };
+
+        let status = match (&proxy_config, &result) {
+            (Some(_), Ok(_)) => InterceptionStatus::Proxied,
+            (None, Ok(_)) => InterceptionStatus::Direct,
+            (_, Err(_)) => InterceptionStatus::Failed,
+        };
+        if let (Some(proxy_config), Ok(response)) = (&proxy_config, &result) {
+            context
+                .proxy_manager
+                .lock()
+                .unwrap()
+                .record_proxy_bytes(proxy_config.id, (query.len() + response.len()) as u64);
+        }
+        Self::record_intercepted_connection(&context.intercepted_connections, connection_id, domain, proxy_config, status);
+
+        match result {
+            Ok(response) => {
+                println!("✅ DNS response sent to {}", client_addr);
+                Self::store_connection_bytes(
+                    &context.intercepted_connections,
+                    connection_id,
+                    query.len() as u64,
+                    response.len() as u64,
+                );
+                Some(dns_packet::with_transaction_id(response, query))
+            }
+            Err(e) => {
+                println!("❌ Failed to resolve DNS query #{}: {}", connection_id, e);
+                dns_packet::servfail_response(query)
+            }
+        }
+    }
+
+    /// Forward a query to system DNS over the transport the client used
+    fn forward_dns(query: &[u8], transport: DnsTransport) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match transport {
+            DnsTransport::Udp => Self::forward_to_system_dns(query),
+            DnsTransport::Tcp => Self::forward_to_system_dns_over_tcp(query),
+        }
+    }
+}
+
+/// Transport a DNS query reached the interceptor on
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DnsTransport {
+    Udp,
+    Tcp,
+}
+
+/// Shared state the UDP and TCP DNS listeners route and record queries with
+#[derive(Clone)]
+struct DnsInterceptContext {
+    proxy_manager: Arc<Mutex<ProxyManager>>,
+    is_paused: Arc<Mutex<bool>>,
+    intercepted_connections: Arc<Mutex<InterceptedHistory>>,
+    connection_counter: Arc<Mutex<u64>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProxyType;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_tcp_dns_query_gets_length_prefixed_response() {
+        let proxy_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_port = proxy_listener.local_addr().unwrap().port();
+
+        let mut query = vec![0xAB, 0xCD, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(b"\x08internal\x04corp\x00\x00\x01\x00\x01");
+        let answer = [&query[..2], &[0x81, 0x80][..], &query[4..]].concat();
+
+        // Mock SOCKS5 proxy that answers the tunnelled query
+        let server_answer = answer.clone();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = proxy_listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+
+            let received = dns_packet::read_tcp_message(&mut stream).unwrap();
+            dns_packet::write_tcp_message(&mut stream, &server_answer).unwrap();
+            received
+        });
+
+        let mut manager = ProxyManager {
+            global_enabled: true,
+            ..Default::default()
+        };
+        let proxy = manager.add_proxy("Local".to_string(), "127.0.0.1".to_string(), proxy_port, ProxyType::Socks5);
+        manager.add_rule("Corp".to_string(), "*.corp".to_string(), proxy).unwrap();
+        let context = DnsInterceptContext {
+            proxy_manager: Arc::new(Mutex::new(manager)),
+            is_paused: Arc::new(Mutex::new(false)),
+            intercepted_connections: Arc::new(Mutex::new(InterceptedHistory::new(10))),
+            connection_counter: Arc::new(Mutex::new(0)),
+        };
+        let history = Arc::clone(&context.intercepted_connections);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let is_running = Arc::new(Mutex::new(true));
+        thread::spawn(move || TrafficInterceptor::intercept_dns_tcp_traffic(listener, is_running, context));
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&query);
+        client.write_all(&framed).unwrap();
+
+        let mut length = [0u8; 2];
+        client.read_exact(&mut length).unwrap();
+        let mut response = vec![0u8; u16::from_be_bytes(length) as usize];
+        client.read_exact(&mut response).unwrap();
+
+        assert_eq!(response, answer);
+        assert_eq!(server.join().unwrap(), query);
+        let history = history.lock().unwrap();
+        let recorded = &history.connections[0];
+        assert_eq!(recorded.status, InterceptionStatus::Proxied);
+        assert_eq!(recorded.domain.as_deref(), Some("internal.corp"));
+        assert_eq!((recorded.bytes_sent, recorded.bytes_received), (query.len() as u64, answer.len() as u64));
+    }
+}
@@ -1,8 +1,13 @@
 use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
 use std::sync::{Arc, Mutex};
-use std::io::{Read, Write};
-use crate::{ProxyConfig, ProxyManager, NetworkConnection};
-use crate::traffic_interceptor::{InterceptedConnection, InterceptionStatus};
+use crate::dns_packet;
+use crate::reverse_dns;
+use crate::socks5_connector::{self, Socks5Connector, Socks5UdpAssociation, DEFAULT_PROXY_TIMEOUT};
+use crate::{ProxyConfig, ProxyManager, NetworkConnection, RuleTarget};
+use crate::traffic_interceptor::{InterceptedConnection, InterceptedHistory, InterceptionStatus};
+
+/// Upstream DNS server for forwarded and tunnelled queries
+const UPSTREAM_DNS_SERVER: &str = "8.8.8.8:53";
 
 /// Helper methods for traffic interception
 impl super::TrafficInterceptor {
@@ -10,12 +15,18 @@ impl super::TrafficInterceptor {
     pub fn intercept_tcp_traffic(
         proxy_manager: Arc<Mutex<ProxyManager>>,
         is_running: Arc<Mutex<bool>>,
-        intercepted_connections: Arc<Mutex<Vec<InterceptedConnection>>>,
+        is_paused: Arc<Mutex<bool>>,
+        intercepted_connections: Arc<Mutex<InterceptedHistory>>,
         connection_counter: Arc<Mutex<u64>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         println!("🔗 Intercepting TCP traffic at system level...");
         
         while *is_running.lock().unwrap() {
+            if *is_paused.lock().unwrap() {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                continue;
+            }
+
             // Monitor system TCP connections
             if let Ok(connections) = Self::get_system_tcp_connections() {
                 for conn in connections {
@@ -57,12 +68,18 @@ impl super::TrafficInterceptor {
     pub fn intercept_udp_traffic(
         proxy_manager: Arc<Mutex<ProxyManager>>,
         is_running: Arc<Mutex<bool>>,
-        intercepted_connections: Arc<Mutex<Vec<InterceptedConnection>>>,
+        is_paused: Arc<Mutex<bool>>,
+        intercepted_connections: Arc<Mutex<InterceptedHistory>>,
         connection_counter: Arc<Mutex<u64>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         println!("📡 Intercepting UDP traffic at system level...");
         
         while *is_running.lock().unwrap() {
+            if *is_paused.lock().unwrap() {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                continue;
+            }
+
             // Monitor system UDP connections
             if let Ok(connections) = Self::get_system_udp_connections() {
                 for conn in connections {
@@ -72,8 +89,8 @@ impl super::TrafficInterceptor {
                             println!("✅ UDP RULE MATCH! {} -> {} (proxy: {}:{})", 
                                      conn.local_addr, remote_addr, proxy_config.host, proxy_config.port);
                             
-                            // Route UDP connection through SOCKS5 proxy
-                            Self::route_udp_through_socks5(&conn, &proxy_config)?;
+                            // netstat only reveals the flow, not its datagrams; those that reach the
+                            // interceptor (DNS queries) are relayed by `route_udp_through_socks5`
                             
                             // Record intercepted connection
                             let mut counter = connection_counter.lock().unwrap();
@@ -189,58 +206,14 @@ impl super::TrafficInterceptor {
 
     /// Parse socket address from string
     pub fn parse_socket_addr(addr_str: &str) -> Result<SocketAddr, Box<dyn std::error::Error>> {
-        if addr_str.starts_with('*') {
-            let port_str = &addr_str[2..];
-            let port = port_str.parse::<u16>()?;
-            Ok(SocketAddr::new(
-                std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)), 
-                port
-            ))
-        } else if addr_str.contains(':') {
-            addr_str.parse::<SocketAddr>()
-                .map_err(|e| format!("Failed to parse '{}': {}", addr_str, e).into())
-        } else {
-            Err("Invalid address format".into())
-        }
+        crate::network_monitor::parse_netstat_addr(addr_str)
     }
 
     /// Extract domain from DNS packet
     pub fn extract_domain_from_dns_packet(packet: &[u8]) -> Option<String> {
-        if packet.len() < 12 {
-            return None; // DNS header is at least 12 bytes
-        }
-
-        // Skip DNS header (12 bytes) and parse the question section
-        let mut offset = 12;
-        let mut domain = String::new();
-        
-        while offset < packet.len() {
-            let length = packet[offset] as usize;
-            if length == 0 {
-                break; // End of domain name
-            }
-            
-            if offset + length >= packet.len() {
-                break; // Invalid packet
-            }
-            
-            if !domain.is_empty() {
-                domain.push('.');
-            }
-            
-            let label = String::from_utf8_lossy(&packet[offset + 1..offset + 1 + length]);
-            domain.push_str(&label);
-            
-            offset += length + 1;
-        }
-        
-        if domain.is_empty() {
-            None
-        } else {
-            Some(domain)
-        }
+        dns_packet::extract_query_domain(packet)
     }
-
+    
     /// Check if domain should be proxied
     pub fn should_proxy_domain(
         proxy_manager: &Arc<Mutex<ProxyManager>>,
@@ -252,15 +225,16 @@ impl super::TrafficInterceptor {
             return None;
         }
 
-        for rule in &manager.rules {
-            if !rule.enabled {
-                continue;
-            }
-
-            if Self::matches_pattern(&rule.pattern, domain) {
+        for rule in &manager.compiled_rules {
+            if rule.matches(domain, None) {
                 println!("✅ DNS rule '{}' matched for domain '{}'", rule.name, domain);
+                manager.record_rule_match(rule.rule_id);
                 
-                if let Some(proxy) = manager.proxies.iter().find(|p| p.id == rule.proxy_id && p.enabled) {
+                if rule.proxy_id == RuleTarget::Direct {
+                    return None;
+                }
+                if let Some(proxy) = manager.proxy_for_target(rule.proxy_id) {
+                    manager.record_proxy_match(proxy.id);
                     return Some(proxy.clone());
                 }
             }
@@ -274,13 +248,12 @@ impl super::TrafficInterceptor {
         proxy_manager: &Arc<Mutex<ProxyManager>>,
         target_addr: &SocketAddr,
     ) -> Option<ProxyConfig> {
-        let manager = proxy_manager.lock().unwrap();
-        
-        if !manager.global_enabled {
+        if !proxy_manager.lock().unwrap().global_enabled {
             return None;
         }
 
-        // Try to resolve IP to hostname for rule matching
+        // Try to resolve IP to hostname for rule matching, without holding the manager lock
+        // while a PTR query is outstanding
         let hostname = Self::resolve_ip_to_hostname(target_addr.ip())
             .unwrap_or_else(|| {
                 match target_addr.ip() {
@@ -288,16 +261,18 @@ impl super::TrafficInterceptor {
                     IpAddr::V6(ip) => ip.to_string(),
                 }
             });
+        let manager = proxy_manager.lock().unwrap();
 
-        for rule in &manager.rules {
-            if !rule.enabled {
-                continue;
-            }
-
-            if Self::matches_pattern(&rule.pattern, &hostname) {
+        for rule in &manager.compiled_rules {
+            if rule.matches(&hostname, Some(target_addr.ip())) {
                 println!("✅ Connection rule '{}' matched for hostname '{}'", rule.name, hostname);
+                manager.record_rule_match(rule.rule_id);
                 
-                if let Some(proxy) = manager.proxies.iter().find(|p| p.id == rule.proxy_id && p.enabled) {
+                if rule.proxy_id == RuleTarget::Direct {
+                    return None;
+                }
+                if let Some(proxy) = manager.proxy_for_target(rule.proxy_id) {
+                    manager.record_proxy_match(proxy.id);
                     return Some(proxy.clone());
                 }
             }
@@ -306,39 +281,32 @@ impl super::TrafficInterceptor {
         None
     }
 
-    /// Route DNS query through SOCKS5 proxy
+    /// Route a client's DNS query through SOCKS5 proxy using DNS over TCP. The query is sent
+    /// unchanged so the answer carries the client's ID and question.
     pub fn route_dns_through_socks5(
-        domain: &str,
+        query_packet: &[u8],
         proxy_config: &ProxyConfig,
     ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        println!("🔗 Routing DNS query for '{}' through SOCKS5 proxy {}:{}", 
-                 domain, proxy_config.host, proxy_config.port);
+        println!("🔗 Routing DNS query through SOCKS5 proxy {}:{}", 
+                 proxy_config.host, proxy_config.port);
 
         // Connect to SOCKS5 proxy
-        let proxy_addr = format!("{}:{}", proxy_config.host, proxy_config.port);
-        let mut proxy_stream = TcpStream::connect(&proxy_addr)?;
+        let mut proxy_stream = socks5_connector::connect_to_proxy(proxy_config, DEFAULT_PROXY_TIMEOUT)?;
         println!("✅ Connected to SOCKS5 proxy");
 
         // Perform SOCKS5 handshake
-        Self::socks5_handshake(&mut proxy_stream, proxy_config)?;
+        let connector = Socks5Connector::from_proxy_config(proxy_config);
+        connector.handshake(&mut proxy_stream)?;
         println!("🤝 SOCKS5 handshake completed");
 
         // Connect to DNS server through proxy
-        let dns_server = "8.8.8.8:53"; // Use Google DNS as upstream
-        let dns_addr: SocketAddr = dns_server.parse()?;
-        Self::socks5_connect(&mut proxy_stream, dns_addr)?;
-        println!("🎯 Connected to DNS server {} through proxy", dns_server);
-
-        // Send DNS query through proxy
-        let query_packet = Self::build_dns_query_packet(domain)?;
-        proxy_stream.write_all(&query_packet)?;
-        println!("📤 DNS query sent through proxy");
-
-        // Read DNS response
-        let mut response = vec![0u8; 512];
-        let size = proxy_stream.read(&mut response)?;
-        response.truncate(size);
-        println!("📥 DNS response received ({} bytes)", size);
+        let dns_addr: SocketAddr = UPSTREAM_DNS_SERVER.parse()?;
+        connector.connect(&mut proxy_stream, dns_addr)?;
+        println!("🎯 Connected to DNS server {} through proxy", UPSTREAM_DNS_SERVER);
+
+        // DNS over TCP: the query and response carry a two-byte length prefix
+        let response = dns_packet::exchange_over_tcp(&mut proxy_stream, query_packet)?;
+        println!("📥 DNS response received ({} bytes)", response.len());
 
         Ok(response)
     }
@@ -352,285 +320,115 @@ impl super::TrafficInterceptor {
                  proxy_config.host, proxy_config.port);
 
         // Connect to SOCKS5 proxy
-        let proxy_addr = format!("{}:{}", proxy_config.host, proxy_config.port);
-        let mut proxy_stream = TcpStream::connect(&proxy_addr)?;
+        let mut proxy_stream = socks5_connector::connect_to_proxy(proxy_config, DEFAULT_PROXY_TIMEOUT)?;
         println!("✅ Connected to SOCKS5 proxy");
 
         // Perform SOCKS5 handshake
-        Self::socks5_handshake(&mut proxy_stream, proxy_config)?;
+        let connector = Socks5Connector::from_proxy_config(proxy_config);
+        connector.handshake(&mut proxy_stream)?;
         println!("🤝 SOCKS5 handshake completed");
 
         // Connect to target through proxy
         if let Some(target_addr) = connection.remote_addr {
-            Self::socks5_connect(&mut proxy_stream, target_addr)?;
+            connector.connect(&mut proxy_stream, target_addr)?;
             println!("🎯 Connected to target {} through proxy", target_addr);
         }
 
         Ok(())
     }
 
-    /// Route UDP connection through SOCKS5 proxy
-    pub fn route_udp_through_socks5(
-        connection: &NetworkConnection,
+    /// Route a client's DNS query that arrived over UDP through the SOCKS5 proxy as a datagram,
+    /// falling back to DNS over TCP when the proxy cannot relay UDP
+    pub fn route_dns_datagram_through_socks5(
+        query_packet: &[u8],
         proxy_config: &ProxyConfig,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        println!("🔗 Routing UDP connection through SOCKS5 proxy {}:{}", 
-                 proxy_config.host, proxy_config.port);
-
-        // UDP over SOCKS5 is more complex and requires UDP ASSOCIATE
-        // This is a simplified implementation
-        println!("📡 UDP routing through SOCKS5 (simplified implementation)");
-        
-        Ok(())
-    }
-
-    /// Forward DNS query to system DNS
-    pub fn forward_to_system_dns(dns_packet: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        // Forward to system DNS server
-        let dns_server = "8.8.8.8:53";
-        let mut dns_socket = UdpSocket::bind("0.0.0.0:0")?;
-        dns_socket.send_to(dns_packet, dns_server)?;
-        
-        let mut response = vec![0u8; 512];
-        let size = dns_socket.recv(&mut response)?;
-        response.truncate(size);
-        
-        Ok(response)
-    }
-
-    /// SOCKS5 handshake
-    pub fn socks5_handshake(
-        stream: &mut TcpStream,
-        proxy_config: &ProxyConfig,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // Send authentication methods
-        let auth_methods = if proxy_config.username.is_some() {
-            vec![0x05, 0x01, 0x02, 0x00] // Username/password and no auth
-        } else {
-            vec![0x05, 0x01, 0x00] // No authentication
-        };
-        
-        stream.write_all(&auth_methods)?;
-
-        // Read server response
-        let mut response = [0u8; 2];
-        stream.read_exact(&mut response)?;
-
-        if response[0] != 0x05 {
-            return Err("Invalid SOCKS5 version".into());
-        }
-
-        // Handle authentication if required
-        if response[1] == 0x02 && proxy_config.username.is_some() {
-            Self::socks5_authenticate(stream, proxy_config)?;
-        } else if response[1] != 0x00 {
-            return Err("SOCKS5 authentication failed".into());
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let dns_addr: SocketAddr = UPSTREAM_DNS_SERVER.parse()?;
+        match Self::route_udp_through_socks5(dns_addr, proxy_config, query_packet) {
+            Ok(response) => Ok(response),
+            Err(e) => {
+                println!("⚠️  UDP relay through {}:{} failed ({}), using DNS over TCP",
+                         proxy_config.host, proxy_config.port, e);
+                Self::route_dns_through_socks5(query_packet, proxy_config)
+            }
         }
-
-        Ok(())
     }
 
-    /// SOCKS5 authentication
-    pub fn socks5_authenticate(
-        stream: &mut TcpStream,
+    /// Relay one UDP datagram to `target_addr` through the SOCKS5 proxy via UDP ASSOCIATE and
+    /// return the response payload
+    pub fn route_udp_through_socks5(
+        target_addr: SocketAddr,
         proxy_config: &ProxyConfig,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let username = proxy_config.username.as_ref().unwrap();
-        let password = proxy_config.password.as_ref().unwrap();
+        payload: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        println!("🔗 Routing UDP datagram to {} through SOCKS5 proxy {}:{}", 
+                 target_addr, proxy_config.host, proxy_config.port);
 
-        let mut auth_request = vec![0x01, username.len() as u8];
-        auth_request.extend_from_slice(username.as_bytes());
-        auth_request.push(password.len() as u8);
-        auth_request.extend_from_slice(password.as_bytes());
+        // The TCP control connection must stay open for as long as the association is used
+        let mut control_stream = socks5_connector::connect_to_proxy(proxy_config, DEFAULT_PROXY_TIMEOUT)?;
+        let connector = Socks5Connector::from_proxy_config(proxy_config);
+        connector.handshake(&mut control_stream)?;
 
-        stream.write_all(&auth_request)?;
+        let association = Socks5UdpAssociation::establish(&connector, control_stream)?;
+        println!("📡 UDP association established, relay {}", association.relay_addr());
 
-        let mut response = [0u8; 2];
-        stream.read_exact(&mut response)?;
+        let response = association.exchange(target_addr, payload)?;
+        println!("📥 UDP response received ({} bytes)", response.len());
 
-        if response[0] != 0x01 || response[1] != 0x00 {
-            return Err("SOCKS5 authentication failed".into());
-        }
-
-        Ok(())
+        Ok(response)
     }
 
-    /// SOCKS5 connect command
-    pub fn socks5_connect(
-        stream: &mut TcpStream,
-        target_addr: SocketAddr,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut connect_request = vec![0x05, 0x01, 0x00]; // VER, CMD, RSV
-
-        match target_addr.ip() {
-            IpAddr::V4(ip) => {
-                connect_request.push(0x01); // ATYP: IPv4
-                connect_request.extend_from_slice(&ip.octets());
-            }
-            IpAddr::V6(ip) => {
-                connect_request.push(0x04); // ATYP: IPv6
-                connect_request.extend_from_slice(&ip.octets());
-            }
-        }
-
-        connect_request.extend_from_slice(&target_addr.port().to_be_bytes());
-        stream.write_all(&connect_request)?;
-
-        // Read response
-        let mut response = vec![0u8; 4];
-        stream.read_exact(&mut response)?;
-
-        if response[0] != 0x05 || response[1] != 0x00 {
-            return Err("SOCKS5 connection failed".into());
-        }
-
-        // Skip the rest of the response
-        let atyp = response[3];
-        let addr_len = match atyp {
-            0x01 => 4,  // IPv4
-            0x04 => 16, // IPv6
-            0x03 => {   // Domain name
-                let mut len_buf = [0u8; 1];
-                stream.read_exact(&mut len_buf)?;
-                len_buf[0] as usize
-            }
-            _ => return Err("Invalid address type".into()),
-        };
-
-        let mut addr_buf = vec![0u8; addr_len];
-        stream.read_exact(&mut addr_buf)?;
-
-        let mut port_buf = [0u8; 2];
-        stream.read_exact(&mut port_buf)?;
-
-        Ok(())
+    /// Forward DNS query to system DNS
+    pub fn forward_to_system_dns(dns_packet: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Self::forward_dns_to(dns_packet, UPSTREAM_DNS_SERVER)
     }
 
-    /// Build DNS query packet
-    pub fn build_dns_query_packet(domain: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let mut packet = Vec::new();
+    /// Forward DNS query to `upstream` over UDP, advertising a large EDNS0 payload and retrying
+    /// over TCP when the answer still comes back truncated
+    pub fn forward_dns_to(dns_packet: &[u8], upstream: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let dns_socket = UdpSocket::bind("0.0.0.0:0")?;
+        dns_socket.send_to(&dns_packet::with_edns0(dns_packet, dns_packet::MAX_UDP_PAYLOAD as u16), upstream)?;
+        
+        let mut response = vec![0u8; dns_packet::MAX_UDP_PAYLOAD];
+        let size = dns_socket.recv(&mut response)?;
+        response.truncate(size);
         
-        // DNS header
-        packet.extend_from_slice(&[0x12, 0x34]); // ID
-        packet.extend_from_slice(&[0x01, 0x00]); // Flags (standard query)
-        packet.extend_from_slice(&1u16.to_be_bytes()); // QDCOUNT
-        packet.extend_from_slice(&0u16.to_be_bytes()); // ANCOUNT
-        packet.extend_from_slice(&0u16.to_be_bytes()); // NSCOUNT
-        packet.extend_from_slice(&0u16.to_be_bytes()); // ARCOUNT
-
-        // Question section
-        // Domain name
-        for part in domain.split('.') {
-            packet.push(part.len() as u8);
-            packet.extend_from_slice(part.as_bytes());
+        if dns_packet::is_truncated(&response) {
+            println!("✂️ Truncated DNS answer ({} bytes), retrying over TCP", size);
+            return Self::forward_dns_over_tcp_to(dns_packet, upstream);
         }
-        packet.push(0); // End of domain name
+        Ok(response)
+    }
 
-        // Query type (A record)
-        packet.extend_from_slice(&1u16.to_be_bytes());
-        // Query class (IN = 1)
-        packet.extend_from_slice(&1u16.to_be_bytes());
+    /// Forward DNS query to system DNS over TCP, for clients whose answers may not fit a datagram
+    pub fn forward_to_system_dns_over_tcp(dns_packet: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Self::forward_dns_over_tcp_to(dns_packet, UPSTREAM_DNS_SERVER)
+    }
 
-        Ok(packet)
+    fn forward_dns_over_tcp_to(dns_packet: &[u8], upstream: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut stream = TcpStream::connect(upstream)?;
+        Ok(dns_packet::exchange_over_tcp(&mut stream, dns_packet)?)
     }
 
     /// Try to resolve IP address to hostname
     pub fn resolve_ip_to_hostname(ip: IpAddr) -> Option<String> {
-        // For localhost addresses, return special names
         match ip {
-            IpAddr::V4(ipv4) => {
-                if ipv4.is_loopback() {
-                    return Some("localhost".to_string());
-                }
-                if ipv4.is_private() {
-                    // For private IPs, try reverse DNS lookup
-                    return Self::reverse_dns_lookup(ip);
-                }
-            }
-            IpAddr::V6(ipv6) => {
-                if ipv6.is_loopback() {
-                    return Some("localhost".to_string());
-                }
-                if ipv6.is_unicast_link_local() {
-                    return Some("link-local".to_string());
-                }
-            }
+            IpAddr::V4(ipv4) if ipv4.is_loopback() => Some("localhost".to_string()),
+            IpAddr::V6(ipv6) if ipv6.is_loopback() => Some("localhost".to_string()),
+            IpAddr::V6(ipv6) if ipv6.is_unicast_link_local() => Some("link-local".to_string()),
+            _ => Self::reverse_dns_lookup(ip),
         }
-
-        // Try reverse DNS lookup
-        Self::reverse_dns_lookup(ip)
     }
 
-    /// Perform reverse DNS lookup
+    /// PTR name of `ip`, falling back to a placeholder for private addresses without one.
+    /// Answers are cached; an uncached lookup blocks for up to `REVERSE_DNS_TIMEOUT`.
     pub fn reverse_dns_lookup(ip: IpAddr) -> Option<String> {
-        // This is a simplified implementation
-        // In a real implementation, you'd use a proper DNS resolver
-        match ip {
-            IpAddr::V4(ipv4) => {
-                // Check for common private IP ranges
-                if ipv4.octets()[0] == 192 && ipv4.octets()[1] == 168 {
-                    return Some(format!("private-{}.{}.{}.{}", 
-                        ipv4.octets()[0], ipv4.octets()[1], ipv4.octets()[2], ipv4.octets()[3]));
-                }
-                if ipv4.octets()[0] == 10 {
-                    return Some(format!("private-{}.{}.{}.{}", 
-                        ipv4.octets()[0], ipv4.octets()[1], ipv4.octets()[2], ipv4.octets()[3]));
-                }
-                if ipv4.octets()[0] == 172 && ipv4.octets()[1] >= 16 && ipv4.octets()[1] <= 31 {
-                    return Some(format!("private-{}.{}.{}.{}", 
-                        ipv4.octets()[0], ipv4.octets()[1], ipv4.octets()[2], ipv4.octets()[3]));
-                }
-                
-                // Check for 100.64.x.x range (Carrier-Grade NAT)
-                if ipv4.octets()[0] == 100 && ipv4.octets()[1] == 64 {
-                    return Some(format!("100.64.{}.{}", ipv4.octets()[2], ipv4.octets()[3]));
-                }
-            }
-            IpAddr::V6(_) => {
-                return Some("ipv6-address".to_string());
-            }
-        }
-
-        None
-    }
-
-    /// Pattern matching for proxy rules
-    pub fn matches_pattern(pattern: &str, hostname: &str) -> bool {
-        if pattern == hostname {
-            return true;
-        }
-
-        if pattern.starts_with("*.") {
-            let suffix = &pattern[2..];
-            return hostname.ends_with(suffix);
-        }
-
-        if pattern.ends_with(".*") {
-            let prefix = &pattern[..pattern.len() - 2];
-            return hostname.starts_with(prefix);
-        }
-
-        if pattern.contains(".*") && !pattern.starts_with("*") && !pattern.ends_with("*") {
-            let parts: Vec<&str> = pattern.split(".*").collect();
-            if parts.len() == 2 {
-                let prefix = parts[0];
-                return hostname.starts_with(prefix);
-            }
-        }
-
-        if pattern.contains("*") {
-            let parts: Vec<&str> = pattern.split('*').collect();
-            if parts.len() == 2 {
-                return hostname.starts_with(parts[0]) && hostname.ends_with(parts[1]);
-            }
-        }
-
-        false
+        reverse_dns::lookup(ip).or_else(|| reverse_dns::synthesized_name(ip))
     }
 
     /// Record intercepted connection
     pub fn record_intercepted_connection(
-        intercepted_connections: &Arc<Mutex<Vec<InterceptedConnection>>>,
+        intercepted_connections: &Arc<Mutex<InterceptedHistory>>,
         connection_id: u64,
         domain: String,
         proxy_used: Option<ProxyConfig>,
@@ -658,12 +456,24 @@ impl super::TrafficInterceptor {
             domain: Some(domain),
         };
 
-        let mut connections = intercepted_connections.lock().unwrap();
-        connections.push(connection);
-        
-        // Keep only last 1000 connections
-        if connections.len() > 1000 {
-            connections.remove(0);
+        intercepted_connections.lock().unwrap().push(connection);
+    }
+
+    /// Set the byte totals of the stored connection `connection_id`
+    pub fn store_connection_bytes(
+        intercepted_connections: &Arc<Mutex<InterceptedHistory>>,
+        connection_id: u64,
+        bytes_sent: u64,
+        bytes_received: u64,
+    ) -> bool {
+        let mut history = intercepted_connections.lock().unwrap();
+        match history.connections.iter_mut().rev().find(|connection| connection.id == connection_id) {
+            Some(connection) => {
+                connection.bytes_sent = bytes_sent;
+                connection.bytes_received = bytes_received;
+                true
+            }
+            None => false,
         }
     }
 
@@ -675,3 +485,213 @@ impl super::TrafficInterceptor {
         println!("   Configuration logging not available from helpers");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::dns_packet;
+    use crate::traffic_interceptor::{InterceptedHistory, InterceptionStatus, TrafficInterceptor};
+    use crate::socks5_connector::{decode_udp_datagram, encode_udp_datagram};
+    use crate::{ProxyConfig, ProxyType};
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+    use std::sync::{Arc, Mutex};
+
+    fn local_proxy(port: u16) -> ProxyConfig {
+        ProxyConfig {
+            id: 1,
+            name: "Local".to_string(),
+            host: "127.0.0.1".to_string(),
+            port,
+            proxy_type: ProxyType::Socks5,
+            username: None,
+            password: None,
+            enabled: true,
+            last_test_result: None,
+        }
+    }
+
+    /// Answer a no-auth greeting and return the 10-byte IPv4 request that follows
+    fn accept_request(stream: &mut TcpStream) -> [u8; 10] {
+        let mut greeting = [0u8; 3];
+        stream.read_exact(&mut greeting).unwrap();
+        stream.write_all(&[0x05, 0x00]).unwrap();
+        let mut request = [0u8; 10];
+        stream.read_exact(&mut request).unwrap();
+        request
+    }
+
+    fn dns_query_and_answer() -> (Vec<u8>, Vec<u8>) {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(b"\x08internal\x04corp\x00\x00\x01\x00\x01");
+        let answer = [&query[..2], &[0x81, 0x80][..], &query[4..]].concat();
+        (query, answer)
+    }
+
+    #[test]
+    fn test_udp_dns_query_is_relayed_with_udp_associate() {
+        let (query, answer) = dns_query_and_answer();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_port = listener.local_addr().unwrap().port();
+        let relay = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let relay_port = relay.local_addr().unwrap().port();
+
+        // Control connection: grant the association and keep it until the client closes it
+        let control = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = accept_request(&mut stream);
+            let mut reply = vec![0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0];
+            reply.extend_from_slice(&relay_port.to_be_bytes());
+            stream.write_all(&reply).unwrap();
+            let mut rest = Vec::new();
+            let _ = stream.read_to_end(&mut rest);
+            request
+        });
+
+        // Relay: the query arrives with the SOCKS5 UDP header for the upstream resolver
+        let relayed_answer = answer.clone();
+        let relay_thread = std::thread::spawn(move || {
+            let mut buffer = [0u8; 1500];
+            let (size, client) = relay.recv_from(&mut buffer).unwrap();
+            let (target, payload) = decode_udp_datagram(&buffer[..size]).unwrap();
+            relay.send_to(&encode_udp_datagram(target, &relayed_answer), client).unwrap();
+            (target, payload.to_vec())
+        });
+
+        let response = TrafficInterceptor::route_dns_datagram_through_socks5(&query, &local_proxy(proxy_port)).unwrap();
+        assert_eq!(response, answer);
+
+        let (target, payload) = relay_thread.join().unwrap();
+        assert_eq!(target, "8.8.8.8:53".parse::<SocketAddr>().unwrap());
+        assert_eq!(payload, query);
+        assert_eq!(control.join().unwrap()[1], 0x03, "expected a UDP ASSOCIATE request");
+    }
+
+    #[test]
+    fn test_udp_dns_query_falls_back_to_tcp_without_udp_associate() {
+        let (query, answer) = dns_query_and_answer();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_port = listener.local_addr().unwrap().port();
+
+        let tcp_answer = answer.clone();
+        let proxy = std::thread::spawn(move || {
+            // Reply 7: command not supported
+            let (mut stream, _) = listener.accept().unwrap();
+            assert_eq!(accept_request(&mut stream)[1], 0x03);
+            stream.write_all(&[0x05, 0x07, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+
+            let (mut stream, _) = listener.accept().unwrap();
+            assert_eq!(accept_request(&mut stream)[1], 0x01);
+            stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+            let received = dns_packet::read_tcp_message(&mut stream).unwrap();
+            dns_packet::write_tcp_message(&mut stream, &tcp_answer).unwrap();
+            received
+        });
+
+        let response = TrafficInterceptor::route_dns_datagram_through_socks5(&query, &local_proxy(proxy_port)).unwrap();
+        assert_eq!(response, answer);
+        assert_eq!(proxy.join().unwrap(), query);
+    }
+
+    #[test]
+    fn test_truncated_answer_is_retried_over_tcp() {
+        let mut query = vec![0xAB, 0xCD, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(b"\x05large\x07example\x00\x00\x10\x00\x01");
+        // A 3000-byte answer: too big for a plain DNS datagram
+        let mut large_answer = [&query[..2], &[0x81, 0x80][..], &query[4..]].concat();
+        large_answer.resize(3000, 0x42);
+
+        // Mock upstream: the UDP answer only carries the header with TC set, TCP has it all
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream = udp.local_addr().unwrap();
+        let tcp = std::net::TcpListener::bind(upstream).unwrap();
+        let truncated = [&query[..2], &[0x83, 0x80][..], &query[4..]].concat();
+        let udp_server = std::thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            let (size, client) = udp.recv_from(&mut buffer).unwrap();
+            udp.send_to(&truncated, client).unwrap();
+            buffer[..size].to_vec()
+        });
+        let tcp_answer = large_answer.clone();
+        let tcp_server = std::thread::spawn(move || {
+            let (mut stream, _) = tcp.accept().unwrap();
+            let received = dns_packet::read_tcp_message(&mut stream).unwrap();
+            dns_packet::write_tcp_message(&mut stream, &tcp_answer).unwrap();
+            received
+        });
+
+        let response = TrafficInterceptor::forward_dns_to(&query, &upstream.to_string()).unwrap();
+        assert_eq!(response, large_answer);
+
+        // The UDP query advertised EDNS0, the TCP retry is the client's query unchanged
+        let udp_query = udp_server.join().unwrap();
+        assert_eq!(udp_query, dns_packet::with_edns0(&query, dns_packet::MAX_UDP_PAYLOAD as u16));
+        assert_eq!(tcp_server.join().unwrap(), query);
+    }
+
+    #[test]
+    fn test_store_connection_bytes() {
+        let connections = Arc::new(Mutex::new(InterceptedHistory::new(10)));
+        TrafficInterceptor::record_intercepted_connection(&connections, 1, "a.example".to_string(), None, InterceptionStatus::Direct);
+        TrafficInterceptor::record_intercepted_connection(&connections, 2, "b.example".to_string(), None, InterceptionStatus::Proxied);
+
+        assert!(TrafficInterceptor::store_connection_bytes(&connections, 2, 41, 1200));
+        assert!(TrafficInterceptor::store_connection_bytes(&connections, 2, 82, 2400));
+        assert!(!TrafficInterceptor::store_connection_bytes(&connections, 3, 1, 1));
+
+        let stored = &connections.lock().unwrap().connections;
+        assert_eq!((stored[0].bytes_sent, stored[0].bytes_received), (0, 0));
+        assert_eq!((stored[1].bytes_sent, stored[1].bytes_received), (82, 2400));
+    }
+
+    #[test]
+    fn test_intercepted_history_limit() {
+        let connections = Arc::new(Mutex::new(InterceptedHistory::new(2)));
+        for id in 1..=3 {
+            TrafficInterceptor::record_intercepted_connection(&connections, id, "a.example".to_string(), None, InterceptionStatus::Direct);
+        }
+        {
+            let history = connections.lock().unwrap();
+            let ids: Vec<u64> = history.connections.iter().map(|connection| connection.id).collect();
+            assert_eq!(ids, vec![2, 3]);
+            assert_eq!(history.dropped(), 1);
+        }
+
+        // Raising the limit keeps what is stored and stops evicting
+        connections.lock().unwrap().set_max_len(4);
+        for id in 4..=5 {
+            TrafficInterceptor::record_intercepted_connection(&connections, id, "a.example".to_string(), None, InterceptionStatus::Direct);
+        }
+        let history = connections.lock().unwrap();
+        assert_eq!(history.connections.len(), 4);
+        assert_eq!(history.dropped(), 1);
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let interceptor = TrafficInterceptor::new(Arc::new(Mutex::new(crate::ProxyManager::default())));
+        assert!(!interceptor.is_paused());
+
+        interceptor.pause();
+        assert!(interceptor.is_paused());
+        interceptor.resume();
+        assert!(!interceptor.is_paused());
+
+        interceptor.pause();
+        interceptor.stop();
+        assert!(!interceptor.is_paused());
+    }
+
+    #[test]
+    fn test_parse_socket_addr_macos_notation() {
+        let ipv4: SocketAddr = "192.168.0.136:51696".parse().unwrap();
+        assert_eq!(TrafficInterceptor::parse_socket_addr("192.168.0.136.51696").unwrap(), ipv4);
+
+        let ipv6: SocketAddr = "[::1]:4200".parse().unwrap();
+        assert_eq!(TrafficInterceptor::parse_socket_addr("::1.4200").unwrap(), ipv6);
+
+        let line = "udp6       0      0  2607:f8b0::1.443       ::1.4200";
+        let connection = TrafficInterceptor::parse_netstat_line(line).unwrap();
+        assert_eq!(connection.local_addr, "[2607:f8b0::1]:443".parse().unwrap());
+        assert_eq!(connection.remote_addr, Some(ipv6));
+    }
+}
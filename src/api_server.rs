@@ -0,0 +1,124 @@
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use warp::{Filter, Reply};
+
+/// Binds the HTTP API `routes` to `addr`, over HTTPS with the PEM certificate chain and
+/// key in `tls` when set, and returns the bound address and the server future.
+///
+/// The server drains in-flight requests and stops once `shutdown` resolves. Bind and
+/// certificate errors are returned rather than panicking.
+pub fn bind<F, R>(
+    routes: F,
+    addr: SocketAddr,
+    tls: Option<(&Path, &Path)>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<(SocketAddr, BoxFuture<'static, ()>)>
+where
+    F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+    R: Reply + 'static,
+{
+    let server = warp::serve(routes);
+    match tls {
+        Some((cert, key)) => {
+            let (addr, server) = server
+                .tls()
+                .cert_path(cert)
+                .key_path(key)
+                .try_bind_with_graceful_shutdown(addr, shutdown)
+                .map_err(|e| anyhow::anyhow!("Failed to serve HTTPS on {}: {}", addr, e))?;
+            Ok((addr, server.boxed()))
+        }
+        None => {
+            let (addr, server) = server.try_bind_with_graceful_shutdown(addr, shutdown)?;
+            Ok((addr, server.boxed()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+    use tokio_rustls::TlsConnector;
+
+    const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost-cert.pem");
+    const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost-key.pem");
+    const REQUEST: &[u8] = b"GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+    fn hello() -> impl Filter<Extract = (&'static str,), Error = warp::Rejection> + Clone {
+        warp::path("hello").and(warp::get()).map(|| "hello")
+    }
+
+    /// A client that trusts only the self-signed fixture certificate
+    fn tls_connector() -> TlsConnector {
+        let pem = std::fs::read(CERT).unwrap();
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let config = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+        TlsConnector::from(Arc::new(config))
+    }
+
+    #[tokio::test]
+    async fn test_serves_https_with_configured_certificate() {
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let (addr, server) = bind(
+            hello(),
+            "127.0.0.1:0".parse().unwrap(),
+            Some((Path::new(CERT), Path::new(KEY))),
+            async {
+                let _ = shutdown_rx.await;
+            },
+        )
+        .unwrap();
+        let server = tokio::spawn(server);
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut stream = tls_connector().connect(server_name, stream).await.unwrap();
+        stream.write_all(REQUEST).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("hello"), "{}", response);
+
+        let _ = shutdown_tx.send(());
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serves_plain_http_without_tls() {
+        let (addr, server) = bind(hello(), "127.0.0.1:0".parse().unwrap(), None, std::future::pending()).unwrap();
+        let server = tokio::spawn(server);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(REQUEST).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_key_is_an_error() {
+        let result = bind(
+            hello(),
+            "127.0.0.1:0".parse().unwrap(),
+            Some((Path::new(CERT), Path::new(CERT))),
+            std::future::pending(),
+        );
+
+        let error = result.err().expect("a certificate is not a private key").to_string();
+        assert!(error.contains("Failed to serve HTTPS"), "{}", error);
+    }
+}
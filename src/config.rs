@@ -1,5 +1,23 @@
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use hyper::Uri;
+use tracing::warn;
+
+use crate::destination_rules::DestinationRules;
+use crate::socks5_client::{UpstreamProxy, UpstreamProxyKind};
+use crate::dns::{AddressSort, DnsResolverOptions, DEFAULT_MAX_RETRIES, DEFAULT_NEGATIVE_TTL};
+
+/// How often `Config::watch` checks the file's modification time
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Body bytes budgeted per host: a 253-byte name plus quotes and a separator
+const REQUEST_BODY_BYTES_PER_HOST: u64 = 256;
+
+/// Body bytes budgeted for everything in a resolve request besides `hosts`
+const REQUEST_BODY_OVERHEAD_BYTES: u64 = 1024;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -12,7 +30,95 @@ pub struct Config {
     pub socks5_enabled: bool,
     #[serde(default = "default_socks5_bind_address")]
     pub socks5_bind_address: String,
+    /// Allow the SOCKS5 BIND command for clients that need inbound connections
+    #[serde(default)]
+    pub socks5_bind_command_enabled: bool,
+    /// CIDRs and domain globs SOCKS5 clients may connect to (empty = any)
+    #[serde(default)]
+    pub socks5_allowed_destinations: Vec<String>,
+    /// CIDRs and domain globs SOCKS5 clients may never connect to; wins over the allow list
+    #[serde(default)]
+    pub socks5_denied_destinations: Vec<String>,
+    /// Proxy that SOCKS5 CONNECTs are chained through (None = connect directly)
+    #[serde(default)]
+    pub socks5_upstream: Option<Socks5UpstreamConfig>,
+    /// HTTP proxy that plain HTTP proxy requests are forwarded through (None = go direct)
+    #[serde(default)]
+    pub http_upstream_proxy: Option<String>,
     pub ssh_tunnel_config: Option<SshTunnelConfig>,
+    /// Origins allowed to call the HTTP API from a browser (empty = CORS disabled, `["*"]` = any)
+    #[serde(default)]
+    pub cors_allowed_origins: Vec<String>,
+    /// Largest `hosts` array accepted by `/api/dns/resolve`
+    #[serde(default = "default_max_hosts_per_request")]
+    pub max_hosts_per_request: usize,
+    /// Log output format: human-readable "text" (default) or "json" lines
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// How long the HTTP and SOCKS5 proxies wait for a destination to accept a connection
+    #[serde(default = "default_connect_timeout_seconds")]
+    pub connect_timeout_seconds: u64,
+    /// How long a SOCKS5 connection may go without traffic before it is dropped
+    #[serde(default = "default_proxy_idle_timeout_seconds")]
+    pub proxy_idle_timeout_seconds: u64,
+    /// Connections each proxy handles at once; further clients wait for one to close
+    #[serde(default = "default_max_concurrent_connections")]
+    pub max_concurrent_connections: usize,
+    /// Connections the proxies and DNS listener queue before accepting them
+    #[serde(default = "default_listen_backlog")]
+    pub listen_backlog: u32,
+    /// Domain resolved by `/ready` to check that upstream DNS works
+    #[serde(default = "default_readiness_canary_host")]
+    pub readiness_canary_host: String,
+    /// Answer lookups from the system hosts file before asking upstream
+    #[serde(default = "default_dns_use_hosts_file")]
+    pub dns_use_hosts_file: bool,
+    /// Names with fewer dots than this are tried with the search domains first
+    #[serde(default = "default_dns_ndots")]
+    pub dns_ndots: usize,
+    /// Retries against the upstream name servers after a failed lookup
+    #[serde(default = "default_dns_attempts")]
+    pub dns_attempts: usize,
+    /// Name servers as `ip` or `ip:port`, tried in order on retries (empty = default upstream)
+    #[serde(default)]
+    pub dns_upstreams: Vec<String>,
+    /// Times a lookup that failed upstream (not NXDOMAIN) is retried
+    #[serde(default = "default_dns_max_retries")]
+    pub dns_max_retries: usize,
+    /// Domain globs that are never resolved, e.g. ad or malware domains
+    #[serde(default)]
+    pub dns_blocklist: Vec<String>,
+    /// Domain globs that may be resolved (empty = any not blocked)
+    #[serde(default)]
+    pub dns_allowlist: Vec<String>,
+    /// Names answered with these addresses without asking upstream (case-insensitive)
+    #[serde(default)]
+    pub static_hosts: HashMap<String, Vec<IpAddr>>,
+    /// Default order of resolved addresses: "none", "v4first", "v6first" or "probe:<port>"
+    #[serde(default)]
+    pub dns_address_sort: Option<String>,
+    /// Seconds NXDOMAIN and empty answers stay cached (0 = never cache them)
+    #[serde(default = "default_dns_negative_ttl_seconds")]
+    pub dns_negative_ttl_seconds: u64,
+    /// Also answer standard DNS queries over UDP and TCP on `dns_listener_address`
+    #[serde(default)]
+    pub dns_listener_enabled: bool,
+    #[serde(default = "default_dns_listener_address")]
+    pub dns_listener_address: String,
+    /// PEM certificate chain for serving the HTTP API over HTTPS; set together with `tls_key_path`
+    #[serde(default)]
+    pub tls_cert_path: Option<String>,
+    /// PEM private key matching `tls_cert_path`
+    #[serde(default)]
+    pub tls_key_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
 }
 
 fn default_socks5_enabled() -> bool {
@@ -23,14 +129,87 @@ fn default_socks5_bind_address() -> String {
     "0.0.0.0:9702".to_string()
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn default_max_hosts_per_request() -> usize {
+    256
+}
+
+fn default_connect_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_proxy_idle_timeout_seconds() -> u64 {
+    300
+}
+
+fn default_max_concurrent_connections() -> usize {
+    crate::socks5::DEFAULT_MAX_CONCURRENT_CONNECTIONS
+}
+
+fn default_listen_backlog() -> u32 {
+    crate::socks5::DEFAULT_LISTEN_BACKLOG
+}
+
+fn default_readiness_canary_host() -> String {
+    "a.root-servers.net".to_string()
+}
+
+fn default_dns_use_hosts_file() -> bool {
+    DnsResolverOptions::default().use_hosts_file
+}
+
+fn default_dns_ndots() -> usize {
+    DnsResolverOptions::default().ndots
+}
+
+fn default_dns_attempts() -> usize {
+    DnsResolverOptions::default().attempts
+}
+
+fn default_dns_max_retries() -> usize {
+    DEFAULT_MAX_RETRIES
+}
+
+fn default_dns_negative_ttl_seconds() -> u64 {
+    DEFAULT_NEGATIVE_TTL.as_secs()
+}
+
+fn default_dns_listener_address() -> String {
+    "0.0.0.0:53".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Socks5UpstreamConfig {
+    /// `"socks5"` (default) or `"http"` for an HTTP CONNECT proxy
+    #[serde(default)]
+    pub kind: UpstreamProxyKind,
+    /// Proxy address as `ip:port`
+    pub address: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SshTunnelConfig {
     pub host: String,
     pub port: u16,
     pub username: String,
     pub password: Option<String>,
     pub key_path: Option<String>,
+    /// Passphrase for an encrypted `key_path`, fed to ssh through `sshpass -P passphrase`
+    #[serde(default)]
+    pub key_passphrase: Option<String>,
+    /// When false, skip host key verification so first connects don't block on a prompt
+    #[serde(default)]
+    pub strict_host_key_checking: Option<bool>,
     pub local_port: u16,
+    /// Maximum reconnection attempts after the tunnel drops (None = retry forever)
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+    /// Use the built-in russh client instead of spawning the `ssh` binary
+    #[serde(default)]
+    pub native: bool,
 }
 
 impl Default for Config {
@@ -43,7 +222,34 @@ impl Default for Config {
             proxy_bind_address: "0.0.0.0:9701".to_string(),
             socks5_enabled: false,
             socks5_bind_address: "0.0.0.0:9702".to_string(),
+            socks5_bind_command_enabled: false,
+            socks5_allowed_destinations: Vec::new(),
+            socks5_denied_destinations: Vec::new(),
+            socks5_upstream: None,
+            http_upstream_proxy: None,
             ssh_tunnel_config: None,
+            cors_allowed_origins: Vec::new(),
+            max_hosts_per_request: default_max_hosts_per_request(),
+            log_format: LogFormat::Text,
+            connect_timeout_seconds: default_connect_timeout_seconds(),
+            proxy_idle_timeout_seconds: default_proxy_idle_timeout_seconds(),
+            max_concurrent_connections: default_max_concurrent_connections(),
+            listen_backlog: default_listen_backlog(),
+            readiness_canary_host: default_readiness_canary_host(),
+            dns_use_hosts_file: default_dns_use_hosts_file(),
+            dns_ndots: default_dns_ndots(),
+            dns_attempts: default_dns_attempts(),
+            dns_upstreams: Vec::new(),
+            dns_max_retries: default_dns_max_retries(),
+            dns_blocklist: Vec::new(),
+            dns_allowlist: Vec::new(),
+            static_hosts: HashMap::new(),
+            dns_address_sort: None,
+            dns_negative_ttl_seconds: default_dns_negative_ttl_seconds(),
+            dns_listener_enabled: false,
+            dns_listener_address: default_dns_listener_address(),
+            tls_cert_path: None,
+            tls_key_path: None,
         }
     }
 }
@@ -51,15 +257,475 @@ impl Default for Config {
 impl Config {
     pub fn load() -> anyhow::Result<Self> {
         // Try to load from config file, fallback to defaults
-        if let Ok(config_str) = std::fs::read_to_string("config.json") {
-            let config: Config = serde_json::from_str(&config_str)?;
-            Ok(config)
+        if Path::new("config.json").exists() {
+            return Self::from_file("config.json");
+        }
+
+        // Create default config file
+        let mut config = Config::default();
+        let config_str = serde_json::to_string_pretty(&config)?;
+        std::fs::write("config.json", config_str)?;
+
+        config.apply_overrides(|key| std::env::var(key).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Overrides fields from `WDNS_*` variables looked up through `lookup`
+    /// (the process environment in `load`).
+    pub fn apply_overrides<F>(&mut self, lookup: F) -> anyhow::Result<()>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(value) = lookup("WDNS_BIND_ADDRESS") {
+            self.bind_address = value;
+        }
+        if let Some(value) = lookup("WDNS_DNS_TIMEOUT_SECONDS") {
+            self.dns_timeout_seconds = parse_env_number("WDNS_DNS_TIMEOUT_SECONDS", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_MAX_CONCURRENT_RESOLUTIONS") {
+            self.max_concurrent_resolutions =
+                parse_env_number("WDNS_MAX_CONCURRENT_RESOLUTIONS", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_PROXY_ENABLED") {
+            self.proxy_enabled = parse_env_bool("WDNS_PROXY_ENABLED", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_PROXY_BIND_ADDRESS") {
+            self.proxy_bind_address = value;
+        }
+        if let Some(value) = lookup("WDNS_SOCKS5_ENABLED") {
+            self.socks5_enabled = parse_env_bool("WDNS_SOCKS5_ENABLED", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_SOCKS5_BIND_ADDRESS") {
+            self.socks5_bind_address = value;
+        }
+        if let Some(value) = lookup("WDNS_SOCKS5_BIND_COMMAND_ENABLED") {
+            self.socks5_bind_command_enabled =
+                parse_env_bool("WDNS_SOCKS5_BIND_COMMAND_ENABLED", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_SOCKS5_ALLOWED_DESTINATIONS") {
+            self.socks5_allowed_destinations = parse_env_list(&value);
+        }
+        if let Some(value) = lookup("WDNS_SOCKS5_DENIED_DESTINATIONS") {
+            self.socks5_denied_destinations = parse_env_list(&value);
+        }
+        if let Some(value) = lookup("WDNS_CORS_ALLOWED_ORIGINS") {
+            self.cors_allowed_origins = parse_env_list(&value);
+        }
+        if let Some(value) = lookup("WDNS_HTTP_UPSTREAM_PROXY") {
+            self.http_upstream_proxy = Some(value).filter(|uri| !uri.is_empty());
+        }
+        if let Some(value) = lookup("WDNS_MAX_HOSTS_PER_REQUEST") {
+            self.max_hosts_per_request = parse_env_number("WDNS_MAX_HOSTS_PER_REQUEST", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_LOG_FORMAT") {
+            self.log_format = parse_env_log_format("WDNS_LOG_FORMAT", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_CONNECT_TIMEOUT_SECONDS") {
+            self.connect_timeout_seconds = parse_env_number("WDNS_CONNECT_TIMEOUT_SECONDS", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_PROXY_IDLE_TIMEOUT_SECONDS") {
+            self.proxy_idle_timeout_seconds =
+                parse_env_number("WDNS_PROXY_IDLE_TIMEOUT_SECONDS", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_MAX_CONCURRENT_CONNECTIONS") {
+            self.max_concurrent_connections =
+                parse_env_number("WDNS_MAX_CONCURRENT_CONNECTIONS", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_LISTEN_BACKLOG") {
+            self.listen_backlog = parse_env_number("WDNS_LISTEN_BACKLOG", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_READINESS_CANARY_HOST") {
+            self.readiness_canary_host = value;
+        }
+        if let Some(value) = lookup("WDNS_DNS_USE_HOSTS_FILE") {
+            self.dns_use_hosts_file = parse_env_bool("WDNS_DNS_USE_HOSTS_FILE", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_DNS_NDOTS") {
+            self.dns_ndots = parse_env_number("WDNS_DNS_NDOTS", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_DNS_ATTEMPTS") {
+            self.dns_attempts = parse_env_number("WDNS_DNS_ATTEMPTS", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_DNS_UPSTREAMS") {
+            self.dns_upstreams = parse_env_list(&value);
+        }
+        if let Some(value) = lookup("WDNS_DNS_MAX_RETRIES") {
+            self.dns_max_retries = parse_env_number("WDNS_DNS_MAX_RETRIES", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_DNS_BLOCKLIST") {
+            self.dns_blocklist = parse_env_list(&value);
+        }
+        if let Some(value) = lookup("WDNS_DNS_ALLOWLIST") {
+            self.dns_allowlist = parse_env_list(&value);
+        }
+        if let Some(value) = lookup("WDNS_STATIC_HOSTS") {
+            self.static_hosts = parse_env_static_hosts("WDNS_STATIC_HOSTS", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_DNS_ADDRESS_SORT") {
+            self.dns_address_sort = Some(value).filter(|sort| !sort.is_empty());
+        }
+        if let Some(value) = lookup("WDNS_DNS_NEGATIVE_TTL_SECONDS") {
+            self.dns_negative_ttl_seconds = parse_env_number("WDNS_DNS_NEGATIVE_TTL_SECONDS", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_DNS_LISTENER_ENABLED") {
+            self.dns_listener_enabled = parse_env_bool("WDNS_DNS_LISTENER_ENABLED", &value)?;
+        }
+        if let Some(value) = lookup("WDNS_DNS_LISTENER_ADDRESS") {
+            self.dns_listener_address = value;
+        }
+        if let Some(value) = lookup("WDNS_TLS_CERT_PATH") {
+            self.tls_cert_path = Some(value).filter(|path| !path.is_empty());
+        }
+        if let Some(value) = lookup("WDNS_TLS_KEY_PATH") {
+            self.tls_key_path = Some(value).filter(|path| !path.is_empty());
+        }
+        Ok(())
+    }
+
+    /// Reads a config file, layering environment overrides on top and
+    /// validating the result, exactly as `load` does.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let config_str = std::fs::read_to_string(path)?;
+        let mut config: Config = serde_json::from_str(&config_str)?;
+        config.apply_overrides(|key| std::env::var(key).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Polls `path` for modifications and calls `on_change` with each new
+    /// config that parses and validates. Invalid edits are logged and skipped.
+    pub async fn watch<P, F>(path: P, on_change: F)
+    where
+        P: AsRef<Path>,
+        F: Fn(Config),
+    {
+        Self::watch_with_interval(path, WATCH_INTERVAL, on_change).await
+    }
+
+    pub async fn watch_with_interval<P, F>(path: P, interval: Duration, on_change: F)
+    where
+        P: AsRef<Path>,
+        F: Fn(Config),
+    {
+        let path = path.as_ref();
+        let modified = |path: &Path| -> Option<SystemTime> {
+            std::fs::metadata(path).and_then(|m| m.modified()).ok()
+        };
+
+        let mut last_modified = modified(path);
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let current = modified(path);
+            if current == last_modified {
+                continue;
+            }
+            last_modified = current;
+
+            match Self::from_file(path) {
+                Ok(config) => on_change(config),
+                Err(e) => warn!("Ignoring invalid config change in {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    /// Names of the fields that differ from `other` and only take effect
+    /// after a restart.
+    pub fn restart_required_changes(&self, other: &Config) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.bind_address != other.bind_address {
+            changed.push("bind_address");
+        }
+        if self.proxy_enabled != other.proxy_enabled {
+            changed.push("proxy_enabled");
+        }
+        if self.proxy_bind_address != other.proxy_bind_address {
+            changed.push("proxy_bind_address");
+        }
+        if self.socks5_enabled != other.socks5_enabled {
+            changed.push("socks5_enabled");
+        }
+        if self.socks5_bind_address != other.socks5_bind_address {
+            changed.push("socks5_bind_address");
+        }
+        if self.socks5_bind_command_enabled != other.socks5_bind_command_enabled {
+            changed.push("socks5_bind_command_enabled");
+        }
+        if self.socks5_allowed_destinations != other.socks5_allowed_destinations {
+            changed.push("socks5_allowed_destinations");
+        }
+        if self.socks5_denied_destinations != other.socks5_denied_destinations {
+            changed.push("socks5_denied_destinations");
+        }
+        if self.socks5_upstream != other.socks5_upstream {
+            changed.push("socks5_upstream");
+        }
+        if self.http_upstream_proxy != other.http_upstream_proxy {
+            changed.push("http_upstream_proxy");
+        }
+        if self.cors_allowed_origins != other.cors_allowed_origins {
+            changed.push("cors_allowed_origins");
+        }
+        if self.max_hosts_per_request != other.max_hosts_per_request {
+            changed.push("max_hosts_per_request");
+        }
+        if self.log_format != other.log_format {
+            changed.push("log_format");
+        }
+        if self.connect_timeout_seconds != other.connect_timeout_seconds {
+            changed.push("connect_timeout_seconds");
+        }
+        if self.proxy_idle_timeout_seconds != other.proxy_idle_timeout_seconds {
+            changed.push("proxy_idle_timeout_seconds");
+        }
+        if self.max_concurrent_connections != other.max_concurrent_connections {
+            changed.push("max_concurrent_connections");
+        }
+        if self.listen_backlog != other.listen_backlog {
+            changed.push("listen_backlog");
+        }
+        if self.readiness_canary_host != other.readiness_canary_host {
+            changed.push("readiness_canary_host");
+        }
+        if self.dns_use_hosts_file != other.dns_use_hosts_file {
+            changed.push("dns_use_hosts_file");
+        }
+        if self.dns_ndots != other.dns_ndots {
+            changed.push("dns_ndots");
+        }
+        if self.dns_attempts != other.dns_attempts {
+            changed.push("dns_attempts");
+        }
+        if self.dns_upstreams != other.dns_upstreams {
+            changed.push("dns_upstreams");
+        }
+        if self.dns_blocklist != other.dns_blocklist {
+            changed.push("dns_blocklist");
+        }
+        if self.dns_allowlist != other.dns_allowlist {
+            changed.push("dns_allowlist");
+        }
+        if self.static_hosts != other.static_hosts {
+            changed.push("static_hosts");
+        }
+        if self.dns_address_sort != other.dns_address_sort {
+            changed.push("dns_address_sort");
+        }
+        if self.dns_negative_ttl_seconds != other.dns_negative_ttl_seconds {
+            changed.push("dns_negative_ttl_seconds");
+        }
+        if self.dns_listener_enabled != other.dns_listener_enabled {
+            changed.push("dns_listener_enabled");
+        }
+        if self.dns_listener_address != other.dns_listener_address {
+            changed.push("dns_listener_address");
+        }
+        if self.tls_cert_path != other.tls_cert_path {
+            changed.push("tls_cert_path");
+        }
+        if self.tls_key_path != other.tls_key_path {
+            changed.push("tls_key_path");
+        }
+        changed
+    }
+
+    /// Checks that every field holds a usable value, naming the offending
+    /// field in the error so a bad `config.json` fails fast at startup.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let dns_addr = self.bind_addr()?;
+
+        if self.dns_timeout_seconds == 0 {
+            anyhow::bail!("Invalid dns_timeout_seconds: must be greater than 0");
+        }
+
+        if self.max_concurrent_resolutions == 0 {
+            anyhow::bail!("Invalid max_concurrent_resolutions: must be greater than 0");
+        }
+
+        if self.max_hosts_per_request == 0 {
+            anyhow::bail!("Invalid max_hosts_per_request: must be greater than 0");
+        }
+
+        if self.connect_timeout_seconds == 0 {
+            anyhow::bail!("Invalid connect_timeout_seconds: must be greater than 0");
+        }
+
+        if self.proxy_idle_timeout_seconds == 0 {
+            anyhow::bail!("Invalid proxy_idle_timeout_seconds: must be greater than 0");
+        }
+
+        if self.max_concurrent_connections == 0 {
+            anyhow::bail!("Invalid max_concurrent_connections: must be greater than 0");
+        }
+
+        if self.listen_backlog == 0 {
+            anyhow::bail!("Invalid listen_backlog: must be greater than 0");
+        }
+
+        let proxy_addr = if self.proxy_enabled {
+            let proxy_addr = self.proxy_bind_addr()?;
+            if proxy_addr.port() == dns_addr.port() {
+                anyhow::bail!(
+                    "Invalid proxy_bind_address '{}': port {} is already used by bind_address",
+                    self.proxy_bind_address,
+                    proxy_addr.port()
+                );
+            }
+            Some(proxy_addr)
         } else {
-            // Create default config file
-            let config = Config::default();
-            let config_str = serde_json::to_string_pretty(&config)?;
-            std::fs::write("config.json", config_str)?;
-            Ok(config)
+            None
+        };
+
+        if self.socks5_enabled {
+            let socks5_addr = self.socks5_bind_addr()?;
+            if socks5_addr.port() == dns_addr.port() {
+                anyhow::bail!(
+                    "Invalid socks5_bind_address '{}': port {} is already used by bind_address",
+                    self.socks5_bind_address,
+                    socks5_addr.port()
+                );
+            }
+            if proxy_addr.is_some_and(|proxy_addr| proxy_addr.port() == socks5_addr.port()) {
+                anyhow::bail!(
+                    "Invalid socks5_bind_address '{}': port {} is already used by proxy_bind_address",
+                    self.socks5_bind_address,
+                    socks5_addr.port()
+                );
+            }
+        }
+
+        if self.dns_listener_enabled {
+            let listener_addr = self.dns_listener_addr()?;
+            if listener_addr.port() == dns_addr.port() {
+                anyhow::bail!(
+                    "Invalid dns_listener_address '{}': port {} is already used by bind_address",
+                    self.dns_listener_address,
+                    listener_addr.port()
+                );
+            }
+        }
+
+        self.socks5_destination_rules()?;
+        self.socks5_upstream_proxy()?;
+        self.http_upstream_proxy_uri()?;
+        self.dns_upstream_addrs()?;
+        crate::dns::parse_domain_rules(&self.dns_blocklist)
+            .map_err(|e| anyhow::anyhow!("Invalid dns_blocklist: {}", e))?;
+        crate::dns::parse_domain_rules(&self.dns_allowlist)
+            .map_err(|e| anyhow::anyhow!("Invalid dns_allowlist: {}", e))?;
+        for (host, addrs) in &self.static_hosts {
+            if let Err(e) = crate::dns::parse_host_segment(host) {
+                anyhow::bail!("Invalid static_hosts entry '{}': {}", host, e);
+            }
+            if addrs.is_empty() {
+                anyhow::bail!("Invalid static_hosts entry '{}': at least one address is required", host);
+            }
+        }
+        self.dns_address_sort()?;
+        self.tls_paths()?;
+
+        if let Err(e) = crate::dns::parse_host_segment(&self.readiness_canary_host) {
+            anyhow::bail!("Invalid readiness_canary_host: {}", e);
+        }
+
+        for origin in &self.cors_allowed_origins {
+            if origin == "*" {
+                if self.cors_allowed_origins.len() > 1 {
+                    anyhow::bail!("Invalid cors_allowed_origins: \"*\" must be the only entry");
+                }
+            } else if !is_valid_origin(origin) {
+                anyhow::bail!(
+                    "Invalid cors_allowed_origins entry '{}': expected scheme://host[:port]",
+                    origin
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Largest request body accepted by the API, sized to fit
+    /// `max_hosts_per_request` maximum-length host names.
+    pub fn max_request_body_bytes(&self) -> u64 {
+        (self.max_hosts_per_request as u64)
+            .saturating_mul(REQUEST_BODY_BYTES_PER_HOST)
+            .saturating_add(REQUEST_BODY_OVERHEAD_BYTES)
+    }
+
+    /// Upstream lookup options built from the `dns_*` fields
+    pub fn dns_resolver_options(&self) -> DnsResolverOptions {
+        DnsResolverOptions {
+            use_hosts_file: self.dns_use_hosts_file,
+            ndots: self.dns_ndots,
+            attempts: self.dns_attempts,
+            ..DnsResolverOptions::default()
+        }
+    }
+
+    /// Parsed `dns_address_sort`, `AddressSort::None` when unset
+    pub fn dns_address_sort(&self) -> anyhow::Result<AddressSort> {
+        AddressSort::parse(self.dns_address_sort.as_deref())
+            .map_err(|e| anyhow::anyhow!("Invalid dns_address_sort: {}", e))
+    }
+
+    /// Parsed `dns_upstreams`, defaulting to port 53
+    pub fn dns_upstream_addrs(&self) -> anyhow::Result<Vec<SocketAddr>> {
+        self.dns_upstreams
+            .iter()
+            .map(|upstream| {
+                upstream
+                    .parse::<SocketAddr>()
+                    .or_else(|_| upstream.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                    .map_err(|_| {
+                        anyhow::anyhow!(
+                            "Invalid dns_upstreams entry '{}': expected ip or ip:port",
+                            upstream
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// Parsed `socks5_allowed_destinations` and `socks5_denied_destinations`
+    pub fn socks5_destination_rules(&self) -> anyhow::Result<DestinationRules> {
+        DestinationRules::new(&self.socks5_allowed_destinations, &self.socks5_denied_destinations)
+            .map_err(|e| anyhow::anyhow!("Invalid SOCKS5 destination rules: {}", e))
+    }
+
+    /// Parsed `socks5_upstream`, if one is configured
+    pub fn socks5_upstream_proxy(&self) -> anyhow::Result<Option<UpstreamProxy>> {
+        let Some(upstream) = &self.socks5_upstream else {
+            return Ok(None);
+        };
+        let addr = upstream.address.parse().map_err(|e| {
+            anyhow::anyhow!("Invalid socks5_upstream address '{}': {}", upstream.address, e)
+        })?;
+        let auth = upstream
+            .username
+            .clone()
+            .map(|username| (username, upstream.password.clone().unwrap_or_default()));
+        Ok(Some(UpstreamProxy { kind: upstream.kind, addr, auth }))
+    }
+
+    /// Parsed `http_upstream_proxy`, if one is configured
+    pub fn http_upstream_proxy_uri(&self) -> anyhow::Result<Option<Uri>> {
+        let Some(upstream) = &self.http_upstream_proxy else {
+            return Ok(None);
+        };
+        let invalid = |reason: &str| {
+            anyhow::anyhow!("Invalid http_upstream_proxy '{}': {}", upstream, reason)
+        };
+        let uri: Uri = upstream.parse().map_err(|e: hyper::http::uri::InvalidUri| invalid(&e.to_string()))?;
+        if uri.scheme_str() != Some("http") {
+            return Err(invalid("expected an http:// URI"));
+        }
+        match uri.authority() {
+            None => Err(invalid("missing host")),
+            Some(authority) if authority.as_str().contains('@') => {
+                Err(invalid("credentials are not supported"))
+            }
+            Some(_) => Ok(Some(uri)),
         }
     }
 
@@ -77,22 +743,196 @@ impl Config {
         self.socks5_bind_address.parse()
             .map_err(|e| anyhow::anyhow!("Invalid SOCKS5 bind address '{}': {}", self.socks5_bind_address, e))
     }
+
+    pub fn dns_listener_addr(&self) -> anyhow::Result<SocketAddr> {
+        self.dns_listener_address.parse()
+            .map_err(|e| anyhow::anyhow!("Invalid DNS listener address '{}': {}", self.dns_listener_address, e))
+    }
+
+    /// Certificate and key paths when the HTTP API is served over HTTPS,
+    /// or None for plain HTTP
+    pub fn tls_paths(&self) -> anyhow::Result<Option<(&Path, &Path)>> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (None, None) => Ok(None),
+            (Some(cert), Some(key)) => {
+                let (cert, key) = (Path::new(cert), Path::new(key));
+                for (field, path) in [("tls_cert_path", cert), ("tls_key_path", key)] {
+                    if !path.is_file() {
+                        anyhow::bail!("Invalid {} '{}': file not found", field, path.display());
+                    }
+                }
+                Ok(Some((cert, key)))
+            }
+            (Some(_), None) => anyhow::bail!("Invalid tls_key_path: required when tls_cert_path is set"),
+            (None, Some(_)) => anyhow::bail!("Invalid tls_cert_path: required when tls_key_path is set"),
+        }
+    }
+}
+
+/// Whether `origin` is a bare `scheme://host[:port]` with no path
+fn is_valid_origin(origin: &str) -> bool {
+    let Some((scheme, authority)) = origin.split_once("://") else {
+        return false;
+    };
+    !scheme.is_empty()
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        && !authority.contains(['/', '@'])
+        && authority.parse::<warp::http::uri::Authority>().is_ok()
+}
+
+/// Splits a comma-separated variable, dropping blank entries
+fn parse_env_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Parses `name=ip` pairs separated by commas; repeating a name adds addresses
+fn parse_env_static_hosts(key: &str, value: &str) -> anyhow::Result<HashMap<String, Vec<IpAddr>>> {
+    let mut hosts: HashMap<String, Vec<IpAddr>> = HashMap::new();
+    for entry in parse_env_list(value) {
+        let (host, addr) = entry
+            .split_once('=')
+            .and_then(|(host, addr)| Some((host.trim(), addr.trim().parse::<IpAddr>().ok()?)))
+            .ok_or_else(|| anyhow::anyhow!("Invalid {} entry '{}': expected name=ip", key, entry))?;
+        hosts.entry(host.to_string()).or_default().push(addr);
+    }
+    Ok(hosts)
+}
+
+fn parse_env_bool(key: &str, value: &str) -> anyhow::Result<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" => Ok(false),
+        _ => Err(anyhow::anyhow!(
+            "Invalid {} '{}': expected 1/true/yes or 0/false/no",
+            key,
+            value
+        )),
+    }
+}
+
+fn parse_env_log_format(key: &str, value: &str) -> anyhow::Result<LogFormat> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "text" => Ok(LogFormat::Text),
+        "json" => Ok(LogFormat::Json),
+        _ => Err(anyhow::anyhow!("Invalid {} '{}': expected text or json", key, value)),
+    }
+}
+
+fn parse_env_number<T: std::str::FromStr>(key: &str, value: &str) -> anyhow::Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid {} '{}': {}", key, value, e))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
+    use std::sync::Mutex;
     use tempfile::TempDir;
 
+    // Tests that change the working directory or WDNS_* variables must not overlap
+    static CWD_LOCK: Mutex<()> = Mutex::new(());
+
     #[test]
     fn test_default_config() {
         let config = Config::default();
         assert_eq!(config.bind_address, "0.0.0.0:9700");
         assert_eq!(config.dns_timeout_seconds, 10);
         assert_eq!(config.max_concurrent_resolutions, 100);
-        assert_eq!(config.proxy_enabled, true);
+        assert!(config.proxy_enabled);
         assert_eq!(config.proxy_bind_address, "0.0.0.0:9701");
+        assert!(!config.socks5_enabled);
+        assert_eq!(config.socks5_bind_address, "0.0.0.0:9702");
+        assert!(!config.socks5_bind_command_enabled);
+        assert!(config.socks5_allowed_destinations.is_empty());
+        assert!(config.socks5_denied_destinations.is_empty());
+        assert!(config.ssh_tunnel_config.is_none());
+        assert!(config.socks5_upstream.is_none());
+        assert!(config.http_upstream_proxy.is_none());
+        assert_eq!(config.max_hosts_per_request, 256);
+        assert_eq!(config.log_format, LogFormat::Text);
+        assert_eq!(config.connect_timeout_seconds, 10);
+        assert_eq!(config.proxy_idle_timeout_seconds, 300);
+        assert_eq!(config.max_concurrent_connections, 1024);
+        assert_eq!(config.listen_backlog, 1024);
+        assert_eq!(config.readiness_canary_host, "a.root-servers.net");
+        assert!(config.dns_use_hosts_file);
+        assert_eq!(config.dns_ndots, 1);
+        assert_eq!(config.dns_attempts, 2);
+        assert_eq!(config.dns_resolver_options(), DnsResolverOptions::default());
+        assert!(config.dns_upstreams.is_empty());
+        assert_eq!(config.dns_max_retries, 1);
+        assert!(config.dns_blocklist.is_empty());
+        assert!(config.dns_allowlist.is_empty());
+        assert!(config.static_hosts.is_empty());
+        assert_eq!(config.dns_address_sort().unwrap(), AddressSort::None);
+        assert_eq!(config.dns_negative_ttl_seconds, 30);
+        assert!(!config.dns_listener_enabled);
+        assert_eq!(config.dns_listener_address, "0.0.0.0:53");
+        assert!(config.tls_paths().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_config_socks5_bind_addr() {
+        let config = Config::default();
+        let addr = config.socks5_bind_addr().expect("Failed to parse SOCKS5 bind address");
+        assert_eq!(addr.to_string(), "0.0.0.0:9702");
+
+        let config = Config {
+            socks5_bind_address: "invalid-address".to_string(),
+            ..Config::default()
+        };
+        let result = config.socks5_bind_addr();
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("invalid-address"));
+    }
+
+    #[test]
+    fn test_config_without_socks5_fields() {
+        // Config files written before SOCKS5 support still load
+        let json = r#"{
+            "bind_address": "0.0.0.0:9700",
+            "dns_timeout_seconds": 10,
+            "max_concurrent_resolutions": 100,
+            "proxy_enabled": true,
+            "proxy_bind_address": "0.0.0.0:9701"
+        }"#;
+        let config: Config = serde_json::from_str(json).expect("Failed to deserialize config");
+
+        assert!(!config.socks5_enabled);
+        assert_eq!(config.socks5_bind_address, "0.0.0.0:9702");
+        assert!(config.ssh_tunnel_config.is_none());
+        assert!(config.cors_allowed_origins.is_empty());
+        assert_eq!(config.max_hosts_per_request, 256);
+        assert_eq!(config.log_format, LogFormat::Text);
+        assert_eq!(config.connect_timeout_seconds, 10);
+        assert_eq!(config.proxy_idle_timeout_seconds, 300);
+    }
+
+    #[test]
+    fn test_config_log_format() {
+        let config: Config = serde_json::from_str(
+            &serde_json::to_string(&Config::default())
+                .unwrap()
+                .replace(r#""log_format":"text""#, r#""log_format":"json""#),
+        )
+        .expect("Failed to deserialize config");
+        assert_eq!(config.log_format, LogFormat::Json);
+
+        let json = serde_json::to_string(&Config::default())
+            .unwrap()
+            .replace(r#""log_format":"text""#, r#""log_format":"xml""#);
+        assert!(serde_json::from_str::<Config>(&json).is_err());
     }
 
     #[test]
@@ -104,6 +944,38 @@ mod tests {
         assert_eq!(config.bind_address, deserialized.bind_address);
         assert_eq!(config.dns_timeout_seconds, deserialized.dns_timeout_seconds);
         assert_eq!(config.max_concurrent_resolutions, deserialized.max_concurrent_resolutions);
+        assert_eq!(config.socks5_enabled, deserialized.socks5_enabled);
+        assert_eq!(config.socks5_bind_address, deserialized.socks5_bind_address);
+    }
+
+    #[test]
+    fn test_config_ssh_tunnel_round_trip() {
+        let _guard = CWD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config_path = temp_dir.path().join("config.json");
+
+        let ssh_config = SshTunnelConfig {
+            host: "bastion.example.com".to_string(),
+            port: 2222,
+            username: "wdns".to_string(),
+            password: None,
+            key_path: Some("/home/wdns/.ssh/id_ed25519".to_string()),
+            key_passphrase: None,
+            strict_host_key_checking: Some(true),
+            local_port: 1080,
+            max_retries: Some(5),
+            native: false,
+        };
+        let config = Config {
+            ssh_tunnel_config: Some(ssh_config.clone()),
+            ..Config::default()
+        };
+
+        let json = serde_json::to_string_pretty(&config).expect("Failed to serialize config");
+        fs::write(&config_path, json).expect("Failed to write config file");
+
+        let loaded = Config::from_file(&config_path).expect("Failed to load config");
+        assert_eq!(loaded.ssh_tunnel_config, Some(ssh_config));
     }
 
     #[test]
@@ -115,16 +987,632 @@ mod tests {
 
     #[test]
     fn test_config_bind_addr_invalid() {
-        let mut config = Config::default();
-        config.bind_address = "invalid-address".to_string();
+        let config = Config {
+            bind_address: "invalid-address".to_string(),
+            ..Config::default()
+        };
         
         let result = config.bind_addr();
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("invalid-address"));
     }
 
+    #[test]
+    fn test_validate_default_config() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_invalid_bind_address() {
+        let config = Config {
+            bind_address: "not-an-address".to_string(),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("bind address 'not-an-address'"));
+    }
+
+    #[test]
+    fn test_validate_invalid_proxy_bind_address() {
+        let config = Config {
+            proxy_bind_address: "not-an-address".to_string(),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("proxy bind address 'not-an-address'"));
+    }
+
+    #[test]
+    fn test_validate_zero_timeout() {
+        let config = Config {
+            dns_timeout_seconds: 0,
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("dns_timeout_seconds"));
+    }
+
+    #[test]
+    fn test_validate_zero_concurrency() {
+        let config = Config {
+            max_concurrent_resolutions: 0,
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("max_concurrent_resolutions"));
+    }
+
+    #[test]
+    fn test_validate_socks5_destination_rules() {
+        let config = Config {
+            socks5_allowed_destinations: vec!["10.0.0.0/8".to_string(), "*.corp.example".to_string()],
+            socks5_denied_destinations: vec!["10.0.0.1".to_string()],
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let config = Config {
+            socks5_denied_destinations: vec!["10.0.0.0/40".to_string()],
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("SOCKS5 destination rules"), "{}", err);
+        assert!(err.contains("10.0.0.0/40"), "{}", err);
+    }
+
+    #[test]
+    fn test_http_upstream_proxy_uri() {
+        let config = Config {
+            http_upstream_proxy: Some("http://proxy.corp.example:3128".to_string()),
+            ..Config::default()
+        };
+        let uri = config.http_upstream_proxy_uri().unwrap().unwrap();
+        assert_eq!(uri.host(), Some("proxy.corp.example"));
+        assert_eq!(uri.port_u16(), Some(3128));
+        assert!(config.validate().is_ok());
+        assert_eq!(Config::default().http_upstream_proxy_uri().unwrap(), None);
+
+        for bad in ["https://proxy:3128", "proxy:3128", "http://user:pw@proxy:3128", "not a uri"] {
+            let config = Config {
+                http_upstream_proxy: Some(bad.to_string()),
+                ..Config::default()
+            };
+            let err = config.validate().unwrap_err().to_string();
+            assert!(err.contains("http_upstream_proxy"), "{}: {}", bad, err);
+        }
+    }
+
+    #[test]
+    fn test_socks5_upstream_proxy() {
+        let upstream: Socks5UpstreamConfig =
+            serde_json::from_str(r#"{"kind": "http", "address": "10.0.0.8:3128", "username": "egress"}"#)
+                .expect("Failed to parse upstream");
+        let config = Config {
+            socks5_upstream: Some(upstream),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.socks5_upstream_proxy().unwrap(),
+            Some(UpstreamProxy {
+                kind: UpstreamProxyKind::Http,
+                addr: "10.0.0.8:3128".parse().unwrap(),
+                auth: Some(("egress".to_string(), String::new())),
+            })
+        );
+
+        let config = Config {
+            socks5_upstream: Some(Socks5UpstreamConfig {
+                kind: UpstreamProxyKind::Socks5,
+                address: "proxy.corp:1080".to_string(),
+                username: None,
+                password: None,
+            }),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("socks5_upstream"), "{}", err);
+        assert_eq!(Config::default().socks5_upstream_proxy().unwrap(), None);
+    }
+
+    #[test]
+    fn test_validate_readiness_canary_host() {
+        let config = Config {
+            readiness_canary_host: "bad host".to_string(),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("readiness_canary_host"));
+    }
+
+    #[test]
+    fn test_validate_dns_listener_address() {
+        let config = Config {
+            dns_listener_enabled: true,
+            dns_listener_address: "0.0.0.0:9700".to_string(),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("dns_listener_address"));
+
+        let config = Config {
+            dns_listener_enabled: true,
+            dns_listener_address: "not an address".to_string(),
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+
+        // The address is only checked when the listener is enabled
+        let config = Config {
+            dns_listener_address: "not an address".to_string(),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_tls_paths() {
+        let existing = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml").to_string();
+
+        let config = Config {
+            tls_cert_path: Some(existing.clone()),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("tls_key_path"));
+
+        let config = Config {
+            tls_key_path: Some(existing.clone()),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("tls_cert_path"));
+
+        let config = Config {
+            tls_cert_path: Some(existing.clone()),
+            tls_key_path: Some("/nonexistent/wdns-key.pem".to_string()),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("tls_key_path") && err.contains("not found"));
+
+        let config = Config {
+            tls_cert_path: Some(existing.clone()),
+            tls_key_path: Some(existing.clone()),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.tls_paths().unwrap(),
+            Some((Path::new(&existing), Path::new(&existing)))
+        );
+    }
+
+    #[test]
+    fn test_validate_dns_upstreams() {
+        let config = Config {
+            dns_upstreams: vec!["1.1.1.1".to_string(), "[2606:4700::1111]:5353".to_string()],
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.dns_upstream_addrs().unwrap(),
+            vec![
+                "1.1.1.1:53".parse::<SocketAddr>().unwrap(),
+                "[2606:4700::1111]:5353".parse().unwrap()
+            ]
+        );
+
+        let config = Config {
+            dns_upstreams: vec!["dns.example.com".to_string()],
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("dns_upstreams"));
+    }
+
+    #[test]
+    fn test_validate_dns_address_sort() {
+        let config = Config {
+            dns_address_sort: Some("probe:443".to_string()),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+        assert_eq!(config.dns_address_sort().unwrap(), AddressSort::Probe(443));
+
+        for bad in ["fastest", "probe:", "probe:0", "probe:https"] {
+            let config = Config {
+                dns_address_sort: Some(bad.to_string()),
+                ..Config::default()
+            };
+            let err = config.validate().unwrap_err().to_string();
+            assert!(err.contains("dns_address_sort"), "{}: {}", bad, err);
+        }
+    }
+
+    #[test]
+    fn test_validate_dns_domain_lists() {
+        let config = Config {
+            dns_blocklist: vec!["*.ads.example".to_string()],
+            dns_allowlist: vec!["*.corp.example".to_string(), "intranet".to_string()],
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let config = Config {
+            dns_blocklist: vec!["10.0.0.0/8".to_string()],
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("dns_blocklist"), "{}", err);
+
+        let config = Config {
+            dns_allowlist: vec!["not a host".to_string()],
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("dns_allowlist"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate_static_hosts() {
+        let config = Config {
+            static_hosts: HashMap::from([("internal.test".to_string(), vec!["10.1.2.3".parse().unwrap()])]),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let config = Config {
+            static_hosts: HashMap::from([("internal.test".to_string(), vec![])]),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("static_hosts"), "{}", err);
+
+        let config = Config {
+            static_hosts: HashMap::from([("bad host".to_string(), vec!["10.1.2.3".parse().unwrap()])]),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("static_hosts"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate_zero_proxy_timeouts() {
+        let config = Config {
+            connect_timeout_seconds: 0,
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("connect_timeout_seconds"));
+
+        let config = Config {
+            proxy_idle_timeout_seconds: 0,
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("proxy_idle_timeout_seconds"));
+    }
+
+    #[test]
+    fn test_validate_zero_max_concurrent_connections() {
+        let config = Config {
+            max_concurrent_connections: 0,
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("max_concurrent_connections"));
+    }
+
+    #[test]
+    fn test_validate_zero_listen_backlog() {
+        let config = Config {
+            listen_backlog: 0,
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("listen_backlog"));
+    }
+
+    #[test]
+    fn test_validate_zero_max_hosts_per_request() {
+        let config = Config {
+            max_hosts_per_request: 0,
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("max_hosts_per_request"));
+    }
+
+    #[test]
+    fn test_max_request_body_bytes() {
+        let config = Config {
+            max_hosts_per_request: 10,
+            ..Config::default()
+        };
+        assert_eq!(config.max_request_body_bytes(), 10 * 256 + 1024);
+
+        // A full batch of maximum-length names fits in the limit
+        let request = serde_json::json!({
+            "hosts": vec![format!("{}.com", "a".repeat(249)); 10],
+            "strict": true,
+            "family": "any"
+        });
+        let body = serde_json::to_vec(&request).unwrap();
+        assert!((body.len() as u64) < config.max_request_body_bytes());
+    }
+
+    #[test]
+    fn test_validate_proxy_port_collision() {
+        let config = Config {
+            proxy_bind_address: "127.0.0.1:9700".to_string(),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("proxy_bind_address"));
+
+        // A disabled proxy may share the port
+        let config = Config {
+            proxy_enabled: false,
+            proxy_bind_address: "127.0.0.1:9700".to_string(),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_socks5_port_collision() {
+        let config = Config {
+            socks5_enabled: true,
+            socks5_bind_address: "0.0.0.0:9700".to_string(),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("socks5_bind_address"));
+        assert!(err.contains("bind_address"));
+
+        let config = Config {
+            socks5_enabled: true,
+            socks5_bind_address: "0.0.0.0:9701".to_string(),
+            ..Config::default()
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("proxy_bind_address"));
+    }
+
+    #[test]
+    fn test_validate_cors_allowed_origins() {
+        let with_origins = |origins: &[&str]| Config {
+            cors_allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            ..Config::default()
+        };
+
+        assert!(with_origins(&["*"]).validate().is_ok());
+        assert!(with_origins(&["http://localhost:3000", "https://dash.internal"]).validate().is_ok());
+
+        let err = with_origins(&["*", "http://localhost:3000"]).validate().unwrap_err().to_string();
+        assert!(err.contains("must be the only entry"));
+
+        for origin in ["dash.internal", "http://dash.internal/", "https://", "http://user@dash.internal"] {
+            let err = with_origins(&[origin]).validate().unwrap_err().to_string();
+            assert!(err.contains("cors_allowed_origins"), "{} was accepted", origin);
+        }
+    }
+
+    #[test]
+    fn test_restart_required_changes() {
+        let config = Config::default();
+        let reloaded = Config {
+            dns_timeout_seconds: 3,
+            max_concurrent_resolutions: 5,
+            ..Config::default()
+        };
+        assert!(config.restart_required_changes(&reloaded).is_empty());
+
+        let reloaded = Config {
+            bind_address: "127.0.0.1:9800".to_string(),
+            socks5_enabled: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            config.restart_required_changes(&reloaded),
+            vec!["bind_address", "socks5_enabled"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config_watch_reports_valid_changes() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config_path = temp_dir.path().join("config.json");
+        let write_config = |config: &Config| {
+            fs::write(&config_path, serde_json::to_string_pretty(config).unwrap())
+                .expect("Failed to write config file");
+        };
+        write_config(&Config::default());
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let watched_path = config_path.clone();
+        let watcher = tokio::spawn(async move {
+            Config::watch_with_interval(watched_path, Duration::from_millis(20), move |config| {
+                let _ = tx.send(config);
+            })
+            .await
+        });
+
+        // An invalid edit is skipped
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        write_config(&Config {
+            max_concurrent_resolutions: 0,
+            ..Config::default()
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(rx.try_recv().is_err());
+
+        // A valid edit is delivered
+        write_config(&Config {
+            max_concurrent_resolutions: 7,
+            ..Config::default()
+        });
+        let reloaded = tokio::time::timeout(Duration::from_secs(2), rx.recv())
+            .await
+            .expect("No config change reported")
+            .unwrap();
+        assert_eq!(reloaded.max_concurrent_resolutions, 7);
+
+        watcher.abort();
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let vars: std::collections::HashMap<&str, &str> = [
+            ("WDNS_BIND_ADDRESS", "127.0.0.1:8000"),
+            ("WDNS_DNS_TIMEOUT_SECONDS", "4"),
+            ("WDNS_MAX_CONCURRENT_RESOLUTIONS", "12"),
+            ("WDNS_PROXY_ENABLED", "no"),
+            ("WDNS_PROXY_BIND_ADDRESS", "127.0.0.1:8001"),
+            ("WDNS_SOCKS5_ENABLED", "YES"),
+            ("WDNS_SOCKS5_BIND_ADDRESS", "127.0.0.1:8002"),
+            ("WDNS_SOCKS5_BIND_COMMAND_ENABLED", "true"),
+            ("WDNS_SOCKS5_ALLOWED_DESTINATIONS", "10.0.0.0/8, *.corp.example"),
+            ("WDNS_SOCKS5_DENIED_DESTINATIONS", "10.0.0.1"),
+            ("WDNS_HTTP_UPSTREAM_PROXY", "http://10.0.0.8:3128"),
+            ("WDNS_CORS_ALLOWED_ORIGINS", "http://a.internal, https://b.internal:8443,"),
+            ("WDNS_MAX_HOSTS_PER_REQUEST", "32"),
+            ("WDNS_LOG_FORMAT", "JSON"),
+            ("WDNS_CONNECT_TIMEOUT_SECONDS", "3"),
+            ("WDNS_PROXY_IDLE_TIMEOUT_SECONDS", "60"),
+            ("WDNS_MAX_CONCURRENT_CONNECTIONS", "64"),
+            ("WDNS_LISTEN_BACKLOG", "4096"),
+            ("WDNS_READINESS_CANARY_HOST", "dns.corp.example"),
+            ("WDNS_DNS_USE_HOSTS_FILE", "0"),
+            ("WDNS_DNS_NDOTS", "0"),
+            ("WDNS_DNS_ATTEMPTS", "4"),
+            ("WDNS_DNS_UPSTREAMS", "10.0.0.53, 10.0.1.53:5353"),
+            ("WDNS_DNS_MAX_RETRIES", "3"),
+            ("WDNS_DNS_BLOCKLIST", "*.ads.example, tracker.example"),
+            ("WDNS_DNS_ALLOWLIST", "*.corp.example"),
+            ("WDNS_STATIC_HOSTS", "internal.test=10.1.2.3, internal.test=fd00::3,db.test=10.1.2.4"),
+            ("WDNS_DNS_ADDRESS_SORT", "v4first"),
+            ("WDNS_DNS_NEGATIVE_TTL_SECONDS", "0"),
+            ("WDNS_DNS_LISTENER_ENABLED", "true"),
+            ("WDNS_DNS_LISTENER_ADDRESS", "127.0.0.1:5353"),
+            ("WDNS_TLS_CERT_PATH", "/etc/wdns/cert.pem"),
+            ("WDNS_TLS_KEY_PATH", ""),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut config = Config::default();
+        config
+            .apply_overrides(|key| vars.get(key).map(|v| v.to_string()))
+            .expect("Failed to apply overrides");
+
+        assert_eq!(config.bind_address, "127.0.0.1:8000");
+        assert_eq!(config.dns_timeout_seconds, 4);
+        assert_eq!(config.max_concurrent_resolutions, 12);
+        assert!(!config.proxy_enabled);
+        assert_eq!(config.proxy_bind_address, "127.0.0.1:8001");
+        assert!(config.socks5_enabled);
+        assert_eq!(config.socks5_bind_address, "127.0.0.1:8002");
+        assert!(config.socks5_bind_command_enabled);
+        assert_eq!(config.socks5_allowed_destinations, vec!["10.0.0.0/8", "*.corp.example"]);
+        assert_eq!(config.socks5_denied_destinations, vec!["10.0.0.1"]);
+        assert_eq!(config.http_upstream_proxy.as_deref(), Some("http://10.0.0.8:3128"));
+        assert_eq!(
+            config.cors_allowed_origins,
+            vec!["http://a.internal", "https://b.internal:8443"]
+        );
+        assert_eq!(config.max_hosts_per_request, 32);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.connect_timeout_seconds, 3);
+        assert_eq!(config.proxy_idle_timeout_seconds, 60);
+        assert_eq!(config.max_concurrent_connections, 64);
+        assert_eq!(config.listen_backlog, 4096);
+        assert_eq!(config.readiness_canary_host, "dns.corp.example");
+        assert!(!config.dns_use_hosts_file);
+        assert_eq!(config.dns_ndots, 0);
+        assert_eq!(config.dns_attempts, 4);
+        assert_eq!(config.dns_upstreams, vec!["10.0.0.53", "10.0.1.53:5353"]);
+        assert_eq!(config.dns_max_retries, 3);
+        assert_eq!(config.dns_blocklist, vec!["*.ads.example", "tracker.example"]);
+        assert_eq!(config.dns_allowlist, vec!["*.corp.example"]);
+        assert_eq!(
+            config.static_hosts["internal.test"],
+            vec!["10.1.2.3".parse::<IpAddr>().unwrap(), "fd00::3".parse().unwrap()]
+        );
+        assert_eq!(config.static_hosts["db.test"], vec!["10.1.2.4".parse::<IpAddr>().unwrap()]);
+        assert_eq!(config.dns_address_sort().unwrap(), AddressSort::V4First);
+        assert_eq!(config.dns_negative_ttl_seconds, 0);
+        assert!(config.dns_listener_enabled);
+        assert_eq!(config.dns_listener_address, "127.0.0.1:5353");
+        assert_eq!(config.tls_cert_path.as_deref(), Some("/etc/wdns/cert.pem"));
+        assert_eq!(config.tls_key_path, None);
+    }
+
+    #[test]
+    fn test_apply_overrides_invalid_values() {
+        let mut config = Config::default();
+        let err = config
+            .apply_overrides(|key| (key == "WDNS_PROXY_ENABLED").then(|| "maybe".to_string()))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("WDNS_PROXY_ENABLED"));
+
+        let err = config
+            .apply_overrides(|key| (key == "WDNS_DNS_TIMEOUT_SECONDS").then(|| "ten".to_string()))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("WDNS_DNS_TIMEOUT_SECONDS"));
+
+        let err = config
+            .apply_overrides(|key| (key == "WDNS_LOG_FORMAT").then(|| "xml".to_string()))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("WDNS_LOG_FORMAT"));
+
+        let err = config
+            .apply_overrides(|key| (key == "WDNS_STATIC_HOSTS").then(|| "internal.test".to_string()))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("WDNS_STATIC_HOSTS"));
+    }
+
+    #[test]
+    fn test_config_load_env_overrides_file() {
+        let _guard = CWD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_config = Config {
+            dns_timeout_seconds: 30,
+            max_concurrent_resolutions: 200,
+            ..Config::default()
+        };
+        fs::write(
+            temp_dir.path().join("config.json"),
+            serde_json::to_string_pretty(&file_config).unwrap(),
+        )
+        .expect("Failed to write config file");
+
+        let original_dir = std::env::current_dir().expect("Failed to get current dir");
+        std::env::set_current_dir(&temp_dir).expect("Failed to change to temp dir");
+        std::env::set_var("WDNS_DNS_TIMEOUT_SECONDS", "7");
+        std::env::set_var("WDNS_SOCKS5_ENABLED", "1");
+
+        let loaded = Config::load();
+
+        std::env::remove_var("WDNS_DNS_TIMEOUT_SECONDS");
+        std::env::remove_var("WDNS_SOCKS5_ENABLED");
+        std::env::set_current_dir(&original_dir).expect("Failed to restore original dir");
+
+        let loaded = loaded.expect("Failed to load config");
+        assert_eq!(loaded.dns_timeout_seconds, 7);
+        assert!(loaded.socks5_enabled);
+        // Fields without an override still come from the file
+        assert_eq!(loaded.max_concurrent_resolutions, 200);
+    }
+
     #[test]
     fn test_config_load_from_file() {
+        let _guard = CWD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
         let config_path = temp_dir.path().join("config.json");
         
@@ -135,6 +1623,7 @@ mod tests {
             max_concurrent_resolutions: 200,
             proxy_enabled: true,
             proxy_bind_address: "0.0.0.0:9091".to_string(),
+            ..Config::default()
         };
         
         let config_json = serde_json::to_string_pretty(&test_config).expect("Failed to serialize");
@@ -156,6 +1645,7 @@ mod tests {
 
     #[test]
     fn test_config_load_default_when_file_missing() {
+        let _guard = CWD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
         let original_dir = std::env::current_dir().expect("Failed to get current dir");
         std::env::set_current_dir(&temp_dir).expect("Failed to change to temp dir");
@@ -182,12 +1672,13 @@ mod tests {
             max_concurrent_resolutions: 50,
             proxy_enabled: false,
             proxy_bind_address: "192.168.1.100:3001".to_string(),
+            ..Config::default()
         };
         
         assert_eq!(config.bind_address, "192.168.1.100:3000");
         assert_eq!(config.dns_timeout_seconds, 5);
         assert_eq!(config.max_concurrent_resolutions, 50);
-        assert_eq!(config.proxy_enabled, false);
+        assert!(!config.proxy_enabled);
         assert_eq!(config.proxy_bind_address, "192.168.1.100:3001");
         
         let addr = config.bind_addr().expect("Failed to parse bind address");
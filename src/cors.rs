@@ -0,0 +1,92 @@
+use warp::filters::BoxedFilter;
+use warp::{Filter, Reply};
+
+/// Wraps `routes` in a CORS layer for browser clients on `allowed_origins`.
+///
+/// An empty list leaves the routes without CORS handling and `["*"]` allows any
+/// origin. Entries are expected to have passed `Config::validate`; warp panics on
+/// malformed origins.
+pub fn with_cors<F, R>(routes: F, allowed_origins: &[String]) -> BoxedFilter<(Box<dyn Reply>,)>
+where
+    F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+    R: Reply + 'static,
+{
+    if allowed_origins.is_empty() {
+        return routes.map(|reply: R| Box::new(reply) as Box<dyn Reply>).boxed();
+    }
+
+    let cors = warp::cors()
+        .allow_methods(["GET", "POST", "OPTIONS"])
+        .allow_header("content-type");
+    let cors = if allowed_origins.iter().any(|origin| origin == "*") {
+        cors.allow_any_origin()
+    } else {
+        cors.allow_origins(allowed_origins.iter().map(String::as_str))
+    };
+
+    routes
+        .with(cors)
+        .map(|reply| Box::new(reply) as Box<dyn Reply>)
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routes(allowed_origins: &[&str]) -> BoxedFilter<(Box<dyn Reply>,)> {
+        let hello = warp::path("hello").and(warp::get()).map(|| "hello");
+        let origins: Vec<String> = allowed_origins.iter().map(|origin| origin.to_string()).collect();
+        with_cors(hello, &origins)
+    }
+
+    #[tokio::test]
+    async fn test_disabled_cors_adds_no_headers() {
+        let response = warp::test::request()
+            .path("/hello")
+            .header("origin", "http://dashboard.internal")
+            .reply(&routes(&[]))
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert!(response.headers().get("access-control-allow-origin").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_listed_origin_is_allowed() {
+        let routes = routes(&["http://dashboard.internal:8080"]);
+
+        let response = warp::test::request()
+            .path("/hello")
+            .header("origin", "http://dashboard.internal:8080")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "http://dashboard.internal:8080"
+        );
+
+        let response = warp::test::request()
+            .path("/hello")
+            .header("origin", "http://evil.example")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_allows_any_origin() {
+        let response = warp::test::request()
+            .path("/hello")
+            .header("origin", "https://anything.example")
+            .reply(&routes(&["*"]))
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "https://anything.example"
+        );
+    }
+}
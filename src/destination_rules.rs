@@ -0,0 +1,226 @@
+use anyhow::Result;
+use std::net::IpAddr;
+
+/// One allow/deny entry: a CIDR network or a domain glob
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DestinationRule {
+    /// Addresses whose first `prefix_len` bits match `addr`
+    Network { addr: IpAddr, prefix_len: u8 },
+    /// Lowercase domain pattern where `*` matches any run of characters
+    Domain(String),
+}
+
+impl DestinationRule {
+    /// Parses `10.0.0.0/8`, `fd00::/8`, a bare IP, or a domain glob such as
+    /// `*.internal.example.com`.
+    pub fn parse(rule: &str) -> Result<Self> {
+        let rule = rule.trim();
+        if let Some((addr, prefix_len)) = rule.split_once('/') {
+            let addr: IpAddr = addr
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid network '{}': {}", rule, e))?;
+            let prefix_len: u8 = prefix_len
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid prefix length in '{}': {}", rule, e))?;
+            if prefix_len > max_prefix_len(&addr) {
+                anyhow::bail!("Invalid prefix length in '{}': too long for the address", rule);
+            }
+            return Ok(Self::Network { addr, prefix_len });
+        }
+
+        if let Ok(addr) = rule.parse::<IpAddr>() {
+            let prefix_len = max_prefix_len(&addr);
+            return Ok(Self::Network { addr, prefix_len });
+        }
+
+        let valid_domain = !rule.is_empty()
+            && rule
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-._*".contains(c));
+        if !valid_domain {
+            anyhow::bail!("Invalid destination rule '{}': expected a CIDR, IP or domain", rule);
+        }
+        Ok(Self::Domain(rule.to_ascii_lowercase()))
+    }
+
+    /// Parses a domain glob only, rejecting networks and IP addresses
+    pub fn parse_domain(rule: &str) -> Result<Self> {
+        match Self::parse(rule)? {
+            rule @ Self::Domain(_) => Ok(rule),
+            Self::Network { .. } => anyhow::bail!("Invalid domain rule '{}': expected a domain glob", rule.trim()),
+        }
+    }
+
+    pub fn matches_ip(&self, ip: IpAddr) -> bool {
+        let Self::Network { addr, prefix_len } = self else {
+            return false;
+        };
+        match (addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(u32::from(*net).into(), u32::from(ip).into(), *prefix_len, 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(*net), u128::from(ip), *prefix_len, 128)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn matches_domain(&self, domain: &str) -> bool {
+        let Self::Domain(pattern) = self else {
+            return false;
+        };
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        glob_matches(pattern.as_bytes(), domain.as_bytes())
+    }
+}
+
+fn max_prefix_len(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Compares the top `prefix_len` of `bits` bits of `net` and `ip`
+fn prefix_matches(net: u128, ip: u128, prefix_len: u8, bits: u8) -> bool {
+    if prefix_len == 0 {
+        return true;
+    }
+    let shift = u32::from(bits - prefix_len);
+    (net >> shift) == (ip >> shift)
+}
+
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| glob_matches(rest, &text[skip..])),
+        Some((c, rest)) => text.first() == Some(c) && glob_matches(rest, &text[1..]),
+    }
+}
+
+/// Allow and deny lists for proxy destinations. Deny takes precedence, and an
+/// empty allow list allows every destination that is not denied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DestinationRules {
+    allowed: Vec<DestinationRule>,
+    denied: Vec<DestinationRule>,
+}
+
+impl DestinationRules {
+    pub fn new(allowed: &[String], denied: &[String]) -> Result<Self> {
+        let parse_all = |rules: &[String]| {
+            rules
+                .iter()
+                .map(|rule| DestinationRule::parse(rule))
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(Self {
+            allowed: parse_all(allowed)?,
+            denied: parse_all(denied)?,
+        })
+    }
+
+    /// Whether `domain` is denied by name, so it can be refused before resolution
+    pub fn denies_domain(&self, domain: &str) -> bool {
+        self.denied.iter().any(|rule| rule.matches_domain(domain))
+    }
+
+    /// Whether a connection to `ip`, reached through `domain` if the client
+    /// asked for a name, is permitted
+    pub fn allows(&self, domain: Option<&str>, ip: IpAddr) -> bool {
+        let matches = |rule: &DestinationRule| {
+            rule.matches_ip(ip) || domain.is_some_and(|domain| rule.matches_domain(domain))
+        };
+
+        if self.denied.iter().any(matches) {
+            return false;
+        }
+        self.allowed.is_empty() || self.allowed.iter().any(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(allowed: &[&str], denied: &[&str]) -> DestinationRules {
+        let to_strings = |rules: &[&str]| rules.iter().map(|rule| rule.to_string()).collect::<Vec<_>>();
+        DestinationRules::new(&to_strings(allowed), &to_strings(denied)).expect("Invalid rules")
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!(
+            DestinationRule::parse("10.0.0.0/8").unwrap(),
+            DestinationRule::Network { addr: "10.0.0.0".parse().unwrap(), prefix_len: 8 }
+        );
+        assert_eq!(
+            DestinationRule::parse("192.168.1.5").unwrap(),
+            DestinationRule::Network { addr: "192.168.1.5".parse().unwrap(), prefix_len: 32 }
+        );
+        assert_eq!(
+            DestinationRule::parse("*.Internal.Example.com").unwrap(),
+            DestinationRule::Domain("*.internal.example.com".to_string())
+        );
+
+        assert!(DestinationRule::parse("10.0.0.0/33").is_err());
+        assert!(DestinationRule::parse("fd00::/129").is_err());
+        assert!(DestinationRule::parse("not a host").is_err());
+        assert!(DestinationRule::parse("").is_err());
+
+        assert!(DestinationRule::parse_domain("ads.example").is_ok());
+        assert!(DestinationRule::parse_domain("10.0.0.0/8").is_err());
+        assert!(DestinationRule::parse_domain("10.0.0.1").is_err());
+    }
+
+    #[test]
+    fn test_network_matching() {
+        let v4 = DestinationRule::parse("10.1.0.0/16").unwrap();
+        assert!(v4.matches_ip("10.1.200.3".parse().unwrap()));
+        assert!(!v4.matches_ip("10.2.0.1".parse().unwrap()));
+        assert!(!v4.matches_ip("::1".parse().unwrap()));
+
+        let v6 = DestinationRule::parse("fd00::/8").unwrap();
+        assert!(v6.matches_ip("fd12:3456::1".parse().unwrap()));
+        assert!(!v6.matches_ip("fe80::1".parse().unwrap()));
+
+        assert!(DestinationRule::parse("0.0.0.0/0").unwrap().matches_ip("8.8.8.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_domain_matching() {
+        let rule = DestinationRule::parse("*.corp.example").unwrap();
+        assert!(rule.matches_domain("git.corp.example"));
+        assert!(rule.matches_domain("a.b.CORP.example."));
+        assert!(!rule.matches_domain("corp.example"));
+        assert!(!rule.matches_domain("corp.example.evil"));
+
+        let exact = DestinationRule::parse("intranet").unwrap();
+        assert!(exact.matches_domain("intranet"));
+        assert!(!exact.matches_domain("intranet2"));
+    }
+
+    #[test]
+    fn test_deny_takes_precedence() {
+        let rules = rules(&["10.0.0.0/8", "*.corp.example"], &["10.0.0.1", "secret.corp.example"]);
+
+        assert!(rules.allows(None, "10.2.3.4".parse().unwrap()));
+        assert!(!rules.allows(None, "10.0.0.1".parse().unwrap()));
+        assert!(!rules.allows(None, "192.168.0.1".parse().unwrap()));
+
+        // A domain rule can allow an address outside the allowed networks
+        assert!(rules.allows(Some("git.corp.example"), "192.168.0.1".parse().unwrap()));
+        assert!(!rules.allows(Some("secret.corp.example"), "10.2.3.4".parse().unwrap()));
+        assert!(rules.denies_domain("secret.corp.example"));
+        assert!(!rules.denies_domain("git.corp.example"));
+    }
+
+    #[test]
+    fn test_empty_allow_list_allows_all() {
+        let rules = rules(&[], &["169.254.0.0/16"]);
+        assert!(rules.allows(None, "8.8.8.8".parse().unwrap()));
+        assert!(!rules.allows(None, "169.254.169.254".parse().unwrap()));
+        assert!(DestinationRules::default().allows(Some("example.com"), "8.8.8.8".parse().unwrap()));
+    }
+}
@@ -1,10 +1,26 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, RandomState};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, OwnedSemaphorePermit, Semaphore};
 use tokio::time::timeout;
-use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+use trust_dns_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+use trust_dns_resolver::proto::error::ProtoErrorKind;
+use trust_dns_resolver::proto::op::ResponseCode;
+use trust_dns_resolver::system_conf::read_system_conf;
 use trust_dns_resolver::TokioAsyncResolver;
-use futures_util::future;
+use futures_util::{future, FutureExt};
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use tracing::{debug, error};
+
+use crate::destination_rules::DestinationRule;
+use crate::metrics::Metrics;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DnsResult {
@@ -12,11 +28,367 @@ pub struct DnsResult {
     pub ip_addresses: Vec<String>,
     pub status: String,
     pub error: Option<String>,
+    /// Machine-readable cause of `error`: "nxdomain", "timeout", "servfail",
+    /// "no_records", "io_error", or "internal" when the lookup itself failed
+    #[serde(default)]
+    pub error_kind: Option<String>,
+    /// Informational message, e.g. when family filtering removed every address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Whether the addresses came from the resolver's cache
+    #[serde(default)]
+    pub from_cache: bool,
+    /// Lookups repeated after a retriable upstream failure
+    #[serde(default)]
+    pub retries_used: u32,
+    /// Whether the addresses came from the configured static hosts
+    #[serde(default)]
+    pub from_static: bool,
+    /// Name servers of the upstream that answered; unset for cached, static and refused hosts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+}
+
+/// One host resolved through the batch API, as pushed to `/api/dns/stream` clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolutionEvent {
+    pub host: String,
+    pub status: String,
+    pub ips: Vec<String>,
+    pub duration_ms: f64,
+}
+
+/// Address family of a resolved IP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn of(ip: &IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => IpFamily::V4,
+            IpAddr::V6(_) => IpFamily::V6,
+        }
+    }
+}
+
+/// Which address families to keep in resolution results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FamilyFilter {
+    #[default]
+    Any,
+    Only(IpFamily),
+}
+
+impl FamilyFilter {
+    /// Parses the request's `family` field: "v4", "v6" or "any" (the default when absent)
+    pub fn parse(family: Option<&str>) -> Result<Self, String> {
+        match family.map(str::to_ascii_lowercase).as_deref() {
+            None | Some("any") => Ok(FamilyFilter::Any),
+            Some("v4") => Ok(FamilyFilter::Only(IpFamily::V4)),
+            Some("v6") => Ok(FamilyFilter::Only(IpFamily::V6)),
+            Some(_) => Err(format!(
+                "Invalid family '{}', expected \"v4\", \"v6\" or \"any\"",
+                family.unwrap_or_default()
+            )),
+        }
+    }
+
+    pub fn allows(&self, family: IpFamily) -> bool {
+        match self {
+            FamilyFilter::Any => true,
+            FamilyFilter::Only(only) => *only == family,
+        }
+    }
+}
+
+/// Order in which resolved addresses are returned, for clients that use the first one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressSort {
+    /// As the upstream answered
+    #[default]
+    None,
+    V4First,
+    V6First,
+    /// Fastest TCP connect to this port first; unreachable addresses last
+    Probe(u16),
+}
+
+impl AddressSort {
+    /// Parses `address_sort`: "none" (the default when absent), "v4first", "v6first"
+    /// or "probe:<port>"
+    pub fn parse(sort: Option<&str>) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "Unknown address sort '{}', expected \"none\", \"v4first\", \"v6first\" or \"probe:<port>\"",
+                sort.unwrap_or_default()
+            )
+        };
+        match sort.map(str::to_ascii_lowercase).as_deref() {
+            None | Some("none") => Ok(AddressSort::None),
+            Some("v4first") => Ok(AddressSort::V4First),
+            Some("v6first") => Ok(AddressSort::V6First),
+            Some(other) => match other.strip_prefix("probe:").map(str::parse::<u16>) {
+                Some(Ok(port)) if port != 0 => Ok(AddressSort::Probe(port)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+/// Time each address gets to accept a connection in `AddressSort::Probe`
+pub const ADDRESS_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Reorders `addresses` by how fast each accepts a TCP connection on `port`. All
+/// addresses are tried at once; those that fail or take longer than `probe_timeout`
+/// keep their relative order after the reachable ones.
+pub async fn probe_order(addresses: &mut Vec<ResolvedIp>, port: u16, probe_timeout: Duration) {
+    let connects = addresses.iter().map(|resolved| async move {
+        let started = Instant::now();
+        match timeout(probe_timeout, tokio::net::TcpStream::connect((resolved.addr, port))).await {
+            Ok(Ok(_)) => Some(started.elapsed()),
+            _ => None,
+        }
+    });
+    let rtts = future::join_all(connects).await;
+
+    let mut ranked: Vec<(Option<Duration>, ResolvedIp)> = rtts.into_iter().zip(addresses.drain(..)).collect();
+    // `None` sorts before `Some`, so rank unreachable addresses with `Duration::MAX`
+    ranked.sort_by_key(|(rtt, _)| rtt.unwrap_or(Duration::MAX));
+    addresses.extend(ranked.into_iter().map(|(_, resolved)| resolved));
+}
+
+/// A resolved address tagged with its family
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResolvedIp {
+    pub addr: IpAddr,
+    pub family: IpFamily,
+}
+
+impl From<IpAddr> for ResolvedIp {
+    fn from(addr: IpAddr) -> Self {
+        Self {
+            addr,
+            family: IpFamily::of(&addr),
+        }
+    }
+}
+
+/// Typed counterpart of `DnsResult` for programmatic use
+#[derive(Debug, Clone)]
+pub struct TypedDnsResult {
+    pub host: String,
+    pub addresses: Vec<ResolvedIp>,
+    pub status: String,
+    pub error: Option<String>,
+    pub error_kind: Option<String>,
+    pub note: Option<String>,
+    pub from_cache: bool,
+    pub retries_used: u32,
+    pub from_static: bool,
+    pub upstream: Option<String>,
+}
+
+impl TypedDnsResult {
+    /// Drops addresses of other families. A host that resolved keeps its
+    /// "success" status even if nothing is left, with a note explaining why.
+    pub fn retain_family(&mut self, filter: FamilyFilter) {
+        let before = self.addresses.len();
+        self.addresses.retain(|resolved| filter.allows(resolved.family));
+
+        if let FamilyFilter::Only(family) = filter {
+            if before > 0 && self.addresses.is_empty() {
+                let family = match family {
+                    IpFamily::V4 => "IPv4",
+                    IpFamily::V6 => "IPv6",
+                };
+                self.note = Some(format!("Host resolved, but has no {} addresses", family));
+            }
+        }
+    }
+
+    /// Applies an `AddressSort`, keeping the upstream order within each family
+    pub async fn sort_addresses(&mut self, sort: AddressSort) {
+        match sort {
+            AddressSort::None => {}
+            AddressSort::V4First => self.addresses.sort_by_key(|resolved| resolved.family != IpFamily::V4),
+            AddressSort::V6First => self.addresses.sort_by_key(|resolved| resolved.family != IpFamily::V6),
+            AddressSort::Probe(port) => probe_order(&mut self.addresses, port, ADDRESS_PROBE_TIMEOUT).await,
+        }
+    }
+
+    /// The resolved addresses without their family tags
+    pub fn ips(&self) -> Vec<IpAddr> {
+        self.addresses.iter().map(|resolved| resolved.addr).collect()
+    }
+}
+
+impl From<TypedDnsResult> for DnsResult {
+    fn from(result: TypedDnsResult) -> Self {
+        Self {
+            host: result.host,
+            ip_addresses: result
+                .addresses
+                .iter()
+                .map(|resolved| resolved.addr.to_string())
+                .collect(),
+            status: result.status,
+            error: result.error,
+            error_kind: result.error_kind,
+            note: result.note,
+            from_cache: result.from_cache,
+            retries_used: result.retries_used,
+            from_static: result.from_static,
+            upstream: result.upstream,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DnsRequest {
     pub hosts: Vec<String>,
+    /// Also report partial failures (207) through the HTTP status
+    #[serde(default)]
+    pub strict: bool,
+    /// Address family to return: "v4", "v6" or "any" (default)
+    #[serde(default)]
+    pub family: Option<String>,
+    /// Address order: "none", "v4first" or "v6first" (default: the service's
+    /// `dns_address_sort`, which may also probe)
+    #[serde(default)]
+    pub address_sort: Option<String>,
+}
+
+impl DnsResult {
+    /// Result for a host whose lookup panicked
+    fn internal_error(host: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            ip_addresses: vec![],
+            status: "error".to_string(),
+            error: Some("Internal error while resolving host".to_string()),
+            error_kind: Some("internal".to_string()),
+            note: None,
+            from_cache: false,
+            retries_used: 0,
+            from_static: false,
+            upstream: None,
+        }
+    }
+
+    /// HTTP status for a single-host lookup: 502 on failure, 504 on timeout,
+    /// 403 when the host is refused by the block or allow list
+    pub fn status_code(&self) -> warp::http::StatusCode {
+        use warp::http::StatusCode;
+
+        match self.status.as_str() {
+            "success" => StatusCode::OK,
+            "timeout" => StatusCode::GATEWAY_TIMEOUT,
+            _ if self.error_kind.as_deref() == Some("blocked") => StatusCode::FORBIDDEN,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+/// Decodes a percent-encoded URL path segment into a host name or IP literal,
+/// rejecting anything that cannot be a valid lookup target.
+pub fn parse_host_segment(segment: &str) -> Result<String, String> {
+    let host = percent_encoding::percent_decode_str(segment)
+        .decode_utf8()
+        .map_err(|_| "Host is not valid UTF-8".to_string())?;
+
+    if host.is_empty() {
+        return Err("No host provided".to_string());
+    }
+    if host.parse::<IpAddr>().is_ok() {
+        return Ok(host.into_owned());
+    }
+
+    let name = host.strip_suffix('.').unwrap_or(&host);
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    if name.is_empty() || name.len() > 253 || !name.split('.').all(valid_label) {
+        return Err(format!("Invalid host: {}", host));
+    }
+
+    Ok(host.into_owned())
+}
+
+/// Checks that `name` has the `_service._proto.domain` form of an SRV name
+pub fn validate_srv_name(name: &str) -> Result<(), String> {
+    let labels: Vec<&str> = name.strip_suffix('.').unwrap_or(name).split('.').collect();
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    let is_service_label = |label: &str| label.len() > 1 && label.starts_with('_');
+    if labels.len() < 3
+        || name.len() > 253
+        || !labels.iter().all(|label| valid_label(label))
+        || !is_service_label(labels[0])
+        || !is_service_label(labels[1])
+    {
+        return Err(format!(
+            "Invalid SRV name: {} (expected _service._proto.domain)",
+            name
+        ));
+    }
+    Ok(())
+}
+
+impl DnsRequest {
+    pub fn family_filter(&self) -> Result<FamilyFilter, String> {
+        FamilyFilter::parse(self.family.as_deref())
+    }
+
+    /// The requested `address_sort`, or `None` to use the resolver's default.
+    /// Probing is refused: it would let clients make the service connect to a port
+    /// of their choosing and learn which addresses accept.
+    pub fn address_sort(&self) -> Result<Option<AddressSort>, String> {
+        let sort = self
+            .address_sort
+            .as_deref()
+            .map(|sort| AddressSort::parse(Some(sort)))
+            .transpose()?;
+        if let Some(AddressSort::Probe(_)) = sort {
+            return Err("address_sort \"probe:<port>\" is only available as the service's dns_address_sort".to_string());
+        }
+        Ok(sort)
+    }
+
+    /// Rejects batches with more than `max_hosts` entries
+    pub fn check_host_limit(&self, max_hosts: usize) -> Result<(), String> {
+        check_batch_limit("hosts", self.hosts.len(), max_hosts)
+    }
+}
+
+/// Rejects a batch of `len` `items` larger than `max`
+fn check_batch_limit(items: &str, len: usize, max: usize) -> Result<(), String> {
+    if len > max {
+        return Err(format!(
+            "Too many {}: {} provided, at most {} allowed per request",
+            items, len, max
+        ));
+    }
+    Ok(())
+}
+
+/// Body of `POST /api/dns/flush-cache`; without `hosts` the whole cache is flushed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FlushCacheRequest {
+    #[serde(default)]
+    pub hosts: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,73 +396,1091 @@ pub struct DnsResponse {
     pub results: Vec<DnsResult>,
     pub total_resolved: usize,
     pub total_errors: usize,
+    /// Id of the API request, also recorded on its log lines
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub summary: DnsSummary,
+}
+
+/// Where the results of a batch came from, counted per requested host
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DnsSummary {
+    pub from_cache: usize,
+    pub from_upstream: usize,
+    pub from_static: usize,
+    /// Name servers asked during the batch, in the order first seen
+    pub upstreams_used: Vec<String>,
+}
+
+impl DnsSummary {
+    pub fn from_results(results: &[DnsResult]) -> Self {
+        let mut summary = Self::default();
+        for result in results {
+            if result.from_cache {
+                summary.from_cache += 1;
+            } else if result.from_static {
+                summary.from_static += 1;
+            } else if let Some(upstream) = &result.upstream {
+                summary.from_upstream += 1;
+                if !summary.upstreams_used.contains(upstream) {
+                    summary.upstreams_used.push(upstream.clone());
+                }
+            }
+        }
+        summary
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReverseDnsRequest {
+    pub ips: Vec<String>,
+}
+
+impl ReverseDnsRequest {
+    /// Rejects batches with more than `max_hosts` addresses
+    pub fn check_host_limit(&self, max_hosts: usize) -> Result<(), String> {
+        check_batch_limit("IPs", self.ips.len(), max_hosts)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReverseDnsResult {
+    pub ip: String,
+    pub names: Vec<String>,
+    pub status: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReverseDnsResponse {
+    pub results: Vec<ReverseDnsResult>,
+    pub total_resolved: usize,
+    pub total_errors: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SrvDnsRequest {
+    /// Service names such as `_sip._tcp.example.com`
+    pub names: Vec<String>,
+}
+
+impl SrvDnsRequest {
+    /// Rejects batches with more than `max_hosts` names
+    pub fn check_host_limit(&self, max_hosts: usize) -> Result<(), String> {
+        check_batch_limit("names", self.names.len(), max_hosts)
+    }
+}
+
+/// One server offering a service, from an SRV record
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SrvRecord {
+    pub target: String,
+    pub port: u16,
+    pub priority: u16,
+    pub weight: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SrvDnsResult {
+    pub name: String,
+    /// Lowest priority first, then highest weight first within a priority
+    pub records: Vec<SrvRecord>,
+    pub status: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SrvDnsResponse {
+    pub results: Vec<SrvDnsResult>,
+    pub total_resolved: usize,
+    pub total_errors: usize,
+}
+
+impl DnsResponse {
+    /// HTTP status for this response. A batch where every host failed is 502,
+    /// or 504 if every failure was a timeout. A partially failed batch is 200,
+    /// or 207 when `strict` is set.
+    pub fn status_code(&self, strict: bool) -> warp::http::StatusCode {
+        use warp::http::StatusCode;
+
+        if self.total_errors == 0 {
+            StatusCode::OK
+        } else if self.total_resolved == 0 {
+            let all_timeouts = !self.results.is_empty()
+                && self.results.iter().all(|result| result.status == "timeout");
+            if all_timeouts {
+                StatusCode::GATEWAY_TIMEOUT
+            } else {
+                StatusCode::BAD_GATEWAY
+            }
+        } else if strict {
+            StatusCode::MULTI_STATUS
+        } else {
+            StatusCode::OK
+        }
+    }
+}
+
+/// Short random id tying an API request to the logs of its per-host lookups
+pub fn new_request_id() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let sequence = NEXT.fetch_add(1, Ordering::Relaxed);
+    format!("{:08x}", RandomState::new().hash_one(sequence) as u32)
+}
+
+/// Time allowed for a readiness probe, well under the default lookup timeout
+pub const READINESS_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Default cap on simultaneous lookups, matching `Config::max_concurrent_resolutions`
+const DEFAULT_MAX_CONCURRENT_RESOLUTIONS: usize = 100;
+
+/// Pause before retrying a lookup that failed upstream
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Retries per lookup unless `set_max_retries` changes it
+pub const DEFAULT_MAX_RETRIES: usize = 1;
+
+/// Most hosts kept in the resolver cache unless the builder's `cache` changes
+/// it; new hosts are not cached beyond this
+pub const DEFAULT_CACHE_CAPACITY: usize = 10_000;
+
+/// Per-lookup timeout unless `set_timeout` changes it
+const DEFAULT_LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long an NXDOMAIN or empty answer is cached unless the builder's
+/// `negative_ttl` changes it
+pub const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(30);
+
+/// Resolution events buffered per stream subscriber before the slowest ones miss some
+pub const RESOLUTION_EVENT_CAPACITY: usize = 256;
+
+/// A successful lookup, kept until its records' TTL runs out, or a definitive
+/// failure (NXDOMAIN or no records), kept for the negative TTL
+#[derive(Debug, Clone)]
+struct CachedLookup {
+    result: TypedDnsResult,
+    expires_at: Instant,
+}
+
+/// Cache key for `host`: names differing only in case or a trailing dot match
+fn cache_key(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Records an upstream address lookup asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AddressQuery {
+    /// The resolver's default strategy: A records, falling back to AAAA when there are none
+    Ip,
+    /// AAAA records only
+    Aaaa,
+}
+
+impl AddressQuery {
+    /// Key of the lookup's cache entry; AAAA-only answers are kept apart from full ones
+    fn cache_key(self, host: &str) -> String {
+        match self {
+            AddressQuery::Ip => cache_key(host),
+            AddressQuery::Aaaa => format!("{} AAAA", cache_key(host)),
+        }
+    }
+}
+
+/// Lookup behaviour knobs, mapped onto trust-dns `ResolverOpts`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DnsResolverOptions {
+    /// Answer from the system hosts file before asking the name servers
+    pub use_hosts_file: bool,
+    /// Names with fewer dots than this are tried with the search domains first
+    pub ndots: usize,
+    /// Retries against the name servers after a failed lookup
+    pub attempts: usize,
+    /// Require DNSSEC-validated answers
+    pub validate: bool,
+}
+
+impl Default for DnsResolverOptions {
+    fn default() -> Self {
+        let opts = ResolverOpts::default();
+        Self {
+            use_hosts_file: opts.use_hosts_file,
+            ndots: opts.ndots,
+            attempts: opts.attempts,
+            validate: opts.validate,
+        }
+    }
+}
+
+impl DnsResolverOptions {
+    fn resolver_opts(&self) -> ResolverOpts {
+        let mut opts = ResolverOpts::default();
+        opts.use_hosts_file = self.use_hosts_file;
+        opts.ndots = self.ndots;
+        opts.attempts = self.attempts;
+        opts.validate = self.validate;
+        opts
+    }
+}
+
+/// Parses block or allow list entries, which must be domain globs
+pub fn parse_domain_rules(rules: &[String]) -> Result<Vec<DestinationRule>> {
+    rules.iter().map(|rule| DestinationRule::parse_domain(rule)).collect()
+}
+
+/// Name server configs for `addrs`, one upstream each, falling back to the
+/// default upstream when `addrs` is empty
+pub fn upstream_configs(addrs: &[SocketAddr]) -> Vec<ResolverConfig> {
+    if addrs.is_empty() {
+        return vec![ResolverConfig::default()];
+    }
+    addrs
+        .iter()
+        .map(|addr| {
+            ResolverConfig::from_parts(
+                None,
+                vec![],
+                NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true),
+            )
+        })
+        .collect()
+}
+
+/// The distinct name server addresses of `config`, e.g. "10.0.0.53:53"
+fn upstream_name(config: &ResolverConfig) -> String {
+    let mut addrs: Vec<String> = Vec::new();
+    for server in config.name_servers() {
+        let addr = server.socket_addr.to_string();
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    addrs.join(", ")
+}
+
+/// Counts an upstream lookup in `in_flight` until dropped, so a lookup that panics or is
+/// cancelled does not stay counted
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl<'a> InFlightGuard<'a> {
+    /// Counts one more lookup in `counter`, returning the guard and the new count
+    fn enter(counter: &'a AtomicUsize) -> (Self, usize) {
+        let count = counter.fetch_add(1, Ordering::Relaxed) + 1;
+        (Self(counter), count)
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The message a panic was raised with, when it is a string
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Whether a failed lookup may succeed when repeated. NXDOMAIN and empty
+/// answers are definitive; SERVFAIL, timeouts and transport errors are not.
+fn is_retriable(error: &ResolveError) -> bool {
+    match error.kind() {
+        ResolveErrorKind::NoRecordsFound { response_code, .. } => {
+            !matches!(*response_code, ResponseCode::NXDomain | ResponseCode::NoError)
+        }
+        _ => true,
+    }
+}
+
+/// Classifies a failed lookup into the `DnsResult::error_kind` vocabulary
+fn error_kind(error: &ResolveError) -> &'static str {
+    match error.kind() {
+        ResolveErrorKind::NoRecordsFound { response_code, .. } => match *response_code {
+            ResponseCode::NXDomain => "nxdomain",
+            ResponseCode::NoError => "no_records",
+            _ => "servfail",
+        },
+        ResolveErrorKind::Timeout => "timeout",
+        ResolveErrorKind::Proto(e) if matches!(e.kind(), ProtoErrorKind::Timeout) => "timeout",
+        _ => "io_error",
+    }
+}
+
+/// Where a built resolver sends lookups when no explicit servers are given
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UpstreamMode {
+    /// trust-dns' built-in public name servers
+    #[default]
+    Default,
+    /// The host's own resolver configuration (resolv.conf, or the registry on Windows)
+    System,
+}
+
+/// Collects resolver settings so several can be set before the resolver is
+/// created.
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// use std::time::Duration;
+/// use wdns_service::DnsResolverBuilder;
+///
+/// let resolver = DnsResolverBuilder::default()
+///     .servers(vec!["1.1.1.1:53".parse()?])
+///     .timeout(Duration::from_secs(3))
+///     .concurrency(16)
+///     .cache(1_000)
+///     .build()?;
+/// let result = resolver.resolve_host("example.com").await;
+/// println!("{:?}", result.ip_addresses);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DnsResolverBuilder {
+    servers: Vec<SocketAddr>,
+    upstream: UpstreamMode,
+    options: DnsResolverOptions,
+    timeout: Duration,
+    concurrency: usize,
+    max_retries: usize,
+    cache_capacity: usize,
+    blocklist: Vec<String>,
+    allowlist: Vec<String>,
+    static_hosts: HashMap<String, Vec<IpAddr>>,
+    address_sort: AddressSort,
+    negative_ttl: Duration,
+}
+
+impl Default for DnsResolverBuilder {
+    fn default() -> Self {
+        Self {
+            servers: Vec::new(),
+            upstream: UpstreamMode::Default,
+            options: DnsResolverOptions::default(),
+            timeout: DEFAULT_LOOKUP_TIMEOUT,
+            concurrency: DEFAULT_MAX_CONCURRENT_RESOLUTIONS,
+            max_retries: DEFAULT_MAX_RETRIES,
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            blocklist: Vec::new(),
+            allowlist: Vec::new(),
+            static_hosts: HashMap::new(),
+            address_sort: AddressSort::None,
+            negative_ttl: DEFAULT_NEGATIVE_TTL,
+        }
+    }
+}
+
+impl DnsResolverBuilder {
+    /// Name servers to ask, one upstream each in preference order. When
+    /// empty, the `upstream` mode decides.
+    pub fn servers(mut self, servers: Vec<SocketAddr>) -> Self {
+        self.servers = servers;
+        self
+    }
+
+    /// Per-lookup timeout
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Most lookups running at once; further lookups queue
+    pub fn concurrency(mut self, limit: usize) -> Self {
+        self.concurrency = limit;
+        self
+    }
+
+    /// Most hosts kept in the resolver cache; 0 disables caching
+    pub fn cache(mut self, capacity: usize) -> Self {
+        self.cache_capacity = capacity;
+        self
+    }
+
+    /// Upstream to use when no `servers` are given
+    pub fn upstream(mut self, mode: UpstreamMode) -> Self {
+        self.upstream = mode;
+        self
+    }
+
+    /// Lookup behaviour passed to trust-dns
+    pub fn options(mut self, options: DnsResolverOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Retries after a lookup fails upstream
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Domain globs that are never resolved
+    pub fn blocklist(mut self, blocklist: Vec<String>) -> Self {
+        self.blocklist = blocklist;
+        self
+    }
+
+    /// Domain globs that may be resolved; when non-empty, every other host is refused
+    pub fn allowlist(mut self, allowlist: Vec<String>) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
+
+    /// Names answered with fixed addresses instead of asking upstream
+    pub fn static_hosts(mut self, static_hosts: HashMap<String, Vec<IpAddr>>) -> Self {
+        self.static_hosts = static_hosts;
+        self
+    }
+
+    /// Order of the returned addresses when a request does not choose one
+    pub fn address_sort(mut self, sort: AddressSort) -> Self {
+        self.address_sort = sort;
+        self
+    }
+
+    /// How long NXDOMAIN and empty answers are cached; zero disables negative caching
+    pub fn negative_ttl(mut self, negative_ttl: Duration) -> Self {
+        self.negative_ttl = negative_ttl;
+        self
+    }
+
+    /// Creates the resolver, failing if a block or allow list entry is not a
+    /// domain glob, or if `UpstreamMode::System` is chosen and the system
+    /// configuration cannot be read
+    pub fn build(self) -> Result<DnsResolver> {
+        let blocklist = parse_domain_rules(&self.blocklist)?;
+        let allowlist = parse_domain_rules(&self.allowlist)?;
+
+        let configs = if !self.servers.is_empty() {
+            upstream_configs(&self.servers)
+        } else {
+            match self.upstream {
+                UpstreamMode::Default => vec![ResolverConfig::default()],
+                UpstreamMode::System => {
+                    let (config, _) = read_system_conf()
+                        .map_err(|e| anyhow::anyhow!("Failed to read system DNS configuration: {}", e))?;
+                    vec![config]
+                }
+            }
+        };
+
+        let mut resolver = DnsResolver::with_upstreams(configs, self.options)?;
+        resolver.cache_capacity = self.cache_capacity;
+        resolver.blocklist = blocklist;
+        resolver.allowlist = allowlist;
+        resolver.static_hosts = self
+            .static_hosts
+            .into_iter()
+            .map(|(host, addrs)| (cache_key(&host), addrs))
+            .collect();
+        resolver.set_timeout(self.timeout);
+        resolver.set_concurrency(self.concurrency);
+        resolver.set_max_retries(self.max_retries);
+        resolver.address_sort = self.address_sort;
+        resolver.negative_ttl = self.negative_ttl;
+        Ok(resolver)
+    }
 }
 
 pub struct DnsResolver {
-    resolver: TokioAsyncResolver,
-    timeout_duration: Duration,
+    /// Upstreams in preference order; retries rotate through them
+    upstreams: Vec<TokioAsyncResolver>,
+    /// Name servers of each upstream, as reported in results
+    upstream_names: Vec<String>,
+    max_retries: AtomicUsize,
+    cache: RwLock<HashMap<String, CachedLookup>>,
+    /// Most hosts kept in `cache`; 0 disables caching
+    cache_capacity: usize,
+    /// Hosts refused without asking upstream
+    blocklist: Vec<DestinationRule>,
+    /// When non-empty, the only hosts that are resolved
+    allowlist: Vec<DestinationRule>,
+    /// Fixed addresses keyed by `cache_key`, answered without asking upstream
+    static_hosts: HashMap<String, Vec<IpAddr>>,
+    /// Order applied to results unless a request asks for another
+    address_sort: AddressSort,
+    /// How long definitive failures stay in `cache`
+    negative_ttl: Duration,
+    timeout_duration: RwLock<Duration>,
+    semaphore: RwLock<Arc<Semaphore>>,
+    upstream_lookups: AtomicUsize,
+    in_flight: AtomicUsize,
+    peak_in_flight: AtomicUsize,
+    metrics: Arc<Metrics>,
+    events: broadcast::Sender<ResolutionEvent>,
 }
 
 impl DnsResolver {
     pub fn new() -> Result<Self> {
-        // Use system DNS configuration
-        let resolver_config = ResolverConfig::default();
-        let resolver_opts = ResolverOpts::default();
-        
-        let resolver = TokioAsyncResolver::tokio(resolver_config, resolver_opts);
-        
-        Ok(Self {
-            resolver,
-            timeout_duration: Duration::from_secs(10),
+        DnsResolverBuilder::default().build()
+    }
+
+    /// Starts a builder for setting several resolver options at once
+    pub fn builder() -> DnsResolverBuilder {
+        DnsResolverBuilder::default()
+    }
+
+    /// Creates a resolver that runs at most `limit` lookups at once; further
+    /// lookups queue until a slot frees up.
+    pub fn with_concurrency(limit: usize) -> Result<Self> {
+        let resolver = DnsResolver::with_options(ResolverConfig::default(), DnsResolverOptions::default())?;
+        resolver.set_concurrency(limit);
+        Ok(resolver)
+    }
+
+    /// Creates a resolver for `config`'s name servers with the lookup
+    /// behaviour in `options`.
+    pub fn with_options(config: ResolverConfig, options: DnsResolverOptions) -> Result<Self> {
+        Self::with_upstreams(vec![config], options)
+    }
+
+    /// Creates a resolver that asks the first of `configs` and moves on to
+    /// the next one each time a lookup is retried.
+    pub fn with_upstreams(configs: Vec<ResolverConfig>, options: DnsResolverOptions) -> Result<Self> {
+        if configs.is_empty() {
+            anyhow::bail!("At least one DNS upstream is required");
+        }
+        let upstream_names = configs.iter().map(upstream_name).collect();
+        let upstreams = configs
+            .into_iter()
+            .map(|config| TokioAsyncResolver::tokio(config, options.resolver_opts()))
+            .collect();
+
+        Ok(Self::with_resolvers(upstreams, upstream_names, DEFAULT_MAX_CONCURRENT_RESOLUTIONS))
+    }
+
+    fn with_resolvers(upstreams: Vec<TokioAsyncResolver>, upstream_names: Vec<String>, limit: usize) -> Self {
+        Self {
+            upstreams,
+            upstream_names,
+            max_retries: AtomicUsize::new(DEFAULT_MAX_RETRIES),
+            cache: RwLock::new(HashMap::new()),
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            blocklist: Vec::new(),
+            allowlist: Vec::new(),
+            static_hosts: HashMap::new(),
+            address_sort: AddressSort::None,
+            negative_ttl: DEFAULT_NEGATIVE_TTL,
+            timeout_duration: RwLock::new(DEFAULT_LOOKUP_TIMEOUT),
+            semaphore: RwLock::new(Arc::new(Semaphore::new(limit))),
+            upstream_lookups: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+            peak_in_flight: AtomicUsize::new(0),
+            metrics: Arc::new(Metrics::new()),
+            events: broadcast::channel(RESOLUTION_EVENT_CAPACITY).0,
+        }
+    }
+
+    /// Changes the per-lookup timeout for subsequent lookups.
+    pub fn set_timeout(&self, timeout_duration: Duration) {
+        *self.timeout_duration.write().unwrap() = timeout_duration;
+    }
+
+    /// Changes the concurrency cap. Lookups already running keep their slot
+    /// in the previous limit; new lookups queue against the new one.
+    pub fn set_concurrency(&self, limit: usize) {
+        *self.semaphore.write().unwrap() = Arc::new(Semaphore::new(limit));
+    }
+
+    /// Changes how many times a lookup that failed upstream is retried.
+    pub fn set_max_retries(&self, max_retries: usize) {
+        self.max_retries.store(max_retries, Ordering::Relaxed);
+    }
+
+    fn timeout_duration(&self) -> Duration {
+        *self.timeout_duration.read().unwrap()
+    }
+
+    async fn acquire_permit(&self) -> OwnedSemaphorePermit {
+        let semaphore = self.semaphore.read().unwrap().clone();
+        // The semaphore is never closed, so acquiring can only wait
+        semaphore.acquire_owned().await.expect("DNS semaphore closed")
+    }
+
+    /// Empties the cache, including the underlying resolver's record cache, and
+    /// returns how many hosts were evicted
+    pub fn clear_cache(&self) -> usize {
+        self.clear_upstream_caches();
+        let mut cache = self.cache.write().unwrap();
+        let evicted = cache.len();
+        cache.clear();
+        evicted
+    }
+
+    /// Evicts only `hosts` from the cache, returning how many were cached
+    pub fn clear_cache_hosts(&self, hosts: &[String]) -> usize {
+        // The underlying resolvers cannot evict single names
+        self.clear_upstream_caches();
+        let mut cache = self.cache.write().unwrap();
+        hosts
+            .iter()
+            .map(|host| cache_key(host))
+            .collect::<HashSet<_>>()
+            .iter()
+            .filter(|host| {
+                let aaaa = cache.remove(&AddressQuery::Aaaa.cache_key(host)).is_some();
+                cache.remove(host.as_str()).is_some() || aaaa
+            })
+            .count()
+    }
+
+    fn clear_upstream_caches(&self) {
+        for upstream in &self.upstreams {
+            upstream.clear_cache();
+        }
+    }
+
+    /// The upstream to use for a lookup's `attempt`, counting from 0
+    fn upstream(&self, attempt: usize) -> &TokioAsyncResolver {
+        &self.upstreams[attempt % self.upstreams.len()]
+    }
+
+    /// Why `host` may not be resolved, if the block or allow list refuses it
+    fn refusal(&self, host: &str) -> Option<&'static str> {
+        if self.blocklist.iter().any(|rule| rule.matches_domain(host)) {
+            Some("Host is on the DNS blocklist")
+        } else if !self.allowlist.is_empty() && !self.allowlist.iter().any(|rule| rule.matches_domain(host)) {
+            Some("Host is not on the DNS allowlist")
+        } else {
+            None
+        }
+    }
+
+    fn static_lookup(&self, host: &str) -> Option<TypedDnsResult> {
+        let addrs = self.static_hosts.get(&cache_key(host))?;
+        Some(TypedDnsResult {
+            host: host.to_string(),
+            addresses: addrs.iter().copied().map(ResolvedIp::from).collect(),
+            status: "success".to_string(),
+            error: None,
+            error_kind: None,
+            note: None,
+            from_cache: false,
+            retries_used: 0,
+            from_static: true,
+            upstream: None,
         })
     }
 
+    fn cached(&self, host: &str, query: AddressQuery) -> Option<TypedDnsResult> {
+        let cache = self.cache.read().unwrap();
+        let entry = cache.get(&query.cache_key(host))?;
+        if entry.expires_at <= Instant::now() {
+            return None;
+        }
+
+        let mut result = entry.result.clone();
+        result.host = host.to_string();
+        result.from_cache = true;
+        result.retries_used = 0;
+        result.upstream = None;
+        Some(result)
+    }
+
+    fn store(&self, result: &TypedDnsResult, query: AddressQuery, expires_at: Instant) {
+        if self.cache_capacity == 0 {
+            return;
+        }
+        let mut cache = self.cache.write().unwrap();
+        if cache.len() >= self.cache_capacity {
+            let now = Instant::now();
+            cache.retain(|_, entry| entry.expires_at > now);
+            if cache.len() >= self.cache_capacity {
+                return;
+            }
+        }
+        cache.insert(
+            query.cache_key(&result.host),
+            CachedLookup {
+                result: result.clone(),
+                expires_at,
+            },
+        );
+    }
+
+    /// Receives an event for each host resolved by `resolve_hosts` and its variants
+    /// from now on; earlier resolutions are not replayed
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ResolutionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Counters updated by this resolver, shared with the `/metrics` endpoint
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Resolves `canary` within `probe_timeout` to check that upstream DNS
+    /// works, returning the reason when it does not
+    pub async fn probe(&self, canary: &str, probe_timeout: Duration) -> Result<(), String> {
+        match timeout(probe_timeout, self.resolve_host(canary)).await {
+            Ok(result) if result.status == "success" => Ok(()),
+            Ok(result) => Err(result
+                .error
+                .unwrap_or_else(|| format!("Resolution of {} failed", canary))),
+            Err(_) => Err(format!("Resolution of {} timed out after {:?}", canary, probe_timeout)),
+        }
+    }
+
     pub async fn resolve_host(&self, host: &str) -> DnsResult {
+        self.resolve_host_with_family(host, FamilyFilter::Any).await
+    }
+
+    /// Resolves `host`, keeping only addresses allowed by `family`
+    pub async fn resolve_host_with_family(&self, host: &str, family: FamilyFilter) -> DnsResult {
+        self.resolve_host_with(host, family, self.address_sort).await
+    }
+
+    /// Resolves `host`, keeping only addresses allowed by `family`, in `sort` order
+    pub async fn resolve_host_with(&self, host: &str, family: FamilyFilter, sort: AddressSort) -> DnsResult {
+        let mut result = self.resolve_host_typed(host).await;
+        result.retain_family(family);
+        result.sort_addresses(sort).await;
+        result.into()
+    }
+
+    /// Order applied to results unless a request asks for another
+    pub fn address_sort(&self) -> AddressSort {
+        self.address_sort
+    }
+
+    /// Like `resolve_host`, but returns the addresses as `IpAddr` values tagged
+    /// with their family instead of strings.
+    pub async fn resolve_host_typed(&self, host: &str) -> TypedDnsResult {
+        self.resolve_address(host, AddressQuery::Ip).await
+    }
+
+    /// Looks up only the AAAA records of `host`. `resolve_host_typed` asks for AAAA records
+    /// only when a host has no A records, so this is how both addresses of a dual-stack
+    /// host are found.
+    pub async fn resolve_host_aaaa(&self, host: &str) -> TypedDnsResult {
+        self.resolve_address(host, AddressQuery::Aaaa).await
+    }
+
+    async fn resolve_address(&self, host: &str, query: AddressQuery) -> TypedDnsResult {
+        if let Some(reason) = self.refusal(host) {
+            let result = TypedDnsResult {
+                host: host.to_string(),
+                addresses: vec![],
+                status: "error".to_string(),
+                error: Some(reason.to_string()),
+                error_kind: Some("blocked".to_string()),
+                note: None,
+                from_cache: false,
+                retries_used: 0,
+                from_static: false,
+                upstream: None,
+            };
+            self.metrics.record_resolution(&result.status, Duration::ZERO);
+            return result;
+        }
+
+        if let Some(result) = self.static_lookup(host) {
+            self.metrics.record_resolution(&result.status, Duration::ZERO);
+            return result;
+        }
+
+        if let Some(result) = self.cached(host, query) {
+            self.metrics.record_resolution(&result.status, Duration::ZERO);
+            return result;
+        }
+
         let host = host.to_string();
-        
-        match timeout(self.timeout_duration, self.resolver.lookup_ip(&host)).await {
+
+        let _permit = self.acquire_permit().await;
+        let (_in_flight, in_flight) = InFlightGuard::enter(&self.in_flight);
+        self.peak_in_flight.fetch_max(in_flight, Ordering::Relaxed);
+
+        self.upstream_lookups.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+
+        let max_retries = self.max_retries.load(Ordering::Relaxed);
+        let mut attempt = 0;
+        let outcome = loop {
+            let upstream = self.upstream(attempt);
+            let lookup = async {
+                match query {
+                    AddressQuery::Ip => upstream.lookup_ip(host.as_str()).await.map(|lookup| {
+                        let addresses: Vec<ResolvedIp> = lookup.iter().map(ResolvedIp::from).collect();
+                        (addresses, lookup.valid_until())
+                    }),
+                    AddressQuery::Aaaa => upstream.ipv6_lookup(host.as_str()).await.map(|lookup| {
+                        let addresses: Vec<ResolvedIp> =
+                            lookup.iter().map(|aaaa| ResolvedIp::from(IpAddr::V6(aaaa.0))).collect();
+                        (addresses, lookup.valid_until())
+                    }),
+                }
+            };
+            match timeout(self.timeout_duration(), lookup).await {
+                Ok(Err(e)) if attempt < max_retries && is_retriable(&e) => {
+                    debug!("Retrying lookup of {} after error: {}", host, e);
+                }
+                Err(_) if attempt < max_retries => {
+                    debug!("Retrying lookup of {} after timeout", host);
+                }
+                outcome => break outcome,
+            }
+            attempt += 1;
+            tokio::time::sleep(RETRY_DELAY).await;
+        };
+        let retries_used = attempt as u32;
+        let upstream = Some(self.upstream_names[attempt % self.upstream_names.len()].clone());
+
+        let result = match outcome {
+            Ok(Ok((addresses, valid_until))) => {
+                let result = TypedDnsResult {
+                    host,
+                    addresses,
+                    status: "success".to_string(),
+                    error: None,
+                    error_kind: None,
+                    note: None,
+                    from_cache: false,
+                    retries_used,
+                    from_static: false,
+                    upstream,
+                };
+                self.store(&result, query, valid_until);
+                result
+            }
+            Ok(Err(e)) => {
+                let result = TypedDnsResult {
+                    host,
+                    addresses: vec![],
+                    status: "error".to_string(),
+                    error: Some(e.to_string()),
+                    error_kind: Some(error_kind(&e).to_string()),
+                    note: None,
+                    from_cache: false,
+                    retries_used,
+                    from_static: false,
+                    upstream,
+                };
+                // Only definitive answers are cached; SERVFAIL and transport errors may clear up
+                if !is_retriable(&e) && !self.negative_ttl.is_zero() {
+                    self.store(&result, query, Instant::now() + self.negative_ttl);
+                }
+                result
+            }
+            Err(_) => TypedDnsResult {
+                host,
+                addresses: vec![],
+                status: "timeout".to_string(),
+                error: Some("DNS resolution timeout".to_string()),
+                error_kind: Some("timeout".to_string()),
+                note: None,
+                from_cache: false,
+                retries_used,
+                from_static: false,
+                upstream,
+            },
+        };
+
+        debug!("Lookup of {} finished with status {} in {:?}", result.host, result.status, started.elapsed());
+        self.metrics.record_resolution(&result.status, started.elapsed());
+        result
+    }
+
+    /// Looks up the PTR names for a single IP address.
+    pub async fn reverse_lookup(&self, ip: IpAddr) -> ReverseDnsResult {
+        let _permit = self.acquire_permit().await;
+
+        match timeout(self.timeout_duration(), self.upstream(0).reverse_lookup(ip)).await {
             Ok(Ok(lookup)) => {
-                let ip_addresses: Vec<String> = lookup
+                let names: Vec<String> = lookup
                     .iter()
-                    .map(|ip| ip.to_string())
+                    .map(|name| name.to_utf8().trim_end_matches('.').to_string())
                     .collect();
-                
-                DnsResult {
-                    host,
-                    ip_addresses,
+
+                ReverseDnsResult {
+                    ip: ip.to_string(),
+                    names,
                     status: "success".to_string(),
                     error: None,
                 }
             }
-            Ok(Err(e)) => DnsResult {
-                host,
-                ip_addresses: vec![],
+            Ok(Err(e)) => ReverseDnsResult {
+                ip: ip.to_string(),
+                names: vec![],
                 status: "error".to_string(),
                 error: Some(e.to_string()),
             },
-            Err(_) => DnsResult {
-                host,
-                ip_addresses: vec![],
+            Err(_) => ReverseDnsResult {
+                ip: ip.to_string(),
+                names: vec![],
                 status: "timeout".to_string(),
                 error: Some("DNS resolution timeout".to_string()),
             },
         }
     }
 
+    /// Looks up the SRV records of a single service name, sorted by priority
+    /// and then by weight, heaviest first.
+    pub async fn resolve_srv(&self, name: &str) -> SrvDnsResult {
+        if let Err(e) = validate_srv_name(name) {
+            return SrvDnsResult {
+                name: name.to_string(),
+                records: vec![],
+                status: "error".to_string(),
+                error: Some(e),
+            };
+        }
+
+        let _permit = self.acquire_permit().await;
+        self.upstream_lookups.fetch_add(1, Ordering::Relaxed);
+
+        match timeout(self.timeout_duration(), self.upstream(0).srv_lookup(name)).await {
+            Ok(Ok(lookup)) => {
+                let mut records: Vec<SrvRecord> = lookup
+                    .iter()
+                    .map(|srv| SrvRecord {
+                        target: srv.target().to_utf8().trim_end_matches('.').to_string(),
+                        port: srv.port(),
+                        priority: srv.priority(),
+                        weight: srv.weight(),
+                    })
+                    .collect();
+                records.sort_by_key(|r| (r.priority, std::cmp::Reverse(r.weight)));
+
+                SrvDnsResult {
+                    name: name.to_string(),
+                    records,
+                    status: "success".to_string(),
+                    error: None,
+                }
+            }
+            Ok(Err(e)) => SrvDnsResult {
+                name: name.to_string(),
+                records: vec![],
+                status: "error".to_string(),
+                error: Some(e.to_string()),
+            },
+            Err(_) => SrvDnsResult {
+                name: name.to_string(),
+                records: vec![],
+                status: "timeout".to_string(),
+                error: Some("DNS resolution timeout".to_string()),
+            },
+        }
+    }
+
+    /// Resolves a batch of SRV names concurrently. Malformed names get a
+    /// per-entry error instead of failing the whole batch.
+    pub async fn resolve_srv_names(&self, names: Vec<String>) -> SrvDnsResponse {
+        let results = future::join_all(names.iter().map(|name| self.resolve_srv(name))).await;
+        let total_resolved = results.iter().filter(|r| r.status == "success").count();
+        let total_errors = results.len() - total_resolved;
+
+        SrvDnsResponse {
+            results,
+            total_resolved,
+            total_errors,
+        }
+    }
+
+    /// Reverse-resolves a batch of IPs concurrently. Entries that are not valid
+    /// IP addresses get a per-entry error instead of failing the whole batch.
+    pub async fn reverse_lookup_ips(&self, ips: Vec<String>) -> ReverseDnsResponse {
+        let futures: Vec<_> = ips
+            .iter()
+            .map(|ip| async move {
+                match ip.parse::<IpAddr>() {
+                    Ok(addr) => self.reverse_lookup(addr).await,
+                    Err(e) => ReverseDnsResult {
+                        ip: ip.clone(),
+                        names: vec![],
+                        status: "error".to_string(),
+                        error: Some(format!("Invalid IP address: {}", e)),
+                    },
+                }
+            })
+            .collect();
+
+        let results = future::join_all(futures).await;
+        let total_resolved = results.iter().filter(|r| r.status == "success").count();
+        let total_errors = results.len() - total_resolved;
+
+        ReverseDnsResponse {
+            results,
+            total_resolved,
+            total_errors,
+        }
+    }
+
+    /// Resolves a batch of hosts concurrently. Duplicate entries are looked up
+    /// only once, but the response still holds one result per input entry, in
+    /// the same order as `hosts`.
     pub async fn resolve_hosts(&self, hosts: Vec<String>) -> DnsResponse {
-        let mut results = Vec::new();
+        self.resolve_hosts_with_family(hosts, FamilyFilter::Any).await
+    }
+
+    /// `resolve_hosts`, keeping only addresses allowed by `family` in each result
+    pub async fn resolve_hosts_with_family(&self, hosts: Vec<String>, family: FamilyFilter) -> DnsResponse {
+        self.resolve_hosts_with(hosts, family, self.address_sort).await
+    }
+
+    /// `resolve_hosts_with_family`, returning each result's addresses in `sort` order
+    pub async fn resolve_hosts_with(&self, hosts: Vec<String>, family: FamilyFilter, sort: AddressSort) -> DnsResponse {
+        self.resolve_batch(&hosts, |host| self.resolve_host_with(host, family, sort)).await
+    }
+
+    /// Resolves each unique host in `hosts` concurrently with `resolve`, answering in request order
+    async fn resolve_batch<'h, F, Fut>(&self, hosts: &'h [String], resolve: F) -> DnsResponse
+    where
+        F: Fn(&'h str) -> Fut,
+        Fut: Future<Output = DnsResult>,
+    {
+        let mut results = Vec::with_capacity(hosts.len());
         let mut total_resolved = 0;
         let mut total_errors = 0;
 
-        // Resolve all hosts concurrently
-        let futures: Vec<_> = hosts
+        let mut seen = HashSet::new();
+        let unique_hosts: Vec<&str> = hosts
+            .iter()
+            .map(String::as_str)
+            .filter(|host| seen.insert(*host))
+            .collect();
+
+        // Resolve all unique hosts concurrently
+        let resolve = &resolve;
+        let futures: Vec<_> = unique_hosts
             .iter()
-            .map(|host| self.resolve_host(host))
+            .map(|host| async move {
+                let started = Instant::now();
+                // A panic is contained to its own host rather than failing the whole batch
+                let result = match AssertUnwindSafe(resolve(host))
+                    .catch_unwind()
+                    .await
+                {
+                    Ok(result) => result,
+                    Err(panic) => {
+                        error!("Resolution of {} panicked: {}", host, panic_message(panic.as_ref()));
+                        self.metrics.record_resolution("error", started.elapsed());
+                        DnsResult::internal_error(host)
+                    }
+                };
+                // Sending only fails when nobody is subscribed
+                let _ = self.events.send(ResolutionEvent {
+                    host: result.host.clone(),
+                    status: result.status.clone(),
+                    ips: result.ip_addresses.clone(),
+                    duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+                });
+                result
+            })
             .collect();
 
-        let resolved_results = future::join_all(futures).await;
+        let resolved: HashMap<&str, DnsResult> = unique_hosts
+            .iter()
+            .copied()
+            .zip(future::join_all(futures).await)
+            .collect();
 
-        for result in resolved_results {
+        for host in hosts {
+            let result = resolved[host.as_str()].clone();
             if result.status == "success" {
                 total_resolved += 1;
             } else {
@@ -100,9 +1490,11 @@ impl DnsResolver {
         }
 
         DnsResponse {
+            summary: DnsSummary::from_results(&results),
             results,
             total_resolved,
             total_errors,
+            request_id: None,
         }
     }
 }
@@ -110,7 +1502,6 @@ impl DnsResolver {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio;
 
     #[tokio::test]
     async fn test_resolve_single_host() {
@@ -164,6 +1555,558 @@ mod tests {
         assert!(invalid_result.error.is_some());
     }
 
+    #[tokio::test]
+    async fn test_resolve_duplicate_hosts_in_order() {
+        let resolver = DnsResolver::new().expect("Failed to create resolver");
+        let hosts = vec![
+            "google.com".to_string(),
+            "google.com".to_string(),
+            "github.com".to_string(),
+        ];
+
+        let response = resolver.resolve_hosts(hosts).await;
+
+        let result_hosts: Vec<&str> = response.results.iter().map(|r| r.host.as_str()).collect();
+        assert_eq!(result_hosts, ["google.com", "google.com", "github.com"]);
+        assert_eq!(resolver.upstream_lookups.load(Ordering::Relaxed), 2);
+        assert_eq!(response.total_resolved + response.total_errors, 3);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit_is_honored() {
+        // A nameserver that never answers keeps every lookup in flight until
+        // the resolver's own timeout fires
+        let silent_server = std::net::UdpSocket::bind("127.0.0.1:0").expect("Failed to bind UDP socket");
+        let nameserver = silent_server.local_addr().unwrap();
+
+        let mut resolver_config = ResolverConfig::new();
+        resolver_config.add_name_server(trust_dns_resolver::config::NameServerConfig::new(
+            nameserver,
+            trust_dns_resolver::config::Protocol::Udp,
+        ));
+        let mut resolver_opts = ResolverOpts::default();
+        resolver_opts.timeout = Duration::from_millis(200);
+        resolver_opts.attempts = 0;
+        resolver_opts.use_hosts_file = false;
+
+        let resolver = DnsResolver::with_resolvers(
+            vec![TokioAsyncResolver::tokio(resolver_config, resolver_opts)],
+            vec!["fake".to_string()],
+            3,
+        );
+
+        let hosts: Vec<String> = (0..12).map(|i| format!("host{}.wdns.test", i)).collect();
+        let response = resolver.resolve_hosts(hosts).await;
+
+        assert_eq!(response.results.len(), 12);
+        assert_eq!(resolver.peak_in_flight.load(Ordering::Relaxed), 3);
+        assert_eq!(resolver.in_flight.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_lookup_is_not_left_in_flight() {
+        let silent_server = std::net::UdpSocket::bind("127.0.0.1:0").expect("Failed to bind UDP socket");
+        let resolver = DnsResolver::with_upstreams(
+            vec![udp_upstream(silent_server.local_addr().unwrap())],
+            upstream_only_options(),
+        )
+        .expect("Failed to create resolver");
+
+        let lookup = resolver.resolve_host("stuck.wdns.test");
+        assert!(timeout(Duration::from_millis(100), lookup).await.is_err());
+
+        assert_eq!(resolver.peak_in_flight.load(Ordering::Relaxed), 1);
+        assert_eq!(resolver.in_flight.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_in_flight_guard_survives_panic() {
+        let in_flight = AtomicUsize::new(0);
+        let panicked = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let (_guard, count) = InFlightGuard::enter(&in_flight);
+            assert_eq!(count, 1);
+            panic!("lookup panicked");
+        }));
+
+        assert!(panicked.is_err());
+        assert_eq!(in_flight.load(Ordering::Relaxed), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_hosts_file_can_be_disabled() {
+        // Any /etc/hosts name other than localhost, which trust-dns answers itself
+        let hosts_file = std::fs::read_to_string("/etc/hosts").unwrap_or_default();
+        let Some(host) = hosts_file
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(|line| line.split_whitespace().skip(1))
+            .find(|name| !name.starts_with("localhost") && !name.starts_with("ip6-"))
+            .map(str::to_string)
+        else {
+            return;
+        };
+
+        // An upstream that knows no names
+        let upstream = spawn_fake_dns_server(None).await;
+
+        let with_hosts_file = DnsResolver::with_options(udp_upstream(upstream), DnsResolverOptions::default())
+            .expect("Failed to create resolver");
+        assert_eq!(with_hosts_file.resolve_host(&host).await.status, "success");
+
+        let without_hosts_file = DnsResolver::with_options(udp_upstream(upstream), upstream_only_options())
+            .expect("Failed to create resolver");
+        let result = without_hosts_file.resolve_host(&host).await;
+        assert_eq!(result.status, "error");
+        assert_eq!(result.error_kind.as_deref(), Some("nxdomain"));
+    }
+
+    /// Name server answering every A query with `answer`, or every query with
+    /// NXDOMAIN when `answer` is None
+    async fn spawn_fake_dns_server(answer: Option<std::net::Ipv4Addr>) -> SocketAddr {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.expect("Failed to bind UDP socket");
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                // Question name, then QTYPE and QCLASS
+                let mut end = 12;
+                while end < len && buf[end] != 0 {
+                    end += usize::from(buf[end]) + 1;
+                }
+                let question_end = end + 5;
+                if question_end > len {
+                    continue;
+                }
+                let is_a_query = buf[end + 1..end + 3] == [0, 1];
+
+                // A known name has no records of other types; an unknown one doesn't exist
+                let rcode = if answer.is_none() { 3 } else { 0 };
+                let answer = answer.filter(|_| is_a_query);
+                let mut response = buf[..2].to_vec();
+                response.extend_from_slice(&[0x81, 0x80 | rcode, 0, 1, 0, u8::from(answer.is_some()), 0, 0, 0, 0]);
+                response.extend_from_slice(&buf[12..question_end]);
+                if let Some(ip) = answer {
+                    response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                    response.extend_from_slice(&ip.octets());
+                }
+                let _ = socket.send_to(&response, peer).await;
+            }
+        });
+        addr
+    }
+
+    /// Answers A queries with `v4` and AAAA queries with `v6`, counting the queries received
+    async fn spawn_dual_stack_dns_server(
+        v4: std::net::Ipv4Addr,
+        v6: std::net::Ipv6Addr,
+    ) -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.expect("Failed to bind UDP socket");
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut end = 12;
+                while end < len && buf[end] != 0 {
+                    end += usize::from(buf[end]) + 1;
+                }
+                let question_end = end + 5;
+                if question_end > len {
+                    continue;
+                }
+
+                let (record_type, rdata) = match buf[end + 1..end + 3] {
+                    [0, 1] => (1u8, v4.octets().to_vec()),
+                    [0, 28] => (28u8, v6.octets().to_vec()),
+                    _ => continue,
+                };
+                let mut response = buf[..2].to_vec();
+                response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+                response.extend_from_slice(&buf[12..question_end]);
+                response.extend_from_slice(&[0xc0, 0x0c, 0, record_type, 0, 1, 0, 0, 0, 60, 0, rdata.len() as u8]);
+                response.extend_from_slice(&rdata);
+                let _ = socket.send_to(&response, peer).await;
+            }
+        });
+        (addr, queries)
+    }
+
+    /// Answers every SRV query with `records` as (priority, weight, port, target)
+    async fn spawn_fake_srv_server(records: Vec<(u16, u16, u16, &'static str)>) -> SocketAddr {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.expect("Failed to bind UDP socket");
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let mut end = 12;
+                while end < len && buf[end] != 0 {
+                    end += usize::from(buf[end]) + 1;
+                }
+                let question_end = end + 5;
+                if question_end > len {
+                    continue;
+                }
+
+                let mut response = buf[..2].to_vec();
+                response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, records.len() as u8, 0, 0, 0, 0]);
+                response.extend_from_slice(&buf[12..question_end]);
+                for (priority, weight, port, target) in &records {
+                    let mut rdata = Vec::new();
+                    for value in [priority, weight, port] {
+                        rdata.extend_from_slice(&value.to_be_bytes());
+                    }
+                    for label in target.split('.') {
+                        rdata.push(label.len() as u8);
+                        rdata.extend_from_slice(label.as_bytes());
+                    }
+                    rdata.push(0);
+                    response.extend_from_slice(&[0xc0, 0x0c, 0, 33, 0, 1, 0, 0, 0, 60]);
+                    response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+                    response.extend_from_slice(&rdata);
+                }
+                let _ = socket.send_to(&response, peer).await;
+            }
+        });
+        addr
+    }
+
+    fn udp_upstream(addr: SocketAddr) -> ResolverConfig {
+        let mut config = ResolverConfig::new();
+        config.add_name_server(trust_dns_resolver::config::NameServerConfig::new(
+            addr,
+            trust_dns_resolver::config::Protocol::Udp,
+        ));
+        config
+    }
+
+    fn upstream_only_options() -> DnsResolverOptions {
+        DnsResolverOptions {
+            use_hosts_file: false,
+            attempts: 0,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_srv_sorts_records() {
+        let upstream = spawn_fake_srv_server(vec![
+            (20, 0, 5060, "backup.example.com"),
+            (10, 10, 5061, "light.example.com"),
+            (10, 60, 5060, "heavy.example.com"),
+        ])
+        .await;
+        let resolver = DnsResolver::with_options(udp_upstream(upstream), upstream_only_options())
+            .expect("Failed to create resolver");
+
+        let result = resolver.resolve_srv("_sip._tcp.example.com").await;
+        assert_eq!(result.status, "success", "{:?}", result.error);
+        assert_eq!(
+            result.records,
+            vec![
+                SrvRecord { target: "heavy.example.com".to_string(), port: 5060, priority: 10, weight: 60 },
+                SrvRecord { target: "light.example.com".to_string(), port: 5061, priority: 10, weight: 10 },
+                SrvRecord { target: "backup.example.com".to_string(), port: 5060, priority: 20, weight: 0 },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_srv_names_rejects_malformed_names() {
+        let upstream = spawn_fake_srv_server(vec![(0, 5, 5269, "xmpp.example.com")]).await;
+        let resolver = DnsResolver::with_options(udp_upstream(upstream), upstream_only_options())
+            .expect("Failed to create resolver");
+
+        let response = resolver
+            .resolve_srv_names(vec![
+                "_xmpp-server._tcp.example.com".to_string(),
+                "example.com".to_string(),
+                "_sip.example.com".to_string(),
+            ])
+            .await;
+
+        assert_eq!(response.total_resolved, 1);
+        assert_eq!(response.total_errors, 2);
+        assert_eq!(response.results[0].records[0].port, 5269);
+        for result in &response.results[1..] {
+            assert_eq!(result.status, "error");
+            assert!(result.error.as_ref().unwrap().contains("Invalid SRV name"));
+        }
+        // Malformed names are refused before reaching the upstream
+        assert_eq!(resolver.upstream_lookups.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_rotates_to_secondary_upstream() {
+        // The primary never answers, so its lookup times out
+        let silent_primary = std::net::UdpSocket::bind("127.0.0.1:0").expect("Failed to bind UDP socket");
+        let secondary = spawn_fake_dns_server(Some(std::net::Ipv4Addr::new(192, 0, 2, 7))).await;
+
+        let resolver = DnsResolver::with_upstreams(
+            vec![udp_upstream(silent_primary.local_addr().unwrap()), udp_upstream(secondary)],
+            upstream_only_options(),
+        )
+        .expect("Failed to create resolver");
+        resolver.set_timeout(Duration::from_millis(300));
+
+        let result = resolver.resolve_host("service.wdns.test").await;
+        assert_eq!(result.status, "success");
+        assert_eq!(result.ip_addresses, vec!["192.0.2.7"]);
+        assert_eq!(result.retries_used, 1);
+
+        // Without retries the primary's timeout is final
+        resolver.set_max_retries(0);
+        let result = resolver.resolve_host("other.wdns.test").await;
+        assert_eq!(result.status, "timeout");
+        assert_eq!(result.retries_used, 0);
+    }
+
+    #[tokio::test]
+    async fn test_error_kind_nxdomain() {
+        let upstream = spawn_fake_dns_server(None).await;
+        let resolver = DnsResolver::with_options(udp_upstream(upstream), upstream_only_options())
+            .expect("Failed to create resolver");
+
+        let result = resolver.resolve_host("missing.wdns.test").await;
+        assert_eq!(result.status, "error");
+        assert_eq!(result.error_kind.as_deref(), Some("nxdomain"));
+        assert!(result.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_nxdomain_is_cached_for_negative_ttl() {
+        let upstream = spawn_fake_dns_server(None).await;
+        let resolver = DnsResolver::with_options(udp_upstream(upstream), upstream_only_options())
+            .expect("Failed to create resolver");
+
+        let first = resolver.resolve_host("missing.wdns.test").await;
+        assert!(!first.from_cache);
+        let second = resolver.resolve_host("missing.wdns.test").await;
+        assert!(second.from_cache);
+        assert_eq!(second.status, "error");
+        assert_eq!(second.error_kind.as_deref(), Some("nxdomain"));
+        assert_eq!(second.error, first.error);
+        assert_eq!(resolver.upstream_lookups.load(Ordering::Relaxed), 1);
+
+        // Expired entries are looked up again
+        let resolver = DnsResolver::builder()
+            .servers(vec![upstream])
+            .options(upstream_only_options())
+            .negative_ttl(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        resolver.resolve_host("missing.wdns.test").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!resolver.resolve_host("missing.wdns.test").await.from_cache);
+        assert_eq!(resolver.upstream_lookups.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_timeouts_are_not_negatively_cached() {
+        let silent_server = std::net::UdpSocket::bind("127.0.0.1:0").expect("Failed to bind UDP socket");
+        let resolver = DnsResolver::with_options(
+            udp_upstream(silent_server.local_addr().unwrap()),
+            upstream_only_options(),
+        )
+        .expect("Failed to create resolver");
+        resolver.set_max_retries(0);
+        resolver.set_timeout(Duration::from_millis(100));
+
+        resolver.resolve_host("slow.wdns.test").await;
+        let second = resolver.resolve_host("slow.wdns.test").await;
+        assert_eq!(second.status, "timeout");
+        assert!(!second.from_cache);
+        assert_eq!(resolver.upstream_lookups.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_error_kind_timeout() {
+        let silent_server = std::net::UdpSocket::bind("127.0.0.1:0").expect("Failed to bind UDP socket");
+        let resolver = DnsResolver::with_options(
+            udp_upstream(silent_server.local_addr().unwrap()),
+            upstream_only_options(),
+        )
+        .expect("Failed to create resolver");
+        resolver.set_max_retries(0);
+        resolver.set_timeout(Duration::from_millis(200));
+
+        let result = resolver.resolve_host("slow.wdns.test").await;
+        assert_eq!(result.status, "timeout");
+        assert_eq!(result.error_kind.as_deref(), Some("timeout"));
+    }
+
+    #[tokio::test]
+    async fn test_nxdomain_is_not_retried() {
+        let primary = spawn_fake_dns_server(None).await;
+        let secondary = spawn_fake_dns_server(Some(std::net::Ipv4Addr::new(192, 0, 2, 7))).await;
+
+        let resolver = DnsResolver::with_upstreams(
+            vec![udp_upstream(primary), udp_upstream(secondary)],
+            upstream_only_options(),
+        )
+        .expect("Failed to create resolver");
+        resolver.set_max_retries(3);
+
+        let result = resolver.resolve_host("missing.wdns.test").await;
+        assert_eq!(result.status, "error");
+        assert_eq!(result.retries_used, 0);
+    }
+
+    #[tokio::test]
+    async fn test_aaaa_lookup_is_explicit_and_cached_separately() {
+        let v4 = std::net::Ipv4Addr::new(192, 0, 2, 7);
+        let v6: std::net::Ipv6Addr = "2001:db8::7".parse().unwrap();
+        let (upstream, queries) = spawn_dual_stack_dns_server(v4, v6).await;
+        let resolver = DnsResolver::with_upstreams(vec![udp_upstream(upstream)], upstream_only_options())
+            .expect("Failed to create resolver");
+
+        // The default lookup stops at the A record
+        assert_eq!(resolver.resolve_host_typed("dual.wdns.test").await.ips(), vec![IpAddr::V4(v4)]);
+        assert_eq!(resolver.resolve_host_aaaa("dual.wdns.test").await.ips(), vec![IpAddr::V6(v6)]);
+
+        let cached_v4 = resolver.resolve_host_typed("dual.wdns.test").await;
+        let cached_v6 = resolver.resolve_host_aaaa("dual.wdns.test").await;
+        assert!(cached_v4.from_cache && cached_v6.from_cache);
+        assert_eq!(cached_v4.ips(), vec![IpAddr::V4(v4)]);
+        assert_eq!(cached_v6.ips(), vec![IpAddr::V6(v6)]);
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+        assert_eq!(resolver.clear_cache_hosts(&["dual.wdns.test".to_string()]), 1);
+        assert!(!resolver.resolve_host_aaaa("dual.wdns.test").await.from_cache);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_host_does_not_fail_batch() {
+        let static_hosts = [("pinned.wdns.test".to_string(), vec!["192.0.2.9".parse().unwrap()])]
+            .into_iter()
+            .collect();
+        let resolver = DnsResolver::builder()
+            .static_hosts(static_hosts)
+            .build()
+            .expect("Failed to create resolver");
+
+        let hosts = vec![
+            "pinned.wdns.test".to_string(),
+            "panic.wdns.test".to_string(),
+            "localhost".to_string(),
+        ];
+        let response = resolver
+            .resolve_batch(&hosts, |host| {
+                let resolver = &resolver;
+                async move {
+                    if host == "panic.wdns.test" {
+                        panic!("lookup of {} panicked", host);
+                    }
+                    resolver.resolve_host(host).await
+                }
+            })
+            .await;
+
+        assert_eq!(response.total_resolved, 2);
+        assert_eq!(response.total_errors, 1);
+        assert_eq!(response.results[0].ip_addresses, vec!["192.0.2.9"]);
+        assert_eq!(response.results[1].host, "panic.wdns.test");
+        assert_eq!(response.results[1].status, "error");
+        assert_eq!(response.results[1].error_kind.as_deref(), Some("internal"));
+        assert_eq!(response.results[2].status, "success");
+    }
+
+    #[tokio::test]
+    async fn test_summary_counts_cache_hits() {
+        let upstream = spawn_fake_dns_server(Some(std::net::Ipv4Addr::new(192, 0, 2, 7))).await;
+        let mut resolver = DnsResolver::with_upstreams(vec![udp_upstream(upstream)], upstream_only_options())
+            .expect("Failed to create resolver");
+        resolver.static_hosts.insert(cache_key("pinned.wdns.test"), vec!["192.0.2.9".parse().unwrap()]);
+        let hosts = vec!["service.wdns.test".to_string(), "pinned.wdns.test".to_string()];
+
+        let first = resolver.resolve_hosts(hosts.clone()).await;
+        assert_eq!(
+            first.summary,
+            DnsSummary {
+                from_cache: 0,
+                from_upstream: 1,
+                from_static: 1,
+                upstreams_used: vec![upstream.to_string()],
+            }
+        );
+        assert_eq!(first.results[0].upstream.as_deref(), Some(upstream.to_string().as_str()));
+
+        let second = resolver.resolve_hosts(hosts).await;
+        assert_eq!(
+            second.summary,
+            DnsSummary {
+                from_cache: 1,
+                from_upstream: 0,
+                from_static: 1,
+                upstreams_used: vec![],
+            }
+        );
+        assert!(second.results[0].upstream.is_none());
+    }
+
+    #[test]
+    fn test_upstream_configs() {
+        assert_eq!(upstream_configs(&[]).len(), 1);
+
+        let configs = upstream_configs(&["10.0.0.53:53".parse().unwrap(), "[::1]:5353".parse().unwrap()]);
+        assert_eq!(configs.len(), 2);
+        assert!(configs[1]
+            .name_servers()
+            .iter()
+            .all(|server| server.socket_addr == "[::1]:5353".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_reverse_lookup_localhost() {
+        let resolver = DnsResolver::new().expect("Failed to create resolver");
+        let result = resolver.reverse_lookup("127.0.0.1".parse().unwrap()).await;
+
+        assert_eq!(result.ip, "127.0.0.1");
+        assert_eq!(result.status, "success");
+        assert!(result.names.iter().any(|name| name == "localhost"));
+    }
+
+    #[tokio::test]
+    async fn test_reverse_lookup_invalid_ip() {
+        let resolver = DnsResolver::new().expect("Failed to create resolver");
+        let response = resolver
+            .reverse_lookup_ips(vec!["127.0.0.1".to_string(), "not-an-ip".to_string()])
+            .await;
+
+        assert_eq!(response.results.len(), 2);
+        assert_eq!(response.total_resolved, 1);
+        assert_eq!(response.total_errors, 1);
+
+        let invalid = &response.results[1];
+        assert_eq!(invalid.ip, "not-an-ip");
+        assert_eq!(invalid.status, "error");
+        assert!(invalid.error.as_ref().unwrap().contains("Invalid IP address"));
+    }
+
+    #[test]
+    fn test_set_timeout_and_concurrency() {
+        let resolver = DnsResolver::with_concurrency(4).expect("Failed to create resolver");
+        assert_eq!(resolver.timeout_duration(), Duration::from_secs(10));
+        assert_eq!(resolver.semaphore.read().unwrap().available_permits(), 4);
+
+        resolver.set_timeout(Duration::from_secs(3));
+        resolver.set_concurrency(8);
+
+        assert_eq!(resolver.timeout_duration(), Duration::from_secs(3));
+        assert_eq!(resolver.semaphore.read().unwrap().available_permits(), 8);
+    }
+
+    #[test]
+    fn test_new_request_id() {
+        let first = new_request_id();
+        let second = new_request_id();
+        assert_eq!(first.len(), 8);
+        assert!(first.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(first, second);
+    }
+
     #[tokio::test]
     async fn test_resolve_empty_hosts() {
         let resolver = DnsResolver::new().expect("Failed to create resolver");
@@ -174,6 +2117,57 @@ mod tests {
         assert_eq!(response.total_errors, 0);
     }
 
+    #[test]
+    fn test_response_status_code() {
+        let response = |total_resolved, total_errors| DnsResponse {
+            results: vec![],
+            total_resolved,
+            total_errors,
+            request_id: None,
+            summary: DnsSummary::default(),
+        };
+
+        assert_eq!(response(2, 0).status_code(true), 200);
+        assert_eq!(response(1, 1).status_code(true), 207);
+        assert_eq!(response(0, 2).status_code(true), 502);
+
+        // Partial failures only surface in strict mode, total failures always do
+        assert_eq!(response(2, 0).status_code(false), 200);
+        assert_eq!(response(1, 1).status_code(false), 200);
+        assert_eq!(response(0, 2).status_code(false), 502);
+    }
+
+    #[test]
+    fn test_response_status_code_all_timeouts() {
+        let result = |status: &str| DnsResult {
+            host: "slow.example".to_string(),
+            ip_addresses: vec![],
+            status: status.to_string(),
+            error: Some("DNS resolution timeout".to_string()),
+            error_kind: Some("timeout".to_string()),
+            note: None,
+            from_cache: false,
+            retries_used: 0,
+            from_static: false,
+            upstream: None,
+        };
+        let response = |results: Vec<DnsResult>| DnsResponse {
+            total_resolved: 0,
+            total_errors: results.len(),
+            results,
+            request_id: None,
+            summary: DnsSummary::default(),
+        };
+
+        let all_timeouts = response(vec![result("timeout"), result("timeout")]);
+        assert_eq!(all_timeouts.status_code(false), 504);
+        assert_eq!(all_timeouts.status_code(true), 504);
+
+        // Any non-timeout failure makes it a plain upstream failure
+        let mixed = response(vec![result("timeout"), result("error")]);
+        assert_eq!(mixed.status_code(false), 502);
+    }
+
     #[tokio::test]
     async fn test_resolve_localhost() {
         let resolver = DnsResolver::new().expect("Failed to create resolver");
@@ -186,6 +2180,132 @@ mod tests {
         assert!(result.ip_addresses.iter().any(|ip| ip == "127.0.0.1" || ip == "::1"));
     }
 
+    #[tokio::test]
+    async fn test_resolve_localhost_typed() {
+        let resolver = DnsResolver::new().expect("Failed to create resolver");
+        let result = resolver.resolve_host_typed("localhost").await;
+
+        assert_eq!(result.status, "success");
+        let loopback_v4: IpAddr = "127.0.0.1".parse().unwrap();
+        let loopback_v6: IpAddr = "::1".parse().unwrap();
+        assert!(result.ips().iter().any(|ip| *ip == loopback_v4 || *ip == loopback_v6));
+        for resolved in &result.addresses {
+            assert_eq!(resolved.family == IpFamily::V4, resolved.addr.is_ipv4());
+        }
+
+        // The string API is derived from the typed one
+        let strings = DnsResult::from(result.clone());
+        assert_eq!(strings.ip_addresses, result.ips().iter().map(IpAddr::to_string).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_family_filter() {
+        assert_eq!(FamilyFilter::parse(None), Ok(FamilyFilter::Any));
+        assert_eq!(FamilyFilter::parse(Some("V4")), Ok(FamilyFilter::Only(IpFamily::V4)));
+        assert_eq!(FamilyFilter::parse(Some("v6")), Ok(FamilyFilter::Only(IpFamily::V6)));
+        assert!(FamilyFilter::parse(Some("ipv4")).is_err());
+
+        let mut result = TypedDnsResult {
+            host: "example.com".to_string(),
+            addresses: vec![
+                ResolvedIp::from("93.184.216.34".parse::<IpAddr>().unwrap()),
+                ResolvedIp::from("2606:2800:220:1::".parse::<IpAddr>().unwrap()),
+            ],
+            status: "success".to_string(),
+            error: None,
+            error_kind: None,
+            note: None,
+            from_cache: false,
+            retries_used: 0,
+            from_static: false,
+            upstream: None,
+        };
+
+        let mut v4_only = result.clone();
+        v4_only.retain_family(FamilyFilter::Only(IpFamily::V4));
+        assert_eq!(v4_only.ips(), ["93.184.216.34".parse::<IpAddr>().unwrap()]);
+        assert!(v4_only.note.is_none());
+
+        // Filtering everything away is still a success, with a note
+        result.addresses.truncate(1);
+        result.retain_family(FamilyFilter::Only(IpFamily::V6));
+        let result = DnsResult::from(result);
+        assert_eq!(result.status, "success");
+        assert!(result.ip_addresses.is_empty());
+        assert!(result.note.unwrap().contains("IPv6"));
+    }
+
+    #[test]
+    fn test_address_sort_parse() {
+        assert_eq!(AddressSort::parse(None), Ok(AddressSort::None));
+        assert_eq!(AddressSort::parse(Some("V4First")), Ok(AddressSort::V4First));
+        assert_eq!(AddressSort::parse(Some("v6first")), Ok(AddressSort::V6First));
+        assert_eq!(AddressSort::parse(Some("probe:443")), Ok(AddressSort::Probe(443)));
+        for bad in ["fastest", "probe", "probe:0", "probe:70000"] {
+            assert!(AddressSort::parse(Some(bad)).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_request_address_sort_refuses_probe() {
+        let request = |sort: &str| DnsRequest {
+            hosts: vec!["localhost".to_string()],
+            strict: false,
+            family: None,
+            address_sort: Some(sort.to_string()),
+        };
+
+        assert_eq!(request("v6first").address_sort(), Ok(Some(AddressSort::V6First)));
+        let err = request("probe:22").address_sort().unwrap_err();
+        assert!(err.contains("dns_address_sort"), "{}", err);
+        assert!(request("fastest").address_sort().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_v4first_and_v6first_keep_order_within_family() {
+        let ips = |list: &[&str]| -> Vec<IpAddr> { list.iter().map(|ip| ip.parse().unwrap()).collect() };
+        let answer = ips(&["2001:db8::1", "10.0.0.1", "2001:db8::2", "10.0.0.2"]);
+        let resolver = DnsResolverBuilder::default()
+            .static_hosts(HashMap::from([("dual.test".to_string(), answer.clone())]))
+            .address_sort(AddressSort::V4First)
+            .build()
+            .unwrap();
+
+        // The configured default applies when nothing else is asked for
+        let result = resolver.resolve_host("dual.test").await;
+        assert_eq!(result.ip_addresses, ["10.0.0.1", "10.0.0.2", "2001:db8::1", "2001:db8::2"]);
+
+        let result = resolver.resolve_host_with("dual.test", FamilyFilter::Any, AddressSort::V6First).await;
+        assert_eq!(result.ip_addresses, ["2001:db8::1", "2001:db8::2", "10.0.0.1", "10.0.0.2"]);
+
+        let result = resolver.resolve_host_with("dual.test", FamilyFilter::Any, AddressSort::None).await;
+        let expected: Vec<String> = answer.iter().map(IpAddr::to_string).collect();
+        assert_eq!(result.ip_addresses, expected);
+    }
+
+    #[tokio::test]
+    async fn test_probe_order_puts_reachable_addresses_first() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Nothing listens on 127.0.0.2 or 127.0.0.3, so those connects are refused
+        let mut addresses: Vec<ResolvedIp> = ["127.0.0.2", "127.0.0.1", "127.0.0.3"]
+            .iter()
+            .map(|ip| ResolvedIp::from(ip.parse::<IpAddr>().unwrap()))
+            .collect();
+        probe_order(&mut addresses, port, ADDRESS_PROBE_TIMEOUT).await;
+
+        let order: Vec<String> = addresses.iter().map(|resolved| resolved.addr.to_string()).collect();
+        assert_eq!(order, ["127.0.0.1", "127.0.0.2", "127.0.0.3"]);
+    }
+
+    #[test]
+    fn test_ip_family_serialization() {
+        assert_eq!(IpFamily::of(&"10.0.0.1".parse().unwrap()), IpFamily::V4);
+        assert_eq!(IpFamily::of(&"fe80::1".parse().unwrap()), IpFamily::V6);
+        assert_eq!(serde_json::to_string(&IpFamily::V6).unwrap(), "\"v6\"");
+    }
+
     #[tokio::test]
     async fn test_concurrent_resolution() {
         let resolver = DnsResolver::new().expect("Failed to create resolver");
@@ -215,4 +2335,208 @@ mod tests {
             assert!(!result.ip_addresses.is_empty());
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_host_segment() {
+        assert_eq!(parse_host_segment("google.com").unwrap(), "google.com");
+        assert_eq!(parse_host_segment("example.com.").unwrap(), "example.com.");
+        assert_eq!(parse_host_segment("127.0.0.1").unwrap(), "127.0.0.1");
+        assert_eq!(parse_host_segment("%3A%3A1").unwrap(), "::1");
+        assert_eq!(parse_host_segment("my%2Dhost.local").unwrap(), "my-host.local");
+
+        assert!(parse_host_segment("").is_err());
+        assert!(parse_host_segment("%20").is_err());
+        assert!(parse_host_segment("invalid..host").is_err());
+        assert!(parse_host_segment("a%2Fb.com").is_err());
+        assert!(parse_host_segment("%FF").is_err());
+        assert!(parse_host_segment(&"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn test_result_status_code() {
+        let result = |status: &str| DnsResult {
+            host: "example.com".to_string(),
+            ip_addresses: vec![],
+            status: status.to_string(),
+            error: None,
+            error_kind: None,
+            note: None,
+            from_cache: false,
+            retries_used: 0,
+            from_static: false,
+            upstream: None,
+        };
+
+        assert_eq!(result("success").status_code(), 200);
+        assert_eq!(result("error").status_code(), 502);
+        assert_eq!(result("timeout").status_code(), 504);
+    }
+
+    #[test]
+    fn test_request_host_limit() {
+        let request = DnsRequest {
+            hosts: vec!["localhost".to_string(); 3],
+            strict: false,
+            family: None,
+            address_sort: None,
+        };
+
+        assert!(request.check_host_limit(3).is_ok());
+        let err = request.check_host_limit(2).unwrap_err();
+        assert!(err.contains("at most 2"), "{}", err);
+    }
+
+    #[test]
+    fn test_srv_request_name_limit() {
+        let request = SrvDnsRequest {
+            names: vec!["_sip._tcp.example.com".to_string(); 3],
+        };
+
+        assert!(request.check_host_limit(3).is_ok());
+        let err = request.check_host_limit(2).unwrap_err();
+        assert!(err.starts_with("Too many names"), "{}", err);
+        assert!(err.contains("at most 2"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let resolver = DnsResolver::new().expect("Failed to create resolver");
+
+        assert!(resolver.probe("localhost", READINESS_PROBE_TIMEOUT).await.is_ok());
+
+        let reason = resolver
+            .probe("invalid..host", READINESS_PROBE_TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(!reason.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_uses_cache_until_cleared() {
+        let resolver = DnsResolver::new().expect("Failed to create resolver");
+
+        let first = resolver.resolve_host("localhost").await;
+        assert_eq!(first.status, "success");
+        assert!(!first.from_cache);
+
+        // Case and a trailing dot do not defeat the cache
+        let cached = resolver.resolve_host("LOCALHOST.").await;
+        assert!(cached.from_cache);
+        assert_eq!(cached.host, "LOCALHOST.");
+        assert_eq!(cached.ip_addresses, first.ip_addresses);
+        assert_eq!(resolver.upstream_lookups.load(Ordering::Relaxed), 1);
+
+        assert_eq!(resolver.clear_cache(), 1);
+        assert_eq!(resolver.clear_cache(), 0);
+        assert!(!resolver.resolve_host("localhost").await.from_cache);
+    }
+
+    #[tokio::test]
+    async fn test_clear_cache_hosts() {
+        let resolver = DnsResolver::new().expect("Failed to create resolver");
+        resolver.resolve_host("localhost").await;
+        resolver.resolve_host("127.0.0.1").await;
+
+        let evicted = resolver.clear_cache_hosts(&[
+            "Localhost".to_string(),
+            "localhost".to_string(),
+            "not-cached.example".to_string(),
+        ]);
+        assert_eq!(evicted, 1);
+        assert!(!resolver.resolve_host("localhost").await.from_cache);
+        assert!(resolver.resolve_host("127.0.0.1").await.from_cache);
+    }
+
+    #[tokio::test]
+    async fn test_builder_sets_every_option() {
+        let resolver = DnsResolverBuilder::default()
+            .timeout(Duration::from_secs(3))
+            .concurrency(8)
+            .max_retries(0)
+            .cache(0)
+            .upstream(UpstreamMode::Default)
+            .build()
+            .expect("Failed to build resolver");
+        assert_eq!(resolver.timeout_duration(), Duration::from_secs(3));
+        assert_eq!(resolver.semaphore.read().unwrap().available_permits(), 8);
+        assert_eq!(resolver.max_retries.load(Ordering::Relaxed), 0);
+
+        // localhost is answered without the network; with caching off the
+        // second lookup goes upstream again
+        let first = resolver.resolve_host("localhost").await;
+        assert_eq!(first.status, "success");
+        assert!(!resolver.resolve_host("localhost").await.from_cache);
+        assert_eq!(resolver.clear_cache(), 0);
+
+        let servers = DnsResolverBuilder::default()
+            .servers(vec!["127.0.0.1:53".parse().unwrap(), "127.0.0.2:53".parse().unwrap()])
+            .build()
+            .expect("Failed to build resolver");
+        assert_eq!(servers.upstreams.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_blocklisted_host_is_refused() {
+        let resolver = DnsResolverBuilder::default()
+            .blocklist(vec!["*.ads.example".to_string(), "localhost".to_string()])
+            .build()
+            .expect("Failed to build resolver");
+
+        let result = resolver.resolve_host("LOCALHOST.").await;
+        assert_eq!(result.status, "error");
+        assert_eq!(result.error_kind.as_deref(), Some("blocked"));
+        assert!(result.ip_addresses.is_empty());
+        assert_eq!(result.status_code(), warp::http::StatusCode::FORBIDDEN);
+        assert_eq!(resolver.resolve_host("tracker.ads.example").await.error_kind.as_deref(), Some("blocked"));
+        assert_eq!(resolver.upstream_lookups.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_allowlist_miss_is_refused() {
+        let resolver = DnsResolverBuilder::default()
+            .allowlist(vec!["localhost".to_string(), "*.corp.example".to_string()])
+            .build()
+            .expect("Failed to build resolver");
+
+        let refused = resolver.resolve_host("example.com").await;
+        assert_eq!(refused.error_kind.as_deref(), Some("blocked"));
+        assert_eq!(resolver.upstream_lookups.load(Ordering::Relaxed), 0);
+
+        assert_eq!(resolver.resolve_host("localhost").await.status, "success");
+        assert_eq!(resolver.upstream_lookups.load(Ordering::Relaxed), 1);
+
+        assert!(DnsResolverBuilder::default()
+            .allowlist(vec!["10.0.0.0/8".to_string()])
+            .build()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_static_host_skips_upstream() {
+        let resolver = DnsResolverBuilder::default()
+            .static_hosts(HashMap::from([(
+                "Internal.Test".to_string(),
+                vec!["10.1.2.3".parse().unwrap()],
+            )]))
+            .build()
+            .expect("Failed to build resolver");
+
+        let result = resolver.resolve_host("INTERNAL.test.").await;
+        assert_eq!(result.status, "success");
+        assert_eq!(result.ip_addresses, vec!["10.1.2.3"]);
+        assert!(result.from_static);
+        assert!(!result.from_cache);
+        assert_eq!(result.host, "INTERNAL.test.");
+        assert_eq!(resolver.upstream_lookups.load(Ordering::Relaxed), 0);
+
+        assert!(!resolver.resolve_host("localhost").await.from_static);
+    }
+
+    #[tokio::test]
+    async fn test_failed_lookups_are_not_cached() {
+        let resolver = DnsResolver::new().expect("Failed to create resolver");
+        resolver.resolve_host("invalid..host").await;
+        assert!(!resolver.resolve_host("invalid..host").await.from_cache);
+        assert_eq!(resolver.clear_cache(), 0);
+    }
+}
@@ -0,0 +1,297 @@
+use anyhow::Result;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tracing::{debug, error, info};
+use trust_dns_resolver::proto::op::{Message, MessageType, OpCode, ResponseCode};
+use trust_dns_resolver::proto::rr::rdata::{A, AAAA};
+use trust_dns_resolver::proto::rr::{RData, Record, RecordType};
+
+use crate::dns::{DnsResolver, FamilyFilter, IpFamily};
+use crate::socks5::{bind_listener, DEFAULT_LISTEN_BACKLOG};
+
+/// TTL given to answers; upstream TTLs are not passed through
+const ANSWER_TTL: u32 = 60;
+
+/// Largest UDP query read from a client
+const MAX_UDP_QUERY_BYTES: usize = 4096;
+
+/// Plain DNS limit for UDP responses; larger answers are truncated so the
+/// client retries over TCP
+const MAX_UDP_RESPONSE_BYTES: usize = 512;
+
+/// Serves standard DNS queries over UDP and TCP, answering A and AAAA
+/// questions through the shared `DnsResolver`
+pub struct DnsListener {
+    pub bind_addr: SocketAddr,
+    resolver: Arc<DnsResolver>,
+    listen_backlog: u32,
+}
+
+impl DnsListener {
+    pub fn new(bind_addr: SocketAddr, resolver: Arc<DnsResolver>) -> Self {
+        Self {
+            bind_addr,
+            resolver,
+            listen_backlog: DEFAULT_LISTEN_BACKLOG,
+        }
+    }
+
+    /// Sets how many TCP connections may wait to be accepted
+    pub fn with_listen_backlog(mut self, backlog: u32) -> Self {
+        self.listen_backlog = backlog;
+        self
+    }
+
+    pub async fn run(self) -> Result<()> {
+        info!("Starting DNS listener on {}", self.bind_addr);
+
+        let udp = UdpSocket::bind(self.bind_addr).await?;
+        let tcp = bind_listener(self.bind_addr, self.listen_backlog)?;
+        info!("DNS listener listening on {} (UDP and TCP)", self.bind_addr);
+
+        serve(udp, tcp, self.resolver).await
+    }
+}
+
+async fn serve(udp: UdpSocket, tcp: TcpListener, resolver: Arc<DnsResolver>) -> Result<()> {
+    tokio::try_join!(serve_udp(udp, resolver.clone()), serve_tcp(tcp, resolver))?;
+    Ok(())
+}
+
+async fn serve_udp(socket: UdpSocket, resolver: Arc<DnsResolver>) -> Result<()> {
+    let socket = Arc::new(socket);
+    let mut buf = vec![0u8; MAX_UDP_QUERY_BYTES];
+
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                // Windows reports ICMP port unreachable from an earlier send here
+                debug!("Failed to receive DNS query: {}", e);
+                continue;
+            }
+        };
+
+        let query = buf[..len].to_vec();
+        let socket = socket.clone();
+        let resolver = resolver.clone();
+        tokio::spawn(async move {
+            if let Some(response) = answer(&query, &resolver, Some(MAX_UDP_RESPONSE_BYTES)).await {
+                if let Err(e) = socket.send_to(&response, peer).await {
+                    debug!("Failed to send DNS response to {}: {}", peer, e);
+                }
+            }
+        });
+    }
+}
+
+async fn serve_tcp(listener: TcpListener, resolver: Arc<DnsResolver>) -> Result<()> {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                debug!("New DNS TCP connection from {}", addr);
+                let resolver = resolver.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_tcp_connection(stream, &resolver).await {
+                        debug!("DNS TCP connection from {} closed: {}", addr, e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("Failed to accept DNS TCP connection: {}", e);
+            }
+        }
+    }
+}
+
+/// Answers length-prefixed queries until the client closes the connection
+async fn handle_tcp_connection(mut stream: TcpStream, resolver: &DnsResolver) -> Result<()> {
+    loop {
+        let len = match stream.read_u16().await {
+            Ok(len) => usize::from(len),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut query = vec![0u8; len];
+        stream.read_exact(&mut query).await?;
+
+        if let Some(response) = answer(&query, resolver, None).await {
+            let len = u16::try_from(response.len())?;
+            stream.write_u16(len).await?;
+            stream.write_all(&response).await?;
+        }
+    }
+}
+
+/// Builds the wire-format response to `query`, or None when it should be
+/// dropped. Responses longer than `max_len` are sent truncated.
+async fn answer(query: &[u8], resolver: &DnsResolver, max_len: Option<usize>) -> Option<Vec<u8>> {
+    let query = match Message::from_vec(query) {
+        Ok(query) => query,
+        Err(e) => {
+            debug!("Dropping malformed DNS query: {}", e);
+            return None;
+        }
+    };
+    if query.message_type() != MessageType::Query {
+        return None;
+    }
+
+    let mut response = Message::new();
+    response
+        .set_id(query.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(query.op_code())
+        .set_recursion_desired(query.recursion_desired())
+        .set_recursion_available(true);
+    for question in query.queries() {
+        response.add_query(question.clone());
+    }
+
+    let response_code = match (query.op_code(), query.queries()) {
+        (OpCode::Query, [question]) => {
+            let family = match question.query_type() {
+                RecordType::A => Some(IpFamily::V4),
+                RecordType::AAAA => Some(IpFamily::V6),
+                _ => None,
+            };
+            match family {
+                Some(family) => {
+                    let host = question.name().to_utf8();
+                    let host = host.trim_end_matches('.');
+                    // The default lookup skips AAAA records when a host has A records
+                    let mut result = match family {
+                        IpFamily::V4 => resolver.resolve_host_typed(host).await,
+                        IpFamily::V6 => resolver.resolve_host_aaaa(host).await,
+                    };
+                    result.retain_family(FamilyFilter::Only(family));
+
+                    for ip in result.ips() {
+                        let rdata = match ip {
+                            IpAddr::V4(ip) => RData::A(A(ip)),
+                            IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+                        };
+                        response.add_answer(Record::from_rdata(question.name().clone(), ANSWER_TTL, rdata));
+                    }
+                    response_code(result.status.as_str(), result.error_kind.as_deref())
+                }
+                None => ResponseCode::NotImp,
+            }
+        }
+        (OpCode::Query, _) => ResponseCode::FormErr,
+        _ => ResponseCode::NotImp,
+    };
+    response.set_response_code(response_code);
+
+    let mut bytes = response.to_vec().ok()?;
+    if max_len.is_some_and(|max_len| bytes.len() > max_len) {
+        response.take_answers();
+        response.set_truncated(true);
+        bytes = response.to_vec().ok()?;
+    }
+    Some(bytes)
+}
+
+/// Maps a lookup outcome onto the DNS response code sent to the client
+fn response_code(status: &str, error_kind: Option<&str>) -> ResponseCode {
+    match (status, error_kind) {
+        ("success", _) | (_, Some("no_records")) => ResponseCode::NoError,
+        (_, Some("nxdomain")) => ResponseCode::NXDomain,
+        (_, Some("blocked")) => ResponseCode::Refused,
+        _ => ResponseCode::ServFail,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use trust_dns_resolver::proto::op::Query;
+    use trust_dns_resolver::proto::rr::Name;
+
+    fn query_bytes(host: &str, record_type: RecordType) -> Vec<u8> {
+        let mut query = Message::new();
+        query
+            .set_id(4242)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(Name::from_ascii(host).unwrap(), record_type));
+        query.to_vec().unwrap()
+    }
+
+    async fn start_listener() -> (SocketAddr, SocketAddr) {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.expect("Failed to bind UDP socket");
+        let tcp = TcpListener::bind("127.0.0.1:0").await.expect("Failed to bind TCP listener");
+        let addrs = (udp.local_addr().unwrap(), tcp.local_addr().unwrap());
+        let resolver = Arc::new(DnsResolver::new().expect("Failed to create resolver"));
+        tokio::spawn(serve(udp, tcp, resolver));
+        addrs
+    }
+
+    #[tokio::test]
+    async fn test_udp_a_query_for_localhost() {
+        let (udp_addr, _) = start_listener().await;
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(&query_bytes("localhost.", RecordType::A), udp_addr).await.unwrap();
+
+        let mut buf = [0u8; 512];
+        let len = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut buf))
+            .await
+            .expect("No DNS response")
+            .unwrap();
+        let response = Message::from_vec(&buf[..len]).expect("Invalid DNS response");
+
+        assert_eq!(response.id(), 4242);
+        assert_eq!(response.message_type(), MessageType::Response);
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        let answers: Vec<_> = response.answers().iter().filter_map(|record| record.data()).collect();
+        assert_eq!(answers, vec![&RData::A(A(Ipv4Addr::LOCALHOST))]);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_aaaa_query_for_localhost() {
+        let (_, tcp_addr) = start_listener().await;
+
+        let mut stream = TcpStream::connect(tcp_addr).await.unwrap();
+        let query = query_bytes("localhost.", RecordType::AAAA);
+        stream.write_u16(query.len() as u16).await.unwrap();
+        stream.write_all(&query).await.unwrap();
+
+        let len = stream.read_u16().await.unwrap();
+        let mut buf = vec![0u8; usize::from(len)];
+        stream.read_exact(&mut buf).await.unwrap();
+        let response = Message::from_vec(&buf).expect("Invalid DNS response");
+
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        let answers: Vec<_> = response.answers().iter().filter_map(|record| record.data()).collect();
+        assert_eq!(answers, vec![&RData::AAAA(AAAA(std::net::Ipv6Addr::LOCALHOST))]);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_query_type() {
+        let resolver = DnsResolver::new().expect("Failed to create resolver");
+        let response = answer(&query_bytes("localhost.", RecordType::MX), &resolver, None)
+            .await
+            .expect("No DNS response");
+        let response = Message::from_vec(&response).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NotImp);
+        assert!(response.answers().is_empty());
+
+        assert!(answer(b"garbage", &resolver, None).await.is_none());
+    }
+
+    #[test]
+    fn test_response_code() {
+        assert_eq!(response_code("success", None), ResponseCode::NoError);
+        assert_eq!(response_code("error", Some("no_records")), ResponseCode::NoError);
+        assert_eq!(response_code("error", Some("nxdomain")), ResponseCode::NXDomain);
+        assert_eq!(response_code("timeout", Some("timeout")), ResponseCode::ServFail);
+        assert_eq!(response_code("error", Some("io_error")), ResponseCode::ServFail);
+        assert_eq!(response_code("error", Some("blocked")), ResponseCode::Refused);
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::Filter;
+
+use crate::dns::{DnsResolver, ResolutionEvent};
+
+/// `GET /api/dns/stream`: a WebSocket that receives one JSON `ResolutionEvent`
+/// per host resolved after the client connected
+pub fn route(
+    dns_resolver: Arc<DnsResolver>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("api")
+        .and(warp::path("dns"))
+        .and(warp::path("stream"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::ws())
+        .map(move |ws: Ws| {
+            // Subscribe during the handshake so nothing resolved after it is missed
+            let events = dns_resolver.subscribe_events();
+            ws.on_upgrade(move |socket| forward_events(socket, events))
+        })
+}
+
+/// Sends events to the client until it disconnects. Messages from the client are ignored.
+async fn forward_events(socket: WebSocket, mut events: broadcast::Receiver<ResolutionEvent>) {
+    let (mut sink, mut incoming) = socket.split();
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let text = match serde_json::to_string(&event) {
+                        Ok(text) => text,
+                        Err(e) => {
+                            debug!("Failed to encode resolution event: {}", e);
+                            continue;
+                        }
+                    };
+                    if sink.send(Message::text(text)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!("DNS stream client fell behind; skipped {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = incoming.next() => match message {
+                Some(Ok(message)) if !message.is_close() => {}
+                _ => break,
+            },
+        }
+    }
+}
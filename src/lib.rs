@@ -1,17 +1,33 @@
 // Library module for WDNS Service
 // This allows the code to be used as both a library and binary
 
+pub mod api_server;
 pub mod dns;
+pub mod metrics;
 pub mod config;
+pub mod cors;
+pub mod destination_rules;
+pub mod dns_listener;
+pub mod dns_stream;
+pub mod logging;
 pub mod service;
 pub mod proxy;
 pub mod socks5;
+pub mod socks5_client;
 pub mod ssh_tunnel;
+pub mod status;
 
 // Re-export main types for external use
-pub use dns::{DnsResolver, DnsRequest, DnsResponse, DnsResult};
-pub use config::{Config, SshTunnelConfig};
+pub use dns::{
+    AddressSort, DnsResolver, DnsResolverBuilder, DnsResolverOptions, DnsRequest, DnsResponse, DnsResult, DnsSummary,
+    FamilyFilter, FlushCacheRequest, IpFamily, ResolutionEvent, ResolvedIp, ReverseDnsRequest, ReverseDnsResponse,
+    ReverseDnsResult, SrvDnsRequest, SrvDnsResponse, SrvDnsResult, SrvRecord, TypedDnsResult, UpstreamMode,
+};
+pub use config::{Config, LogFormat, Socks5UpstreamConfig, SshTunnelConfig};
+pub use metrics::Metrics;
 pub use service::{is_service_mode, run_as_service};
+pub use dns_listener::DnsListener;
 pub use proxy::ProxyServer;
 pub use socks5::Socks5Server;
+pub use socks5_client::{Socks5Client, UpstreamProxy, UpstreamProxyKind};
 pub use ssh_tunnel::SshTunnelManager;
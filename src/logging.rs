@@ -0,0 +1,101 @@
+use std::net::SocketAddr;
+
+use crate::config::LogFormat;
+
+/// Installs the global tracing subscriber, writing human-readable lines for
+/// `LogFormat::Text` and one JSON object per event for `LogFormat::Json`.
+pub fn init(format: LogFormat) {
+    let builder = tracing_subscriber::fmt().with_env_filter("wdns=debug,warp=info");
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().flatten_event(true).init(),
+    }
+}
+
+/// One served HTTP request, as recorded by the access log
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessRecord {
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub elapsed_ms: f64,
+    pub remote_addr: Option<SocketAddr>,
+}
+
+impl AccessRecord {
+    pub fn from_info(info: &warp::log::Info<'_>) -> Self {
+        Self {
+            method: info.method().to_string(),
+            path: info.path().to_string(),
+            status: info.status().as_u16(),
+            elapsed_ms: info.elapsed().as_secs_f64() * 1000.0,
+            remote_addr: info.remote_addr(),
+        }
+    }
+
+    /// Emits this record as an `info` event on the `wdns::access` target,
+    /// with every field as a separate key for the JSON formatter.
+    pub fn emit(&self) {
+        let remote_addr = self
+            .remote_addr
+            .map_or_else(|| "-".to_string(), |addr| addr.to_string());
+        tracing::info!(
+            target: "wdns::access",
+            method = %self.method,
+            path = %self.path,
+            status = self.status,
+            elapsed_ms = self.elapsed_ms,
+            remote_addr = %remote_addr,
+            "request served"
+        );
+    }
+}
+
+/// Access log wrapper for the API routes, emitting an `AccessRecord` per request
+pub fn access_log() -> warp::log::Log<impl Fn(warp::log::Info<'_>) + Clone + Send> {
+    access_log_with(|record| record.emit())
+}
+
+/// Like `access_log`, but hands each record to `sink` instead of tracing
+pub fn access_log_with<F>(sink: F) -> warp::log::Log<impl Fn(warp::log::Info<'_>) + Clone + Send>
+where
+    F: Fn(AccessRecord) + Clone + Send,
+{
+    warp::log::custom(move |info| sink(AccessRecord::from_info(&info)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn test_access_log_records_health_check() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink = records.clone();
+
+        let health = warp::path("health")
+            .and(warp::get())
+            .map(|| warp::reply::json(&serde_json::json!({ "status": "healthy" })))
+            .with(access_log_with(move |record| sink.lock().unwrap().push(record)));
+
+        let remote_addr: SocketAddr = "192.0.2.10:50123".parse().unwrap();
+        let response = warp::test::request()
+            .method("GET")
+            .path("/health")
+            .remote_addr(remote_addr)
+            .reply(&health)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.method, "GET");
+        assert_eq!(record.path, "/health");
+        assert_eq!(record.status, 200);
+        assert!(record.elapsed_ms >= 0.0);
+        assert_eq!(record.remote_addr, Some(remote_addr));
+    }
+}
@@ -1,47 +1,143 @@
 use anyhow::Result;
+use std::future::Future;
 use std::sync::Arc;
-use tracing::info;
+use std::time::Duration;
+use tracing::{info, Instrument};
 use warp::Filter;
 
-mod dns;
-mod service;
-mod config;
 mod api;
-mod proxy;
-mod socks5;
-mod ssh_tunnel;
 
-use config::Config;
+use wdns_service::config::{Config, LogFormat};
+use wdns_service::Metrics;
+use wdns_service::{api_server, cors, dns, dns_listener, dns_stream, logging, proxy, service, socks5, ssh_tunnel, status};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter("wdns=debug,warp=info")
-        .init();
-
-    info!("Starting WDNS Service...");
+    // Service registration commands exit without starting any servers
+    if std::env::args().any(|arg| arg == "--install-service") {
+        logging::init(LogFormat::Text);
+        return service::install_service();
+    }
+    if std::env::args().any(|arg| arg == "--uninstall-service") {
+        logging::init(LogFormat::Text);
+        return service::uninstall_service();
+    }
 
-    // Load configuration
+    // Load configuration before logging, since it selects the log format
     let config = Config::load()?;
+
+    // One-shot lookups print only their JSON result, so no logging is set up
+    if let Some(hosts) = resolve_arg(std::env::args())? {
+        return run_resolve(config, hosts).await;
+    }
+
+    logging::init(config.log_format);
+
+    info!("Starting WDNS Service...");
     info!("Configuration loaded: {:?}", config);
 
     // Check if running as Windows service
     if service::is_service_mode() {
-        service::run_as_service().await?;
+        service::run_as_service(move |shutdown| run_standalone(config, shutdown)).await?;
     } else {
         // Run as standalone application
-        run_standalone(config).await?;
+        run_standalone(config, shutdown_signal()).await?;
+    }
+
+    Ok(())
+}
+
+/// Hosts passed as `--resolve host1,host2` or `--resolve=host1,host2`, if any
+fn resolve_arg(args: impl IntoIterator<Item = String>) -> Result<Option<Vec<String>>> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let list = if arg == "--resolve" {
+            args.next().unwrap_or_default()
+        } else if let Some(list) = arg.strip_prefix("--resolve=") {
+            list.to_string()
+        } else {
+            continue;
+        };
+        let hosts: Vec<String> = list
+            .split(',')
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .map(str::to_string)
+            .collect();
+        if hosts.is_empty() {
+            anyhow::bail!("--resolve needs a comma-separated list of hosts");
+        }
+        return Ok(Some(hosts));
     }
+    Ok(None)
+}
 
+/// Resolves `hosts` with the configured resolver and prints the response as JSON,
+/// exiting with status 1 if any host failed
+async fn run_resolve(config: Config, hosts: Vec<String>) -> Result<()> {
+    let dns_resolver = build_resolver(&config)?;
+    let response = dns_resolver.resolve_hosts(hosts).await;
+    println!("{}", serde_json::to_string_pretty(&response)?);
+    if response.total_errors > 0 {
+        std::process::exit(1);
+    }
     Ok(())
 }
 
-async fn run_standalone(config: Config) -> Result<()> {
-    let dns_resolver = Arc::new(dns::DnsResolver::new()?);
+/// The resolver shared by the API, the proxies and the DNS listener
+fn build_resolver(config: &Config) -> Result<dns::DnsResolver> {
+    dns::DnsResolverBuilder::default()
+        .servers(config.dns_upstream_addrs()?)
+        .options(config.dns_resolver_options())
+        .concurrency(config.max_concurrent_resolutions)
+        .max_retries(config.dns_max_retries)
+        .blocklist(config.dns_blocklist.clone())
+        .allowlist(config.dns_allowlist.clone())
+        .static_hosts(config.static_hosts.clone())
+        .address_sort(config.dns_address_sort()?)
+        .negative_ttl(Duration::from_secs(config.dns_negative_ttl_seconds))
+        .timeout(Duration::from_secs(config.dns_timeout_seconds))
+        .build()
+}
+
+async fn run_standalone(config: Config, shutdown: impl Future<Output = ()>) -> Result<()> {
+    let dns_resolver = Arc::new(build_resolver(&config)?);
+    let metrics = dns_resolver.metrics();
+    let live_resolver = dns_resolver.clone();
     
     info!("DNS service listening on {}", config.bind_address);
 
+    // Servers are created up front so `/api/status` can report on them; they start below
+    let proxy_server_handle = if config.proxy_enabled {
+        Some(
+            proxy::ProxyServer::new(config.proxy_bind_addr()?)
+                .with_connect_timeout(Duration::from_secs(config.connect_timeout_seconds))
+                .with_upstream_proxy(config.http_upstream_proxy_uri()?)
+                .with_max_concurrent_connections(config.max_concurrent_connections)
+                .with_listen_backlog(config.listen_backlog),
+        )
+    } else {
+        None
+    };
+    let socks5_server_handle = if config.socks5_enabled {
+        Some(
+            socks5::Socks5Server::new_with_resolver(config.socks5_bind_addr()?, live_resolver.clone())
+                .with_connect_timeout(Duration::from_secs(config.connect_timeout_seconds))
+                .with_idle_timeout(Duration::from_secs(config.proxy_idle_timeout_seconds))
+                .with_bind_command_enabled(config.socks5_bind_command_enabled)
+                .with_destination_rules(config.socks5_destination_rules()?)
+                .with_upstream_proxy(config.socks5_upstream_proxy()?)
+                .with_max_concurrent_connections(config.max_concurrent_connections)
+                .with_listen_backlog(config.listen_backlog),
+        )
+    } else {
+        None
+    };
+    let ssh_tunnel = config
+        .ssh_tunnel_config
+        .clone()
+        .map(|ssh_config| Arc::new(ssh_tunnel::SshTunnelManager::new(ssh_config)));
+
     // Health check endpoint
     let health = warp::path("health")
         .and(warp::get())
@@ -58,56 +154,164 @@ async fn run_standalone(config: Config) -> Result<()> {
         .map(move || warp::reply::json(&serde_json::json!({
             "service": "WDNS",
             "version": "0.1.0",
-            "endpoints": ["/health", "/api/dns/resolve"],
+            "endpoints": ["/health", "/ready", "/metrics", "/api/dns/resolve", "/api/dns/resolve/{host}", "/api/dns/flush-cache", "/api/dns/reverse", "/api/dns/srv", "/api/dns/stream", "/api/status"],
             "proxy_enabled": proxy_enabled,
             "proxy_port": if proxy_enabled { Some(9701) } else { None },
             "socks5_enabled": socks5_enabled,
             "socks5_port": if socks5_enabled { Some(9702) } else { None }
         })));
 
+    // Prometheus metrics endpoint
+    let metrics_filter = warp::any().map(move || metrics.clone());
+
+    let metrics_route = warp::path("metrics")
+        .and(warp::get())
+        .and(metrics_filter)
+        .map(|metrics: Arc<Metrics>| {
+            warp::reply::with_header(
+                metrics.render(),
+                "content-type",
+                "text/plain; version=0.0.4",
+            )
+        });
+
     // DNS resolution endpoint
     let dns_resolver_filter = warp::any().map(move || dns_resolver.clone());
+    let max_hosts_per_request = config.max_hosts_per_request;
+    let max_hosts_filter = warp::any().map(move || max_hosts_per_request);
     
+    // Readiness endpoint, which checks that a canary domain resolves
+    let canary_host = config.readiness_canary_host.clone();
+    let ready = warp::path("ready")
+        .and(warp::get())
+        .and(warp::any().map(move || canary_host.clone()))
+        .and(dns_resolver_filter.clone())
+        .and_then(handle_ready);
+
     let dns_resolve = warp::path("api")
         .and(warp::path("dns"))
         .and(warp::path("resolve"))
         .and(warp::post())
+        .and(warp::body::content_length_limit(config.max_request_body_bytes()))
         .and(warp::body::json())
-        .and(dns_resolver_filter)
+        .and(max_hosts_filter)
+        .and(dns_resolver_filter.clone())
         .and_then(handle_dns_resolve);
 
-    let routes = health.or(root).or(dns_resolve);
+    let dns_resolve_host = warp::path("api")
+        .and(warp::path("dns"))
+        .and(warp::path("resolve"))
+        .and(
+            warp::path::param::<String>()
+                .or(warp::path::end().map(String::new))
+                .unify(),
+        )
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(dns_resolver_filter.clone())
+        .and_then(handle_dns_resolve_host);
+
+    let dns_flush_cache = warp::path("api")
+        .and(warp::path("dns"))
+        .and(warp::path("flush-cache"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(config.max_request_body_bytes()))
+        .and(warp::body::bytes())
+        .and(dns_resolver_filter.clone())
+        .and_then(handle_dns_flush_cache);
+
+    let dns_reverse = warp::path("api")
+        .and(warp::path("dns"))
+        .and(warp::path("reverse"))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(config.max_request_body_bytes()))
+        .and(warp::body::json())
+        .and(max_hosts_filter)
+        .and(dns_resolver_filter.clone())
+        .and_then(handle_dns_reverse);
+
+    let dns_srv = warp::path("api")
+        .and(warp::path("dns"))
+        .and(warp::path("srv"))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(config.max_request_body_bytes()))
+        .and(warp::body::json())
+        .and(max_hosts_filter)
+        .and(dns_resolver_filter)
+        .and_then(handle_dns_srv);
+
+    // Live resolution events over a WebSocket
+    let dns_stream = dns_stream::route(live_resolver.clone());
 
-    // Start DNS service
-    let dns_server = warp::serve(routes).run(config.bind_addr()?);
+    // State of every subsystem
+    let status_route = status::route(status::Subsystems {
+        dns_resolver: live_resolver.clone(),
+        canary_host: config.readiness_canary_host.clone(),
+        proxy: proxy_server_handle.clone(),
+        socks5: socks5_server_handle.clone(),
+        ssh_tunnels: ssh_tunnel.iter().cloned().collect(),
+    });
 
-    // Start proxy servers if enabled
+    let routes = health
+        .or(ready)
+        .or(root)
+        .or(metrics_route)
+        .or(dns_resolve)
+        .or(dns_resolve_host)
+        .or(dns_flush_cache)
+        .or(dns_reverse)
+        .or(dns_srv)
+        .or(dns_stream)
+        .or(status_route);
+    let routes = cors::with_cors(routes, &config.cors_allowed_origins).with(logging::access_log());
+
+    // Start DNS service, over HTTPS when a certificate is configured, draining
+    // in-flight requests once shutdown is requested
+    let tls_paths = config.tls_paths()?;
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let (_, dns_server) = api_server::bind(routes, config.bind_addr()?, tls_paths, async {
+        let _ = shutdown_rx.await;
+    })?;
+    if tls_paths.is_some() {
+        info!("Serving the HTTP API over HTTPS");
+    }
+
+    // Start proxy servers if enabled; `proxy_tasks` are stopped gracefully on shutdown
     let mut tasks = vec![];
-    
-    if config.proxy_enabled {
+    let mut proxy_tasks = vec![];
+
+    if let Some(proxy_server) = proxy_server_handle.clone() {
         info!("HTTP Proxy server listening on {}", config.proxy_bind_address);
-        let proxy_server = proxy::ProxyServer::new(config.proxy_bind_addr()?);
-        tasks.push(tokio::spawn(async move {
+        proxy_tasks.push(tokio::spawn(async move {
             if let Err(e) = proxy_server.run().await {
                 tracing::error!("HTTP Proxy server error: {}", e);
             }
         }));
     }
 
-    if config.socks5_enabled {
+    if let Some(socks5_server) = socks5_server_handle.clone() {
         info!("SOCKS5 server listening on {}", config.socks5_bind_address);
-        let socks5_server = socks5::Socks5Server::new(config.socks5_bind_addr()?)?;
-        tasks.push(tokio::spawn(async move {
+        proxy_tasks.push(tokio::spawn(async move {
             if let Err(e) = socks5_server.run().await {
                 tracing::error!("SOCKS5 server error: {}", e);
             }
         }));
     }
 
+    if config.dns_listener_enabled {
+        info!("DNS listener listening on {}", config.dns_listener_address);
+        let dns_listener = dns_listener::DnsListener::new(config.dns_listener_addr()?, live_resolver.clone())
+            .with_listen_backlog(config.listen_backlog);
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = dns_listener.run().await {
+                tracing::error!("DNS listener error: {}", e);
+            }
+        }));
+    }
+
     // Start SSH tunnel if configured
-    if let Some(ssh_config) = config.ssh_tunnel_config.clone() {
-        info!("Starting SSH tunnel to {}:{}", ssh_config.host, ssh_config.port);
-        let ssh_tunnel = ssh_tunnel::SshTunnelManager::new(ssh_config);
+    if let Some(ssh_tunnel) = ssh_tunnel.clone() {
         tasks.push(tokio::spawn(async move {
             if let Err(e) = ssh_tunnel.start().await {
                 tracing::error!("SSH tunnel error: {}", e);
@@ -115,27 +319,124 @@ async fn run_standalone(config: Config) -> Result<()> {
         }));
     }
 
-    // Run all servers concurrently
-    if tasks.is_empty() {
+    if tasks.is_empty() && proxy_tasks.is_empty() {
         info!("No proxy servers enabled");
-        dns_server.await;
-    } else {
-        tasks.push(tokio::spawn(async move {
-            dns_server.await;
-        }));
+    }
 
-        tokio::select! {
-            _ = futures::future::join_all(tasks) => {
-                info!("All servers stopped");
-            }
+    // Apply resolver settings from config.json edits without a restart
+    let running_config = config.clone();
+    tasks.push(tokio::spawn(Config::watch("config.json", move |new_config| {
+        info!("Configuration file changed, applying DNS settings");
+        live_resolver.set_timeout(Duration::from_secs(new_config.dns_timeout_seconds));
+        live_resolver.set_concurrency(new_config.max_concurrent_resolutions);
+        live_resolver.set_max_retries(new_config.dns_max_retries);
+
+        let restart_fields = running_config.restart_required_changes(&new_config);
+        if !restart_fields.is_empty() {
+            tracing::warn!(
+                "Changes to {} require a restart to take effect",
+                restart_fields.join(", ")
+            );
+        }
+    })));
+
+    // Run until a shutdown signal arrives
+    let mut dns_task = tokio::spawn(dns_server);
+    tokio::select! {
+        _ = shutdown => {}
+        _ = &mut dns_task => {
+            tracing::error!("DNS server stopped unexpectedly");
         }
     }
 
+    info!("WDNS Service shutting down");
+
+    // Stop the tunnel first so the ssh child is not left orphaned
+    if let Some(ssh_tunnel) = ssh_tunnel {
+        if let Err(e) = ssh_tunnel.stop().await {
+            tracing::error!("Failed to stop SSH tunnel: {}", e);
+        }
+    }
+
+    // Let the proxies finish open connections, up to their drain timeout
+    if let Some(proxy_server) = &proxy_server_handle {
+        proxy_server.stop();
+    }
+    if let Some(socks5_server) = &socks5_server_handle {
+        socks5_server.stop();
+    }
+    futures::future::join_all(proxy_tasks).await;
+
+    for task in &tasks {
+        task.abort();
+    }
+    futures::future::join_all(tasks).await;
+
+    let _ = shutdown_tx.send(());
+    let _ = dns_task.await;
+
+    info!("All servers stopped");
     Ok(())
 }
 
+/// Resolves when the process receives Ctrl-C (SIGINT) or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+async fn handle_ready(
+    canary_host: String,
+    dns_resolver: Arc<dns::DnsResolver>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match dns_resolver.probe(&canary_host, dns::READINESS_PROBE_TIMEOUT).await {
+        Ok(()) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "status": "ready",
+                "canary": canary_host
+            })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(reason) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "status": "unavailable",
+                "canary": canary_host,
+                "reason": reason
+            })),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        )),
+    }
+}
+
 async fn handle_dns_resolve(
     request: dns::DnsRequest,
+    max_hosts_per_request: usize,
     dns_resolver: Arc<dns::DnsResolver>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     // Validate request
@@ -148,11 +449,179 @@ async fn handle_dns_resolve(
         ));
     }
 
-    // Resolve DNS
-    let dns_response = dns_resolver.resolve_hosts(request.hosts).await;
+    if let Err(e) = request.check_host_limit(max_hosts_per_request) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": e
+            })),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let family = match request.family_filter() {
+        Ok(family) => family,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "error": e
+                })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+
+    let sort = match request.address_sort() {
+        Ok(sort) => sort.unwrap_or_else(|| dns_resolver.address_sort()),
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "error": e
+                })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+
+    dns_resolver.metrics().record_request();
+
+    // Resolve DNS inside a span so per-host lookup logs carry the request id
+    let request_id = dns::new_request_id();
+    let span = tracing::info_span!("dns_resolve", request_id = %request_id, hosts = request.hosts.len());
+    let mut dns_response = dns_resolver
+        .resolve_hosts_with(request.hosts, family, sort)
+        .instrument(span)
+        .await;
+    dns_response.request_id = Some(request_id);
+    let status = dns_response.status_code(request.strict);
 
     Ok(warp::reply::with_status(
         warp::reply::json(&dns_response),
+        status,
+    ))
+}
+
+async fn handle_dns_resolve_host(
+    host: String,
+    dns_resolver: Arc<dns::DnsResolver>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let host = match dns::parse_host_segment(&host) {
+        Ok(host) => host,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "error": e
+                })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+
+    dns_resolver.metrics().record_request();
+
+    let result = dns_resolver.resolve_host(&host).await;
+    let status = result.status_code();
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&result),
+        status,
+    ))
+}
+
+async fn handle_dns_flush_cache(
+    body: bytes::Bytes,
+    dns_resolver: Arc<dns::DnsResolver>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // An empty body flushes everything
+    let request: dns::FlushCacheRequest = if body.is_empty() {
+        dns::FlushCacheRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(request) => request,
+            Err(e) => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({
+                        "error": format!("Invalid request body: {}", e)
+                    })),
+                    warp::http::StatusCode::BAD_REQUEST,
+                ));
+            }
+        }
+    };
+
+    let evicted = match request.hosts {
+        Some(hosts) => dns_resolver.clear_cache_hosts(&hosts),
+        None => dns_resolver.clear_cache(),
+    };
+    tracing::info!("Flushed {} cached DNS entries", evicted);
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({
+            "evicted": evicted
+        })),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+async fn handle_dns_reverse(
+    request: dns::ReverseDnsRequest,
+    max_hosts_per_request: usize,
+    dns_resolver: Arc<dns::DnsResolver>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // Validate request
+    if request.ips.is_empty() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": "No IPs provided"
+            })),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    if let Err(e) = request.check_host_limit(max_hosts_per_request) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": e
+            })),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let response = dns_resolver.reverse_lookup_ips(request.ips).await;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
         warp::http::StatusCode::OK,
     ))
-}
\ No newline at end of file
+}
+
+async fn handle_dns_srv(
+    request: dns::SrvDnsRequest,
+    max_hosts_per_request: usize,
+    dns_resolver: Arc<dns::DnsResolver>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // Validate request
+    if request.names.is_empty() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": "No names provided"
+            })),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    if let Err(e) = request.check_host_limit(max_hosts_per_request) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": e
+            })),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let response = dns_resolver.resolve_srv_names(request.names).await;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        warp::http::StatusCode::OK,
+    ))
+}
@@ -0,0 +1,151 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds (in seconds) of the resolution latency histogram buckets
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Process-wide DNS counters, rendered in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    dns_requests: AtomicU64,
+    hosts_resolved: AtomicU64,
+    errors: AtomicU64,
+    timeouts: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_sum_micros: AtomicU64,
+    latency_count: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts one call to the resolve API
+    pub fn record_request(&self) {
+        self.dns_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the outcome and latency of a single host lookup
+    pub fn record_resolution(&self, status: &str, elapsed: Duration) {
+        match status {
+            "success" => {
+                self.hosts_resolved.fetch_add(1, Ordering::Relaxed);
+            }
+            "timeout" => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                self.timeouts.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency_sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders all counters in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        write_counter(
+            &mut out,
+            "wdns_dns_requests_total",
+            "Total DNS resolve API requests",
+            self.dns_requests.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "wdns_dns_hosts_resolved_total",
+            "Total hosts resolved successfully",
+            self.hosts_resolved.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "wdns_dns_errors_total",
+            "Total host resolutions that failed, including timeouts",
+            self.errors.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "wdns_dns_timeouts_total",
+            "Total host resolutions that timed out",
+            self.timeouts.load(Ordering::Relaxed),
+        );
+
+        let name = "wdns_dns_resolution_duration_seconds";
+        let _ = writeln!(out, "# HELP {} Host resolution latency", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.latency_buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let count = self.latency_count.load(Ordering::Relaxed);
+        let sum = self.latency_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+
+        out
+    }
+}
+
+fn write_counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_empty_metrics() {
+        let metrics = Metrics::new();
+        let output = metrics.render();
+
+        assert!(output.contains("# TYPE wdns_dns_requests_total counter"));
+        assert!(output.contains("wdns_dns_requests_total 0"));
+        assert!(output.contains("wdns_dns_resolution_duration_seconds_bucket{le=\"+Inf\"} 0"));
+        assert!(output.contains("wdns_dns_resolution_duration_seconds_count 0"));
+    }
+
+    #[test]
+    fn test_record_resolution_outcomes() {
+        let metrics = Metrics::new();
+        metrics.record_request();
+        metrics.record_resolution("success", Duration::from_millis(3));
+        metrics.record_resolution("error", Duration::from_millis(40));
+        metrics.record_resolution("timeout", Duration::from_secs(10));
+
+        let output = metrics.render();
+        assert!(output.contains("wdns_dns_requests_total 1"));
+        assert!(output.contains("wdns_dns_hosts_resolved_total 1"));
+        assert!(output.contains("wdns_dns_errors_total 2"));
+        assert!(output.contains("wdns_dns_timeouts_total 1"));
+    }
+
+    #[test]
+    fn test_latency_histogram_is_cumulative() {
+        let metrics = Metrics::new();
+        metrics.record_resolution("success", Duration::from_millis(3));
+        metrics.record_resolution("success", Duration::from_millis(200));
+        metrics.record_resolution("success", Duration::from_secs(30));
+
+        let output = metrics.render();
+        assert!(output.contains("wdns_dns_resolution_duration_seconds_bucket{le=\"0.005\"} 1"));
+        assert!(output.contains("wdns_dns_resolution_duration_seconds_bucket{le=\"0.25\"} 2"));
+        assert!(output.contains("wdns_dns_resolution_duration_seconds_bucket{le=\"10\"} 2"));
+        assert!(output.contains("wdns_dns_resolution_duration_seconds_bucket{le=\"+Inf\"} 3"));
+        assert!(output.contains("wdns_dns_resolution_duration_seconds_count 3"));
+    }
+}
@@ -1,72 +1,289 @@
 use anyhow::Result;
+use hyper::client::connect::{Connected, Connection};
 use hyper::client::HttpConnector;
+use hyper::http::uri::Authority;
 use hyper::http::{HeaderValue, Method, StatusCode};
-use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Client, Request, Response, Server};
+use hyper::server::conn::Http;
+use hyper::service::{service_fn, Service};
+use hyper::{Body, Client, Request, Response, Uri};
 use std::convert::Infallible;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::net::TcpStream;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
 use tracing::{debug, error, info};
 
+use crate::socks5::{
+    bind_listener, ConnectionLimit, ServerControl, DEFAULT_CONNECT_TIMEOUT, DEFAULT_LISTEN_BACKLOG,
+    DEFAULT_MAX_CONCURRENT_CONNECTIONS,
+};
+
+#[derive(Clone)]
 pub struct ProxyServer {
     pub bind_addr: SocketAddr,
-    client: Client<HttpConnector>,
+    client: Client<UpstreamConnector>,
+    connect_timeout: Duration,
+    connection_limit: ConnectionLimit,
+    listen_backlog: u32,
+    /// HTTP proxy that plain requests are forwarded through, if any
+    upstream_proxy: Option<Uri>,
+    control: ServerControl,
 }
 
 impl ProxyServer {
     pub fn new(bind_addr: SocketAddr) -> Self {
-        let client = Client::builder()
-            .http1_title_case_headers(true)
-            .http1_allow_obsolete_multiline_headers_in_responses(true)
-            .build_http();
+        Self {
+            bind_addr,
+            client: build_client(DEFAULT_CONNECT_TIMEOUT, None),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            connection_limit: ConnectionLimit::new(DEFAULT_MAX_CONCURRENT_CONNECTIONS),
+            listen_backlog: DEFAULT_LISTEN_BACKLOG,
+            upstream_proxy: None,
+            control: ServerControl::new(),
+        }
+    }
+
+    /// Sets how long to wait for a target server to accept the connection
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.client = build_client(connect_timeout, self.upstream_proxy.clone());
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Forwards plain HTTP requests through the HTTP proxy at `upstream`
+    /// (e.g. `http://10.0.0.8:3128`) instead of to the origin directly.
+    /// CONNECT tunnels still connect directly.
+    pub fn with_upstream_proxy(mut self, upstream: Option<Uri>) -> Self {
+        self.client = build_client(self.connect_timeout, upstream.clone());
+        self.upstream_proxy = upstream;
+        self
+    }
+
+    /// Caps how many client connections are handled at once; further clients
+    /// wait until a connection closes
+    pub fn with_max_concurrent_connections(mut self, max_connections: usize) -> Self {
+        self.connection_limit = ConnectionLimit::new(max_connections);
+        self
+    }
+
+    /// Sets how many connections may wait to be accepted
+    pub fn with_listen_backlog(mut self, backlog: u32) -> Self {
+        self.listen_backlog = backlog;
+        self
+    }
+
+    /// Asks `run` on this server, or on any clone of it, to stop accepting
+    /// connections. `run` returns once open connections finish, or after a
+    /// short drain timeout.
+    pub fn stop(&self) {
+        self.control.stop();
+    }
+
+    /// Whether `run` is currently accepting or draining connections
+    pub fn is_running(&self) -> bool {
+        self.control.is_running()
+    }
+
+    /// Address `run` is listening on, with the actual port when bound to port 0
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.control.local_addr()
+    }
 
-        Self { bind_addr, client }
+    /// Client connections currently open
+    pub fn active_connections(&self) -> usize {
+        self.connection_limit.open()
     }
 
     pub async fn run(self) -> Result<()> {
         info!("Starting proxy server on {}", self.bind_addr);
 
+        let listener = bind_listener(self.bind_addr, self.listen_backlog)?;
+
+        info!("Proxy server listening on {}", self.bind_addr);
+
+        self.serve(listener).await
+    }
+
+    async fn serve(self, listener: TcpListener) -> Result<()> {
         let client = Arc::new(self.client);
+        let connect_timeout = self.connect_timeout;
+        let connection_limit = self.connection_limit;
+        let control = self.control;
+
+        control.start_serving(&listener);
+        loop {
+            let permit = tokio::select! {
+                _ = control.stopped() => break,
+                permit = connection_limit.acquire("HTTP proxy") => permit,
+            };
+            let accepted = tokio::select! {
+                _ = control.stopped() => break,
+                accepted = listener.accept() => accepted,
+            };
+            let (stream, addr) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Failed to accept proxy connection: {}", e);
+                    continue;
+                }
+            };
+            debug!("New proxy connection from {} ({} active)", addr, connection_limit.active());
 
-        let make_svc = make_service_fn(move |_conn| {
             let client = client.clone();
-            async move {
-                Ok::<_, Infallible>(service_fn(move |req| {
-                    let client = client.clone();
-                    handle_request(req, client)
-                }))
-            }
-        });
+            let connection_limit = connection_limit.clone();
+            let open = connection_limit.opened();
+            tokio::spawn(async move {
+                let service = service_fn(move |req| handle_request(req, client.clone(), connect_timeout));
+                if let Err(e) = Http::new().serve_connection(stream, service).await {
+                    debug!("Proxy connection from {} failed: {}", addr, e);
+                }
+                drop(open);
+                drop(permit);
+                debug!("Proxy connection from {} closed ({} active)", addr, connection_limit.active());
+            });
+        }
 
-        let server = Server::bind(&self.bind_addr).serve(make_svc);
+        drop(listener);
+        control.drain("HTTP proxy", &connection_limit).await;
+        Ok(())
+    }
+}
 
-        info!("Proxy server listening on {}", self.bind_addr);
+fn build_client(connect_timeout: Duration, upstream_proxy: Option<Uri>) -> Client<UpstreamConnector> {
+    let mut http = HttpConnector::new();
+    http.set_connect_timeout(Some(connect_timeout));
+
+    Client::builder()
+        .http1_title_case_headers(true)
+        .http1_allow_obsolete_multiline_headers_in_responses(true)
+        .build(UpstreamConnector { http, upstream_proxy })
+}
+
+/// Connects to the origin, or to `upstream_proxy` when set. Connections to
+/// the proxy are marked as proxied so hyper writes absolute-form request
+/// targets (`GET http://host/path`), as an HTTP proxy expects.
+#[derive(Clone)]
+struct UpstreamConnector {
+    http: HttpConnector,
+    upstream_proxy: Option<Uri>,
+}
+
+impl Service<Uri> for UpstreamConnector {
+    type Response = UpstreamStream;
+    type Error = <HttpConnector as Service<Uri>>::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let proxied = self.upstream_proxy.is_some();
+        let connect = self.http.call(self.upstream_proxy.clone().unwrap_or(dst));
+        Box::pin(async move {
+            let stream = connect.await?;
+            Ok(UpstreamStream { stream, proxied })
+        })
+    }
+}
+
+/// A connection made by `UpstreamConnector`
+struct UpstreamStream {
+    stream: TcpStream,
+    proxied: bool,
+}
+
+impl Connection for UpstreamStream {
+    fn connected(&self) -> Connected {
+        self.stream.connected().proxy(self.proxied)
+    }
+}
+
+impl AsyncRead for UpstreamStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UpstreamStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
 
-        if let Err(e) = server.await {
-            error!("Proxy server error: {}", e);
+/// Whether `e` was caused by a timed-out connection attempt
+fn is_connect_timeout(e: &hyper::Error) -> bool {
+    let mut source = std::error::Error::source(e);
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+            if io_err.kind() == std::io::ErrorKind::TimedOut {
+                return true;
+            }
         }
+        source = err.source();
+    }
+    false
+}
 
-        Ok(())
+/// Port a CONNECT target uses when the authority has none
+const DEFAULT_CONNECT_PORT: u16 = 443;
+
+/// Port a plain HTTP request uses when the authority has none
+const DEFAULT_HTTP_PORT: u16 = 80;
+
+/// Host without IPv6 brackets and port of `authority`, so IPv6 literals
+/// such as `[::1]:443` connect as `(::1, 443)`
+fn host_and_port(authority: &Authority, default_port: u16) -> (&str, u16) {
+    let host = authority.host();
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    (host, authority.port_u16().unwrap_or(default_port))
+}
+
+/// Value for the `Host` header: the host, bracketed if IPv6, with the port
+/// only when it differs from `default_port`
+fn host_header(authority: &Authority, default_port: u16) -> String {
+    match authority.port_u16() {
+        Some(port) if port != default_port => format!("{}:{}", authority.host(), port),
+        _ => authority.host().to_string(),
     }
 }
 
 async fn handle_request(
     req: Request<Body>,
-    client: Arc<Client<HttpConnector>>,
+    client: Arc<Client<UpstreamConnector>>,
+    connect_timeout: Duration,
 ) -> Result<Response<Body>, Infallible> {
     debug!("Received request: {} {}", req.method(), req.uri());
 
     // Handle CONNECT method for HTTPS tunneling
     if req.method() == Method::CONNECT {
-        return handle_connect(req).await;
+        return handle_connect(req, connect_timeout).await;
     }
 
     // Handle regular HTTP requests
     handle_http_request(req, client).await
 }
 
-async fn handle_connect(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+async fn handle_connect(
+    req: Request<Body>,
+    connect_timeout: Duration,
+) -> Result<Response<Body>, Infallible> {
     let authority = match req.uri().authority() {
         Some(auth) => auth.clone(),
         None => {
@@ -80,12 +297,12 @@ async fn handle_connect(req: Request<Body>) -> Result<Response<Body>, Infallible
     debug!("CONNECT request to: {}", authority);
 
     // Parse the target address
-    let port = authority.port().map(|p| p.as_str().to_string()).unwrap_or("443".to_string());
+    let (host, port) = host_and_port(&authority, DEFAULT_CONNECT_PORT);
     let target_addr = format!("{}:{}", authority.host(), port);
 
     // Connect to the target server
-    match TcpStream::connect(&target_addr).await {
-        Ok(_target_stream) => {
+    match timeout(connect_timeout, TcpStream::connect((host, port))).await {
+        Ok(Ok(_target_stream)) => {
             debug!("Connected to target: {}", target_addr);
 
             // Send 200 Connection Established response
@@ -96,27 +313,34 @@ async fn handle_connect(req: Request<Body>) -> Result<Response<Body>, Infallible
 
             Ok(response)
         }
-        Err(e) => {
+        Ok(Err(e)) => {
             error!("Failed to connect to target {}: {}", target_addr, e);
             Ok(Response::builder()
                 .status(StatusCode::BAD_GATEWAY)
                 .body(Body::from(format!("Failed to connect to target: {}", e)))
                 .unwrap())
         }
+        Err(_) => {
+            error!("Timed out connecting to target {} after {:?}", target_addr, connect_timeout);
+            Ok(Response::builder()
+                .status(StatusCode::GATEWAY_TIMEOUT)
+                .body(Body::from("Timed out connecting to target"))
+                .unwrap())
+        }
     }
 }
 
 async fn handle_http_request(
     mut req: Request<Body>,
-    client: Arc<Client<HttpConnector>>,
+    client: Arc<Client<UpstreamConnector>>,
 ) -> Result<Response<Body>, Infallible> {
     // Remove proxy-specific headers
     req.headers_mut().remove("proxy-connection");
     req.headers_mut().remove("proxy-authorization");
 
-    // Set the correct host header
-    if let Some(host) = req.uri().host() {
-        if let Ok(host_value) = HeaderValue::from_str(host) {
+    // Set the correct host header, keeping a non-default port
+    if let Some(authority) = req.uri().authority() {
+        if let Ok(host_value) = HeaderValue::from_str(&host_header(authority, DEFAULT_HTTP_PORT)) {
             req.headers_mut().insert("host", host_value);
         }
     }
@@ -131,8 +355,13 @@ async fn handle_http_request(
         }
         Err(e) => {
             error!("Request failed: {}", e);
+            let status = if is_connect_timeout(&e) {
+                StatusCode::GATEWAY_TIMEOUT
+            } else {
+                StatusCode::BAD_GATEWAY
+            };
             Ok(Response::builder()
-                .status(StatusCode::BAD_GATEWAY)
+                .status(status)
                 .body(Body::from(format!("Proxy error: {}", e)))
                 .unwrap())
         }
@@ -168,4 +397,148 @@ mod tests {
         let proxy = ProxyServer::new(addr);
         assert_eq!(proxy.bind_addr, addr);
     }
+
+    #[tokio::test]
+    async fn test_connections_over_limit_wait_for_a_slot() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let proxy = ProxyServer::new(addr).with_max_concurrent_connections(1);
+        tokio::spawn(proxy.serve(listener));
+
+        // An idle connection holds the only slot
+        let first = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Nothing listens on port 1, so once served this request gets a 502
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        second
+            .write_all(b"GET http://127.0.0.1:1/ HTTP/1.1\r\nHost: 127.0.0.1:1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0u8; 12];
+        assert!(timeout(Duration::from_millis(300), second.read_exact(&mut buf)).await.is_err());
+
+        drop(first);
+        timeout(Duration::from_secs(5), second.read_exact(&mut buf))
+            .await
+            .expect("Queued connection was never served")
+            .unwrap();
+        assert_eq!(&buf, b"HTTP/1.1 502");
+    }
+
+    #[tokio::test]
+    async fn test_stop_ends_run() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let proxy = ProxyServer::new(addr);
+        let handle = proxy.clone();
+        assert!(!handle.is_running());
+
+        let run = tokio::spawn(proxy.serve(listener));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(handle.is_running());
+
+        handle.stop();
+        timeout(Duration::from_secs(2), run)
+            .await
+            .expect("run did not return after stop")
+            .unwrap()
+            .unwrap();
+        assert!(!handle.is_running());
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout_returns_gateway_timeout() {
+        let (_listener, _backlog_filler, target_addr) =
+            crate::socks5::unresponsive_destination().await;
+
+        let req = Request::builder()
+            .method(Method::CONNECT)
+            .uri(target_addr.to_string())
+            .body(Body::empty())
+            .unwrap();
+        let response = handle_connect(req, Duration::from_millis(200)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_connect_to_ipv6_literal() {
+        let listener = TcpListener::bind("[::1]:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let req = Request::builder()
+            .method(Method::CONNECT)
+            .uri(format!("[::1]:{}", port))
+            .body(Body::empty())
+            .unwrap();
+        let response = handle_connect(req, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_host_and_port_defaults() {
+        let authority = |s: &str| s.parse::<Authority>().unwrap();
+
+        assert_eq!(host_and_port(&authority("[::1]:8443"), DEFAULT_CONNECT_PORT), ("::1", 8443));
+        assert_eq!(host_and_port(&authority("[::1]"), DEFAULT_CONNECT_PORT), ("::1", 443));
+        assert_eq!(host_and_port(&authority("example.com"), DEFAULT_HTTP_PORT), ("example.com", 80));
+        assert_eq!(host_and_port(&authority("10.0.0.1:8080"), DEFAULT_HTTP_PORT), ("10.0.0.1", 8080));
+
+        assert_eq!(host_header(&authority("[::1]:80"), DEFAULT_HTTP_PORT), "[::1]");
+        assert_eq!(host_header(&authority("[::1]:8080"), DEFAULT_HTTP_PORT), "[::1]:8080");
+        assert_eq!(host_header(&authority("example.com:8080"), DEFAULT_HTTP_PORT), "example.com:8080");
+    }
+
+    #[tokio::test]
+    async fn test_http_request_is_sent_to_upstream_proxy() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Mock upstream proxy that records the request head it receives
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_uri: Uri = format!("http://{}", upstream.local_addr().unwrap()).parse().unwrap();
+        let upstream_task = tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(stream.read_u8().await.unwrap());
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\nvia upstream")
+                .await
+                .unwrap();
+            String::from_utf8(head).unwrap()
+        });
+
+        // The origin name does not resolve, so only the upstream can answer
+        let client = Arc::new(build_client(Duration::from_secs(5), Some(upstream_uri)));
+        let req = Request::builder()
+            .uri("http://origin.invalid:8080/status?x=1")
+            .body(Body::empty())
+            .unwrap();
+        let response = handle_http_request(req, client).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"via upstream");
+
+        let head = upstream_task.await.unwrap();
+        assert!(head.starts_with("GET http://origin.invalid:8080/status?x=1 HTTP/1.1\r\n"), "{}", head);
+        assert!(head.contains("Host: origin.invalid:8080\r\n"), "{}", head);
+    }
+
+    #[tokio::test]
+    async fn test_http_request_connect_timeout_returns_gateway_timeout() {
+        let (_listener, _backlog_filler, target_addr) =
+            crate::socks5::unresponsive_destination().await;
+
+        let client = Arc::new(build_client(Duration::from_millis(200), None));
+        let req = Request::builder()
+            .uri(format!("http://{}/", target_addr))
+            .body(Body::empty())
+            .unwrap();
+        let response = handle_http_request(req, client).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
 }
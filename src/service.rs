@@ -1,23 +1,248 @@
 use anyhow::Result;
+use std::future::Future;
+use std::pin::Pin;
 use tracing::info;
 
+/// Name the service is registered under with the Windows Service Control Manager
+pub const SERVICE_NAME: &str = "WDNSService";
+
+/// Future that resolves when the service has been asked to stop
+pub type ShutdownSignal = Pin<Box<dyn Future<Output = ()> + Send>>;
+
 pub fn is_service_mode() -> bool {
     std::env::args().any(|arg| arg == "--service")
 }
 
-pub async fn run_as_service() -> Result<()> {
+/// Registers the current executable with the SCM to start automatically with `--service`.
+#[cfg(windows)]
+pub fn install_service() -> Result<()> {
+    windows::install()
+}
+
+#[cfg(not(windows))]
+pub fn install_service() -> Result<()> {
+    anyhow::bail!("--install-service is only supported on Windows")
+}
+
+/// Stops the service if it is running and removes its SCM entry.
+#[cfg(windows)]
+pub fn uninstall_service() -> Result<()> {
+    windows::uninstall()
+}
+
+#[cfg(not(windows))]
+pub fn uninstall_service() -> Result<()> {
+    anyhow::bail!("--uninstall-service is only supported on Windows")
+}
+
+/// Runs `run` under the Service Control Manager, passing it a future that resolves on
+/// `Stop`/`Shutdown` so the service can shut down gracefully.
+#[cfg(windows)]
+pub async fn run_as_service<F, Fut>(run: F) -> Result<()>
+where
+    F: FnOnce(ShutdownSignal) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + 'static,
+{
     info!("Running as Windows service");
-    
-    // For now, just run the service logic
-    // In a real implementation, you would use the windows-service crate
-    // but for simplicity, we'll just run the main logic
-    info!("WDNS Service is running as Windows service");
-    
-    // Keep the service running
-    tokio::signal::ctrl_c().await?;
-    info!("Service shutdown requested");
-    
-    Ok(())
+    windows::run(run).await
+}
+
+/// Service mode is Windows-only; elsewhere `run` is driven in the foreground until Ctrl-C.
+#[cfg(not(windows))]
+pub async fn run_as_service<F, Fut>(run: F) -> Result<()>
+where
+    F: FnOnce(ShutdownSignal) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + 'static,
+{
+    info!("Service mode is only supported on Windows, running in the foreground");
+    run(Box::pin(async {
+        let _ = tokio::signal::ctrl_c().await;
+    }))
+    .await
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::{ShutdownSignal, SERVICE_NAME};
+    use anyhow::{Context, Result};
+    use std::ffi::{OsStr, OsString};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio::runtime::Handle;
+    use tracing::{error, info};
+    use windows_service::service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl,
+        ServiceExitCode, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus,
+        ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    type ServiceBody =
+        Box<dyn FnOnce(ShutdownSignal) -> Pin<Box<dyn Future<Output = Result<()>>>> + Send>;
+
+    /// The service body and the runtime to drive it on, handed from `run` to `service_main`
+    /// because the dispatcher only accepts a plain function pointer.
+    static SERVICE_BODY: Mutex<Option<(Handle, ServiceBody)>> = Mutex::new(None);
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    pub(super) async fn run<F, Fut>(run: F) -> Result<()>
+    where
+        F: FnOnce(ShutdownSignal) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + 'static,
+    {
+        let body: ServiceBody = Box::new(move |shutdown| Box::pin(run(shutdown)));
+        *SERVICE_BODY.lock().unwrap_or_else(|e| e.into_inner()) = Some((Handle::current(), body));
+
+        // Blocks until the service has stopped
+        tokio::task::spawn_blocking(|| service_dispatcher::start(SERVICE_NAME, ffi_service_main))
+            .await?
+            .context("Failed to start the service dispatcher")
+    }
+
+    /// Win32 `ERROR_ACCESS_DENIED`, returned by the SCM when the caller is not elevated
+    const ERROR_ACCESS_DENIED: i32 = 5;
+
+    pub(super) fn install() -> Result<()> {
+        let manager =
+            open_manager(ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?;
+        let executable_path =
+            std::env::current_exe().context("Failed to locate the current executable")?;
+
+        let service_info = ServiceInfo {
+            name: OsString::from(SERVICE_NAME),
+            display_name: OsString::from("WDNS Service"),
+            service_type: ServiceType::OWN_PROCESS,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path,
+            launch_arguments: vec![OsString::from("--service")],
+            dependencies: vec![],
+            account_name: None,
+            account_password: None,
+        };
+
+        let service = manager
+            .create_service(&service_info, ServiceAccess::CHANGE_CONFIG)
+            .map_err(|e| elevation_error(e, "install"))?;
+        service.set_description("Windows DNS Resolution Service")?;
+
+        info!("Installed service {}", SERVICE_NAME);
+        Ok(())
+    }
+
+    pub(super) fn uninstall() -> Result<()> {
+        let manager = open_manager(ServiceManagerAccess::CONNECT)?;
+        let service = manager
+            .open_service(
+                SERVICE_NAME,
+                ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+            )
+            .map_err(|e| elevation_error(e, "uninstall"))?;
+
+        if service.query_status()?.current_state != ServiceState::Stopped {
+            info!("Stopping service {}", SERVICE_NAME);
+            service.stop()?;
+        }
+
+        // The entry is removed once the service has stopped and all handles are closed
+        service.delete()?;
+        info!("Uninstalled service {}", SERVICE_NAME);
+        Ok(())
+    }
+
+    fn open_manager(access: ServiceManagerAccess) -> Result<ServiceManager> {
+        ServiceManager::local_computer(None::<&OsStr>, access)
+            .map_err(|e| elevation_error(e, "manage"))
+    }
+
+    /// Turns an access-denied SCM error into a hint to rerun from an elevated prompt.
+    fn elevation_error(error: windows_service::Error, action: &str) -> anyhow::Error {
+        match &error {
+            windows_service::Error::Winapi(io_error)
+                if io_error.raw_os_error() == Some(ERROR_ACCESS_DENIED) =>
+            {
+                anyhow::anyhow!(
+                    "Administrator privileges are required to {} the {} service; \
+                     rerun from an elevated prompt",
+                    action,
+                    SERVICE_NAME
+                )
+            }
+            _ => anyhow::Error::new(error)
+                .context(format!("Failed to {} the {} service", action, SERVICE_NAME)),
+        }
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            error!("Windows service failed: {:#}", e);
+        }
+    }
+
+    fn run_service() -> Result<()> {
+        let (handle, body) = SERVICE_BODY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .context("Service started without a registered service body")?;
+
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let mut shutdown_tx = Some(shutdown_tx);
+        let event_handler = move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                info!("Service stop requested");
+                if let Some(tx) = shutdown_tx.take() {
+                    let _ = tx.send(());
+                }
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        };
+
+        let status_handle = service_control_handler::register(SERVICE_NAME, event_handler)
+            .context("Failed to register the service control handler")?;
+
+        status_handle.set_service_status(status(
+            ServiceState::Running,
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            0,
+        ))?;
+
+        let result = handle.block_on(body(Box::pin(async {
+            let _ = shutdown_rx.await;
+        })));
+
+        let exit_code = if result.is_ok() { 0 } else { 1 };
+        status_handle.set_service_status(status(
+            ServiceState::Stopped,
+            ServiceControlAccept::empty(),
+            exit_code,
+        ))?;
+
+        result
+    }
+
+    fn status(
+        current_state: ServiceState,
+        controls_accepted: ServiceControlAccept,
+        exit_code: u32,
+    ) -> ServiceStatus {
+        ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state,
+            controls_accepted,
+            exit_code: ServiceExitCode::Win32(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -31,14 +256,14 @@ mod tests {
         let args: Vec<String> = env::args()
             .filter(|arg| arg != "--service")
             .collect();
-        
+
         // Temporarily replace args
         env::set_var("RUST_TEST_ARGS", args.join(" "));
-        
+
         // Reset args for this test
         let original_args = env::args().collect::<Vec<String>>();
         env::set_var("RUST_TEST_ARGS", original_args.join(" "));
-        
+
         assert!(!is_service_mode());
     }
 
@@ -47,19 +272,40 @@ mod tests {
         // This test is tricky because we can't easily modify env::args()
         // In a real test environment, you'd need to mock this
         // For now, we'll just test the function exists and can be called
-        let result = is_service_mode();
         // We can't easily test the true case without modifying the actual args
         // This is a limitation of testing command line argument parsing
-        assert!(result == true || result == false);
+        let _ = is_service_mode();
     }
 
+    #[cfg(not(windows))]
     #[tokio::test]
     async fn test_run_as_service() {
-        // This test would require mocking the ctrl_c signal
-        // For now, we'll just test that the function can be called
-        // In a real implementation, you'd use a timeout or mock the signal
-        let result = tokio::time::timeout(std::time::Duration::from_millis(100), run_as_service()).await;
-        // The function should timeout because ctrl_c() waits indefinitely
+        // Outside Windows the service body runs until Ctrl-C, which never arrives here
+        let result = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            run_as_service(|shutdown| async move {
+                shutdown.await;
+                Ok(())
+            }),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_install_commands_unsupported() {
+        let install = install_service().unwrap_err();
+        assert!(install.to_string().contains("only supported on Windows"));
+
+        let uninstall = uninstall_service().unwrap_err();
+        assert!(uninstall.to_string().contains("only supported on Windows"));
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn test_run_as_service_returns_body_result() {
+        let result = run_as_service(|_shutdown| async { anyhow::bail!("startup failed") }).await;
         assert!(result.is_err());
     }
-}
\ No newline at end of file
+}
@@ -1,37 +1,429 @@
 use anyhow::Result;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{debug, error, info};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+use tracing::{debug, error, info, warn};
+use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+use trust_dns_resolver::system_conf::read_system_conf;
 use trust_dns_resolver::TokioAsyncResolver;
 
+use crate::destination_rules::DestinationRules;
+use crate::dns::{AddressSort, DnsResolver};
+use crate::socks5_client::UpstreamProxy;
+
+/// Default limit on connecting to a destination, matching `Config::connect_timeout_seconds`
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default time a relayed connection may go without traffic, matching
+/// `Config::proxy_idle_timeout_seconds`
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Default cap on connections handled at once, matching
+/// `Config::max_concurrent_connections`
+pub const DEFAULT_MAX_CONCURRENT_CONNECTIONS: usize = 1024;
+
+/// Default queue of connections waiting to be accepted, matching `Config::listen_backlog`
+pub const DEFAULT_LISTEN_BACKLOG: u32 = 1024;
+
+/// How long a BIND request waits for the inbound connection
+const BIND_ACCEPT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a stopped server waits for open connections to finish
+pub(crate) const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Size of each direction's buffer in `proxy_data`
+const RELAY_BUFFER_SIZE: usize = 8192;
+
+/// Binds a TCP listener that queues up to `backlog` connections. Outside Windows the
+/// socket gets `SO_REUSEADDR`, so a restarted server can bind while connections from
+/// its previous run linger in TIME_WAIT. Windows allows that anyway, and its
+/// `SO_REUSEADDR` would let another process bind the same port.
+pub(crate) fn bind_listener(addr: SocketAddr, backlog: u32) -> std::io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    #[cfg(not(windows))]
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(backlog)
+}
+
+/// Caps how many connections a server handles at once. Accepting waits for
+/// a free slot, so excess clients queue in the listen backlog.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionLimit {
+    slots: Arc<Semaphore>,
+    max: usize,
+    /// Accepted connections still open; unlike `active`, this leaves out the
+    /// slot reserved for the next accept
+    open: Arc<AtomicUsize>,
+}
+
+impl ConnectionLimit {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(max)),
+            max,
+            open: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Counts an accepted connection as open until the returned guard is dropped
+    pub(crate) fn opened(&self) -> OpenConnection {
+        self.open.fetch_add(1, Ordering::Relaxed);
+        OpenConnection(self.open.clone())
+    }
+
+    /// Accepted connections that have not closed yet
+    pub(crate) fn open(&self) -> usize {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    /// Waits for a free slot, warning when `server` is at its limit
+    pub(crate) async fn acquire(&self, server: &str) -> OwnedSemaphorePermit {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return permit;
+        }
+        warn!(
+            "{} connection limit of {} reached; waiting for a connection to close",
+            server, self.max
+        );
+        // The semaphore is never closed, so acquiring can only wait
+        self.slots.clone().acquire_owned().await.expect("Connection semaphore closed")
+    }
+
+    /// Connections currently holding a slot
+    pub(crate) fn active(&self) -> usize {
+        self.max - self.slots.available_permits()
+    }
+
+    /// Waits up to `limit` for every connection to release its slot,
+    /// returning how many were still open when it gave up
+    pub(crate) async fn drain(&self, limit: Duration) -> usize {
+        match timeout(limit, self.slots.acquire_many(self.max as u32)).await {
+            Ok(_) => 0,
+            Err(_) => self.active(),
+        }
+    }
+}
+
+/// Marks a connection open for `ConnectionLimit::open` while alive
+pub(crate) struct OpenConnection(Arc<AtomicUsize>);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Stop signal, running flag and listening address shared by a server and its clones
+#[derive(Debug, Clone)]
+pub(crate) struct ServerControl {
+    shutdown: Arc<watch::Sender<bool>>,
+    running: Arc<AtomicBool>,
+    local_addr: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+impl ServerControl {
+    pub(crate) fn new() -> Self {
+        let (shutdown, _) = watch::channel(false);
+        Self {
+            shutdown: Arc::new(shutdown),
+            running: Arc::new(AtomicBool::new(false)),
+            local_addr: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Records the address `listener` is bound to and marks the server running
+    pub(crate) fn start_serving(&self, listener: &TcpListener) {
+        *self.local_addr.lock().unwrap() = listener.local_addr().ok();
+        self.set_running(true);
+    }
+
+    /// Address the server is listening on, `None` unless it is running
+    pub(crate) fn local_addr(&self) -> Option<SocketAddr> {
+        *self.local_addr.lock().unwrap()
+    }
+
+    pub(crate) fn stop(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_running(&self, running: bool) {
+        self.running.store(running, Ordering::Relaxed);
+    }
+
+    /// Resolves once `stop` has been called
+    pub(crate) async fn stopped(&self) {
+        let mut shutdown_rx = self.shutdown.subscribe();
+        // The sender lives as long as `self`, so waiting cannot fail
+        let _ = shutdown_rx.wait_for(|stop| *stop).await;
+    }
+
+    /// Marks `server` stopped once open connections finish or the drain
+    /// timeout passes, whichever comes first
+    pub(crate) async fn drain(&self, server: &str, connection_limit: &ConnectionLimit) {
+        info!("{} server stopping, draining {} connections", server, connection_limit.active());
+        let remaining = connection_limit.drain(SHUTDOWN_DRAIN_TIMEOUT).await;
+        if remaining > 0 {
+            warn!("{} server stopped with {} connections still open", server, remaining);
+        }
+        *self.local_addr.lock().unwrap() = None;
+        self.set_running(false);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Socks5Server {
     pub bind_addr: SocketAddr,
-    resolver: TokioAsyncResolver,
+    resolver: TargetResolver,
+    bytes_relayed: Arc<AtomicU64>,
+    connect_timeout: Duration,
+    idle_timeout: Duration,
+    bind_command_enabled: bool,
+    destination_rules: Arc<DestinationRules>,
+    connection_limit: ConnectionLimit,
+    listen_backlog: u32,
+    /// When set, CONNECTs are opened through this proxy instead of directly
+    upstream_proxy: Option<Arc<UpstreamProxy>>,
+    control: ServerControl,
+}
+
+/// Where domain targets are resolved
+#[derive(Clone)]
+enum TargetResolver {
+    /// A resolver of the server's own
+    Own(Arc<TokioAsyncResolver>),
+    /// The service's resolver, shared with the DNS API along with its cache,
+    /// upstreams, static hosts and block/allow lists
+    Shared(Arc<DnsResolver>),
+}
+
+/// A domain target the shared resolver's block or allow list refused
+#[derive(Debug)]
+struct RefusedDomain(String);
+
+impl std::fmt::Display for RefusedDomain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RefusedDomain {}
+
+impl std::fmt::Debug for TargetResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetResolver::Own(_) => f.write_str("Own"),
+            TargetResolver::Shared(_) => f.write_str("Shared"),
+        }
+    }
+}
+
+impl TargetResolver {
+    /// The address to connect to for `domain`, which is reached on `port`
+    async fn lookup(&self, domain: &str, port: u16) -> Result<IpAddr> {
+        match self {
+            TargetResolver::Own(resolver) => {
+                let lookup = resolver.lookup_ip(domain).await?;
+                lookup
+                    .iter()
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("No IP addresses found for domain: {}", domain))
+            }
+            TargetResolver::Shared(resolver) => {
+                let mut result = resolver.resolve_host_typed(domain).await;
+                if let Some(error) = result.error.take() {
+                    if result.error_kind.as_deref() == Some("blocked") {
+                        return Err(RefusedDomain(error).into());
+                    }
+                    anyhow::bail!(error);
+                }
+                // Probing checks the port the client is connecting to, not the configured one
+                let sort = match resolver.address_sort() {
+                    AddressSort::Probe(_) => AddressSort::Probe(port),
+                    sort => sort,
+                };
+                result.sort_addresses(sort).await;
+                result
+                    .addresses
+                    .first()
+                    .map(|resolved| resolved.addr)
+                    .ok_or_else(|| anyhow::anyhow!("No IP addresses found for domain: {}", domain))
+            }
+        }
+    }
+}
+
+/// Resolver for CONNECT host names, using the system configuration when it can be
+/// read and trust-dns' default upstream otherwise (e.g. containers without resolv.conf)
+fn system_resolver<E: std::fmt::Display>(
+    read_conf: impl FnOnce() -> std::result::Result<(ResolverConfig, ResolverOpts), E>,
+) -> TokioAsyncResolver {
+    let (config, opts) = read_conf().unwrap_or_else(|e| {
+        warn!("Failed to read system DNS configuration ({}), using default upstream for SOCKS5", e);
+        (ResolverConfig::default(), ResolverOpts::default())
+    });
+    TokioAsyncResolver::tokio(config, opts)
 }
 
 impl Socks5Server {
     pub fn new(bind_addr: SocketAddr) -> Result<Self> {
-        let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
-        Ok(Self { bind_addr, resolver })
+        Ok(Self::with_resolver(bind_addr, TargetResolver::Own(Arc::new(system_resolver(read_system_conf)))))
+    }
+
+    /// Creates a server that resolves domain targets through `resolver`, so SOCKS5
+    /// clients see the same answers as the DNS API
+    pub fn new_with_resolver(bind_addr: SocketAddr, resolver: Arc<DnsResolver>) -> Self {
+        Self::with_resolver(bind_addr, TargetResolver::Shared(resolver))
+    }
+
+    fn with_resolver(bind_addr: SocketAddr, resolver: TargetResolver) -> Self {
+        Self {
+            bind_addr,
+            resolver,
+            bytes_relayed: Arc::new(AtomicU64::new(0)),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            bind_command_enabled: false,
+            destination_rules: Arc::new(DestinationRules::default()),
+            connection_limit: ConnectionLimit::new(DEFAULT_MAX_CONCURRENT_CONNECTIONS),
+            listen_backlog: DEFAULT_LISTEN_BACKLOG,
+            upstream_proxy: None,
+            control: ServerControl::new(),
+        }
+    }
+
+    /// Restricts which destinations CONNECT may reach
+    pub fn with_destination_rules(mut self, rules: DestinationRules) -> Self {
+        self.destination_rules = Arc::new(rules);
+        self
+    }
+
+    /// Sets how long to wait for a destination to accept the connection
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Sets how long a relayed connection may go without traffic in either
+    /// direction before it is dropped
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Caps how many client connections are handled at once; further clients
+    /// wait until a connection closes
+    pub fn with_max_concurrent_connections(mut self, max_connections: usize) -> Self {
+        self.connection_limit = ConnectionLimit::new(max_connections);
+        self
+    }
+
+    /// Sets how many connections may wait to be accepted
+    pub fn with_listen_backlog(mut self, backlog: u32) -> Self {
+        self.listen_backlog = backlog;
+        self
+    }
+
+    /// Allows the BIND command, which listens for one inbound connection on
+    /// behalf of the client. Disabled by default.
+    pub fn with_bind_command_enabled(mut self, enabled: bool) -> Self {
+        self.bind_command_enabled = enabled;
+        self
+    }
+
+    /// Opens CONNECT destinations through `upstream` (e.g. a corporate egress
+    /// proxy) instead of connecting directly
+    pub fn with_upstream_proxy(mut self, upstream: Option<UpstreamProxy>) -> Self {
+        self.upstream_proxy = upstream.map(Arc::new);
+        self
+    }
+
+    /// Total bytes relayed in both directions across all closed connections
+    pub fn bytes_relayed(&self) -> u64 {
+        self.bytes_relayed.load(Ordering::Relaxed)
+    }
+
+    /// Shared handle to the relayed byte counter, readable after `run` takes `self`
+    pub fn bytes_relayed_counter(&self) -> Arc<AtomicU64> {
+        self.bytes_relayed.clone()
+    }
+
+    /// Asks `run` on this server, or on any clone of it, to stop accepting
+    /// connections. `run` returns once open connections finish, or after a
+    /// short drain timeout.
+    pub fn stop(&self) {
+        self.control.stop();
+    }
+
+    /// Whether `run` is currently accepting or draining connections
+    pub fn is_running(&self) -> bool {
+        self.control.is_running()
+    }
+
+    /// Address `run` is listening on, with the actual port when bound to port 0
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.control.local_addr()
+    }
+
+    /// Client connections currently open
+    pub fn active_connections(&self) -> usize {
+        self.connection_limit.open()
     }
 
     pub async fn run(self) -> Result<()> {
         info!("Starting SOCKS5 server on {}", self.bind_addr);
 
-        let listener = TcpListener::bind(self.bind_addr).await?;
+        let listener = bind_listener(self.bind_addr, self.listen_backlog)?;
         info!("SOCKS5 server listening on {}", self.bind_addr);
 
+        self.serve(listener).await
+    }
+
+    /// Accepts connections on an already bound `listener`
+    pub(crate) async fn serve(self, listener: TcpListener) -> Result<()> {
+        self.control.start_serving(&listener);
         loop {
-            match listener.accept().await {
+            let permit = tokio::select! {
+                _ = self.control.stopped() => break,
+                permit = self.connection_limit.acquire("SOCKS5") => permit,
+            };
+            let accepted = tokio::select! {
+                _ = self.control.stopped() => break,
+                accepted = listener.accept() => accepted,
+            };
+            match accepted {
                 Ok((stream, addr)) => {
-                    debug!("New SOCKS5 connection from {}", addr);
-                    let resolver = self.resolver.clone();
+                    debug!(
+                        "New SOCKS5 connection from {} ({} active)",
+                        addr,
+                        self.connection_limit.active()
+                    );
+                    let server = self.clone();
+                    let open = self.connection_limit.opened();
                     tokio::spawn(async move {
-                        if let Err(e) = handle_socks5_connection(stream, resolver).await {
+                        if let Err(e) = server.handle_connection(stream).await {
                             error!("SOCKS5 connection error: {}", e);
                         }
+                        drop(open);
+                        drop(permit);
+                        debug!(
+                            "SOCKS5 connection from {} closed ({} active)",
+                            addr,
+                            server.connection_limit.active()
+                        );
                     });
                 }
                 Err(e) => {
@@ -39,51 +431,253 @@ impl Socks5Server {
                 }
             }
         }
+
+        drop(listener);
+        self.control.drain("SOCKS5", &self.connection_limit).await;
+        Ok(())
+    }
+
+    async fn handle_connection(&self, mut stream: TcpStream) -> Result<()> {
+        let request = negotiate_request(&mut stream, self.bind_command_enabled).await?;
+        match request.command {
+            Command::Connect => self.handle_connect(stream, request.target).await,
+            Command::Bind => {
+                let expected_peer = self.resolve(&request.target).await?;
+                self.handle_bind(stream, expected_peer).await
+            }
+        }
+    }
+
+    /// Resolves a domain target on the server side
+    async fn resolve(&self, target: &TargetAddr) -> Result<SocketAddr> {
+        match target {
+            TargetAddr::Ip(addr) => Ok(*addr),
+            TargetAddr::Domain(domain, port) => {
+                debug!("Resolving domain name: {}", domain);
+
+                match self.resolver.lookup(domain, *port).await {
+                    Ok(ip) => {
+                        debug!("Resolved {} to {}", domain, ip);
+                        Ok(SocketAddr::new(ip, *port))
+                    }
+                    Err(e) => {
+                        error!("Failed to resolve domain {}: {}", domain, e);
+                        Err(e.context(format!("DNS resolution failed for domain: {}", domain)))
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_connect(&self, mut stream: TcpStream, target: TargetAddr) -> Result<()> {
+        let domain = match &target {
+            TargetAddr::Domain(domain, _) => Some(domain.as_str()),
+            TargetAddr::Ip(_) => None,
+        };
+
+        // Refuse denied names before they reach the resolver
+        if domain.is_some_and(|domain| self.destination_rules.denies_domain(domain)) {
+            return deny_destination(&mut stream, &target).await;
+        }
+
+        let dest_addr = match self.resolve(&target).await {
+            Ok(dest_addr) => dest_addr,
+            Err(e) => {
+                // Reply 2 (not allowed by ruleset) for refused names, 4 (host unreachable) otherwise
+                let reply = if e.downcast_ref::<RefusedDomain>().is_some() { 2 } else { 4 };
+                stream.write_all(&[5, reply, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+                return Err(e);
+            }
+        };
+        if !self.destination_rules.allows(domain, dest_addr.ip()) {
+            return deny_destination(&mut stream, &target).await;
+        }
+
+        debug!("SOCKS5 request to connect to: {}", dest_addr);
+
+        // Attempt to connect to destination, through the upstream proxy if configured
+        let connect = async {
+            match &self.upstream_proxy {
+                Some(upstream) => {
+                    debug!("Chaining connection to {} through {:?} proxy {}", dest_addr, upstream.kind, upstream.addr);
+                    upstream.connect(dest_addr).await
+                }
+                None => Ok(TcpStream::connect(dest_addr).await?),
+            }
+        };
+        match timeout(self.connect_timeout, connect).await {
+            Ok(Ok(dest_stream)) => {
+                debug!("Connected to destination: {}", dest_addr);
+
+                // Send success response
+                send_reply(&mut stream, 0, dest_addr).await?;
+
+                // Start proxying data
+                self.relay(stream, dest_stream, dest_addr).await;
+            }
+            Ok(Err(e)) => {
+                error!("Failed to connect to destination {}: {}", dest_addr, e);
+                stream.write_all(&[5, 1, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+                return Err(anyhow::anyhow!("Connection failed: {}", e));
+            }
+            Err(_) => {
+                error!(
+                    "Timed out connecting to destination {} after {:?}",
+                    dest_addr, self.connect_timeout
+                );
+                // Reply 6: TTL expired
+                stream.write_all(&[5, 6, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+                return Err(anyhow::anyhow!("Connection to {} timed out", dest_addr));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles BIND: listens on an ephemeral port, reports it in the first
+    /// reply, then reports the peer in a second reply once it connects and
+    /// relays between the two. `expected_peer` is the address the client
+    /// expects the connection from; an unspecified IP accepts any peer.
+    async fn handle_bind(&self, mut stream: TcpStream, expected_peer: SocketAddr) -> Result<()> {
+        // Listen on the interface the client reached us on, so the reply is routable
+        let listener = TcpListener::bind(SocketAddr::new(stream.local_addr()?.ip(), 0)).await?;
+        let bound_addr = listener.local_addr()?;
+        debug!("SOCKS5 BIND listening on {} for {}", bound_addr, expected_peer);
+
+        send_reply(&mut stream, 0, bound_addr).await?;
+
+        let (peer_stream, peer_addr) = match timeout(BIND_ACCEPT_TIMEOUT, listener.accept()).await {
+            Ok(Ok(accepted)) => accepted,
+            Ok(Err(e)) => {
+                error!("Failed to accept BIND connection on {}: {}", bound_addr, e);
+                stream.write_all(&[5, 1, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+                return Err(anyhow::anyhow!("BIND accept failed: {}", e));
+            }
+            Err(_) => {
+                // Reply 6: TTL expired
+                stream.write_all(&[5, 6, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+                return Err(anyhow::anyhow!(
+                    "No connection to BIND port {} within {:?}",
+                    bound_addr,
+                    BIND_ACCEPT_TIMEOUT
+                ));
+            }
+        };
+
+        if !expected_peer.ip().is_unspecified() && peer_addr.ip() != expected_peer.ip() {
+            // Reply 2: connection not allowed by ruleset
+            stream.write_all(&[5, 2, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+            return Err(anyhow::anyhow!(
+                "BIND connection from {} rejected, expected {}",
+                peer_addr,
+                expected_peer.ip()
+            ));
+        }
+
+        debug!("SOCKS5 BIND accepted connection from {}", peer_addr);
+        send_reply(&mut stream, 0, peer_addr).await?;
+
+        self.relay(stream, peer_stream, peer_addr).await;
+        Ok(())
     }
+
+    /// Relays between the client and `peer`, then records and logs the byte counts
+    async fn relay(&self, client: TcpStream, peer: TcpStream, peer_addr: SocketAddr) {
+        let (client_to_dest, dest_to_client) = proxy_data(client, peer, self.idle_timeout).await;
+        self.bytes_relayed
+            .fetch_add(client_to_dest + dest_to_client, Ordering::Relaxed);
+        info!(
+            "SOCKS5 connection to {} closed: client->dest: {} bytes, dest->client: {} bytes",
+            peer_addr, client_to_dest, dest_to_client
+        );
+    }
+}
+
+/// Destination requested by a SOCKS5 client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetAddr {
+    Ip(SocketAddr),
+    Domain(String, u16),
 }
 
-async fn handle_socks5_connection(mut stream: TcpStream, resolver: TokioAsyncResolver) -> Result<()> {
-    let mut buffer = [0u8; 1024];
-    
-    // Read SOCKS5 greeting
-    let n = stream.read(&mut buffer).await?;
-    debug!("Received {} bytes from SOCKS5 client", n);
-    
-    if n < 3 {
-        debug!("Invalid SOCKS5 greeting: too short ({} bytes)", n);
-        return Err(anyhow::anyhow!("Invalid SOCKS5 greeting: too short"));
+impl TargetAddr {
+    pub fn host(&self) -> String {
+        match self {
+            TargetAddr::Ip(addr) => addr.ip().to_string(),
+            TargetAddr::Domain(domain, _) => domain.clone(),
+        }
     }
-    
-    // Log the first few bytes for debugging
-    debug!("SOCKS5 greeting bytes: {:?}", &buffer[0..std::cmp::min(n, 10)]);
 
-    let version = buffer[0];
-    let nmethods = buffer[1] as usize;
-    
+    pub fn port(&self) -> u16 {
+        match self {
+            TargetAddr::Ip(addr) => addr.port(),
+            TargetAddr::Domain(_, port) => *port,
+        }
+    }
+}
+
+/// SOCKS5 command requested by a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Connect,
+    Bind,
+}
+
+/// Command and destination read from a SOCKS5 request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocksRequest {
+    pub command: Command,
+    pub target: TargetAddr,
+}
+
+/// Runs the SOCKS5 greeting and reads the CONNECT request, returning the
+/// requested destination. Domain names are returned unresolved so the caller
+/// decides where resolution happens.
+pub(crate) async fn negotiate<S>(stream: &mut S) -> Result<TargetAddr>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    Ok(negotiate_request(stream, false).await?.target)
+}
+
+/// Like `negotiate`, but also accepts BIND when `allow_bind` is set and
+/// returns the requested command alongside the destination.
+pub(crate) async fn negotiate_request<S>(stream: &mut S, allow_bind: bool) -> Result<SocksRequest>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Read SOCKS5 greeting: version, method count, then that many methods.
+    // Clients may split any of it across segments, so read exact lengths.
+    let mut header = [0u8; 2];
+    stream
+        .read_exact(&mut header)
+        .await
+        .map_err(|e| anyhow::anyhow!("Invalid SOCKS5 greeting: too short ({})", e))?;
+    debug!("SOCKS5 greeting header: {:?}", header);
+
+    let version = header[0];
+    let nmethods = header[1] as usize;
+
     if version != 5 {
         debug!("Invalid SOCKS version: {} (expected 5)", version);
-        
+
         // Check if this might be an HTTP request
-        if buffer[0] == b'G' && buffer[1] == b'E' && buffer[2] == b'T' {
+        if header == *b"GE" {
             debug!("Client sent HTTP GET request instead of SOCKS5");
             return Err(anyhow::anyhow!("Client sent HTTP request instead of SOCKS5"));
         }
-        
+
         return Err(anyhow::anyhow!("Unsupported SOCKS version: {} (expected 5)", version));
     }
 
-    if n < 2 + nmethods {
-        return Err(anyhow::anyhow!("Invalid SOCKS5 greeting length"));
-    }
+    let mut methods = vec![0u8; nmethods];
+    stream
+        .read_exact(&mut methods)
+        .await
+        .map_err(|e| anyhow::anyhow!("Invalid SOCKS5 greeting length ({})", e))?;
 
     // Check if no authentication is supported
-    let mut no_auth_supported = false;
-    for i in 0..nmethods {
-        if buffer[2 + i] == 0 {
-            no_auth_supported = true;
-            break;
-        }
-    }
+    let no_auth_supported = methods.contains(&0);
 
     if !no_auth_supported {
         // Send "no acceptable methods" response
@@ -94,75 +688,73 @@ async fn handle_socks5_connection(mut stream: TcpStream, resolver: TokioAsyncRes
     // Send "no authentication required" response
     stream.write_all(&[5, 0]).await?;
 
-    // Read connection request
-    let n = stream.read(&mut buffer).await?;
-    if n < 10 {
-        return Err(anyhow::anyhow!("Invalid SOCKS5 request"));
-    }
+    // Read connection request header; the address length depends on ATYP
+    let mut request = [0u8; 4];
+    stream
+        .read_exact(&mut request)
+        .await
+        .map_err(|e| anyhow::anyhow!("Invalid SOCKS5 request ({})", e))?;
 
-    let version = buffer[0];
-    let cmd = buffer[1];
-    let _rsv = buffer[2];
-    let atyp = buffer[3];
+    let version = request[0];
+    let cmd = request[1];
+    let _rsv = request[2];
+    let atyp = request[3];
 
     if version != 5 {
         return Err(anyhow::anyhow!("Invalid SOCKS5 version in request"));
     }
 
-    if cmd != 1 {
-        // Only support CONNECT command
-        stream.write_all(&[5, 7, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
-        return Err(anyhow::anyhow!("Unsupported SOCKS5 command: {}", cmd));
-    }
+    let command = match cmd {
+        1 => Command::Connect,
+        2 if allow_bind => Command::Bind,
+        _ => {
+            // Only CONNECT and, when enabled, BIND are supported
+            stream.write_all(&[5, 7, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+            return Err(anyhow::anyhow!("Unsupported SOCKS5 command: {}", cmd));
+        }
+    };
 
     // Parse destination address
-    let (dest_addr, _addr_len) = match atyp {
+    let target = match atyp {
         1 => {
             // IPv4
-            if n < 10 {
-                return Err(anyhow::anyhow!("Invalid IPv4 address length"));
-            }
-            let ip = Ipv4Addr::new(buffer[4], buffer[5], buffer[6], buffer[7]);
-            let port = u16::from_be_bytes([buffer[8], buffer[9]]);
-            (SocketAddr::new(IpAddr::V4(ip), port), 6)
+            let mut addr = [0u8; 6];
+            stream
+                .read_exact(&mut addr)
+                .await
+                .map_err(|e| anyhow::anyhow!("Invalid SOCKS5 request ({})", e))?;
+            let ip = Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]);
+            let port = u16::from_be_bytes([addr[4], addr[5]]);
+            TargetAddr::Ip(SocketAddr::new(IpAddr::V4(ip), port))
         }
         3 => {
-            // Domain name - resolve on server side
-            let domain_len = buffer[4] as usize;
-            if n < 5 + domain_len + 2 {
-                return Err(anyhow::anyhow!("Invalid domain name length"));
-            }
-            let domain = String::from_utf8_lossy(&buffer[5..5 + domain_len]);
-            let port = u16::from_be_bytes([buffer[5 + domain_len], buffer[5 + domain_len + 1]]);
-            
-            debug!("Resolving domain name: {}", domain);
-            
-            // Resolve domain name on server side
-            match resolver.lookup_ip(domain.as_ref()).await {
-                Ok(lookup) => {
-                    if let Some(ip) = lookup.iter().next() {
-                        debug!("Resolved {} to {}", domain, ip);
-                        (SocketAddr::new(ip, port), 5 + domain_len + 2)
-                    } else {
-                        return Err(anyhow::anyhow!("No IP addresses found for domain: {}", domain));
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to resolve domain {}: {}", domain, e);
-                    return Err(anyhow::anyhow!("DNS resolution failed for domain: {}", domain));
-                }
-            }
+            // Domain name
+            let domain_len = stream
+                .read_u8()
+                .await
+                .map_err(|e| anyhow::anyhow!("Invalid domain name length ({})", e))?
+                as usize;
+            let mut addr = vec![0u8; domain_len + 2];
+            stream
+                .read_exact(&mut addr)
+                .await
+                .map_err(|e| anyhow::anyhow!("Invalid domain name length ({})", e))?;
+            let domain = String::from_utf8_lossy(&addr[..domain_len]).into_owned();
+            let port = u16::from_be_bytes([addr[domain_len], addr[domain_len + 1]]);
+            TargetAddr::Domain(domain, port)
         }
         4 => {
             // IPv6
-            if n < 22 {
-                return Err(anyhow::anyhow!("Invalid IPv6 address length"));
-            }
+            let mut addr = [0u8; 18];
+            stream
+                .read_exact(&mut addr)
+                .await
+                .map_err(|e| anyhow::anyhow!("Invalid IPv6 address length ({})", e))?;
             let mut ip_bytes = [0u8; 16];
-            ip_bytes.copy_from_slice(&buffer[4..20]);
+            ip_bytes.copy_from_slice(&addr[..16]);
             let ip = Ipv6Addr::from(ip_bytes);
-            let port = u16::from_be_bytes([buffer[20], buffer[21]]);
-            (SocketAddr::new(IpAddr::V6(ip), port), 18)
+            let port = u16::from_be_bytes([addr[16], addr[17]]);
+            TargetAddr::Ip(SocketAddr::new(IpAddr::V6(ip), port))
         }
         _ => {
             stream.write_all(&[5, 8, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
@@ -170,78 +762,735 @@ async fn handle_socks5_connection(mut stream: TcpStream, resolver: TokioAsyncRes
         }
     };
 
-    debug!("SOCKS5 request to connect to: {}", dest_addr);
-
-    // Attempt to connect to destination
-    match TcpStream::connect(dest_addr).await {
-        Ok(dest_stream) => {
-            debug!("Connected to destination: {}", dest_addr);
-            
-            // Send success response
-            let mut response = vec![5, 0, 0];
-            match dest_addr.ip() {
-                IpAddr::V4(ip) => {
-                    response.push(1); // IPv4 address type
-                    response.extend_from_slice(&ip.octets());
-                }
-                IpAddr::V6(ip) => {
-                    response.push(4); // IPv6 address type
-                    response.extend_from_slice(&ip.octets());
-                }
-            }
-            response.extend_from_slice(&dest_addr.port().to_be_bytes());
-            stream.write_all(&response).await?;
+    Ok(SocksRequest { command, target })
+}
 
-            // Start proxying data
-            proxy_data(stream, dest_stream).await?;
+/// Writes a SOCKS5 reply with the given reply code and bound address.
+pub(crate) async fn send_reply<S>(stream: &mut S, reply: u8, bound_addr: SocketAddr) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut response = vec![5, reply, 0];
+    match bound_addr.ip() {
+        IpAddr::V4(ip) => {
+            response.push(1); // IPv4 address type
+            response.extend_from_slice(&ip.octets());
         }
-        Err(e) => {
-            error!("Failed to connect to destination {}: {}", dest_addr, e);
-            stream.write_all(&[5, 1, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
-            return Err(anyhow::anyhow!("Connection failed: {}", e));
+        IpAddr::V6(ip) => {
+            response.push(4); // IPv6 address type
+            response.extend_from_slice(&ip.octets());
         }
     }
-
+    response.extend_from_slice(&bound_addr.port().to_be_bytes());
+    stream.write_all(&response).await?;
     Ok(())
 }
 
-async fn proxy_data(
-    client: TcpStream,
-    dest: TcpStream,
-) -> Result<()> {
+/// Sends reply 2 (connection not allowed by ruleset) for a destination the
+/// rules refuse
+async fn deny_destination(stream: &mut TcpStream, target: &TargetAddr) -> Result<()> {
+    info!("SOCKS5 connection to {}:{} denied by destination rules", target.host(), target.port());
+    stream.write_all(&[5, 2, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+    Err(anyhow::anyhow!(
+        "Destination {}:{} not allowed",
+        target.host(),
+        target.port()
+    ))
+}
+
+/// Relays data both ways until each side has finished sending or no traffic
+/// has passed for `idle_timeout`, returning the byte counts as
+/// `(client->dest, dest->client)`. The directions run independently, so a
+/// half-closed side still receives everything the other side sends.
+async fn proxy_data(client: TcpStream, dest: TcpStream, idle_timeout: Duration) -> (u64, u64) {
     let (mut client_read, mut client_write) = client.into_split();
     let (mut dest_read, mut dest_write) = dest.into_split();
+    let activity = RelayActivity::new();
+    let (client_to_dest, dest_to_client) = (AtomicU64::new(0), AtomicU64::new(0));
 
-    // Create bidirectional proxy
-    let client_to_dest = tokio::io::copy(&mut client_read, &mut dest_write);
-    let dest_to_client = tokio::io::copy(&mut dest_read, &mut client_write);
-
-    // Run both directions concurrently
+    let relay = async {
+        tokio::join!(
+            relay_one_way(&mut client_read, &mut dest_write, &client_to_dest, &activity, "Client to destination"),
+            relay_one_way(&mut dest_read, &mut client_write, &dest_to_client, &activity, "Destination to client"),
+        )
+    };
     tokio::select! {
-        result = client_to_dest => {
-            if let Err(e) = result {
-                debug!("Client to destination proxy error: {}", e);
+        _ = relay => {}
+        _ = activity.idle_for(idle_timeout) => {
+            debug!("Closing SOCKS5 connection after {:?} without traffic", idle_timeout);
+        }
+    }
+
+    (client_to_dest.into_inner(), dest_to_client.into_inner())
+}
+
+/// Copies `reader` into `writer` until EOF or an error, then shuts `writer`
+/// down so the peer sees the half-close
+async fn relay_one_way<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    relayed: &AtomicU64,
+    activity: &RelayActivity,
+    direction: &str,
+) where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; RELAY_BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => {
+                if let Err(e) = writer.write_all(&buf[..n]).await {
+                    debug!("{} proxy error: {}", direction, e);
+                    break;
+                }
+                relayed.fetch_add(n as u64, Ordering::Relaxed);
+                activity.touch();
+            }
+            Err(e) => {
+                debug!("{} proxy error: {}", direction, e);
+                break;
             }
         }
-        result = dest_to_client => {
-            if let Err(e) = result {
-                debug!("Destination to client proxy error: {}", e);
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// Time of the last relayed chunk in either direction
+struct RelayActivity {
+    started: tokio::time::Instant,
+    last_millis: AtomicU64,
+}
+
+impl RelayActivity {
+    fn new() -> Self {
+        Self {
+            started: tokio::time::Instant::now(),
+            last_millis: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        self.last_millis.fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    /// Completes once `idle_timeout` has passed since the last relayed chunk
+    async fn idle_for(&self, idle_timeout: Duration) {
+        loop {
+            let last = self.started + Duration::from_millis(self.last_millis.load(Ordering::Relaxed));
+            let deadline = last + idle_timeout;
+            if tokio::time::Instant::now() >= deadline {
+                return;
             }
+            tokio::time::sleep_until(deadline).await;
         }
     }
+}
 
-    Ok(())
+/// A destination that never completes a TCP handshake: a listener with a full
+/// accept backlog. Unlike an unroutable address this does not depend on the
+/// host network, where sandboxes and transparent proxies may accept anything.
+#[cfg(test)]
+pub(crate) async fn unresponsive_destination() -> (TcpListener, TcpStream, SocketAddr) {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let listener = socket.listen(0).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let backlog_filler = TcpStream::connect(addr).await.unwrap();
+    (listener, backlog_filler, addr)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dns::DnsResolverOptions;
     use std::net::SocketAddr;
 
     #[test]
     fn test_socks5_server_creation() {
         let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
-        let server = Socks5Server::new(addr);
+        let server = Socks5Server::new(addr).expect("Failed to create SOCKS5 server");
         assert_eq!(server.bind_addr, addr);
     }
+
+    #[tokio::test]
+    async fn test_listener_rebinds_port_with_connections_in_time_wait() {
+        let listener = bind_listener("127.0.0.1:0".parse().unwrap(), DEFAULT_LISTEN_BACKLOG).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // The server side closes first, so its end of the connection lingers in TIME_WAIT
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+        drop(accepted);
+        drop(listener);
+        drop(client);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let listener = bind_listener(addr, DEFAULT_LISTEN_BACKLOG).expect("Failed to bind the port again");
+        assert_eq!(listener.local_addr().unwrap(), addr);
+    }
+
+    #[tokio::test]
+    async fn test_server_starts_without_system_dns_config() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let resolver = system_resolver(|| {
+            Err(std::io::Error::new(std::io::ErrorKind::NotFound, "/etc/resolv.conf not found"))
+        });
+        let server = Socks5Server::with_resolver(addr, TargetResolver::Own(Arc::new(resolver)));
+        tokio::spawn(server.serve(listener));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut reply = [0u8; 2];
+        timeout(Duration::from_secs(5), client.read_exact(&mut reply)).await.unwrap().unwrap();
+        assert_eq!(reply, [0x05, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn test_connections_over_limit_wait_for_a_slot() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Socks5Server::new(addr)
+            .expect("Failed to create SOCKS5 server")
+            .with_max_concurrent_connections(1);
+        let connection_limit = server.connection_limit.clone();
+        tokio::spawn(server.serve(listener));
+
+        let greet = |mut client: TcpStream| async move {
+            client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+            let mut reply = [0u8; 2];
+            let replied = timeout(Duration::from_millis(300), client.read_exact(&mut reply)).await;
+            (client, replied.is_ok())
+        };
+
+        // The first client holds the only slot while it sits in negotiation
+        let (first, replied) = greet(TcpStream::connect(addr).await.unwrap()).await;
+        assert!(replied);
+        assert_eq!(connection_limit.active(), 1);
+
+        // The second is queued rather than served
+        let (mut second, replied) = greet(TcpStream::connect(addr).await.unwrap()).await;
+        assert!(!replied);
+
+        drop(first);
+        let mut reply = [0u8; 2];
+        timeout(Duration::from_secs(5), second.read_exact(&mut reply))
+            .await
+            .expect("Queued connection was never served")
+            .unwrap();
+        assert_eq!(reply, [0x05, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn test_stop_ends_run() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Socks5Server::new(addr).unwrap();
+        let handle = server.clone();
+        assert!(!handle.is_running());
+
+        let run = tokio::spawn(server.serve(listener));
+        let client = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(handle.is_running());
+
+        // The open client holds up the drain until it disconnects
+        handle.stop();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!run.is_finished());
+        drop(client);
+
+        timeout(Duration::from_secs(2), run)
+            .await
+            .expect("run did not return after stop")
+            .unwrap()
+            .unwrap();
+        assert!(!handle.is_running());
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_domain_target() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let client_task = tokio::spawn(async move {
+            client.write_all(&[5, 1, 0]).await.unwrap();
+            let mut method = [0u8; 2];
+            client.read_exact(&mut method).await.unwrap();
+            assert_eq!(method, [5, 0]);
+
+            let mut request = vec![5, 1, 0, 3, 11];
+            request.extend_from_slice(b"example.com");
+            request.extend_from_slice(&443u16.to_be_bytes());
+            client.write_all(&request).await.unwrap();
+        });
+
+        let target = negotiate(&mut server).await.expect("Negotiation failed");
+        client_task.await.unwrap();
+
+        assert_eq!(target, TargetAddr::Domain("example.com".to_string(), 443));
+        assert_eq!(target.host(), "example.com");
+        assert_eq!(target.port(), 443);
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_greeting_split_across_writes() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let client_task = tokio::spawn(async move {
+            for byte in [5u8, 2, 2, 0] {
+                client.write_all(&[byte]).await.unwrap();
+                client.flush().await.unwrap();
+                tokio::task::yield_now().await;
+            }
+            let mut method = [0u8; 2];
+            client.read_exact(&mut method).await.unwrap();
+            assert_eq!(method, [5, 0]);
+
+            let mut request = vec![5, 1, 0, 3, 11];
+            request.extend_from_slice(b"example.com");
+            request.extend_from_slice(&443u16.to_be_bytes());
+            for byte in request {
+                client.write_all(&[byte]).await.unwrap();
+                client.flush().await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let target = negotiate(&mut server).await.expect("Negotiation failed");
+        client_task.await.unwrap();
+
+        assert_eq!(target, TargetAddr::Domain("example.com".to_string(), 443));
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_ipv4_target() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let client_task = tokio::spawn(async move {
+            client.write_all(&[5, 1, 0]).await.unwrap();
+            let mut method = [0u8; 2];
+            client.read_exact(&mut method).await.unwrap();
+            client.write_all(&[5, 1, 0, 1, 10, 0, 0, 1, 0, 80]).await.unwrap();
+        });
+
+        let target = negotiate(&mut server).await.expect("Negotiation failed");
+        client_task.await.unwrap();
+
+        assert_eq!(target, TargetAddr::Ip("10.0.0.1:80".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_connection_counts_relayed_bytes() {
+        let request_payload = vec![0xAB; 5000];
+        let response_payload = vec![0xCD; 1234];
+
+        // Destination reads the whole request, answers and closes
+        let dest_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dest_addr = dest_listener.local_addr().unwrap();
+        let expected_request = request_payload.clone();
+        let dest_response = response_payload.clone();
+        let dest_task = tokio::spawn(async move {
+            let (mut stream, _) = dest_listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received, expected_request);
+            stream.write_all(&dest_response).await.unwrap();
+        });
+
+        let server = Socks5Server::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let (mut client, proxy_task) = start_connection(server.clone()).await;
+
+        assert_eq!(connect_request(&mut client, dest_addr).await, 0);
+
+        client.write_all(&request_payload).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, response_payload);
+
+        proxy_task.await.unwrap().expect("SOCKS5 connection failed");
+        dest_task.await.unwrap();
+
+        let expected = (request_payload.len() + response_payload.len()) as u64;
+        assert_eq!(server.bytes_relayed(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_final_burst_after_client_half_close() {
+        // Destination waits for the client's EOF, then keeps sending for a while
+        let dest_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dest_addr = dest_listener.local_addr().unwrap();
+        let dest_task = tokio::spawn(async move {
+            let (mut stream, _) = dest_listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            stream.read_to_end(&mut request).await.unwrap();
+            assert_eq!(request, b"bye");
+            for chunk in 0..4u8 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                stream.write_all(&[chunk; 64 * 1024]).await.unwrap();
+            }
+        });
+
+        let server = Socks5Server::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let (mut client, proxy_task) = start_connection(server).await;
+        assert_eq!(connect_request(&mut client, dest_addr).await, 0);
+
+        client.write_all(b"bye").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+
+        assert_eq!(response.len(), 4 * 64 * 1024);
+        assert!(response.chunks(64 * 1024).enumerate().all(|(i, chunk)| chunk.iter().all(|&b| b == i as u8)));
+        proxy_task.await.unwrap().expect("SOCKS5 connection failed");
+        dest_task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_is_chained_through_upstream_proxy() {
+        let dest_addr: SocketAddr = "203.0.113.7:443".parse().unwrap();
+
+        // Mock upstream SOCKS5 proxy: records the CONNECT it receives, then
+        // answers as the destination would
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        let upstream_task = tokio::spawn(async move {
+            let (mut stream, _) = upstream_listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            stream.write_all(&[5, 0]).await.unwrap();
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).await.unwrap();
+            stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await.unwrap();
+            stream.write_all(b"from upstream").await.unwrap();
+            request
+        });
+
+        let server = Socks5Server::new("127.0.0.1:0".parse().unwrap())
+            .unwrap()
+            .with_upstream_proxy(Some(UpstreamProxy {
+                kind: crate::socks5_client::UpstreamProxyKind::Socks5,
+                addr: upstream_addr,
+                auth: None,
+            }));
+        let (mut client, _proxy_task) = start_connection(server).await;
+
+        assert_eq!(connect_request(&mut client, dest_addr).await, 0);
+        let mut relayed = [0u8; 13];
+        client.read_exact(&mut relayed).await.unwrap();
+        assert_eq!(&relayed, b"from upstream");
+
+        // The upstream was asked for the client's destination, not contacted directly
+        assert_eq!(upstream_task.await.unwrap(), [5, 1, 0, 1, 203, 0, 113, 7, 1, 187]);
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout_replies_ttl_expired() {
+        let (_listener, _backlog_filler, dest_addr) = unresponsive_destination().await;
+        let server = Socks5Server::new("127.0.0.1:0".parse().unwrap())
+            .unwrap()
+            .with_connect_timeout(Duration::from_millis(200));
+        let (mut client, proxy_task) = start_connection(server).await;
+
+        assert_eq!(connect_request(&mut client, dest_addr).await, 6);
+        let err = proxy_task.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_is_dropped() {
+        // Destination accepts but never sends or closes
+        let dest_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dest_addr = dest_listener.local_addr().unwrap();
+        let dest_task = tokio::spawn(async move {
+            let (stream, _) = dest_listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            drop(stream);
+        });
+
+        let server = Socks5Server::new("127.0.0.1:0".parse().unwrap())
+            .unwrap()
+            .with_idle_timeout(Duration::from_millis(200));
+        let (mut client, proxy_task) = start_connection(server).await;
+        assert_eq!(connect_request(&mut client, dest_addr).await, 0);
+
+        tokio::time::timeout(Duration::from_secs(5), proxy_task)
+            .await
+            .expect("Idle connection was not dropped")
+            .unwrap()
+            .unwrap();
+
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+        dest_task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_bind_command_sends_both_replies() {
+        let server = Socks5Server::new("127.0.0.1:0".parse().unwrap())
+            .unwrap()
+            .with_bind_command_enabled(true);
+        let (mut client, proxy_task) = start_connection(server.clone()).await;
+
+        client.write_all(&[5, 1, 0]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        send_command(&mut client, 2, "0.0.0.0:0".parse().unwrap()).await;
+
+        // First reply: the port the server listens on
+        let (code, bound_addr) = read_reply(&mut client).await;
+        assert_eq!(code, 0);
+        assert_eq!(bound_addr.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_ne!(bound_addr.port(), 0);
+
+        // Second reply: the inbound peer, once it connects
+        let mut peer = TcpStream::connect(bound_addr).await.unwrap();
+        let (code, peer_addr) = read_reply(&mut client).await;
+        assert_eq!(code, 0);
+        assert_eq!(peer_addr, peer.local_addr().unwrap());
+
+        // Data is relayed both ways
+        client.write_all(b"from client").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut received = Vec::new();
+        peer.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"from client");
+
+        peer.write_all(b"from peer").await.unwrap();
+        peer.shutdown().await.unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"from peer");
+
+        proxy_task.await.unwrap().expect("SOCKS5 BIND failed");
+        assert_eq!(server.bytes_relayed(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_bind_command_disabled_by_default() {
+        let server = Socks5Server::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let (mut client, proxy_task) = start_connection(server).await;
+
+        client.write_all(&[5, 1, 0]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        send_command(&mut client, 2, "0.0.0.0:0".parse().unwrap()).await;
+
+        // Reply 7: command not supported
+        assert_eq!(read_reply(&mut client).await.0, 7);
+        assert!(proxy_task.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_allowed_destination_connects() {
+        let dest_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dest_addr = dest_listener.local_addr().unwrap();
+
+        let rules = DestinationRules::new(&["127.0.0.0/8".to_string()], &[]).unwrap();
+        let server = Socks5Server::new("127.0.0.1:0".parse().unwrap())
+            .unwrap()
+            .with_destination_rules(rules);
+        let (mut client, _proxy_task) = start_connection(server).await;
+
+        assert_eq!(connect_request(&mut client, dest_addr).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_denied_network_is_refused() {
+        let dest_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dest_addr = dest_listener.local_addr().unwrap();
+
+        // Deny wins over a matching allow entry
+        let rules = DestinationRules::new(
+            &["127.0.0.0/8".to_string()],
+            &["127.0.0.0/24".to_string()],
+        )
+        .unwrap();
+        let server = Socks5Server::new("127.0.0.1:0".parse().unwrap())
+            .unwrap()
+            .with_destination_rules(rules);
+        let (mut client, proxy_task) = start_connection(server).await;
+
+        assert_eq!(connect_request(&mut client, dest_addr).await, 2);
+        assert!(proxy_task.await.unwrap().is_err());
+
+        // The connection is closed after the reply
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_denied_domain_is_refused_before_resolution() {
+        // The name does not resolve, so only a pre-resolution check can reply 2
+        let rules = DestinationRules::new(&[], &["*.blocked.invalid".to_string()]).unwrap();
+        let server = Socks5Server::new("127.0.0.1:0".parse().unwrap())
+            .unwrap()
+            .with_destination_rules(rules);
+        let (mut client, proxy_task) = start_connection(server).await;
+
+        client.write_all(&[5, 1, 0]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+
+        let domain = b"tracker.blocked.invalid";
+        let mut request = vec![5, 1, 0, 3, domain.len() as u8];
+        request.extend_from_slice(domain);
+        request.extend_from_slice(&443u16.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+
+        assert_eq!(read_reply(&mut client).await.0, 2);
+        let err = proxy_task.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("not allowed"));
+    }
+
+    #[tokio::test]
+    async fn test_shared_resolver_answers_domain_targets() {
+        let dest_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dest_port = dest_listener.local_addr().unwrap().port();
+
+        // Only the shared resolver knows this name
+        let resolver = DnsResolver::builder()
+            .static_hosts(std::collections::HashMap::from([(
+                "pinned.test".to_string(),
+                vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            )]))
+            .build()
+            .unwrap();
+        let server = Socks5Server::new_with_resolver("127.0.0.1:0".parse().unwrap(), Arc::new(resolver));
+        let (mut client, _proxy_task) = start_connection(server).await;
+
+        client.write_all(&[5, 1, 0]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+
+        let domain = b"pinned.test";
+        let mut request = vec![5, 1, 0, 3, domain.len() as u8];
+        request.extend_from_slice(domain);
+        request.extend_from_slice(&dest_port.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+
+        assert_eq!(read_reply(&mut client).await.0, 0);
+        timeout(Duration::from_secs(5), dest_listener.accept())
+            .await
+            .expect("Pinned address was never connected to")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shared_resolver_probes_the_target_port() {
+        let dest_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dest_port = dest_listener.local_addr().unwrap().port();
+
+        // Nothing listens on 127.0.0.2, and the configured probe port is closed on both
+        let closed_port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let resolver = DnsResolver::builder()
+            .static_hosts(std::collections::HashMap::from([(
+                "pinned.test".to_string(),
+                vec!["127.0.0.2".parse().unwrap(), IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            )]))
+            .address_sort(AddressSort::Probe(closed_port))
+            .build()
+            .unwrap();
+        let server = Socks5Server::new_with_resolver("127.0.0.1:0".parse().unwrap(), Arc::new(resolver));
+        let (mut client, _proxy_task) = start_connection(server).await;
+
+        client.write_all(&[5, 1, 0]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+
+        let domain = b"pinned.test";
+        let mut request = vec![5, 1, 0, 3, domain.len() as u8];
+        request.extend_from_slice(domain);
+        request.extend_from_slice(&dest_port.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+
+        assert_eq!(read_reply(&mut client).await.0, 0);
+        timeout(Duration::from_secs(5), dest_listener.accept())
+            .await
+            .expect("Reachable address was never connected to")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resolution_failures_get_a_reply() {
+        // An upstream that never answers, so unblocked names time out
+        let silent_upstream = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = DnsResolver::builder()
+            .servers(vec![silent_upstream.local_addr().unwrap()])
+            .options(DnsResolverOptions { use_hosts_file: false, attempts: 0, ..Default::default() })
+            .timeout(Duration::from_millis(200))
+            .max_retries(0)
+            .blocklist(vec!["*.blocked.test".to_string()])
+            .build()
+            .unwrap();
+        let resolver = Arc::new(resolver);
+
+        // Blocked by the resolver: not allowed; unresolvable: host unreachable
+        for (domain, expected_reply) in [("ads.blocked.test", 2), ("missing.test", 4)] {
+            let server = Socks5Server::new_with_resolver("127.0.0.1:0".parse().unwrap(), resolver.clone());
+            let (mut client, proxy_task) = start_connection(server).await;
+
+            client.write_all(&[5, 1, 0]).await.unwrap();
+            let mut method = [0u8; 2];
+            client.read_exact(&mut method).await.unwrap();
+
+            let mut request = vec![5, 1, 0, 3, domain.len() as u8];
+            request.extend_from_slice(domain.as_bytes());
+            request.extend_from_slice(&443u16.to_be_bytes());
+            client.write_all(&request).await.unwrap();
+
+            assert_eq!(read_reply(&mut client).await.0, expected_reply, "{}", domain);
+            let err = proxy_task.await.unwrap().unwrap_err();
+            assert!(err.to_string().contains(domain), "{}", err);
+        }
+    }
+
+    /// Connects a client to `server` through a loopback listener, returning the
+    /// client stream and the task running the server side of the connection
+    async fn start_connection(
+        server: Socks5Server,
+    ) -> (TcpStream, tokio::task::JoinHandle<Result<()>>) {
+        let proxy_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy_listener.local_addr().unwrap();
+        let proxy_task = tokio::spawn(async move {
+            let (stream, _) = proxy_listener.accept().await.unwrap();
+            server.handle_connection(stream).await
+        });
+        (TcpStream::connect(proxy_addr).await.unwrap(), proxy_task)
+    }
+
+    /// Sends a CONNECT for the IPv4 `dest_addr` and returns the reply code
+    async fn connect_request(client: &mut TcpStream, dest_addr: SocketAddr) -> u8 {
+        client.write_all(&[5, 1, 0]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        send_command(client, 1, dest_addr).await;
+        read_reply(client).await.0
+    }
+
+    /// Sends command `cmd` for the IPv4 `dest_addr`
+    async fn send_command(client: &mut TcpStream, cmd: u8, dest_addr: SocketAddr) {
+        let mut request = vec![5, cmd, 0, 1];
+        if let IpAddr::V4(ip) = dest_addr.ip() {
+            request.extend_from_slice(&ip.octets());
+        }
+        request.extend_from_slice(&dest_addr.port().to_be_bytes());
+        client.write_all(&request).await.unwrap();
+    }
+
+    /// Reads an IPv4 reply, returning the reply code and address
+    async fn read_reply(client: &mut TcpStream) -> (u8, SocketAddr) {
+        let mut reply = [0u8; 10];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[3], 1, "expected an IPv4 reply");
+        let ip = Ipv4Addr::new(reply[4], reply[5], reply[6], reply[7]);
+        let port = u16::from_be_bytes([reply[8], reply[9]]);
+        (reply[1], SocketAddr::new(IpAddr::V4(ip), port))
+    }
 }
@@ -0,0 +1,342 @@
+use anyhow::Result;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::socks5::DEFAULT_CONNECT_TIMEOUT;
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_VERSION: u8 = 0x01;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NO_ACCEPTABLE: u8 = 0xFF;
+
+const CMD_CONNECT: u8 = 0x01;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Largest HTTP CONNECT response head accepted from an upstream proxy
+const MAX_HTTP_RESPONSE_HEAD: usize = 8192;
+
+/// Protocol spoken by an upstream proxy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamProxyKind {
+    #[default]
+    Socks5,
+    /// HTTP proxy supporting the CONNECT method
+    Http,
+}
+
+/// A proxy that outbound connections are chained through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamProxy {
+    pub kind: UpstreamProxyKind,
+    pub addr: SocketAddr,
+    pub auth: Option<(String, String)>,
+}
+
+impl UpstreamProxy {
+    /// Opens a tunnel to `target` through this proxy
+    pub async fn connect(&self, target: SocketAddr) -> Result<TcpStream> {
+        match self.kind {
+            UpstreamProxyKind::Socks5 => Socks5Client::connect(self.addr, target, self.auth.clone()).await,
+            UpstreamProxyKind::Http => {
+                let mut stream = TcpStream::connect(self.addr)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to reach HTTP proxy {}: {}", self.addr, e))?;
+                http_connect(&mut stream, target, self.auth.as_ref()).await?;
+                Ok(stream)
+            }
+        }
+    }
+}
+
+/// Async client side of SOCKS5 (RFC 1928) with username/password auth
+/// (RFC 1929), for chaining through an upstream proxy such as an SSH tunnel
+pub struct Socks5Client;
+
+impl Socks5Client {
+    /// Opens a tunnel to `target` through the SOCKS5 proxy at `proxy`. The
+    /// returned stream carries the target's traffic once the proxy has
+    /// accepted the CONNECT.
+    pub async fn connect(
+        proxy: SocketAddr,
+        target: SocketAddr,
+        auth: Option<(String, String)>,
+    ) -> Result<TcpStream> {
+        timeout(DEFAULT_CONNECT_TIMEOUT, async {
+            let mut stream = TcpStream::connect(proxy)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to reach SOCKS5 proxy {}: {}", proxy, e))?;
+            handshake(&mut stream, auth.as_ref()).await?;
+            request_connect(&mut stream, target).await?;
+            Ok(stream)
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("SOCKS5 proxy {} did not answer within {:?}", proxy, DEFAULT_CONNECT_TIMEOUT))?
+    }
+}
+
+/// Sends an HTTP CONNECT for `target` and reads the response head byte by
+/// byte, so no tunnelled data is consumed
+async fn http_connect<S>(stream: &mut S, target: SocketAddr, auth: Option<&(String, String)>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some((username, password)) = auth {
+        let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() > MAX_HTTP_RESPONSE_HEAD {
+            anyhow::bail!("HTTP proxy response header too large");
+        }
+        head.push(stream.read_u8().await?);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let status_line = head.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => anyhow::bail!("HTTP proxy refused CONNECT to {}: {}", target, status_line),
+    }
+}
+
+/// Negotiates an authentication method, authenticating if the proxy asks for it
+async fn handshake<S>(stream: &mut S, auth: Option<&(String, String)>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let methods: &[u8] = if auth.is_some() {
+        &[METHOD_USERNAME_PASSWORD, METHOD_NO_AUTH]
+    } else {
+        &[METHOD_NO_AUTH]
+    };
+
+    let mut greeting = vec![SOCKS_VERSION, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    stream.write_all(&greeting).await?;
+
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await?;
+    if response[0] != SOCKS_VERSION {
+        anyhow::bail!("Invalid SOCKS5 version in method selection: {}", response[0]);
+    }
+
+    match (response[1], auth) {
+        (METHOD_NO_AUTH, _) => Ok(()),
+        (METHOD_USERNAME_PASSWORD, Some((username, password))) => {
+            authenticate(stream, username, password).await
+        }
+        (METHOD_NO_ACCEPTABLE, _) => {
+            anyhow::bail!("SOCKS5 proxy accepted none of the offered authentication methods")
+        }
+        (method, _) => anyhow::bail!("SOCKS5 proxy selected unsupported authentication method {:#04x}", method),
+    }
+}
+
+/// Username/password sub-negotiation, run after the proxy selects method 0x02
+async fn authenticate<S>(stream: &mut S, username: &str, password: &str) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if username.len() > u8::MAX as usize || password.len() > u8::MAX as usize {
+        anyhow::bail!("SOCKS5 username and password must be at most 255 bytes");
+    }
+
+    let mut request = vec![AUTH_VERSION, username.len() as u8];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password.len() as u8);
+    request.extend_from_slice(password.as_bytes());
+    stream.write_all(&request).await?;
+
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await?;
+    if response[0] != AUTH_VERSION || response[1] != 0x00 {
+        anyhow::bail!("SOCKS5 authentication failed");
+    }
+    Ok(())
+}
+
+/// Sends a CONNECT for `target` and consumes the proxy's reply, returning the
+/// address the proxy bound, or `None` if it answered with a domain name
+async fn request_connect<S>(stream: &mut S, target: SocketAddr) -> Result<Option<SocketAddr>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    match target.ip() {
+        IpAddr::V4(ip) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    // VER, REP, RSV, ATYP
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        anyhow::bail!("Invalid SOCKS5 version in reply: {}", reply[0]);
+    }
+    if reply[1] != 0x00 {
+        anyhow::bail!("SOCKS5 proxy refused CONNECT to {} (reply code {:#04x})", target, reply[1]);
+    }
+
+    let addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        atyp => anyhow::bail!("Invalid address type in SOCKS5 reply: {}", atyp),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    let port = u16::from_be_bytes([bound[addr_len], bound[addr_len + 1]]);
+    let ip = match reply[3] {
+        ATYP_IPV4 => IpAddr::V4(Ipv4Addr::new(bound[0], bound[1], bound[2], bound[3])),
+        ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&bound[..16]);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(SocketAddr::new(ip, port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socks5::Socks5Server;
+    use tokio::net::TcpListener;
+
+    /// Loopback server that echoes everything back on each connection
+    async fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_connect_through_socks5_server_to_echo() {
+        let echo_addr = echo_server().await;
+
+        let proxy_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy_listener.local_addr().unwrap();
+        let server = Socks5Server::new(proxy_addr).unwrap();
+        tokio::spawn(server.serve(proxy_listener));
+
+        let mut stream = Socks5Client::connect(proxy_addr, echo_addr, None)
+            .await
+            .expect("Failed to connect through SOCKS5");
+        stream.write_all(b"ping through socks").await.unwrap();
+        let mut echoed = [0u8; 18];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"ping through socks");
+    }
+
+    #[tokio::test]
+    async fn test_connect_authenticates_when_asked() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let target: SocketAddr = "10.0.0.1:443".parse().unwrap();
+
+        let server_task = tokio::spawn(async move {
+            let mut greeting = [0u8; 4];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 2, METHOD_USERNAME_PASSWORD, METHOD_NO_AUTH]);
+            server.write_all(&[5, METHOD_USERNAME_PASSWORD]).await.unwrap();
+
+            let mut auth = [0u8; 11];
+            server.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x04user\x04pass");
+            server.write_all(&[AUTH_VERSION, 0]).await.unwrap();
+
+            let mut request = [0u8; 10];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, [5, CMD_CONNECT, 0, ATYP_IPV4, 10, 0, 0, 1, 1, 187]);
+            server.write_all(&[5, 0, 0, ATYP_IPV4, 127, 0, 0, 1, 0, 80]).await.unwrap();
+        });
+
+        let auth = ("user".to_string(), "pass".to_string());
+        handshake(&mut client, Some(&auth)).await.expect("Handshake failed");
+        let bound = request_connect(&mut client, target).await.expect("CONNECT failed");
+        server_task.await.unwrap();
+
+        assert_eq!(bound, Some("127.0.0.1:80".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_http_connect_sends_credentials() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let server_task = tokio::spawn(async move {
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(server.read_u8().await.unwrap());
+            }
+            server.write_all(b"HTTP/1.1 200 Connection established\r\n\r\ntunnelled").await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let auth = ("user".to_string(), "pass".to_string());
+        http_connect(&mut client, "10.0.0.1:443".parse().unwrap(), Some(&auth))
+            .await
+            .expect("CONNECT failed");
+        let request = server_task.await.unwrap();
+        assert!(request.starts_with("CONNECT 10.0.0.1:443 HTTP/1.1\r\n"), "{}", request);
+        assert!(request.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"), "{}", request);
+
+        // Data after the response head is left for the tunnel
+        let mut tunnelled = [0u8; 9];
+        client.read_exact(&mut tunnelled).await.unwrap();
+        assert_eq!(&tunnelled, b"tunnelled");
+    }
+
+    #[tokio::test]
+    async fn test_connect_reports_refusal() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            server.write_all(&[5, METHOD_NO_AUTH]).await.unwrap();
+            let mut request = [0u8; 10];
+            server.read_exact(&mut request).await.unwrap();
+            // Reply code 2: connection not allowed by ruleset
+            server.write_all(&[5, 2, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await.unwrap();
+        });
+
+        handshake(&mut client, None).await.expect("Handshake failed");
+        let err = request_connect(&mut client, "10.0.0.1:443".parse().unwrap())
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("0x02"), "{}", err);
+    }
+}
@@ -1,32 +1,161 @@
 use anyhow::Result;
+use async_trait::async_trait;
+use russh::client;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
 use tokio::process::Command as TokioCommand;
-use tokio::sync::Mutex;
-use tracing::{error, info};
+use tokio::sync::{watch, Mutex};
+use tracing::{debug, error, info, warn};
 
 use crate::config::SshTunnelConfig;
+use crate::socks5;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const MONITOR_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a spawned ssh process gets to start accepting SOCKS5 connections
+const TUNNEL_READY_TIMEOUT: Duration = Duration::from_secs(10);
+const READY_PROBE_INTERVAL: Duration = Duration::from_millis(250);
 
 pub struct SshTunnelManager {
     config: SshTunnelConfig,
     process: Arc<Mutex<Option<tokio::process::Child>>>,
     is_connected: Arc<Mutex<bool>>,
+    shutdown: watch::Sender<bool>,
 }
 
 impl SshTunnelManager {
     pub fn new(config: SshTunnelConfig) -> Self {
+        let (shutdown, _) = watch::channel(false);
         Self {
             config,
             process: Arc::new(Mutex::new(None)),
             is_connected: Arc::new(Mutex::new(false)),
+            shutdown,
         }
     }
 
     pub async fn start(&self) -> Result<()> {
         info!("Starting SSH tunnel to {}:{}", self.config.host, self.config.port);
-        
-        // Build SSH command for dynamic port forwarding
+
+        let mut shutdown_rx = self.shutdown.subscribe();
+        let mut retries: u32 = 0;
+        let mut backoff = INITIAL_BACKOFF;
+
+        loop {
+            if *shutdown_rx.borrow() {
+                break;
+            }
+
+            let outcome = match self.mode() {
+                TunnelMode::Shell => self.run_shell(&mut shutdown_rx).await,
+                TunnelMode::Native => self.run_native(&mut shutdown_rx).await,
+            };
+
+            match outcome {
+                Ok(TunnelExit::Shutdown) => break,
+                Ok(TunnelExit::Dropped { established }) => {
+                    if established {
+                        // The tunnel was healthy before it dropped, start counting afresh
+                        retries = 0;
+                        backoff = INITIAL_BACKOFF;
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to start SSH tunnel: {}", e);
+                }
+            }
+
+            self.set_connected(false).await;
+
+            if let Some(max_retries) = self.config.max_retries {
+                if retries >= max_retries {
+                    error!("SSH tunnel giving up after {} retries", retries);
+                    return Err(anyhow::anyhow!(
+                        "SSH tunnel to {}:{} failed after {} retries",
+                        self.config.host,
+                        self.config.port,
+                        retries
+                    ));
+                }
+            }
+
+            retries += 1;
+            info!("Reconnecting SSH tunnel in {:?} (attempt {})", backoff, retries);
+
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = shutdown_rx.changed() => break,
+            }
+
+            backoff = next_backoff(backoff);
+        }
+
+        info!("SSH tunnel monitor stopped");
+        Ok(())
+    }
+
+    /// Port of the local SOCKS5 proxy the tunnel serves
+    pub fn local_port(&self) -> u16 {
+        self.config.local_port
+    }
+
+    /// SSH server the tunnel connects to, as `host:port`
+    pub fn remote(&self) -> String {
+        format!("{}:{}", self.config.host, self.config.port)
+    }
+
+    /// Which implementation `start` drives, selected by `SshTunnelConfig::native`.
+    pub fn mode(&self) -> TunnelMode {
+        if self.config.native {
+            TunnelMode::Native
+        } else {
+            TunnelMode::Shell
+        }
+    }
+
+    /// Spawns the ssh process and watches it until it exits or a stop is requested.
+    async fn run_shell(&self, shutdown_rx: &mut watch::Receiver<bool>) -> Result<TunnelExit> {
+        self.spawn_tunnel().await?;
+
+        let mut established = false;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(MONITOR_INTERVAL) => {}
+                _ = shutdown_rx.changed() => return Ok(TunnelExit::Shutdown),
+            }
+
+            let mut process_guard = self.process.lock().await;
+            match process_guard.as_mut().map(|process| process.try_wait()) {
+                Some(Ok(None)) => {
+                    established = true;
+                }
+                Some(Ok(Some(status))) => {
+                    warn!("SSH tunnel process exited with status: {:?}", status);
+                    process_guard.take();
+                    return Ok(TunnelExit::Dropped { established });
+                }
+                Some(Err(e)) => {
+                    error!("Failed to poll SSH tunnel process: {}", e);
+                    process_guard.take();
+                    return Ok(TunnelExit::Dropped { established });
+                }
+                None => {
+                    error!("SSH tunnel process not found");
+                    return Ok(TunnelExit::Dropped { established });
+                }
+            }
+        }
+    }
+
+    /// Spawns the ssh process and waits for the tunnel to come up, killing the
+    /// process if it does not.
+    async fn spawn_tunnel(&self) -> Result<()> {
         let ssh_cmd = format!(
-            "ssh -D {} -N -f {}@{} -p {}",
+            "ssh -D {} -N {}@{} -p {}",
             self.config.local_port,
             self.config.username,
             self.config.host,
@@ -35,86 +164,219 @@ impl SshTunnelManager {
 
         info!("Executing SSH command: {}", ssh_cmd);
 
-        // Start SSH tunnel process
-        let mut cmd = TokioCommand::new("ssh");
-        cmd.args(&[
-            "-D", &self.config.local_port.to_string(),
-            "-N", "-f",
-            &format!("{}@{}", self.config.username, self.config.host),
-            "-p", &self.config.port.to_string(),
-        ]);
-
-        // Add authentication
-        if let Some(password) = &self.config.password {
-            // Use sshpass for password authentication
-            let mut sshpass_cmd = TokioCommand::new("sshpass");
-            sshpass_cmd.args(&["-p", password]);
-            sshpass_cmd.arg("ssh");
-            sshpass_cmd.args(&[
-                "-D", &self.config.local_port.to_string(),
-                "-N", "-f",
-                &format!("{}@{}", self.config.username, self.config.host),
-                "-p", &self.config.port.to_string(),
-            ]);
-            
-            let child = sshpass_cmd.spawn()?;
-            {
-                let mut process_guard = self.process.lock().await;
-                *process_guard = Some(child);
+        let child = self.build_command().spawn()?;
+        {
+            let mut process_guard = self.process.lock().await;
+            *process_guard = Some(child);
+        }
+
+        if let Err(e) = self.wait_until_listening().await {
+            if let Some(mut process) = self.process.lock().await.take() {
+                let _ = process.kill().await;
             }
-        } else if let Some(key_path) = &self.config.key_path {
-            cmd.arg("-i").arg(key_path);
-            let child = cmd.spawn()?;
+            return Err(e);
+        }
+
+        self.set_connected(true).await;
+
+        info!("SSH tunnel established on port {}", self.config.local_port);
+        info!("SOCKS5 proxy available at 127.0.0.1:{}", self.config.local_port);
+
+        Ok(())
+    }
+
+    /// Probes `local_port` until it answers a SOCKS5 handshake. Fails as soon as
+    /// the ssh process exits, or after `TUNNEL_READY_TIMEOUT`.
+    async fn wait_until_listening(&self) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + TUNNEL_READY_TIMEOUT;
+        loop {
             {
                 let mut process_guard = self.process.lock().await;
-                *process_guard = Some(child);
+                match process_guard.as_mut().map(|process| process.try_wait()) {
+                    Some(Ok(None)) => {}
+                    Some(Ok(Some(status))) => {
+                        return Err(anyhow::anyhow!(
+                            "SSH process exited with {} before the tunnel came up",
+                            status
+                        ));
+                    }
+                    Some(Err(e)) => return Err(e.into()),
+                    None => return Err(anyhow::anyhow!("SSH tunnel process not found")),
+                }
             }
-        } else {
-            // Try without authentication (key-based)
-            let child = cmd.spawn()?;
-            {
-                let mut process_guard = self.process.lock().await;
-                *process_guard = Some(child);
+
+            match probe_socks5(self.config.local_port).await {
+                Ok(()) => return Ok(()),
+                Err(e) if tokio::time::Instant::now() >= deadline => {
+                    return Err(anyhow::anyhow!(
+                        "SSH tunnel did not start listening on 127.0.0.1:{} within {:?}: {}",
+                        self.config.local_port,
+                        TUNNEL_READY_TIMEOUT,
+                        e
+                    ));
+                }
+                Err(e) => debug!("SSH tunnel not ready yet: {}", e),
             }
+
+            tokio::time::sleep(READY_PROBE_INTERVAL).await;
         }
+    }
 
-        // Wait a moment for SSH to establish
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    fn build_command(&self) -> TokioCommand {
+        // ssh is kept in the foreground (no -f) so the child can be supervised
+        // and re-spawned when it exits
+        let mut ssh_args = vec![
+            "-D".to_string(),
+            self.config.local_port.to_string(),
+            "-N".to_string(),
+            format!("{}@{}", self.config.username, self.config.host),
+            "-p".to_string(),
+            self.config.port.to_string(),
+        ];
 
-        // Mark as connected
-        {
-            let mut connected_guard = self.is_connected.lock().await;
-            *connected_guard = true;
+        if let Some(key_path) = &self.config.key_path {
+            ssh_args.push("-i".to_string());
+            ssh_args.push(key_path.clone());
         }
 
-        info!("SSH tunnel established on port {}", self.config.local_port);
+        // Interactive host key prompts would block the tunnel forever, so an
+        // explicit `false` disables verification and the known_hosts write.
+        match self.config.strict_host_key_checking {
+            Some(false) => {
+                ssh_args.extend([
+                    "-o".to_string(),
+                    "StrictHostKeyChecking=no".to_string(),
+                    "-o".to_string(),
+                    "UserKnownHostsFile=/dev/null".to_string(),
+                ]);
+            }
+            Some(true) => {
+                ssh_args.extend(["-o".to_string(), "StrictHostKeyChecking=yes".to_string()]);
+            }
+            None => {}
+        }
+
+        // Secrets are answered by sshpass, which watches the child's tty for a
+        // prompt: "assword" by default, or "passphrase" (via -P) for the
+        // "Enter passphrase for key" prompt of an encrypted private key.
+        // This avoids running and tearing down a dedicated ssh-agent.
+        let sshpass = match (&self.config.key_passphrase, &self.config.password) {
+            (Some(passphrase), _) if self.config.key_path.is_some() => {
+                Some(vec!["-P", "passphrase", "-p", passphrase.as_str()])
+            }
+            (_, Some(password)) => Some(vec!["-p", password.as_str()]),
+            _ => None,
+        };
+
+        match sshpass {
+            Some(sshpass_args) => {
+                let mut cmd = TokioCommand::new("sshpass");
+                cmd.args(sshpass_args);
+                cmd.arg("ssh");
+                cmd.args(&ssh_args);
+                cmd
+            }
+            None => {
+                let mut cmd = TokioCommand::new("ssh");
+                cmd.args(&ssh_args);
+                cmd
+            }
+        }
+    }
+
+    /// Opens an SSH session with russh and serves a local SOCKS5 listener on
+    /// `local_port`, forwarding each accepted connection over a direct-tcpip
+    /// channel. No external binaries are needed, so this also works on Windows.
+    async fn run_native(&self, shutdown_rx: &mut watch::Receiver<bool>) -> Result<TunnelExit> {
+        let session = Arc::new(self.connect_native().await?);
+        let listener = TcpListener::bind(("127.0.0.1", self.config.local_port)).await?;
+
+        self.set_connected(true).await;
+        info!("Native SSH tunnel established to {}:{}", self.config.host, self.config.port);
         info!("SOCKS5 proxy available at 127.0.0.1:{}", self.config.local_port);
 
-        // Keep the tunnel running
         loop {
-            tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
-            
-            // Check if process is still running
-            let mut process_guard = self.process.lock().await;
-            if let Some(process) = process_guard.as_mut() {
-                if let Ok(Some(status)) = process.try_wait() {
-                    if !status.success() {
-                        error!("SSH tunnel process exited with status: {:?}", status);
-                        break;
+            tokio::select! {
+                _ = shutdown_rx.changed() => {
+                    let _ = session
+                        .disconnect(russh::Disconnect::ByApplication, "", "en")
+                        .await;
+                    return Ok(TunnelExit::Shutdown);
+                }
+                _ = tokio::time::sleep(MONITOR_INTERVAL) => {
+                    if session.is_closed() {
+                        warn!("Native SSH session to {} closed", self.config.host);
+                        return Ok(TunnelExit::Dropped { established: true });
                     }
                 }
-            } else {
-                error!("SSH tunnel process not found");
-                break;
+                accepted = listener.accept() => {
+                    let (stream, peer_addr) = accepted?;
+                    let session = session.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = forward_native_connection(stream, peer_addr, session).await {
+                            debug!("Native SSH tunnel connection error: {}", e);
+                        }
+                    });
+                }
             }
         }
+    }
 
-        Ok(())
+    async fn connect_native(&self) -> Result<client::Handle<NativeClientHandler>> {
+        let ssh_config = Arc::new(client::Config {
+            keepalive_interval: Some(Duration::from_secs(30)),
+            ..Default::default()
+        });
+        let handler = NativeClientHandler {
+            host: self.config.host.clone(),
+            port: self.config.port,
+            verify_host_key: self.config.strict_host_key_checking != Some(false),
+        };
+
+        let mut session = client::connect(
+            ssh_config,
+            (self.config.host.as_str(), self.config.port),
+            handler,
+        )
+        .await?;
+
+        let authenticated = if let Some(key_path) = &self.config.key_path {
+            let key = russh::keys::load_secret_key(key_path, self.config.key_passphrase.as_deref())?;
+            session
+                .authenticate_publickey(self.config.username.as_str(), Arc::new(key))
+                .await?
+        } else if let Some(password) = &self.config.password {
+            session
+                .authenticate_password(self.config.username.as_str(), password.as_str())
+                .await?
+        } else {
+            return Err(anyhow::anyhow!(
+                "Native SSH tunnel requires either a password or a key_path"
+            ));
+        };
+
+        if !authenticated {
+            return Err(anyhow::anyhow!(
+                "SSH authentication failed for {}@{}",
+                self.config.username,
+                self.config.host
+            ));
+        }
+
+        Ok(session)
+    }
+
+    async fn set_connected(&self, connected: bool) {
+        let mut connected_guard = self.is_connected.lock().await;
+        *connected_guard = connected;
     }
 
     pub async fn stop(&self) -> Result<()> {
         info!("Stopping SSH tunnel");
-        
+
+        // Break the reconnection loop before killing the child so it is not re-spawned
+        self.shutdown.send_replace(true);
+
         {
             let mut process_guard = self.process.lock().await;
             if let Some(mut process) = process_guard.take() {
@@ -122,10 +384,7 @@ impl SshTunnelManager {
             }
         }
 
-        {
-            let mut connected_guard = self.is_connected.lock().await;
-            *connected_guard = false;
-        }
+        self.set_connected(false).await;
 
         info!("SSH tunnel stopped");
         Ok(())
@@ -137,23 +396,270 @@ impl SshTunnelManager {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelMode {
+    /// Spawn the system `ssh` binary with `-D` dynamic forwarding
+    Shell,
+    /// Use the in-process russh client and a local SOCKS5 listener
+    Native,
+}
+
+enum TunnelExit {
+    Shutdown,
+    Dropped { established: bool },
+}
+
+struct NativeClientHandler {
+    host: String,
+    port: u16,
+    verify_host_key: bool,
+}
+
+#[async_trait]
+impl client::Handler for NativeClientHandler {
+    type Error = anyhow::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &russh::keys::key::PublicKey,
+    ) -> Result<bool, Self::Error> {
+        if !self.verify_host_key {
+            return Ok(true);
+        }
+
+        match russh::keys::check_known_hosts(&self.host, self.port, server_public_key) {
+            Ok(true) => Ok(true),
+            Ok(false) => {
+                error!("Host key for {} is not in known_hosts", self.host);
+                Ok(false)
+            }
+            Err(e) => {
+                error!("Host key verification failed for {}: {}", self.host, e);
+                Ok(false)
+            }
+        }
+    }
+}
+
+async fn forward_native_connection(
+    mut stream: TcpStream,
+    peer_addr: SocketAddr,
+    session: Arc<client::Handle<NativeClientHandler>>,
+) -> Result<()> {
+    let target = socks5::negotiate(&mut stream).await?;
+    debug!("Native SSH tunnel request to {}:{}", target.host(), target.port());
+
+    let unspecified = SocketAddr::from(([0, 0, 0, 0], 0));
+    let channel = match session
+        .channel_open_direct_tcpip(
+            target.host(),
+            target.port() as u32,
+            peer_addr.ip().to_string(),
+            peer_addr.port() as u32,
+        )
+        .await
+    {
+        Ok(channel) => channel,
+        Err(e) => {
+            socks5::send_reply(&mut stream, 4, unspecified).await?;
+            return Err(anyhow::anyhow!(
+                "Failed to open channel to {}:{}: {}",
+                target.host(),
+                target.port(),
+                e
+            ));
+        }
+    };
+
+    socks5::send_reply(&mut stream, 0, unspecified).await?;
+
+    let mut channel_stream = channel.into_stream();
+    tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await?;
+
+    Ok(())
+}
+
+/// Connects to the local SOCKS5 port and checks it accepts a no-auth greeting
+async fn probe_socks5(port: u16) -> Result<()> {
+    let probe = async {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await?;
+        stream.write_all(&[0x05, 0x01, 0x00]).await?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply != [0x05, 0x00] {
+            return Err(anyhow::anyhow!("Unexpected SOCKS5 greeting reply {:?}", reply));
+        }
+        Ok(())
+    };
+    tokio::time::timeout(READY_PROBE_INTERVAL * 4, probe)
+        .await
+        .map_err(|_| anyhow::anyhow!("SOCKS5 greeting timed out"))?
+}
+
+fn next_backoff(current: Duration) -> Duration {
+    (current * 2).min(MAX_BACKOFF)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::SshTunnelConfig;
 
-    #[test]
-    fn test_ssh_tunnel_manager_creation() {
-        let config = SshTunnelConfig {
+    fn test_config() -> SshTunnelConfig {
+        SshTunnelConfig {
             host: "example.com".to_string(),
             port: 22,
             username: "user".to_string(),
             password: Some("password".to_string()),
             key_path: None,
+            key_passphrase: None,
+            strict_host_key_checking: None,
             local_port: 1080,
+            max_retries: None,
+            native: false,
+        }
+    }
+
+    fn command_line(manager: &SshTunnelManager) -> (String, Vec<String>) {
+        let cmd = manager.build_command();
+        let std_cmd = cmd.as_std();
+        (
+            std_cmd.get_program().to_string_lossy().into_owned(),
+            std_cmd
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_ssh_tunnel_manager_creation() {
+        let manager = SshTunnelManager::new(test_config());
+        assert_eq!(manager.config.host, "example.com");
+    }
+
+    #[test]
+    fn test_mode_selects_native_path() {
+        let manager = SshTunnelManager::new(test_config());
+        assert_eq!(manager.mode(), TunnelMode::Shell);
+
+        let config = SshTunnelConfig {
+            native: true,
+            ..test_config()
         };
-        
         let manager = SshTunnelManager::new(config);
-        assert_eq!(manager.config.host, "example.com");
+        assert_eq!(manager.mode(), TunnelMode::Native);
+    }
+
+    #[test]
+    fn test_password_uses_sshpass() {
+        let (program, args) = command_line(&SshTunnelManager::new(test_config()));
+        assert_eq!(program, "sshpass");
+        assert_eq!(&args[..3], ["-p", "password", "ssh"]);
+    }
+
+    #[test]
+    fn test_key_passphrase_uses_passphrase_prompt() {
+        let config = SshTunnelConfig {
+            password: None,
+            key_path: Some("/home/user/.ssh/id_ed25519".to_string()),
+            key_passphrase: Some("secret".to_string()),
+            ..test_config()
+        };
+        let (program, args) = command_line(&SshTunnelManager::new(config));
+        assert_eq!(program, "sshpass");
+        assert_eq!(&args[..5], ["-P", "passphrase", "-p", "secret", "ssh"]);
+        assert!(args.windows(2).any(|w| w == ["-i", "/home/user/.ssh/id_ed25519"]));
+    }
+
+    #[test]
+    fn test_key_without_passphrase_runs_ssh_directly() {
+        let config = SshTunnelConfig {
+            password: None,
+            key_path: Some("/home/user/.ssh/id_ed25519".to_string()),
+            ..test_config()
+        };
+        let (program, _) = command_line(&SshTunnelManager::new(config));
+        assert_eq!(program, "ssh");
+    }
+
+    #[test]
+    fn test_strict_host_key_checking_options() {
+        let (_, args) = command_line(&SshTunnelManager::new(test_config()));
+        assert!(!args.iter().any(|arg| arg.starts_with("StrictHostKeyChecking")));
+
+        let config = SshTunnelConfig {
+            strict_host_key_checking: Some(false),
+            ..test_config()
+        };
+        let (_, args) = command_line(&SshTunnelManager::new(config));
+        assert!(args.windows(2).any(|w| w == ["-o", "StrictHostKeyChecking=no"]));
+        assert!(args.windows(2).any(|w| w == ["-o", "UserKnownHostsFile=/dev/null"]));
+
+        let config = SshTunnelConfig {
+            strict_host_key_checking: Some(true),
+            ..test_config()
+        };
+        let (_, args) = command_line(&SshTunnelManager::new(config));
+        assert!(args.windows(2).any(|w| w == ["-o", "StrictHostKeyChecking=yes"]));
+    }
+
+    #[test]
+    fn test_next_backoff_is_capped() {
+        assert_eq!(next_backoff(Duration::from_secs(1)), Duration::from_secs(2));
+        assert_eq!(next_backoff(Duration::from_secs(16)), Duration::from_secs(30));
+        assert_eq!(next_backoff(MAX_BACKOFF), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn test_stop_breaks_reconnection_loop() {
+        let manager = SshTunnelManager::new(test_config());
+        manager.stop().await.expect("Failed to stop tunnel");
+
+        let result = tokio::time::timeout(Duration::from_secs(1), manager.start()).await;
+        assert!(result.expect("start() did not return after stop()").is_ok());
+        assert!(!manager.is_connected().await);
+    }
+
+    async fn unused_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn test_probe_requires_socks5_greeting() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            for reply in [[0x05, 0x00], [0x05, 0xff]] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut greeting = [0u8; 3];
+                stream.read_exact(&mut greeting).await.unwrap();
+                stream.write_all(&reply).await.unwrap();
+            }
+        });
+
+        assert!(probe_socks5(port).await.is_ok());
+        assert!(probe_socks5(port).await.is_err());
+        assert!(probe_socks5(unused_port().await).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_start_fails_when_tunnel_never_comes_up() {
+        let config = SshTunnelConfig {
+            host: "127.0.0.1".to_string(),
+            port: unused_port().await,
+            password: None,
+            strict_host_key_checking: Some(false),
+            local_port: unused_port().await,
+            max_retries: Some(0),
+            ..test_config()
+        };
+        let manager = SshTunnelManager::new(config);
+
+        let result = tokio::time::timeout(TUNNEL_READY_TIMEOUT * 2, manager.start())
+            .await
+            .expect("start() did not give up");
+        assert!(result.is_err());
+        assert!(!manager.is_connected().await);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,118 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use warp::Filter;
+
+use crate::dns::{DnsResolver, READINESS_PROBE_TIMEOUT};
+use crate::proxy::ProxyServer;
+use crate::socks5::Socks5Server;
+use crate::ssh_tunnel::SshTunnelManager;
+
+/// Handles to the running parts of the service, read on every status request.
+/// Servers that are not enabled are `None`.
+#[derive(Clone)]
+pub struct Subsystems {
+    pub dns_resolver: Arc<DnsResolver>,
+    /// Domain resolved to decide `DnsStatus::ready`, as for `/ready`
+    pub canary_host: String,
+    pub proxy: Option<ProxyServer>,
+    pub socks5: Option<Socks5Server>,
+    pub ssh_tunnels: Vec<Arc<SshTunnelManager>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    pub dns: DnsStatus,
+    pub proxy: ServerStatus,
+    pub socks5: ServerStatus,
+    pub ssh_tunnels: Vec<SshTunnelStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsStatus {
+    /// Whether the canary host resolved within the readiness timeout
+    pub ready: bool,
+    pub canary: String,
+    /// Why the canary did not resolve
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerStatus {
+    pub enabled: bool,
+    pub running: bool,
+    /// Listening address while running
+    pub bound_addr: Option<String>,
+    pub active_connections: usize,
+}
+
+impl ServerStatus {
+    fn enabled(running: bool, bound_addr: Option<SocketAddr>, active_connections: usize) -> Self {
+        Self {
+            enabled: true,
+            running,
+            bound_addr: bound_addr.map(|addr| addr.to_string()),
+            active_connections,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SshTunnelStatus {
+    pub remote: String,
+    pub local_port: u16,
+    pub connected: bool,
+}
+
+impl Subsystems {
+    /// Probes the resolver and reads the state of every other subsystem
+    pub async fn status(&self) -> ServiceStatus {
+        let probe = self.dns_resolver.probe(&self.canary_host, READINESS_PROBE_TIMEOUT).await;
+        let dns = DnsStatus {
+            ready: probe.is_ok(),
+            canary: self.canary_host.clone(),
+            reason: probe.err(),
+        };
+
+        let proxy = self.proxy.as_ref().map_or_else(ServerStatus::default, |server| {
+            ServerStatus::enabled(server.is_running(), server.local_addr(), server.active_connections())
+        });
+        let socks5 = self.socks5.as_ref().map_or_else(ServerStatus::default, |server| {
+            ServerStatus::enabled(server.is_running(), server.local_addr(), server.active_connections())
+        });
+
+        let mut ssh_tunnels = Vec::with_capacity(self.ssh_tunnels.len());
+        for tunnel in &self.ssh_tunnels {
+            ssh_tunnels.push(SshTunnelStatus {
+                remote: tunnel.remote(),
+                local_port: tunnel.local_port(),
+                connected: tunnel.is_connected().await,
+            });
+        }
+
+        ServiceStatus {
+            dns,
+            proxy,
+            socks5,
+            ssh_tunnels,
+        }
+    }
+}
+
+/// `GET /api/status`: what each subsystem is doing right now
+pub fn route(
+    subsystems: Subsystems,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("api")
+        .and(warp::path("status"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::any().map(move || subsystems.clone()))
+        .and_then(handle_status)
+}
+
+async fn handle_status(subsystems: Subsystems) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&subsystems.status().await))
+}
@@ -1,9 +1,11 @@
 use anyhow::Result;
-use serde_json;
 use std::sync::Arc;
 use warp::Filter;
 
-use wdns_service::{DnsResolver, DnsRequest};
+use wdns_service::config::SshTunnelConfig;
+use wdns_service::socks5::Socks5Server;
+use wdns_service::ssh_tunnel::SshTunnelManager;
+use wdns_service::{dns_stream, status, DnsResolver, DnsRequest, ResolutionEvent};
 
 // Helper function to create test server
 async fn create_test_server() -> Result<impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone> {
@@ -57,10 +59,11 @@ async fn handle_dns_resolve(
 
     // Resolve DNS
     let dns_response = dns_resolver.resolve_hosts(request.hosts).await;
+    let status = dns_response.status_code(request.strict);
 
     Ok(warp::reply::with_status(
         warp::reply::json(&dns_response),
-        warp::http::StatusCode::OK,
+        status,
     ))
 }
 
@@ -245,3 +248,189 @@ async fn test_api_mixed_success_failure() {
     let another_invalid_result = results.iter().find(|r| r["host"] == "another-invalid-host.example").unwrap();
     assert_eq!(another_invalid_result["status"], "error");
 }
+
+#[tokio::test]
+async fn test_api_strict_all_success() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .header("content-type", "application/json")
+        .json(&serde_json::json!({"hosts": ["localhost"], "strict": true}))
+        .reply(&routes)
+        .await;
+
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn test_api_strict_partial_failure() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .header("content-type", "application/json")
+        .json(&serde_json::json!({"hosts": ["localhost", "invalid..host"], "strict": true}))
+        .reply(&routes)
+        .await;
+
+    assert_eq!(response.status(), 207);
+
+    let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+    let json: serde_json::Value = serde_json::from_str(&body).expect("Invalid JSON");
+    assert_eq!(json["total_resolved"], 1);
+    assert_eq!(json["total_errors"], 1);
+}
+
+#[tokio::test]
+async fn test_api_strict_all_failure() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .header("content-type", "application/json")
+        .json(&serde_json::json!({"hosts": ["invalid..host"], "strict": true}))
+        .reply(&routes)
+        .await;
+
+    assert_eq!(response.status(), 502);
+}
+
+#[tokio::test]
+async fn test_api_non_strict_total_failure_is_bad_gateway() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .header("content-type", "application/json")
+        .json(&serde_json::json!({"hosts": ["invalid..host"]}))
+        .reply(&routes)
+        .await;
+
+    assert_eq!(response.status(), 502);
+}
+
+#[tokio::test]
+async fn test_api_stream_receives_resolution_events() {
+    let static_hosts = [("stream.test".to_string(), vec!["10.1.2.3".parse().unwrap()])].into_iter().collect();
+    let dns_resolver = Arc::new(
+        DnsResolver::builder()
+            .static_hosts(static_hosts)
+            .build()
+            .expect("Failed to create resolver"),
+    );
+    let resolver_filter = {
+        let dns_resolver = dns_resolver.clone();
+        warp::any().map(move || dns_resolver.clone())
+    };
+    let dns_resolve = warp::path("api")
+        .and(warp::path("dns"))
+        .and(warp::path("resolve"))
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(resolver_filter)
+        .and_then(handle_dns_resolve);
+    let routes = dns_stream::route(dns_resolver).or(dns_resolve);
+
+    let mut client = warp::test::ws()
+        .path("/api/dns/stream")
+        .handshake(routes.clone())
+        .await
+        .expect("WebSocket handshake failed");
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .header("content-type", "application/json")
+        .json(&serde_json::json!({"hosts": ["stream.test"]}))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let message = tokio::time::timeout(std::time::Duration::from_secs(5), client.recv())
+        .await
+        .expect("No event received")
+        .expect("WebSocket closed");
+    let event: ResolutionEvent = serde_json::from_str(message.to_str().unwrap()).unwrap();
+    assert_eq!(event.host, "stream.test");
+    assert_eq!(event.status, "success");
+    assert_eq!(event.ips, vec!["10.1.2.3"]);
+    assert!(event.duration_ms >= 0.0);
+}
+
+#[tokio::test]
+async fn test_api_status_reports_subsystems() {
+    let static_hosts = [("canary.test".to_string(), vec!["10.1.2.3".parse().unwrap()])].into_iter().collect();
+    let dns_resolver = Arc::new(
+        DnsResolver::builder()
+            .static_hosts(static_hosts)
+            .build()
+            .expect("Failed to create resolver"),
+    );
+
+    let socks5 = Socks5Server::new_with_resolver("127.0.0.1:0".parse().unwrap(), dns_resolver.clone());
+    tokio::spawn(socks5.clone().run());
+    let ssh_tunnel = Arc::new(SshTunnelManager::new(SshTunnelConfig {
+        host: "ssh.example.com".to_string(),
+        port: 22,
+        username: "user".to_string(),
+        password: None,
+        key_path: None,
+        key_passphrase: None,
+        strict_host_key_checking: None,
+        local_port: 1080,
+        max_retries: None,
+        native: true,
+    }));
+
+    let routes = status::route(status::Subsystems {
+        dns_resolver,
+        canary_host: "canary.test".to_string(),
+        proxy: None,
+        socks5: Some(socks5.clone()),
+        ssh_tunnels: vec![ssh_tunnel],
+    });
+
+    // Hold one connection open to the SOCKS5 server once it is listening
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while socks5.local_addr().is_none() && std::time::Instant::now() < deadline {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    let socks5_addr = socks5.local_addr().expect("SOCKS5 server did not start");
+    let _client = tokio::net::TcpStream::connect(socks5_addr).await.unwrap();
+    while socks5.active_connections() == 0 && std::time::Instant::now() < deadline {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let response = warp::test::request()
+        .method("GET")
+        .path("/api/status")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert!(body["dns"]["ready"].is_boolean());
+    assert_eq!(body["dns"]["ready"], true);
+    assert_eq!(body["dns"]["canary"], "canary.test");
+
+    assert_eq!(body["proxy"]["enabled"], false);
+    assert!(body["proxy"]["bound_addr"].is_null());
+
+    assert_eq!(body["socks5"]["enabled"], true);
+    assert_eq!(body["socks5"]["running"], true);
+    assert_eq!(body["socks5"]["bound_addr"], socks5_addr.to_string());
+    assert_eq!(body["socks5"]["active_connections"], 1);
+
+    let tunnels = body["ssh_tunnels"].as_array().unwrap();
+    assert_eq!(tunnels.len(), 1);
+    assert_eq!(tunnels[0]["remote"], "ssh.example.com:22");
+    assert_eq!(tunnels[0]["local_port"], 1080);
+    assert_eq!(tunnels[0]["connected"], false);
+
+    socks5.stop();
+}
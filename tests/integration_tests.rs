@@ -1,13 +1,31 @@
 use anyhow::Result;
-use serde_json;
 use std::sync::Arc;
+use tracing::Instrument;
 use warp::Filter;
 
-use wdns_service::{DnsResolver, DnsRequest};
+use wdns_service::{
+    cors, dns, Config, DnsResolver, DnsRequest, FlushCacheRequest, Metrics, ReverseDnsRequest,
+};
 
 // Helper function to create test server
 async fn create_test_server() -> Result<impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone> {
+    create_test_server_with_config(test_config()).await
+}
+
+// Default config, with a readiness canary that resolves without network access
+fn test_config() -> Config {
+    Config {
+        readiness_canary_host: "localhost".to_string(),
+        ..Config::default()
+    }
+}
+
+// Test server built from `config`, as `run_standalone` would
+async fn create_test_server_with_config(
+    config: Config,
+) -> Result<impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone> {
     let dns_resolver = Arc::new(DnsResolver::new()?);
+    let metrics = dns_resolver.metrics();
     
     // Health check endpoint
     let health = warp::path("health")
@@ -26,23 +44,113 @@ async fn create_test_server() -> Result<impl Filter<Extract = impl warp::Reply,
             "endpoints": ["/health", "/api/dns/resolve"]
         })));
 
+    // Prometheus metrics endpoint
+    let metrics_filter = warp::any().map(move || metrics.clone());
+
+    let metrics_route = warp::path("metrics")
+        .and(warp::get())
+        .and(metrics_filter)
+        .map(|metrics: Arc<Metrics>| {
+            warp::reply::with_header(
+                metrics.render(),
+                "content-type",
+                "text/plain; version=0.0.4",
+            )
+        });
+
     // DNS resolution endpoint
     let dns_resolver_filter = warp::any().map(move || dns_resolver.clone());
+    let max_hosts_per_request = config.max_hosts_per_request;
+    let max_hosts_filter = warp::any().map(move || max_hosts_per_request);
     
+    // Readiness endpoint, which checks that a canary domain resolves
+    let canary_host = config.readiness_canary_host.clone();
+    let ready = warp::path("ready")
+        .and(warp::get())
+        .and(warp::any().map(move || canary_host.clone()))
+        .and(dns_resolver_filter.clone())
+        .and_then(handle_ready);
+
     let dns_resolve = warp::path("api")
         .and(warp::path("dns"))
         .and(warp::path("resolve"))
         .and(warp::post())
+        .and(warp::body::content_length_limit(config.max_request_body_bytes()))
         .and(warp::body::json())
-        .and(dns_resolver_filter)
+        .and(max_hosts_filter)
+        .and(dns_resolver_filter.clone())
         .and_then(handle_dns_resolve);
 
-    let routes = health.or(root).or(dns_resolve);
-    Ok(routes)
+    let dns_resolve_host = warp::path("api")
+        .and(warp::path("dns"))
+        .and(warp::path("resolve"))
+        .and(
+            warp::path::param::<String>()
+                .or(warp::path::end().map(String::new))
+                .unify(),
+        )
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(dns_resolver_filter.clone())
+        .and_then(handle_dns_resolve_host);
+
+    let dns_flush_cache = warp::path("api")
+        .and(warp::path("dns"))
+        .and(warp::path("flush-cache"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(config.max_request_body_bytes()))
+        .and(warp::body::bytes())
+        .and(dns_resolver_filter.clone())
+        .and_then(handle_dns_flush_cache);
+
+    let dns_reverse = warp::path("api")
+        .and(warp::path("dns"))
+        .and(warp::path("reverse"))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(config.max_request_body_bytes()))
+        .and(warp::body::json())
+        .and(max_hosts_filter)
+        .and(dns_resolver_filter)
+        .and_then(handle_dns_reverse);
+
+    let routes = health
+        .or(ready)
+        .or(root)
+        .or(metrics_route)
+        .or(dns_resolve)
+        .or(dns_resolve_host)
+        .or(dns_flush_cache)
+        .or(dns_reverse);
+    Ok(cors::with_cors(routes, &config.cors_allowed_origins))
+}
+
+async fn handle_ready(
+    canary_host: String,
+    dns_resolver: Arc<DnsResolver>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match dns_resolver.probe(&canary_host, dns::READINESS_PROBE_TIMEOUT).await {
+        Ok(()) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "status": "ready",
+                "canary": canary_host
+            })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(reason) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "status": "unavailable",
+                "canary": canary_host,
+                "reason": reason
+            })),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        )),
+    }
 }
 
 async fn handle_dns_resolve(
     request: DnsRequest,
+    max_hosts_per_request: usize,
     dns_resolver: Arc<DnsResolver>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     // Validate request
@@ -55,11 +163,135 @@ async fn handle_dns_resolve(
         ));
     }
 
-    // Resolve DNS
-    let dns_response = dns_resolver.resolve_hosts(request.hosts).await;
+    if let Err(e) = request.check_host_limit(max_hosts_per_request) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": e
+            })),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let family = match request.family_filter() {
+        Ok(family) => family,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "error": e
+                })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+
+    dns_resolver.metrics().record_request();
+
+    // Resolve DNS inside a span so per-host lookup logs carry the request id
+    let request_id = dns::new_request_id();
+    let span = tracing::info_span!("dns_resolve", request_id = %request_id, hosts = request.hosts.len());
+    let mut dns_response = dns_resolver
+        .resolve_hosts_with_family(request.hosts, family)
+        .instrument(span)
+        .await;
+    dns_response.request_id = Some(request_id);
+    let status = dns_response.status_code(request.strict);
 
     Ok(warp::reply::with_status(
         warp::reply::json(&dns_response),
+        status,
+    ))
+}
+
+async fn handle_dns_resolve_host(
+    host: String,
+    dns_resolver: Arc<DnsResolver>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let host = match dns::parse_host_segment(&host) {
+        Ok(host) => host,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "error": e
+                })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+
+    dns_resolver.metrics().record_request();
+
+    let result = dns_resolver.resolve_host(&host).await;
+    let status = result.status_code();
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&result),
+        status,
+    ))
+}
+
+async fn handle_dns_flush_cache(
+    body: bytes::Bytes,
+    dns_resolver: Arc<DnsResolver>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // An empty body flushes everything
+    let request: FlushCacheRequest = if body.is_empty() {
+        FlushCacheRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(request) => request,
+            Err(e) => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({
+                        "error": format!("Invalid request body: {}", e)
+                    })),
+                    warp::http::StatusCode::BAD_REQUEST,
+                ));
+            }
+        }
+    };
+
+    let evicted = match request.hosts {
+        Some(hosts) => dns_resolver.clear_cache_hosts(&hosts),
+        None => dns_resolver.clear_cache(),
+    };
+    tracing::info!("Flushed {} cached DNS entries", evicted);
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({
+            "evicted": evicted
+        })),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+async fn handle_dns_reverse(
+    request: ReverseDnsRequest,
+    max_hosts_per_request: usize,
+    dns_resolver: Arc<DnsResolver>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // Validate request
+    if request.ips.is_empty() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": "No IPs provided"
+            })),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    if let Err(e) = request.check_host_limit(max_hosts_per_request) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": e
+            })),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let response = dns_resolver.reverse_lookup_ips(request.ips).await;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
         warp::http::StatusCode::OK,
     ))
 }
@@ -83,6 +315,57 @@ async fn test_health_endpoint() {
     assert_eq!(json["service"], "wdns");
 }
 
+#[tokio::test]
+async fn test_ready_endpoint() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+    
+    let response = warp::test::request()
+        .method("GET")
+        .path("/ready")
+        .reply(&routes)
+        .await;
+    
+    assert_eq!(response.status(), 200);
+    
+    let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+    let json: serde_json::Value = serde_json::from_str(&body).expect("Invalid JSON");
+    
+    assert_eq!(json["status"], "ready");
+    assert_eq!(json["canary"], "localhost");
+}
+
+#[tokio::test]
+async fn test_ready_endpoint_unavailable() {
+    let routes = create_test_server_with_config(Config {
+        readiness_canary_host: "invalid-host-that-does-not-exist.example".to_string(),
+        ..test_config()
+    })
+    .await
+    .expect("Failed to create test server");
+    
+    let response = warp::test::request()
+        .method("GET")
+        .path("/ready")
+        .reply(&routes)
+        .await;
+    
+    assert_eq!(response.status(), 503);
+    
+    let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+    let json: serde_json::Value = serde_json::from_str(&body).expect("Invalid JSON");
+    
+    assert_eq!(json["status"], "unavailable");
+    assert!(json["reason"].is_string());
+    
+    // Liveness is unaffected by a failing upstream
+    let response = warp::test::request()
+        .method("GET")
+        .path("/health")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), 200);
+}
+
 #[tokio::test]
 async fn test_root_endpoint() {
     let routes = create_test_server().await.expect("Failed to create test server");
@@ -136,6 +419,29 @@ async fn test_dns_resolve_single_host() {
     assert!(!result["ip_addresses"].as_array().unwrap().is_empty());
 }
 
+#[tokio::test]
+async fn test_dns_resolve_returns_request_id() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/dns/resolve")
+            .header("content-type", "application/json")
+            .json(&serde_json::json!({ "hosts": ["localhost"] }))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        let json: serde_json::Value = serde_json::from_slice(response.body()).expect("Invalid JSON");
+        let request_id = json["request_id"].as_str().expect("Missing request_id").to_string();
+        assert!(!request_id.is_empty());
+        ids.push(request_id);
+    }
+    assert_ne!(ids[0], ids[1]);
+}
+
 #[tokio::test]
 async fn test_dns_resolve_multiple_hosts() {
     let routes = create_test_server().await.expect("Failed to create test server");
@@ -179,6 +485,276 @@ async fn test_dns_resolve_multiple_hosts() {
     assert!(invalid_result["error"].is_string());
 }
 
+#[tokio::test]
+async fn test_dns_resolve_get_single_host() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+    
+    let response = warp::test::request()
+        .method("GET")
+        .path("/api/dns/resolve/google.com")
+        .reply(&routes)
+        .await;
+    
+    assert_eq!(response.status(), 200);
+    
+    let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+    let json: serde_json::Value = serde_json::from_str(&body).expect("Invalid JSON");
+    
+    // A single DnsResult, not wrapped in a batch response
+    assert_eq!(json["host"], "google.com");
+    assert_eq!(json["status"], "success");
+    assert!(!json["ip_addresses"].as_array().unwrap().is_empty());
+    assert!(json.get("results").is_none());
+}
+
+#[tokio::test]
+async fn test_dns_resolve_get_decodes_host() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+    
+    let response = warp::test::request()
+        .method("GET")
+        .path("/api/dns/resolve/%3A%3A1")
+        .reply(&routes)
+        .await;
+    
+    assert_eq!(response.status(), 200);
+    
+    let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+    let json: serde_json::Value = serde_json::from_str(&body).expect("Invalid JSON");
+    
+    assert_eq!(json["host"], "::1");
+    assert_eq!(json["status"], "success");
+}
+
+#[tokio::test]
+async fn test_dns_resolve_get_invalid_host() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+    
+    for path in ["/api/dns/resolve/invalid..host", "/api/dns/resolve/%20", "/api/dns/resolve/"] {
+        let response = warp::test::request()
+            .method("GET")
+            .path(path)
+            .reply(&routes)
+            .await;
+        
+        assert_eq!(response.status(), 400, "unexpected status for {}", path);
+        
+        let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+        let json: serde_json::Value = serde_json::from_str(&body).expect("Invalid JSON");
+        assert!(json["error"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn test_dns_resolve_at_host_limit() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+    let max_hosts = Config::default().max_hosts_per_request;
+    
+    // Duplicates are looked up once, so this stays fast
+    let request_body = serde_json::json!({
+        "hosts": vec!["localhost"; max_hosts]
+    });
+    
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .header("content-type", "application/json")
+        .json(&request_body)
+        .reply(&routes)
+        .await;
+    
+    assert_eq!(response.status(), 200);
+    
+    let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+    let json: serde_json::Value = serde_json::from_str(&body).expect("Invalid JSON");
+    assert_eq!(json["results"].as_array().unwrap().len(), max_hosts);
+}
+
+#[tokio::test]
+async fn test_dns_resolve_over_host_limit() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+    let max_hosts = Config::default().max_hosts_per_request;
+    
+    let request_body = serde_json::json!({
+        "hosts": vec!["localhost"; max_hosts + 1]
+    });
+    
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .header("content-type", "application/json")
+        .json(&request_body)
+        .reply(&routes)
+        .await;
+    
+    assert_eq!(response.status(), 400);
+    
+    let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+    let json: serde_json::Value = serde_json::from_str(&body).expect("Invalid JSON");
+    assert!(json["error"].as_str().unwrap().contains(&max_hosts.to_string()));
+}
+
+#[tokio::test]
+async fn test_dns_resolve_body_too_large() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+    let max_body_bytes = Config::default().max_request_body_bytes() as usize;
+    
+    let request_body = serde_json::json!({
+        "hosts": ["localhost"],
+        "padding": "x".repeat(max_body_bytes)
+    });
+    
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .header("content-type", "application/json")
+        .json(&request_body)
+        .reply(&routes)
+        .await;
+    
+    assert_eq!(response.status(), 413);
+}
+
+#[tokio::test]
+async fn test_dns_resolve_all_hosts_fail() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+    
+    let request_body = serde_json::json!({
+        "hosts": ["invalid..host", "also..invalid"]
+    });
+    
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .header("content-type", "application/json")
+        .json(&request_body)
+        .reply(&routes)
+        .await;
+    
+    assert_eq!(response.status(), 502);
+    
+    // The body is the same as for a successful batch
+    let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+    let json: serde_json::Value = serde_json::from_str(&body).expect("Invalid JSON");
+    
+    assert_eq!(json["results"].as_array().unwrap().len(), 2);
+    assert_eq!(json["total_resolved"], 0);
+    assert_eq!(json["total_errors"], 2);
+    for result in json["results"].as_array().unwrap() {
+        assert_eq!(result["status"], "error");
+        assert!(result["ip_addresses"].as_array().unwrap().is_empty());
+        assert!(result["error"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn test_dns_resolve_cors_preflight() {
+    let origin = "http://dashboard.internal:3000";
+    let routes = create_test_server_with_config(Config {
+        cors_allowed_origins: vec![origin.to_string()],
+        ..test_config()
+    })
+    .await
+    .expect("Failed to create test server");
+    
+    let response = warp::test::request()
+        .method("OPTIONS")
+        .path("/api/dns/resolve")
+        .header("origin", origin)
+        .header("access-control-request-method", "POST")
+        .header("access-control-request-headers", "content-type")
+        .reply(&routes)
+        .await;
+    
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["access-control-allow-origin"], origin);
+    let allowed_methods = response.headers()["access-control-allow-methods"].to_str().unwrap();
+    assert!(allowed_methods.contains("POST"));
+    let allowed_headers = response.headers()["access-control-allow-headers"].to_str().unwrap();
+    assert!(allowed_headers.contains("content-type"));
+    
+    // Other origins are refused
+    let response = warp::test::request()
+        .method("OPTIONS")
+        .path("/api/dns/resolve")
+        .header("origin", "http://elsewhere.example")
+        .header("access-control-request-method", "POST")
+        .reply(&routes)
+        .await;
+    
+    assert_eq!(response.status(), 403);
+}
+
+#[tokio::test]
+async fn test_dns_resolve_family_v4() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+    
+    let request_body = serde_json::json!({
+        "hosts": ["google.com"],
+        "family": "v4"
+    });
+    
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .header("content-type", "application/json")
+        .json(&request_body)
+        .reply(&routes)
+        .await;
+    
+    assert_eq!(response.status(), 200);
+    
+    let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+    let json: serde_json::Value = serde_json::from_str(&body).expect("Invalid JSON");
+    
+    let result = &json["results"][0];
+    assert_eq!(result["status"], "success");
+    let addresses = result["ip_addresses"].as_array().unwrap();
+    assert!(!addresses.is_empty());
+    // IPv6 addresses always contain a colon
+    assert!(addresses.iter().all(|ip| !ip.as_str().unwrap().contains(':')));
+}
+
+#[tokio::test]
+async fn test_dns_resolve_family_filters_localhost() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+    
+    for (family, expected) in [("v4", "127.0.0.1"), ("V4", "127.0.0.1")] {
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/dns/resolve")
+            .header("content-type", "application/json")
+            .json(&serde_json::json!({ "hosts": ["localhost"], "family": family }))
+            .reply(&routes)
+            .await;
+        
+        assert_eq!(response.status(), 200);
+        
+        let json: serde_json::Value = serde_json::from_slice(response.body()).expect("Invalid JSON");
+        let result = &json["results"][0];
+        assert_eq!(result["status"], "success");
+        assert_eq!(result["ip_addresses"], serde_json::json!([expected]));
+    }
+}
+
+#[tokio::test]
+async fn test_dns_resolve_invalid_family() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+    
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .header("content-type", "application/json")
+        .json(&serde_json::json!({ "hosts": ["localhost"], "family": "ipv4" }))
+        .reply(&routes)
+        .await;
+    
+    assert_eq!(response.status(), 400);
+    
+    let json: serde_json::Value = serde_json::from_slice(response.body()).expect("Invalid JSON");
+    assert!(json["error"].as_str().unwrap().contains("Invalid family"));
+}
+
 #[tokio::test]
 async fn test_dns_resolve_empty_hosts() {
     let routes = create_test_server().await.expect("Failed to create test server");
@@ -285,6 +861,98 @@ async fn test_dns_resolve_performance() {
     assert!(duration.as_secs() < 5); // Should be fast due to concurrent resolution
 }
 
+#[tokio::test]
+async fn test_metrics_endpoint() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .json(&serde_json::json!({"hosts": ["localhost", "invalid..host"]}))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let response = warp::test::request()
+        .method("GET")
+        .path("/metrics")
+        .reply(&routes)
+        .await;
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "text/plain; version=0.0.4");
+
+    let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+    assert!(body.contains("wdns_dns_requests_total 1"));
+    assert!(body.contains("wdns_dns_hosts_resolved_total 1"));
+    assert!(body.contains("wdns_dns_errors_total 1"));
+    assert!(body.contains("wdns_dns_resolution_duration_seconds_count 2"));
+}
+
+#[tokio::test]
+async fn test_dns_reverse_endpoint() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/reverse")
+        .header("content-type", "application/json")
+        .json(&serde_json::json!({"ips": ["127.0.0.1", "not-an-ip"]}))
+        .reply(&routes)
+        .await;
+
+    assert_eq!(response.status(), 200);
+
+    let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+    let json: serde_json::Value = serde_json::from_str(&body).expect("Invalid JSON");
+
+    let results = json["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(json["total_resolved"], 1);
+    assert_eq!(json["total_errors"], 1);
+
+    assert_eq!(results[0]["ip"], "127.0.0.1");
+    assert_eq!(results[0]["status"], "success");
+    assert!(results[0]["names"].as_array().unwrap().iter().any(|n| n == "localhost"));
+
+    assert_eq!(results[1]["ip"], "not-an-ip");
+    assert_eq!(results[1]["status"], "error");
+    assert!(results[1]["error"].is_string());
+}
+
+#[tokio::test]
+async fn test_dns_reverse_empty_ips() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/reverse")
+        .json(&serde_json::json!({"ips": []}))
+        .reply(&routes)
+        .await;
+
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_dns_reverse_over_host_limit() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+    let max_hosts = Config::default().max_hosts_per_request;
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/reverse")
+        .json(&serde_json::json!({"ips": vec!["127.0.0.1"; max_hosts + 1]}))
+        .reply(&routes)
+        .await;
+
+    assert_eq!(response.status(), 400);
+
+    let body = String::from_utf8(response.body().to_vec()).expect("Invalid UTF-8");
+    let json: serde_json::Value = serde_json::from_str(&body).expect("Invalid JSON");
+    assert!(json["error"].as_str().unwrap().contains(&max_hosts.to_string()));
+}
+
 #[tokio::test]
 async fn test_not_found_endpoint() {
     let routes = create_test_server().await.expect("Failed to create test server");
@@ -298,3 +966,107 @@ async fn test_not_found_endpoint() {
     // Warp returns 404 for unmatched routes
     assert_eq!(response.status(), 404);
 }
+
+// Resolves "localhost" through the API and returns its `from_cache` flag
+async fn resolve_localhost_from_cache(
+    routes: &(impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static),
+) -> bool {
+    let request_body = DnsRequest {
+        hosts: vec!["localhost".to_string()],
+        strict: false,
+        family: None,
+        address_sort: None,
+    };
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/resolve")
+        .header("content-type", "application/json")
+        .json(&request_body)
+        .reply(routes)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let json: serde_json::Value = serde_json::from_slice(response.body()).expect("Invalid JSON");
+    json["results"][0]["from_cache"].as_bool().expect("Missing from_cache")
+}
+
+#[tokio::test]
+async fn test_dns_flush_cache() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+
+    assert!(!resolve_localhost_from_cache(&routes).await);
+    assert!(resolve_localhost_from_cache(&routes).await);
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/flush-cache")
+        .header("content-length", "0")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let json: serde_json::Value = serde_json::from_slice(response.body()).expect("Invalid JSON");
+    assert!(json["evicted"].as_u64().unwrap() >= 1);
+
+    assert!(!resolve_localhost_from_cache(&routes).await);
+}
+
+#[tokio::test]
+async fn test_dns_flush_cache_hosts() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+
+    assert!(!resolve_localhost_from_cache(&routes).await);
+
+    let flush = |hosts: Vec<String>| {
+        warp::test::request()
+            .method("POST")
+            .path("/api/dns/flush-cache")
+            .header("content-type", "application/json")
+            .json(&FlushCacheRequest { hosts: Some(hosts) })
+    };
+
+    let response = flush(vec!["example.invalid".to_string()]).reply(&routes).await;
+    assert_eq!(response.status(), 200);
+    let json: serde_json::Value = serde_json::from_slice(response.body()).expect("Invalid JSON");
+    assert_eq!(json["evicted"], 0);
+    assert!(resolve_localhost_from_cache(&routes).await);
+
+    let response = flush(vec!["LOCALHOST".to_string()]).reply(&routes).await;
+    let json: serde_json::Value = serde_json::from_slice(response.body()).expect("Invalid JSON");
+    assert_eq!(json["evicted"], 1);
+    assert!(!resolve_localhost_from_cache(&routes).await);
+}
+
+#[tokio::test]
+async fn test_dns_flush_cache_invalid_body() {
+    let routes = create_test_server().await.expect("Failed to create test server");
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/api/dns/flush-cache")
+        .header("content-type", "application/json")
+        .body("not json")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[test]
+fn test_resolve_cli_prints_json_response() {
+    // Config::load writes a default config.json into the working directory
+    let dir = std::env::temp_dir().join(format!("wdns-resolve-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_wdns-service"))
+        .args(["--resolve", "localhost"])
+        .current_dir(&dir)
+        .output()
+        .expect("Failed to run wdns-service");
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("127.0.0.1"), "{}", stdout);
+    let response: serde_json::Value = serde_json::from_str(&stdout).expect("stdout is not JSON");
+    assert_eq!(response["total_resolved"], 1);
+}
@@ -1,5 +1,4 @@
 use anyhow::Result;
-use serde_json;
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::time::{sleep, Duration};
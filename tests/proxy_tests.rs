@@ -3,6 +3,7 @@ use std::net::SocketAddr;
 use wdns_service::{Config, ProxyServer};
 
 // Helper function to create test proxy server
+#[allow(dead_code)]
 async fn create_test_proxy_server() -> Result<(ProxyServer, SocketAddr)> {
     let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
     let proxy = ProxyServer::new(addr);
@@ -26,7 +27,7 @@ async fn test_proxy_server_bind() {
 #[tokio::test]
 async fn test_config_proxy_settings() {
     let config = Config::default();
-    assert_eq!(config.proxy_enabled, true);
+    assert!(config.proxy_enabled);
     assert_eq!(config.proxy_bind_address, "0.0.0.0:9701");
     
     let proxy_addr = config.proxy_bind_addr().expect("Failed to parse proxy bind address");
@@ -41,9 +42,10 @@ async fn test_config_proxy_disabled() {
         max_concurrent_resolutions: 100,
         proxy_enabled: false,
         proxy_bind_address: "0.0.0.0:9701".to_string(),
+        ..Config::default()
     };
     
-    assert_eq!(config.proxy_enabled, false);
+    assert!(!config.proxy_enabled);
     assert_eq!(config.proxy_bind_address, "0.0.0.0:9701");
 }
 
@@ -55,9 +57,10 @@ async fn test_config_custom_proxy_address() {
         max_concurrent_resolutions: 100,
         proxy_enabled: true,
         proxy_bind_address: "192.168.1.100:8080".to_string(),
+        ..Config::default()
     };
     
-    assert_eq!(config.proxy_enabled, true);
+    assert!(config.proxy_enabled);
     assert_eq!(config.proxy_bind_address, "192.168.1.100:8080");
     
     let proxy_addr = config.proxy_bind_addr().expect("Failed to parse proxy bind address");
@@ -82,6 +85,7 @@ async fn test_proxy_server_with_custom_config() {
         max_concurrent_resolutions: 50,
         proxy_enabled: true,
         proxy_bind_address: "127.0.0.1:8080".to_string(),
+        ..Config::default()
     };
     
     let proxy_addr = config.proxy_bind_addr().expect("Failed to parse proxy bind address");
@@ -136,6 +140,7 @@ async fn test_config_load_with_proxy_settings() {
         max_concurrent_resolutions: 200,
         proxy_enabled: true,
         proxy_bind_address: "0.0.0.0:8080".to_string(),
+        ..Config::default()
     };
     
     let config_json = serde_json::to_string_pretty(&test_config).expect("Failed to serialize");
@@ -150,7 +155,7 @@ async fn test_config_load_with_proxy_settings() {
     // Restore original directory
     std::env::set_current_dir(&original_dir).expect("Failed to restore original dir");
     
-    assert_eq!(loaded_config.proxy_enabled, true);
+    assert!(loaded_config.proxy_enabled);
     assert_eq!(loaded_config.proxy_bind_address, "0.0.0.0:8080");
     assert_eq!(loaded_config.dns_timeout_seconds, 15);
     assert_eq!(loaded_config.max_concurrent_resolutions, 200);
@@ -179,6 +184,7 @@ async fn test_proxy_server_error_handling() {
         max_concurrent_resolutions: 100,
         proxy_enabled: true,
         proxy_bind_address: "invalid-address".to_string(),
+        ..Config::default()
     };
     
     let result = invalid_config.proxy_bind_addr();
@@ -216,6 +222,7 @@ async fn test_proxy_server_configuration_combinations() {
             max_concurrent_resolutions: 100,
             proxy_enabled,
             proxy_bind_address: proxy_bind_address.to_string(),
+            ..Config::default()
         };
         
         assert_eq!(config.proxy_enabled, proxy_enabled);